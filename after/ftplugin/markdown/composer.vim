@@ -3,16 +3,67 @@ let s:plugin_root = expand('<sfile>:p:h:h:h:h')
 if exists('g:markdown_composer_refresh_rate')
   let s:refresh_rate = g:markdown_composer_refresh_rate
 elseif exists('g:markdown_composer_external_renderer')
+      \ || !empty(get(g:, 'markdown_composer_external_renderers', {}))
   let s:refresh_rate = 500
 else
   let s:refresh_rate = 0
 endif
 
+" Returns the color scheme of the preview, following 'background' if requested.
+function! s:colorScheme()
+  let l:scheme = get(g:, 'markdown_composer_color_scheme', 'auto')
+  return l:scheme ==# 'background' ? &background : l:scheme
+endfunction
+
+" Returns whether editors connect to the composer over a socket, rather than
+" starting it as a job.
+function! s:usesSocket()
+  return exists('g:markdown_composer_rpc_socket')
+        \ || exists('g:markdown_composer_rpc_address')
+endfunction
+
+" Connects to a composer that is listening on g:markdown_composer_rpc_socket
+" or g:markdown_composer_rpc_address. Returns whether the connection
+" succeeded.
+function! s:connect() abort
+  if exists('g:markdown_composer_rpc_socket')
+    let l:socket = expand(g:markdown_composer_rpc_socket)
+    if getftype(l:socket) !=# 'socket'
+      return 0
+    endif
+    let [l:mode, l:address] = ['pipe', l:socket]
+  else
+    let [l:mode, l:address] = ['tcp', g:markdown_composer_rpc_address]
+  endif
+
+  try
+    if has('nvim')
+      let s:job = sockconnect(l:mode, l:address, { 'rpc': v:true })
+    else
+      let l:prefix = l:mode ==# 'pipe' ? 'unix:' : ''
+      let l:channel = ch_open(l:prefix . l:address, { 'mode': 'json' })
+      if ch_status(l:channel) !=# 'open'
+        return 0
+      endif
+      let s:job = l:channel
+    endif
+  catch
+    return 0
+  endtry
+
+  return 1
+endfunction
+
 function! s:startServer()
   if exists('s:job')
     return
   endif
 
+  " Reattach to a composer that is already running.
+  if s:usesSocket() && s:connect()
+    return
+  endif
+
   let l:binary = get(
 			  \g:,
 			  \'markdown_composer_binary',
@@ -23,34 +74,335 @@ function! s:startServer()
 
   if exists('g:markdown_composer_browser')
     call extend(l:args, ['--browser', g:markdown_composer_browser])
+    if exists('g:markdown_composer_browser_args')
+      call extend(l:args, ['--browser-args', g:markdown_composer_browser_args])
+    endif
+  endif
+
+  if exists('g:markdown_composer_browser_mode')
+    call extend(l:args, ['--browser-mode', g:markdown_composer_browser_mode])
+  endif
+
+  if exists('g:markdown_composer_pdf_browser')
+    call extend(l:args, ['--pdf-browser', g:markdown_composer_pdf_browser])
   endif
 
-  if exists('g:markdown_composer_open_browser')
-    if !g:markdown_composer_open_browser
-      call add(l:args, '--no-auto-open')
+  for l:property in ['pdf_paper_size', 'pdf_margin', 'pdf_header', 'pdf_footer']
+    if exists('g:markdown_composer_' . l:property)
+      let l:value = g:markdown_composer_{l:property}
+      call extend(l:args, ['--' . substitute(l:property, '_', '-', 'g'),
+            \ type(l:value) == type('') ? l:value : string(l:value)])
     endif
+  endfor
+
+  if get(g:, 'markdown_composer_pdf_landscape', 0)
+    call add(l:args, '--pdf-landscape')
+  endif
+
+  " The browser is opened once the buffer is sent, so that it doesn't show an
+  " empty preview first.
+  if get(g:, 'markdown_composer_open_browser', 1)
+    call add(l:args, '--open-on-first-send')
+  else
+    call add(l:args, '--no-auto-open')
+  endif
+
+  if get(g:, 'markdown_composer_close_on_shutdown', 0)
+    call add(l:args, '--close-on-shutdown')
   endif
 
   if exists('g:markdown_composer_syntax_theme')
     call extend(l:args, ['--highlight-theme', g:markdown_composer_syntax_theme])
   endif
 
+  if exists('g:markdown_composer_syntax_theme_dark')
+    call extend(l:args, ['--highlight-theme-dark', g:markdown_composer_syntax_theme_dark])
+  endif
+
+  call extend(l:args, ['--color-scheme', s:colorScheme()])
+
+  for l:property in ['font_family', 'code_font_family', 'font_size', 'max_width']
+    if exists('g:markdown_composer_' . l:property)
+      let l:value = g:markdown_composer_{l:property}
+      call extend(l:args, ['--' . substitute(l:property, '_', '-', 'g'),
+            \ type(l:value) == type('') ? l:value : string(l:value)])
+    endif
+  endfor
+
+  if exists('g:markdown_composer_math')
+    call extend(l:args, ['--math', g:markdown_composer_math])
+  endif
+
+  if exists('g:markdown_composer_flavor')
+    call extend(l:args, ['--flavor', g:markdown_composer_flavor])
+  endif
+
+  if !empty(get(g:, 'markdown_composer_enable_extensions', []))
+    call extend(l:args, ['--enable', join(g:markdown_composer_enable_extensions, ',')])
+  endif
+
+  if !empty(get(g:, 'markdown_composer_disable_extensions', []))
+    call extend(l:args, ['--disable', join(g:markdown_composer_disable_extensions, ',')])
+  endif
+
+  if exists('g:markdown_composer_front_matter')
+    call extend(l:args, ['--front-matter', g:markdown_composer_front_matter])
+  endif
+
+  if exists('g:markdown_composer_wikilink_extension')
+    call extend(l:args, ['--wikilink-extension', g:markdown_composer_wikilink_extension])
+  endif
+
+  if exists('g:markdown_composer_wikilink_case')
+    call extend(l:args, ['--wikilink-case', g:markdown_composer_wikilink_case])
+  endif
+
+  if exists('g:markdown_composer_heading_slugs')
+    call extend(l:args, ['--heading-slugs', g:markdown_composer_heading_slugs])
+  endif
+
+  if get(g:, 'markdown_composer_emoji', 0)
+    call add(l:args, '--emoji')
+  endif
+
+  if get(g:, 'markdown_composer_code_line_numbers', 0)
+    call add(l:args, '--code-line-numbers')
+  endif
+
+  if get(g:, 'markdown_composer_sanitize', 0)
+    call add(l:args, '--sanitize')
+  endif
+
+  if get(g:, 'markdown_composer_lazy_images', 0)
+    call add(l:args, '--lazy-images')
+  endif
+
+  if exists('g:markdown_composer_image_max_width')
+    call extend(l:args, ['--image-max-width', g:markdown_composer_image_max_width])
+  endif
+
+  if exists('g:markdown_composer_image_max_height')
+    call extend(l:args, ['--image-max-height', g:markdown_composer_image_max_height])
+  endif
+
+  if get(g:, 'markdown_composer_inline_images', 0)
+    call add(l:args, '--inline-images')
+  endif
+
+  if exists('g:markdown_composer_thumbnail_command')
+    call extend(l:args, ['--thumbnail-command', g:markdown_composer_thumbnail_command])
+
+    if exists('g:markdown_composer_thumbnail_size')
+      call extend(l:args, ['--thumbnail-size', g:markdown_composer_thumbnail_size])
+    endif
+  endif
+
+  if exists('g:markdown_composer_mermaid')
+    call extend(l:args, ['--mermaid', g:markdown_composer_mermaid])
+  endif
+
+  if exists('g:markdown_composer_plantuml')
+    call extend(l:args, ['--plantuml', g:markdown_composer_plantuml])
+  elseif exists('g:markdown_composer_plantuml_server')
+    call extend(l:args, ['--plantuml-server', g:markdown_composer_plantuml_server])
+  endif
+
+  if exists('g:markdown_composer_auto_scroll')
+    if !g:markdown_composer_auto_scroll
+      call add(l:args, '--no-auto-scroll')
+    endif
+  endif
+
+  if get(g:, 'markdown_composer_toc', 0)
+    call add(l:args, '--toc')
+  endif
+
+  if get(g:, 'markdown_composer_spell_check', 0)
+    call add(l:args, '--spell-check')
+  endif
+
+  if exists('g:markdown_composer_spell_check_language')
+    call extend(l:args, ['--spell-check-language', g:markdown_composer_spell_check_language])
+  endif
+
+  if exists('g:markdown_composer_spell_check_dictionary')
+    call extend(l:args, ['--spell-check-dictionary',
+          \ expand(g:markdown_composer_spell_check_dictionary)])
+  endif
+
+  if exists('g:markdown_composer_linter')
+    call extend(l:args, ['--linter', g:markdown_composer_linter])
+  endif
+
+  if get(g:, 'markdown_composer_offline', 0)
+    call add(l:args, '--offline')
+  endif
+
+  if exists('g:markdown_composer_csp')
+    call extend(l:args, ['--csp', g:markdown_composer_csp])
+  endif
+
+  for l:source in get(g:, 'markdown_composer_csp_script_sources', [])
+    call extend(l:args, ['--csp-script-src', l:source])
+  endfor
+
+  for l:source in get(g:, 'markdown_composer_csp_style_sources', [])
+    call extend(l:args, ['--csp-style-src', l:source])
+  endfor
+
+  if get(g:, 'markdown_composer_presentation', 0)
+    call add(l:args, '--presentation')
+  endif
+
+  if get(g:, 'markdown_composer_side_by_side', 0)
+    call add(l:args, '--side-by-side')
+  endif
+
+  if get(g:, 'markdown_composer_focus', 0)
+    call add(l:args, '--focus')
+  endif
+
+  if get(g:, 'markdown_composer_render_timings', 0)
+    call add(l:args, '--render-timings')
+  endif
+
+  if exists('g:markdown_composer_zoom')
+    call extend(l:args, ['--zoom', string(g:markdown_composer_zoom)])
+  endif
+
+  if exists('g:markdown_composer_title_source')
+    call extend(l:args, ['--title-source', g:markdown_composer_title_source])
+  endif
+
+  for [l:filetype, l:renderer] in items(get(g:, 'markdown_composer_external_renderers', {}))
+    call extend(l:args, ['--external-renderer-for', l:filetype . '=' . l:renderer])
+  endfor
+
   if exists('g:markdown_composer_external_renderer')
     call extend(l:args, ['--external-renderer', g:markdown_composer_external_renderer])
   endif
 
-  if exists('g:markdown_composer_address')
-    call extend(l:args, ['--address', g:markdown_composer_address])
+  if exists('g:markdown_composer_external_renderer')
+        \ || !empty(get(g:, 'markdown_composer_external_renderers', {}))
+    if exists('g:markdown_composer_external_renderer_framing')
+      call extend(l:args, ['--external-renderer-framing',
+            \ g:markdown_composer_external_renderer_framing])
+    endif
+
+    for [l:name, l:value] in items(get(g:, 'markdown_composer_external_renderer_env', {}))
+      call extend(l:args, ['--external-renderer-env', l:name . '=' . l:value])
+    endfor
+
+    if exists('g:markdown_composer_external_renderer_cwd')
+      call extend(l:args, ['--external-renderer-cwd',
+            \ fnamemodify(g:markdown_composer_external_renderer_cwd, ':p')])
+    endif
+
+    if exists('g:markdown_composer_external_renderer_encoding')
+      call extend(l:args, ['--external-renderer-encoding',
+            \ g:markdown_composer_external_renderer_encoding])
+    endif
+
+    if exists('g:markdown_composer_external_renderer_timeout')
+      call extend(l:args, ['--external-renderer-timeout',
+            \ string(g:markdown_composer_external_renderer_timeout)])
+    endif
+
+    if get(g:, 'markdown_composer_external_renderer_fallback', 0)
+      call add(l:args, '--external-renderer-fallback')
+    endif
+  endif
+
+  if exists('g:markdown_composer_debounce_ms')
+    call extend(l:args, ['--debounce-ms', g:markdown_composer_debounce_ms])
+  endif
+
+  if exists('g:markdown_composer_virtualize_size')
+    call extend(l:args, ['--virtualize-size', g:markdown_composer_virtualize_size])
+  endif
+
+  if exists('g:markdown_composer_listen_unix')
+    call extend(l:args, ['--listen-unix', g:markdown_composer_listen_unix])
+  else
+    if exists('g:markdown_composer_address')
+      let l:addresses = g:markdown_composer_address
+      for l:address in type(l:addresses) == type([]) ? l:addresses : [l:addresses]
+        call extend(l:args, ['--address', l:address])
+      endfor
+    endif
+
+    if exists('g:markdown_composer_port')
+      call extend(l:args, ['--port', g:markdown_composer_port])
+    endif
+  endif
+
+  if exists('g:markdown_composer_auth_token')
+    call extend(l:args, ['--auth-token', g:markdown_composer_auth_token])
+  endif
+
+  for l:origin in get(g:, 'markdown_composer_allow_origins', [])
+    call extend(l:args, ['--allow-origin', l:origin])
+  endfor
+
+  if exists('g:markdown_composer_tls_cert') && exists('g:markdown_composer_tls_key')
+    call extend(l:args, ['--tls-cert', expand(g:markdown_composer_tls_cert)])
+    call extend(l:args, ['--tls-key', expand(g:markdown_composer_tls_key)])
+  endif
+
+  if exists('g:markdown_composer_log_level')
+    call extend(l:args, ['--log-level', g:markdown_composer_log_level])
+  endif
+
+  if exists('g:markdown_composer_log_format')
+    call extend(l:args, ['--log-format', g:markdown_composer_log_format])
+  endif
+
+  if exists('g:markdown_composer_log_max_size')
+    call extend(l:args, ['--log-max-size', g:markdown_composer_log_max_size])
+  endif
+
+  if exists('g:markdown_composer_log_max_backups')
+    call extend(l:args, ['--log-max-backups', g:markdown_composer_log_max_backups])
+  endif
+
+  call extend(l:args, ['--log-file',
+        \ get(g:, 'markdown_composer_log_file', s:plugin_root . '/error.log')])
+
+  if exists('g:markdown_composer_port_file')
+    call extend(l:args, ['--port-file', g:markdown_composer_port_file])
+  endif
+
+  if exists('g:markdown_composer_session')
+    call add(l:args, '--session')
+    if !empty(g:markdown_composer_session)
+      call add(l:args, g:markdown_composer_session)
+    endif
   endif
 
-  if exists('g:markdown_composer_port')
-    call extend(l:args, ['--port', g:markdown_composer_port])
+  for l:js in get(g:, 'markdown_composer_custom_js', [])
+    call extend(l:args, ['--custom-js', l:js])
+  endfor
+
+  if exists('g:markdown_composer_template')
+    call extend(l:args, ['--template', expand(g:markdown_composer_template)])
+  endif
+
+  if exists('g:markdown_composer_favicon')
+    call extend(l:args, ['--favicon', g:markdown_composer_favicon])
   endif
 
   for l:css in get(g:, 'markdown_composer_custom_css', [])
     call extend(l:args, ['--custom-css', l:css])
   endfor
 
+  for l:language in get(g:, 'markdown_composer_highlight_languages', [])
+    call extend(l:args, ['--highlight-language', l:language])
+  endfor
+
+  for [l:alias, l:language] in items(get(g:, 'markdown_composer_highlight_aliases', {}))
+    call extend(l:args, ['--highlight-alias', l:alias . '=' . l:language])
+  endfor
+
   call extend(l:args, ['--working-directory', getcwd()])
 
   let s:file = expand('%:p')
@@ -59,73 +411,612 @@ function! s:startServer()
   endif
 
   function! s:onServerExit(id, exit_status, event) abort
-    if exists(s:job)
+    if exists('s:job')
       unlet s:job
     endif
   endfunction
 
-  if has('nvim')
-    let l:job = jobstart(l:args, {
-          \ 'cwd': s:plugin_root,
-          \ 'rpc': v:true,
-          \ 'on_exit': function('s:onServerExit'),
-          \ })
-    if l:job == -1
-      echom 'Could not execute markdown composer: try ' .
-            \ '`cargo build --release` in the plugin directory'
+  if s:usesSocket()
+    " The composer outlives this editor, so that editors may reattach to it.
+    if exists('g:markdown_composer_rpc_socket')
+      call extend(l:args, ['--rpc-socket', expand(g:markdown_composer_rpc_socket)])
+    else
+      call extend(l:args, ['--rpc-listen', g:markdown_composer_rpc_address])
+    endif
+
+    if has('nvim')
+      call jobstart(l:args, { 'cwd': s:plugin_root, 'detach': v:true })
+    else
+      call job_start(l:args, {
+            \ 'cwd': s:plugin_root,
+            \ 'in_io': 'null',
+            \ 'out_io': 'null',
+            \ 'err_io': 'null',
+            \ 'stoponexit': '',
+            \ })
+    endif
+
+    for l:attempt in range(50)
+      if s:connect()
+        break
+      endif
+      sleep 100m
+    endfor
+
+    if !exists('s:job')
+      echom 'Could not connect to markdown composer at ' .
+            \ get(g:, 'markdown_composer_rpc_socket', get(g:, 'markdown_composer_rpc_address'))
       return
     endif
-    let s:job = l:job
   else
-    function! s:onServerExit(channel, exit_status) abort
-      if a:exit_status != 0
+    " The server stops when this editor exits, even if it crashes.
+    call extend(l:args, ['--parent-pid', string(getpid())])
+
+    if has('nvim')
+      let l:job = jobstart(l:args, {
+            \ 'cwd': s:plugin_root,
+            \ 'rpc': v:true,
+            \ 'on_exit': function('s:onServerExit'),
+            \ })
+      if l:job == -1
         echom 'Could not execute markdown composer: try ' .
-              \ '`cargo build --release --no-default-features --features json-rpc`' .
-              \ ' in the plugin directory'
+              \ '`cargo build --release` in the plugin directory'
+        return
       endif
-    endfunction
+      let s:job = l:job
+    else
+      function! s:onServerExit(channel, exit_status) abort
+        if exists('s:job')
+          unlet s:job
+        endif
 
-    let l:job = job_start(l:args, {
-          \ 'mode': 'json',
-          \ 'cwd': s:plugin_root,
-          \ 'err_io': 'null',
-          \ 'exit_cb': function('s:onServerExit'),
-          \ })
-    let l:channel = job_getchannel(l:job)
-    if string(l:channel) !=# 'channel fail'
-      let s:job = job_getchannel(l:job)
+        if a:exit_status != 0
+          echom 'Could not execute markdown composer: try ' .
+                \ '`cargo build --release --no-default-features --features json-rpc`' .
+                \ ' in the plugin directory'
+        endif
+      endfunction
+
+      let l:job = job_start(l:args, {
+            \ 'mode': 'json',
+            \ 'cwd': s:plugin_root,
+            \ 'err_io': 'null',
+            \ 'exit_cb': function('s:onServerExit'),
+            \ })
+      let l:channel = job_getchannel(l:job)
+      if string(l:channel) !=# 'channel fail'
+        let s:job = job_getchannel(l:job)
+      endif
     endif
   endif
 
+  " The files whose changes since HEAD the new server highlights, by buffer.
+  let s:git_paths = {}
+
   if s:refresh_rate > 0 && !exists('s:timer')
     let s:timer = timer_start(s:refresh_rate, function('s:markdownHandler'), { 'repeat': -1 })
    endif
 endfunction
 
+" Returns the parameters of a procedure that acts on a buffer, prefixed with
+" the buffer's number if each buffer is previewed on its own page.
+function! s:bufferParams(bufnr, params)
+  if get(g:, 'markdown_composer_multiple_buffers', 0)
+    return [string(a:bufnr)] + a:params
+  endif
+  return a:params
+endfunction
+
 function! s:sendBuffer()
   if exists('s:job')
-    let l:data = join(getline(1, '$'), "\n")
+    let s:bufnr = bufnr('%')
+
+    " If the buffer matches the file on disk, let the server read it instead
+    " of sending the whole buffer over the channel. A composer on an RPC
+    " address may be running on another machine that can't read the file.
+    let l:file = expand('%:p')
+    let l:remote = exists('g:markdown_composer_rpc_address')
+          \ && !exists('g:markdown_composer_rpc_socket')
+    if !&modified && filereadable(l:file) && !l:remote
+      let l:method = 'render_file'
+      let l:data = l:file
+    else
+      let l:method = 'send_data'
+      let l:data = join(getline(1, '$'), "\n")
+    endif
+
+    " Documents are rendered by the renderer or as the format of their filetype.
+    let l:params = [l:data]
+    if l:method ==# 'send_data'
+      let l:method = 'send_data_as'
+      let l:params = [&filetype] + l:params
+    endif
+
+    if get(g:, 'markdown_composer_git_changes', 0)
+      call s:sendGitPath(s:bufnr, l:file)
+    endif
+
+    let l:params = s:bufferParams(s:bufnr, l:params)
     if has('nvim')
-      call rpcnotify(s:job, 'send_data', l:data)
+      call call('rpcnotify', [s:job, l:method] + l:params)
+      let b:markdown_composer_job = s:job
+      call s:attachBuffer()
     else
       call ch_sendexpr(s:job, {
-            \ 'method': 'send_data',
-            \ 'params': [l:data],
+            \ 'method': l:method,
+            \ 'params': l:params,
             \ })
     endif
   endif
 endfunction
 
-function! s:openBrowser()
+" Tells the server which file a buffer is read from, so that its changes since
+" HEAD are highlighted, unless the server already knows.
+function! s:sendGitPath(bufnr, file)
+  let l:params = s:bufferParams(a:bufnr, [a:file])
+  let l:key = join(l:params[:-2])
+  if get(s:git_paths, l:key, '') ==# a:file
+    return
+  endif
+  let s:git_paths[l:key] = a:file
+
+  if has('nvim')
+    call call('rpcnotify', [s:job, 'set_git_path'] + l:params)
+  else
+    call ch_sendexpr(s:job, {
+          \ 'method': 'set_git_path',
+          \ 'params': l:params,
+          \ })
+  endif
+endfunction
+
+" Sends the buffer after it changes, unless its changes are already being
+" sent as they're made.
+function! s:bufferChanged()
+  if !get(b:, 'markdown_composer_attached', 0)
+    call s:sendBuffer()
+  endif
+endfunction
+
+" Sends each change to the current buffer as it's made, instead of sending
+" the whole buffer after it changes. Only supported by Neovim.
+function! s:attachBuffer()
+  if !get(g:, 'markdown_composer_send_diffs', 0)
+        \ || get(b:, 'markdown_composer_attached', 0)
+    return
+  endif
+
+  let b:markdown_composer_attached = 1
+  lua << EOF
+  vim.api.nvim_buf_attach(0, false, {
+    on_lines = function(_, bufnr, _, first, last, new_last)
+      local lines = vim.api.nvim_buf_get_lines(bufnr, first, new_last, true)
+      local data = #lines > 0 and table.concat(lines, '\n') .. '\n' or ''
+      return vim.fn.MarkdownComposerSendDiff(bufnr, first, last, data) == 0
+    end,
+    on_detach = function(_, bufnr)
+      vim.b[bufnr].markdown_composer_attached = nil
+    end,
+  })
+EOF
+endfunction
+
+" Sends a change to an attached buffer. Returns 0 if the buffer should be
+" detached, such as when the server was restarted since the whole buffer was
+" last sent.
+function! MarkdownComposerSendDiff(bufnr, first, last, lines)
+  if !exists('s:job') || !get(g:, 'markdown_composer_send_diffs', 0)
+        \ || getbufvar(a:bufnr, 'markdown_composer_job', -1) != s:job
+    return 0
+  endif
+
+  " Unless each buffer has its own preview, only the buffer that was sent
+  " last is previewed.
+  if get(g:, 'markdown_composer_multiple_buffers', 0) || a:bufnr == s:bufnr
+    call call('rpcnotify', [s:job, 'send_diff'] +
+          \ s:bufferParams(a:bufnr, [string(a:first), string(a:last), a:lines]))
+  endif
+  return 1
+endfunction
+
+" Sends the whole of each buffer that sends changes, such as when a change
+" couldn't be applied to the server's copy of the buffer.
+function! s:resendAttachedBuffers()
+  if !exists('s:job')
+    return
+  endif
+
+  if get(g:, 'markdown_composer_multiple_buffers', 0)
+    let l:buffers = []
+    for l:bufnr in range(1, bufnr('$'))
+      if getbufvar(l:bufnr, 'markdown_composer_attached', 0)
+            \ && getbufvar(l:bufnr, 'markdown_composer_job', -1) == s:job
+        call add(l:buffers, l:bufnr)
+      endif
+    endfor
+  else
+    let l:buffers = exists('s:bufnr') ? [s:bufnr] : []
+  endif
+
+  for l:bufnr in l:buffers
+    let l:data = join(getbufline(l:bufnr, 1, '$'), "\n")
+    call call('rpcnotify', [s:job, 'send_data_as'] +
+          \ s:bufferParams(l:bufnr, [getbufvar(l:bufnr, '&filetype'), l:data]))
+  endfor
+endfunction
+
+function! s:openBrowser(anchor, force)
   if exists('s:job')
+    " Anchors start with '#', which distinguishes them from buffer IDs.
+    let l:anchor = empty(a:anchor) ? [] : ['#' . substitute(a:anchor, '^#', '', '')]
+    let l:force = a:force ? ['--force'] : []
+    let l:params = s:bufferParams(bufnr('%'), l:anchor + l:force)
     if has('nvim')
-      call rpcnotify(s:job, 'open_browser')
+      let l:url = call('rpcrequest', [s:job, 'open_browser'] + l:params)
     else
-      call ch_sendexpr(s:job, {
+      let l:url = ch_evalexpr(s:job, {
             \ 'method': 'open_browser',
+            \ 'params': l:params,
+            \ })
+    endif
+
+    " Without a display, such as over SSH, the server returns the URL instead.
+    if type(l:url) == type('') && !empty(l:url)
+      echo 'No display to open a browser on. Open the preview at ' . l:url
+    endif
+  endif
+endfunction
+
+" Ends the preview of a deleted buffer, if each buffer has its own preview.
+function! s:closeBuffer(bufnr)
+  if exists('s:job') && get(g:, 'markdown_composer_multiple_buffers', 0)
+    if has('nvim')
+      call rpcnotify(s:job, 'close_buffer', string(a:bufnr))
+    else
+      call ch_sendexpr(s:job, {
+            \ 'method': 'close_buffer',
+            \ 'params': [string(a:bufnr)],
+            \ })
+    endif
+  endif
+endfunction
+
+function! s:closeBrowser()
+  if exists('s:job')
+    if has('nvim')
+      call rpcnotify(s:job, 'close_browser')
+    else
+      call ch_sendexpr(s:job, {
+            \ 'method': 'close_browser',
+            \ 'params': [],
+            \ })
+    endif
+  endif
+endfunction
+
+function! s:shutdown()
+  if exists('s:job')
+    if has('nvim')
+      call rpcnotify(s:job, 'shutdown')
+    else
+      call ch_sendexpr(s:job, {
+            \ 'method': 'shutdown',
+            \ 'params': [],
+            \ })
+    endif
+
+    " A composer on an RPC socket isn't a job that reports when it exits.
+    if s:usesSocket()
+      unlet s:job
+    endif
+  endif
+endfunction
+
+function! s:sendCursor()
+  if exists('s:job')
+    let l:line = line('.')
+    if get(b:, 'markdown_composer_cursor_line', 0) == l:line
+      return
+    endif
+    let b:markdown_composer_cursor_line = l:line
+
+    let l:params = s:bufferParams(bufnr('%'), [string(l:line)])
+    if has('nvim')
+      call call('rpcnotify', [s:job, 'scroll_to_line'] + l:params)
+    else
+      call ch_sendexpr(s:job, {
+            \ 'method': 'scroll_to_line',
+            \ 'params': l:params,
+            \ })
+    endif
+  endif
+endfunction
+
+function! s:toggleAutoScroll()
+  if exists('s:job')
+    if has('nvim')
+      let l:enabled = rpcrequest(s:job, 'toggle_auto_scroll')
+    else
+      let l:enabled = ch_evalexpr(s:job, {
+            \ 'method': 'toggle_auto_scroll',
+            \ 'params': [],
+            \ })
+    endif
+    echo 'Auto-scroll ' . (l:enabled ? 'enabled' : 'disabled')
+  endif
+endfunction
+
+" Sets the zoom of the preview from a percentage, or from a change such as '+10'.
+function! s:setZoom(percent)
+  if exists('s:job')
+    if has('nvim')
+      let l:zoom = rpcrequest(s:job, 'set_zoom', a:percent)
+    else
+      let l:zoom = ch_evalexpr(s:job, {
+            \ 'method': 'set_zoom',
+            \ 'params': [a:percent],
+            \ })
+    endif
+    echo 'Zoom: ' . l:zoom . '%'
+  endif
+endfunction
+
+function! s:toggleFocus()
+  if exists('s:job')
+    if has('nvim')
+      let l:enabled = rpcrequest(s:job, 'toggle_focus')
+    else
+      let l:enabled = ch_evalexpr(s:job, {
+            \ 'method': 'toggle_focus',
+            \ 'params': [],
+            \ })
+    endif
+    echo 'Focus mode ' . (l:enabled ? 'enabled' : 'disabled')
+  endif
+endfunction
+
+function! s:toggleRenderTimings()
+  if exists('s:job')
+    if has('nvim')
+      let l:enabled = rpcrequest(s:job, 'toggle_render_timings')
+    else
+      let l:enabled = ch_evalexpr(s:job, {
+            \ 'method': 'toggle_render_timings',
+            \ 'params': [],
+            \ })
+    endif
+    echo 'Render timings ' . (l:enabled ? 'shown' : 'hidden')
+  endif
+endfunction
+
+function! s:toggleToc()
+  if exists('s:job')
+    if has('nvim')
+      let l:enabled = rpcrequest(s:job, 'toggle_toc')
+    else
+      let l:enabled = ch_evalexpr(s:job, {
+            \ 'method': 'toggle_toc',
+            \ 'params': [],
+            \ })
+    endif
+    echo 'Table of contents ' . (l:enabled ? 'shown' : 'hidden')
+  endif
+endfunction
+
+function! s:toggleSpellCheck()
+  if exists('s:job')
+    if has('nvim')
+      let l:enabled = rpcrequest(s:job, 'toggle_spell_check')
+    else
+      let l:enabled = ch_evalexpr(s:job, {
+            \ 'method': 'toggle_spell_check',
             \ 'params': [],
             \ })
     endif
+    echo 'Spell checking ' . (l:enabled ? 'enabled' : 'disabled')
+  endif
+endfunction
+
+function! s:togglePresentation()
+  if exists('s:job')
+    if has('nvim')
+      let l:enabled = rpcrequest(s:job, 'toggle_presentation')
+    else
+      let l:enabled = ch_evalexpr(s:job, {
+            \ 'method': 'toggle_presentation',
+            \ 'params': [],
+            \ })
+    endif
+    echo 'Presentation mode ' . (l:enabled ? 'enabled' : 'disabled')
+  endif
+endfunction
+
+function! s:toggleSideBySide()
+  if exists('s:job')
+    if has('nvim')
+      let l:enabled = rpcrequest(s:job, 'toggle_side_by_side')
+    else
+      let l:enabled = ch_evalexpr(s:job, {
+            \ 'method': 'toggle_side_by_side',
+            \ 'params': [],
+            \ })
+    endif
+    echo 'Side-by-side layout ' . (l:enabled ? 'enabled' : 'disabled')
+  endif
+endfunction
+
+function! s:sendColorScheme()
+  if exists('s:job') && get(g:, 'markdown_composer_color_scheme', 'auto') ==# 'background'
+    if has('nvim')
+      call rpcnotify(s:job, 'set_color_scheme', &background)
+    else
+      call ch_sendexpr(s:job, {
+            \ 'method': 'set_color_scheme',
+            \ 'params': [&background],
+            \ })
+    endif
+  endif
+endfunction
+
+" Sets a typography property from '{property} [{value}]'.
+function! s:setTypography(args)
+  if exists('s:job')
+    let [l:property, l:value] = matchlist(a:args, '^\(\S\+\)\s*\(.*\)$')[1:2]
+    if has('nvim')
+      call rpcnotify(s:job, 'set_typography', l:property, l:value)
+    else
+      call ch_sendexpr(s:job, {
+            \ 'method': 'set_typography',
+            \ 'params': [l:property, l:value],
+            \ })
+    endif
+  endif
+endfunction
+
+function! s:export(method, path)
+  if get(g:, 'markdown_composer_multiple_buffers', 0)
+    echoerr 'Export the preview from its toolbar instead'
+    return
+  endif
+
+  if exists('s:job')
+    let l:path = fnamemodify(a:path, ':p')
+    if has('nvim')
+      call rpcnotify(s:job, a:method, l:path)
+    else
+      call ch_sendexpr(s:job, {
+            \ 'method': a:method,
+            \ 'params': [l:path],
+            \ })
+    endif
+  endif
+endfunction
+
+function! s:echoUrl()
+  if exists('s:job')
+    let l:params = s:bufferParams(bufnr('%'), [])
+    if has('nvim')
+      echo call('rpcrequest', [s:job, 'get_url'] + l:params)
+    else
+      echo ch_evalexpr(s:job, {
+            \ 'method': 'get_url',
+            \ 'params': l:params,
+            \ })
+    endif
+  else
+    echo 'No job running'
+  endif
+endfunction
+
+function! s:echoStatus()
+  if !exists('s:job')
+    echo 'No job running'
+    return
+  endif
+
+  if has('nvim')
+    let l:status = rpcrequest(s:job, 'status')
+  else
+    let l:status = ch_evalexpr(s:job, {
+          \ 'method': 'status',
+          \ 'params': [],
+          \ })
+  endif
+
+  echo 'markdown-composer ' . l:status.version .
+        \ ', up ' . l:status.uptime_secs . 's' .
+        \ ', ' . l:status.clients . ' preview(s) connected'
+  if type(l:status.last_render) == type({})
+    echo printf('Last rendered %ds ago in %.1fms',
+          \ localtime() - l:status.last_render.timestamp,
+          \ l:status.last_render.duration_ms)
+  else
+    echo 'Nothing rendered yet'
+  endif
+  for [l:name, l:value] in sort(items(l:status.settings))
+    echo l:name . ': ' . (type(l:value) == type('') ? l:value : json_encode(l:value))
+  endfor
+endfunction
+
+function! s:checkLinks()
+  if !exists('s:job')
+    echo 'No job running'
+    return
+  endif
+
+  " The report should cover the latest contents of the buffer.
+  call s:sendBuffer()
+
+  let l:bufnr = bufnr('%')
+  let l:params = s:bufferParams(l:bufnr, [])
+  if has('nvim')
+    let l:problems = call('rpcrequest', [s:job, 'check_links'] + l:params)
+  else
+    let l:problems = ch_evalexpr(s:job, {
+          \ 'method': 'check_links',
+          \ 'params': l:params,
+          \ })
+  endif
+
+  call setqflist(map(l:problems, {_, problem -> {
+        \ 'bufnr': l:bufnr,
+        \ 'lnum': problem.line,
+        \ 'col': problem.column,
+        \ 'text': problem.message,
+        \ }}), 'r')
+  call setqflist([], 'a', {'title': 'Broken links'})
+
+  if empty(l:problems)
+    echo 'No broken links'
+  else
+    copen
+  endif
+endfunction
+
+function! s:echoStats()
+  if !exists('s:job')
+    echo 'No job running'
+    return
+  endif
+
+  " The stats should cover the latest contents of the buffer.
+  call s:sendBuffer()
+
+  let l:params = s:bufferParams(bufnr('%'), [])
+  if has('nvim')
+    let l:stats = call('rpcrequest', [s:job, 'stats'] + l:params)
+  else
+    let l:stats = ch_evalexpr(s:job, {
+          \ 'method': 'stats',
+          \ 'params': l:params,
+          \ })
+  endif
+
+  echo printf('%d words, %d characters, %d min read',
+        \ l:stats.words, l:stats.characters, l:stats.reading_time_minutes)
+endfunction
+
+function! s:printPreview()
+  if !exists('s:job')
+    echo 'No job running'
+    return
+  endif
+
+  " The printed preview should include the latest contents of the buffer.
+  call s:sendBuffer()
+
+  let l:params = s:bufferParams(bufnr('%'), [])
+  if has('nvim')
+    let l:printing = call('rpcrequest', [s:job, 'print_preview'] + l:params)
+  else
+    let l:printing = ch_evalexpr(s:job, {
+          \ 'method': 'print_preview',
+          \ 'params': l:params,
+          \ })
+  endif
+
+  if !l:printing
+    echo 'No preview is open to print'
   endif
 endfunction
 
@@ -152,27 +1043,141 @@ function! s:chdir()
   endif
 endfunction
 
+" Checks or unchecks the task list item on a line of the previewed buffer, or
+" of the given buffer if each buffer has its own preview.
+function! s:toggleTask(line, buffer)
+  let l:bufnr = type(a:buffer) == type('') ? str2nr(a:buffer) : get(s:, 'bufnr', -1)
+  if !bufloaded(l:bufnr)
+    return
+  endif
+
+  let l:text = get(getbufline(l:bufnr, a:line), 0, '')
+  let l:pattern = '^\(\%(\s*>\)*\s*\%([-+*]\|\d\+[.)]\)\s\+\)\[\([ xX]\)\]'
+  let l:match = matchlist(l:text, l:pattern)
+  if empty(l:match)
+    return
+  endif
+
+  let l:mark = l:match[2] ==# ' ' ? 'x' : ' '
+  call setbufline(l:bufnr, a:line, substitute(l:text, l:pattern, '\1[' . l:mark . ']', ''))
+
+  if bufnr('%') == l:bufnr
+    call s:sendBuffer()
+  endif
+endfunction
+
+" Replaces the quickfix list of a previous lint, or adds a new list so that other lists aren't
+" lost.
+function! s:setLintList(diagnostics, buffer)
+  let l:bufnr = type(a:buffer) == type('') ? str2nr(a:buffer) : get(s:, 'bufnr', -1)
+  if !bufexists(l:bufnr)
+    return
+  endif
+
+  let l:title = 'Markdown lint'
+  let l:items = map(copy(a:diagnostics), {_, diagnostic -> {
+        \ 'bufnr': l:bufnr,
+        \ 'lnum': diagnostic.line,
+        \ 'col': diagnostic.column is v:null ? 0 : diagnostic.column,
+        \ 'text': diagnostic.message,
+        \ }})
+  let l:action = getqflist({'title': 0}).title ==# l:title ? 'r' : ' '
+  call setqflist([], l:action, {'title': l:title, 'items': l:items})
+endfunction
+
+" Receives notifications from the server.
+function! MarkdownComposerNotify(method, params)
+  if a:method ==# 'toggle_task'
+    call s:toggleTask(a:params[0], get(a:params, 1, v:null))
+  elseif a:method ==# 'diagnostics' && get(g:, 'markdown_composer_lint_quickfix', 0)
+    call s:setLintList(a:params[0], get(a:params, 1, v:null))
+  elseif a:method ==# 'error' && a:params[0] =~# '^send_diff failed:'
+    " The preview no longer matches the buffer, so send the whole buffer.
+    call s:resendAttachedBuffers()
+  elseif a:method ==# 'error'
+    echohl ErrorMsg
+    echom 'markdown-composer: ' . a:params[0]
+    echohl None
+  endif
+endfunction
+
 command! ComposerUpdate call s:sendBuffer()
-command! ComposerOpen call s:openBrowser() | call s:sendBuffer()
+command! -nargs=? -bang ComposerOpen call s:openBrowser(<q-args>, <bang>0) | call s:sendBuffer()
+command! ComposerClose call s:closeBrowser()
 command! ComposerStart call s:startServer()
 command! ComposerJob call s:echoJob()
+command! ComposerUrl call s:echoUrl()
+command! ComposerStatus call s:echoStatus()
+command! ComposerCheckLinks call s:checkLinks()
+command! ComposerStats call s:echoStats()
+command! ComposerPrint call s:printPreview()
+command! -nargs=1 -complete=file ComposerExport call s:export('export_html', <q-args>)
+command! -nargs=1 -complete=file ComposerExportEpub call s:export('export_epub', <q-args>)
+command! -nargs=1 -complete=file ComposerExportPdf call s:export('export_pdf', <q-args>)
+command! ComposerToggleAutoScroll call s:toggleAutoScroll()
+command! ComposerToggleToc call s:toggleToc()
+command! ComposerTogglePresentation call s:togglePresentation()
+command! ComposerToggleSpellCheck call s:toggleSpellCheck()
+command! ComposerToggleSideBySide call s:toggleSideBySide()
+command! ComposerToggleFocus call s:toggleFocus()
+command! ComposerToggleRenderTimings call s:toggleRenderTimings()
+command! -nargs=1 ComposerZoom call s:setZoom(<q-args>)
+command! -nargs=+ ComposerTypography call s:setTypography(<q-args>)
+command! ComposerShutdown call s:shutdown()
+
+" The file extensions of the formats other than markdown.
+let s:format_extensions = {
+      \ 'asciidoc': ['adoc', 'asciidoc', 'asc'],
+      \ 'rst': ['rst', 'rest'],
+      \ 'org': ['org'],
+      \ 'csv': ['csv'],
+      \ 'tsv': ['tsv'],
+      \ }
+
+" Returns the extensions of the files that are previewed: markdown files, files
+" of the formats in g:markdown_composer_formats, and files of the filetypes
+" that have their own renderers.
+function! s:extensions()
+  let l:extensions = ['md', 'mkd', 'markdown']
+  for l:format in get(g:, 'markdown_composer_formats', [])
+    call extend(l:extensions, get(s:format_extensions, l:format, []))
+  endfor
+  call extend(l:extensions, keys(get(g:, 'markdown_composer_external_renderers', {})))
+  return uniq(sort(l:extensions))
+endfunction
+
+" Returns whether buffers of a filetype are previewed.
+function! s:previewsFiletype(filetype)
+  return a:filetype ==# 'markdown' || a:filetype ==# 'pandoc'
+        \ || index(get(g:, 'markdown_composer_formats', []), a:filetype) >= 0
+        \ || has_key(get(g:, 'markdown_composer_external_renderers', {}), a:filetype)
+endfunction
+
+let s:patterns = join(map(s:extensions(), '"*." . v:val'), ',')
 
 augroup markdown-composer
   autocmd!
-  autocmd BufEnter *.md,*.mkd,*.markdown
-        \ if !(exists('g:markdown_composer_autostart') && !g:markdown_composer_autostart) |
-        \   call s:startServer() |
-        \ endif |
-        \ call s:chdir() |
-        \ call s:sendBuffer()
+  execute 'autocmd BufEnter' s:patterns
+        \ 'if !(exists("g:markdown_composer_autostart") && !g:markdown_composer_autostart) |'
+        \ '  call s:startServer() |'
+        \ 'endif |'
+        \ 'call s:chdir() |'
+        \ 'call s:sendBuffer()'
+
+  execute 'autocmd CursorMoved,CursorMovedI' s:patterns 'call s:sendCursor()'
+
+  execute 'autocmd BufDelete' s:patterns
+        \ 'call s:closeBuffer(str2nr(expand("<abuf>")))'
+
+  autocmd OptionSet background call s:sendColorScheme()
 
   if s:refresh_rate == 0
-    autocmd TextChanged,TextChangedI *.md,*.mkd,*.markdown call s:sendBuffer()
+    execute 'autocmd TextChanged,TextChangedI' s:patterns 'call s:bufferChanged()'
   endif
 augroup END
 
 function! s:markdownHandler(timer)
-  if &filetype ==# 'markdown' || &filetype ==# 'pandoc'
-    call s:sendBuffer()
+  if s:previewsFiletype(&filetype)
+    call s:bufferChanged()
   endif
 endfunction
@@ -0,0 +1,175 @@
+//! Measurements of how long a document takes to render, so that the cost of extensions,
+//! sanitization and external renderers can be compared.
+//!
+//! The document is sent to a server repeatedly, like an editor sending each change, but the
+//! server's caches are cleared before each render, so that every render goes through the whole
+//! pipeline.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::server::Server;
+
+/// Renders a markdown file with the server's settings the given number of times, and reports how
+/// long each render took.
+///
+/// The document is rendered once more before the renders that are measured, so that one-time
+/// setup, such as loading syntax definitions, isn't measured.
+///
+/// # Errors
+///
+/// This function fails if any render fails, such as if the external renderer can't be run.
+pub fn run(
+    server: &mut Server,
+    path: impl AsRef<Path>,
+    markdown: &str,
+    iterations: usize,
+) -> io::Result<Report> {
+    let path = path.as_ref();
+
+    server.send_file(path, markdown.to_owned())?;
+
+    let mut durations = Vec::with_capacity(iterations);
+    for _ in 0..iterations {
+        server.clear_render_caches();
+        let markdown = markdown.to_owned();
+
+        let start = Instant::now();
+        server.send_file(path, markdown)?;
+        durations.push(start.elapsed());
+    }
+
+    Ok(Report::new(durations, markdown.len()))
+}
+
+/// How long renders of a document took.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// The duration of each render, from fastest to slowest.
+    durations: Vec<Duration>,
+
+    /// The length of the markdown, in bytes.
+    bytes: usize,
+}
+
+impl Report {
+    /// Creates a report of renders of markdown of the given length.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no durations.
+    pub fn new(mut durations: Vec<Duration>, bytes: usize) -> Self {
+        assert!(!durations.is_empty(), "no renders were measured");
+        durations.sort();
+        Report { durations, bytes }
+    }
+
+    /// Returns the number of renders.
+    pub fn iterations(&self) -> usize {
+        self.durations.len()
+    }
+
+    /// Returns the time that the renders took altogether.
+    pub fn total(&self) -> Duration {
+        self.durations.iter().sum()
+    }
+
+    /// Returns the duration that the given percentage of renders took at most, by the
+    /// nearest-rank method. The 0th percentile is the fastest render, and the 100th is the
+    /// slowest.
+    pub fn percentile(&self, percent: f64) -> Duration {
+        let rank = (percent / 100.0 * self.durations.len() as f64).ceil() as usize;
+        self.durations[rank.clamp(1, self.durations.len()) - 1]
+    }
+
+    /// Returns the mean duration of a render.
+    pub fn mean(&self) -> Duration {
+        self.total() / self.durations.len() as u32
+    }
+
+    /// Returns how many renders were done per second.
+    pub fn renders_per_second(&self) -> f64 {
+        self.durations.len() as f64 / self.total().as_secs_f64()
+    }
+
+    /// Returns how many bytes of markdown were rendered per second.
+    pub fn bytes_per_second(&self) -> f64 {
+        (self.bytes * self.durations.len()) as f64 / self.total().as_secs_f64()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} renders of {} bytes in {:.3} s",
+            self.iterations(),
+            self.bytes,
+            self.total().as_secs_f64()
+        )?;
+        writeln!(f)?;
+
+        let latencies = [
+            ("min", self.percentile(0.0)),
+            ("p50", self.percentile(50.0)),
+            ("p90", self.percentile(90.0)),
+            ("p99", self.percentile(99.0)),
+            ("max", self.percentile(100.0)),
+            ("mean", self.mean()),
+        ];
+        for (name, duration) in latencies {
+            writeln!(f, "{:<6}{:>10.3} ms", name, duration.as_secs_f64() * 1000.0)?;
+        }
+        writeln!(f)?;
+
+        writeln!(
+            f,
+            "{:.1} renders/s, {:.2} MiB/s",
+            self.renders_per_second(),
+            self.bytes_per_second() / (1024.0 * 1024.0)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::time::Duration;
+
+    use crate::server::Server;
+
+    use super::{run, Report};
+
+    #[test]
+    fn percentiles() {
+        let durations = (1..=100).rev().map(Duration::from_millis).collect();
+        let report = Report::new(durations, 1024);
+
+        assert_eq!(report.iterations(), 100);
+        assert_eq!(report.percentile(0.0), Duration::from_millis(1));
+        assert_eq!(report.percentile(50.0), Duration::from_millis(50));
+        assert_eq!(report.percentile(99.0), Duration::from_millis(99));
+        assert_eq!(report.percentile(100.0), Duration::from_millis(100));
+        assert_eq!(report.total(), Duration::from_millis(5050));
+        assert_eq!(report.mean(), Duration::from_micros(50500));
+        assert!((report.bytes_per_second() - 1024.0 * 100.0 / 5.05).abs() < 1e-6);
+
+        let report = Report::new(vec![Duration::from_millis(3)], 0);
+        assert_eq!(report.percentile(0.0), Duration::from_millis(3));
+        assert_eq!(report.percentile(90.0), Duration::from_millis(3));
+    }
+
+    #[test]
+    fn benchmark() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::unbound()?;
+        let report = run(&mut server, "bench.md", "# Benchmark\n\nSome *text*.", 5)?;
+
+        assert_eq!(report.iterations(), 5);
+        assert!(report.to_string().contains("5 renders of 25 bytes"));
+        assert!(server.markdown().unwrap().starts_with("# Benchmark"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+//! The user's default browser, which previews are opened with unless another browser is given.
+//!
+//! Each platform has its own program that opens a URL with the default browser:
+//!
+//! | Platform | Program                                    |
+//! | -------- | ------------------------------------------ |
+//! | Linux    | `xdg-open`                                 |
+//! | WSL      | `wslview`, or else `explorer.exe`          |
+//! | OS X     | `open -g`                                  |
+//! | Windows  | `explorer`                                 |
+//!
+//! Under WSL, the browser runs on Windows, so it's opened through the Windows interop rather than
+//! with `xdg-open`, which usually has no browser to open. Over SSH, or on a console without a
+//! graphical session, there's no display to open a browser on at all.
+//!
+//! Previews may also be opened as apps: Chromium-based browsers open a URL that is given with
+//! `--app` in a standalone window without tabs or an address bar, like a dedicated preview pane.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Returns the command that opens a URL, which is appended to it, with the user's default
+/// browser, or `None` if there's no display to open a browser on.
+pub fn default_browser() -> Option<Command> {
+    if cfg!(target_os = "macos") {
+        let mut command = Command::new("open");
+        command.arg("-g");
+        Some(command)
+    } else if cfg!(target_os = "windows") {
+        Some(Command::new("explorer"))
+    } else if is_wsl() {
+        // `explorer.exe` is always available, unlike `wslview`, but it may not focus the browser.
+        if is_on_path("wslview") {
+            Some(Command::new("wslview"))
+        } else {
+            Some(Command::new("explorer.exe"))
+        }
+    } else if display_available() {
+        Some(Command::new("xdg-open"))
+    } else {
+        None
+    }
+}
+
+/// Chromium-based browsers that previews may be opened as apps with, in order of preference, by
+/// the name of their executable on the `PATH`.
+const APP_BROWSERS: &[&str] = &[
+    "chromium",
+    "chromium-browser",
+    "google-chrome",
+    "google-chrome-stable",
+    "microsoft-edge",
+    "brave-browser",
+    "chrome.exe",
+    "msedge.exe",
+];
+
+/// Chromium-based browsers on macOS, by the name of their application.
+const MACOS_APP_BROWSERS: &[&str] = &[
+    "Google Chrome",
+    "Chromium",
+    "Microsoft Edge",
+    "Brave Browser",
+];
+
+/// Chromium-based browsers on Windows, by the path of their executable.
+const WINDOWS_APP_BROWSERS: &[&str] = &[
+    r"C:\Program Files\Google\Chrome\Application\chrome.exe",
+    r"C:\Program Files (x86)\Google\Chrome\Application\chrome.exe",
+    r"C:\Program Files (x86)\Microsoft\Edge\Application\msedge.exe",
+];
+
+/// Returns a command line that opens a URL as an app, which replaces `{url}` in it, with a
+/// Chromium-based browser that is installed, or `None` if none is found.
+pub fn app_browser() -> Option<String> {
+    if cfg!(target_os = "macos") {
+        return MACOS_APP_BROWSERS
+            .iter()
+            .find(|name| {
+                Path::new("/Applications")
+                    .join(format!("{}.app", name))
+                    .exists()
+            })
+            .map(|name| format!("open -na '{}' --args --app={{url}}", name));
+    }
+
+    let program = APP_BROWSERS
+        .iter()
+        .find(|program| is_on_path(program))
+        .map(|program| program.to_string())
+        .or_else(|| {
+            WINDOWS_APP_BROWSERS
+                .iter()
+                .filter(|_| cfg!(target_os = "windows"))
+                .find(|path| Path::new(path).is_file())
+                .map(|path| format!("'{}'", path))
+        })?;
+    Some(format!("{} --app={{url}}", program))
+}
+
+/// Returns whether a browser can be opened on the display of the user's session, which isn't the
+/// case over SSH, unless X11 forwarding is enabled, or on a console.
+pub fn display_available() -> bool {
+    if !cfg!(unix) || cfg!(target_os = "macos") || is_wsl() {
+        return true;
+    }
+
+    ["DISPLAY", "WAYLAND_DISPLAY"]
+        .iter()
+        .any(|variable| env::var_os(variable).is_some_and(|value| !value.is_empty()))
+}
+
+/// Returns whether the process is running under the Windows Subsystem for Linux.
+pub fn is_wsl() -> bool {
+    if !cfg!(target_os = "linux") {
+        return false;
+    }
+
+    env::var_os("WSL_DISTRO_NAME").is_some()
+        || Path::new("/proc/sys/fs/binfmt_misc/WSLInterop").exists()
+        || fs::read_to_string("/proc/sys/kernel/osrelease")
+            .is_ok_and(|release| release.to_ascii_lowercase().contains("microsoft"))
+}
+
+/// Returns whether a program is in one of the directories of the `PATH`.
+fn is_on_path(program: &str) -> bool {
+    env::var_os("PATH")
+        .is_some_and(|path| env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_on_path;
+
+    #[cfg(unix)]
+    #[test]
+    fn path() {
+        assert!(is_on_path("sh"));
+        assert!(!is_on_path("composer-no-such-program"));
+    }
+}
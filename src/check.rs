@@ -0,0 +1,134 @@
+//! Reports of broken links and missing images in markdown documents.
+
+use std::path::Path;
+
+use pulldown_cmark::{Event, LinkType, Parser, Tag};
+use serde::Serialize;
+use url::{ParseError, Url};
+
+use crate::export;
+use crate::render::{self, RenderOptions};
+
+/// A link or image of a document that doesn't resolve.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Problem {
+    /// The (one-based) line of the markdown that the link starts on.
+    pub line: usize,
+
+    /// The (one-based) byte offset of the start of the link within its line.
+    pub column: usize,
+
+    /// The destination of the link or the source of the image, as written.
+    pub url: String,
+
+    pub message: String,
+}
+
+/// Scans markdown for relative links and images whose files don't exist under the static root,
+/// and for URLs that are malformed.
+///
+/// Links to other sites are not requested, and fragments are not checked against the headings of
+/// the target document.
+pub fn check_links(markdown: &str, options: &RenderOptions, root: &Path) -> Vec<Problem> {
+    let line_starts = render::line_starts(markdown);
+    let mut problems = vec![];
+
+    for (event, range) in Parser::new_ext(markdown, options.parser_options()).into_offset_iter() {
+        let (kind, link_type, url) = match event {
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                ..
+            }) => ("link", link_type, dest_url),
+            Event::Start(Tag::Image {
+                link_type,
+                dest_url,
+                ..
+            }) => ("image", link_type, dest_url),
+            _ => continue,
+        };
+
+        let target = match link_type {
+            LinkType::WikiLink { .. } => options
+                .wiki_links
+                .resolve(&url, options.heading_slugs)
+                .into(),
+            _ => url.clone(),
+        };
+
+        let message = match check_url(&target, root) {
+            Ok(()) => continue,
+            Err(message) => format!("{} {}", kind, message),
+        };
+
+        let line = line_starts.partition_point(|&start| start <= range.start);
+        problems.push(Problem {
+            line,
+            column: range.start - line_starts[line - 1] + 1,
+            url: url.into_string(),
+            message,
+        });
+    }
+
+    problems
+}
+
+/// Checks that a URL is well-formed and, if it refers to a local file, that the file exists.
+fn check_url(url: &str, root: &Path) -> Result<(), String> {
+    if url.is_empty() || url.starts_with('#') {
+        return Ok(());
+    }
+
+    match Url::parse(url) {
+        Ok(_) | Err(ParseError::RelativeUrlWithoutBase) => (),
+        Err(e) => return Err(format!("URL is malformed: {}", e)),
+    }
+
+    match export::local_path(url, Some(root)) {
+        Some(path) if !path.exists() => Err(format!("not found: {}", path.display())),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use crate::render::RenderOptions;
+
+    use super::check_links;
+
+    #[test]
+    fn broken_links() {
+        let root = env::temp_dir().join(format!("composer-check-{}", process::id()));
+        fs::create_dir_all(root.join("images")).unwrap();
+        fs::write(root.join("images/cat.png"), "").unwrap();
+        fs::write(root.join("other.md"), "").unwrap();
+
+        let markdown = "\
+# Links
+
+[ok](other.md#heading) [missing](missing.md) [site](https://example.com)
+![cat](/images/cat.png) and ![dog](images/dog.png)
+
+[malformed](http://exa%20mple.com) [anchor](#links)
+";
+        let problems = check_links(markdown, &RenderOptions::default(), &root)
+            .into_iter()
+            .map(|problem| (problem.line, problem.column, problem.url))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            problems,
+            vec![
+                (3, 24, String::from("missing.md")),
+                (4, 29, String::from("images/dog.png")),
+                (6, 1, String::from("http://exa%20mple.com")),
+            ]
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,804 @@
+//! An embeddable preview: a server together with the handling of the RPCs that editors send it.
+
+use std::collections::HashMap;
+use std::io::{self, prelude::*};
+use std::mem;
+use std::net::ToSocketAddrs;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use log::*;
+use serde::Serialize;
+use shlex::Shlex;
+
+use crate::render::{self, Flavor};
+use crate::rpc::{self, Rpc};
+use crate::server::{ColorScheme, PreviewRequest, Server};
+
+/// A markdown preview that may be embedded into other programs, such as editors and note-taking
+/// apps.
+///
+/// The composer wraps a [`Server`], dispatching the same procedures that editors call over RPC.
+/// Markdown sent with `send_data` may be debounced, in which case the owner of the composer is
+/// responsible for calling [`Composer::render_pending`] once [`Composer::deadline`] passes.
+///
+/// ```no_run
+/// use markdown_composer::Composer;
+///
+/// let mut composer = Composer::bind(&["localhost:0"])?;
+/// composer.send(String::from("# Hello"))?;
+/// println!("{}", composer.server().url());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct Composer {
+    server: Server,
+
+    /// The browser that `open_browser` opens the preview with, instead of the user's default.
+    browser: Option<String>,
+
+    /// Whether the browser is opened once markdown is first published, rather than when the
+    /// editor asks for it, and the anchor to open the preview at.
+    open_on_first_send: bool,
+    open_to: Option<String>,
+
+    /// The browser that `export_pdf` prints the preview with.
+    pdf_browser: String,
+
+    /// How long to wait for more markdown before rendering it.
+    debounce: Duration,
+
+    /// The markdown waiting for the debounce window to pass, and its filetype, by buffer.
+    pending: HashMap<Option<String>, (Option<String>, String)>,
+    deadline: Option<Instant>,
+
+    /// Whether the previews show an error, until a procedure succeeds again.
+    failed: bool,
+}
+
+impl Composer {
+    /// Wraps a server that was already set up.
+    pub fn new(server: Server) -> Composer {
+        Composer {
+            server,
+            browser: None,
+            open_on_first_send: false,
+            open_to: None,
+            pdf_browser: String::from("chromium"),
+            debounce: Duration::ZERO,
+            pending: HashMap::new(),
+            deadline: None,
+            failed: false,
+        }
+    }
+
+    /// Binds a server to one or more addresses, like [`Server::bind`], and wraps it.
+    pub fn bind<A: ToSocketAddrs>(addrs: &[A]) -> io::Result<Composer> {
+        Ok(Composer::new(Server::bind(addrs)?))
+    }
+
+    /// Returns the server, whose settings may be changed directly.
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    pub fn server_mut(&mut self) -> &mut Server {
+        &mut self.server
+    }
+
+    /// Set the browser command that `open_browser` opens previews with, such as
+    /// `firefox --new-window`. The URL of the preview is appended to it, or replaces `{url}` in
+    /// its arguments. If `None`, the user's default browser is used.
+    pub fn set_browser(&mut self, browser: Option<String>) {
+        self.browser = browser;
+    }
+
+    /// Opens the browser once markdown is first published, rather than showing an empty preview
+    /// while the editor starts, at an anchor if one is given. If markdown was already rendered,
+    /// the browser is opened now.
+    pub fn open_on_first_send(&mut self, anchor: Option<String>) -> io::Result<()> {
+        if self.server.markdown().is_some() {
+            return self
+                .open_browser(None, anchor.as_deref(), false)
+                .map(|_| ());
+        }
+
+        self.open_on_first_send = true;
+        self.open_to = anchor;
+        Ok(())
+    }
+
+    /// Set the command of the headless browser that `export_pdf` prints previews with.
+    pub fn set_pdf_browser(&mut self, pdf_browser: String) {
+        self.pdf_browser = pdf_browser;
+    }
+
+    /// Set how long markdown sent with `send_data` and `send_diff` waits for more markdown before
+    /// it's rendered, so that only the latest content is rendered while the user is typing.
+    /// Defaults to rendering markdown immediately.
+    pub fn set_debounce(&mut self, debounce: Duration) {
+        self.debounce = debounce;
+    }
+
+    /// Returns when the markdown waiting for the debounce window should be rendered, if any is.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Renders the markdown that was waiting for the debounce window to pass.
+    pub fn render_pending(&mut self) -> io::Result<()> {
+        self.deadline = None;
+
+        for (buffer, (filetype, markdown)) in mem::take(&mut self.pending) {
+            self.publish(buffer.as_deref(), filetype.as_deref(), markdown)?;
+        }
+
+        Ok(())
+    }
+
+    /// Publishes markdown to a buffer or the main document, with a filetype if one was given.
+    fn publish(
+        &mut self,
+        buffer: Option<&str>,
+        filetype: Option<&str>,
+        markdown: String,
+    ) -> io::Result<()> {
+        match (buffer, filetype) {
+            (Some(buffer), Some(filetype)) => {
+                self.server.send_buffer_as(buffer, filetype, markdown)?
+            }
+            (Some(buffer), None) => self.server.send_buffer(buffer, markdown)?,
+            (None, Some(filetype)) => self.server.send_as(filetype, markdown)?,
+            (None, None) => self.server.send(markdown)?,
+        }
+
+        self.clear_error();
+        self.opened(buffer)
+    }
+
+    /// Logs an error that the composer recovered from, and shows it in the previews until a
+    /// procedure succeeds or markdown is rendered again.
+    pub fn show_error(&mut self, message: &str) {
+        error!("{}", message);
+        self.server.show_error(Some(message));
+        self.failed = true;
+    }
+
+    fn clear_error(&mut self) {
+        if self.failed {
+            self.server.show_error(None);
+            self.failed = false;
+        }
+    }
+
+    /// Opens the browser at the preview of a buffer or the main document, if it was waiting for
+    /// markdown to be published.
+    fn opened(&mut self, buffer: Option<&str>) -> io::Result<()> {
+        if !self.open_on_first_send {
+            return Ok(());
+        }
+
+        self.open_on_first_send = false;
+        let anchor = self.open_to.take();
+        self.open_browser(buffer, anchor.as_deref(), false)
+            .map(|_| ())
+    }
+
+    /// Opens the browser that was set, or the user's default. Unless the browser is forced to
+    /// open, previews that are already connected are brought to the front instead, so that
+    /// opening the preview again doesn't add another tab.
+    ///
+    /// Returns `false` if there's no display to open the user's default browser on.
+    fn open_browser(
+        &self,
+        buffer: Option<&str>,
+        anchor: Option<&str>,
+        force: bool,
+    ) -> io::Result<bool> {
+        if !force && self.server.raise_preview(buffer, anchor) {
+            return Ok(true);
+        }
+
+        match &self.browser {
+            Some(browser) => self
+                .server
+                .open_specific_browser(parse_command(browser)?, buffer, anchor)
+                .map(|()| true),
+            None => self.server.open_browser(buffer, anchor),
+        }
+    }
+
+    /// Publishes markdown to the main document, waiting for the debounce window if one is set.
+    pub fn send(&mut self, markdown: String) -> io::Result<()> {
+        if self.debounce.is_zero() {
+            return self.publish(None, None, markdown);
+        }
+
+        let filetype = self
+            .pending
+            .remove(&None)
+            .and_then(|(filetype, _)| filetype);
+        self.pending.insert(None, (filetype, markdown));
+        self.deadline = Some(Instant::now() + self.debounce);
+        Ok(())
+    }
+
+    /// Notifies open previews that the preview has ended, then stops the server.
+    pub fn shutdown(self) {
+        self.server.shutdown();
+    }
+
+    /// Handles a request made from a preview page, other than toggling a task, which only the
+    /// editor can do.
+    pub fn handle_request(&mut self, request: PreviewRequest) {
+        match request {
+            PreviewRequest::Open { path, buffer } => {
+                info!("opening file from preview: {}", path.display());
+                if let Err(e) = self.server.open_file(buffer.as_deref(), &path) {
+                    warn!("could not open {}: {}", path.display(), e);
+                }
+            }
+            PreviewRequest::Back { buffer } => {
+                if let Err(e) = self.server.go_back(buffer.as_deref()) {
+                    warn!("could not go back: {}", e);
+                }
+            }
+            PreviewRequest::Render { markdown, path } => {
+                let res = match path {
+                    Some(path) => {
+                        info!("rendering pushed file: {}", path.display());
+                        self.server.send_file(path, markdown)
+                    }
+                    None => self.server.send(markdown),
+                };
+                if let Err(e) = res {
+                    warn!("could not render pushed markdown: {}", e);
+                }
+            }
+            PreviewRequest::ToggleTask { line, .. } => {
+                debug!("ignoring task toggled on line {}", line);
+            }
+            PreviewRequest::Diagnostics { diagnostics, .. } => {
+                debug!("ignoring {} diagnostics", diagnostics.len());
+            }
+        }
+    }
+
+    /// Calls a procedure, writing its response to the writer if the procedure returns a value
+    /// and the RPC is a request.
+    ///
+    /// A procedure that fails, such as rendering a file that can't be read, doesn't stop the
+    /// composer: the error is logged, shown in the previews, and written to the writer instead.
+    ///
+    /// Returns `false` if the editor asked for the server to shut down, in which case
+    /// [`Composer::shutdown`] should be called.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error only if the error of a failed procedure couldn't be written.
+    pub fn call(&mut self, rpc: Rpc, mut writer: impl Write) -> Result<bool> {
+        let method = rpc.method.clone();
+        let id = rpc.id;
+
+        match self.dispatch(rpc, &mut writer) {
+            Ok(running) => {
+                self.clear_error();
+                Ok(running)
+            }
+            Err(e) => {
+                let message = format!("{} failed: {:#}", method, e);
+                self.show_error(&message);
+                match id {
+                    Some(id) => rpc::write_error(writer, id, &message)?,
+                    None => rpc::write_notification(writer, "error", [&message])?,
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    fn dispatch(&mut self, mut rpc: Rpc, mut writer: impl Write) -> Result<bool> {
+        // Procedures that take parameters are rejected without enough of them, rather than
+        // panicking.
+        let required = match &rpc.method[..] {
+            "send_diff" => 3,
+            "send_data_as" => 2,
+            "send_data" | "render_file" | "chdir" | "set_git_path" | "set_title"
+            | "scroll_to_line" | "set_zoom" | "set_color_scheme" | "set_typography"
+            | "set_flavor" | "enable_extension" | "disable_extension" | "export_html"
+            | "export_epub" | "export_pdf" => 1,
+            _ => 0,
+        };
+        if rpc.params.len() < required {
+            return Err(anyhow!(
+                "expected {} parameters, but got {}",
+                required,
+                rpc.params.len()
+            ));
+        }
+
+        // Procedures that act on a buffer take the ID of the buffer as their first parameter, if
+        // the buffer is previewed separately from the main document.
+        let buffer = match &rpc.method[..] {
+            "send_data" | "render_file" | "scroll_to_line" if rpc.params.len() > 1 => {
+                Some(rpc.params.remove(0))
+            }
+            "send_data_as" if rpc.params.len() > 2 => Some(rpc.params.remove(0)),
+            "send_diff" if rpc.params.len() > 3 => Some(rpc.params.remove(0)),
+            "set_git_path" if rpc.params.len() > 1 => Some(rpc.params.remove(0)),
+            // An anchor to open the preview at starts with `#`, unlike the ID of a buffer.
+            "open_browser"
+                if !rpc.params.is_empty()
+                    && !rpc.params[0].starts_with('#')
+                    && rpc.params[0] != "--force" =>
+            {
+                Some(rpc.params.remove(0))
+            }
+            "get_url" | "close_buffer" | "check_links" | "stats" | "print_preview"
+                if !rpc.params.is_empty() =>
+            {
+                Some(rpc.params.remove(0))
+            }
+            _ => None,
+        };
+
+        match &rpc.method[..] {
+            // The new content replaces any markdown that is still waiting to be rendered.
+            "render_file" | "close_buffer" => {
+                self.pending.remove(&buffer);
+            }
+            // Exports and reports should include the latest markdown.
+            "export_html" | "export_epub" | "export_pdf" | "check_links" | "stats"
+            | "print_preview" => {
+                self.deadline = None;
+                self.render_pending()?;
+            }
+            _ => (),
+        }
+
+        let res = match &rpc.method[..] {
+            "send_data" | "send_data_as" | "send_diff" => {
+                let filetype = match &rpc.method[..] {
+                    "send_data_as" => Some(rpc.params.remove(0)),
+                    _ => None,
+                };
+                let markdown = if rpc.method != "send_diff" {
+                    mem::take(&mut rpc.params[0])
+                } else {
+                    // Diffs apply to the latest markdown, even if it hasn't been rendered yet.
+                    let markdown = match (self.pending.get(&buffer), &buffer) {
+                        (Some((_, markdown)), _) => Some(&markdown[..]),
+                        (None, Some(buffer)) => self.server.buffer_markdown(buffer),
+                        (None, None) => self.server.markdown(),
+                    };
+
+                    // The markdown no longer matches the editor's buffer, so the editor is told,
+                    // and sends the whole buffer again.
+                    apply_diff(
+                        markdown.unwrap_or_default(),
+                        &rpc.params[0],
+                        &rpc.params[1],
+                        &rpc.params[2],
+                    )?
+                };
+
+                if self.debounce.is_zero() {
+                    self.publish(buffer.as_deref(), filetype.as_deref(), markdown)
+                } else {
+                    // Markdown that is still waiting keeps the filetype it was sent with.
+                    let filetype = filetype.or_else(|| {
+                        self.pending
+                            .remove(&buffer)
+                            .and_then(|(filetype, _)| filetype)
+                    });
+                    self.pending.insert(buffer, (filetype, markdown));
+                    self.deadline = Some(Instant::now() + self.debounce);
+                    Ok(())
+                }
+            }
+            "render_file" => match &buffer {
+                Some(buffer) => self.server.render_buffer_file(buffer, &rpc.params[0]),
+                None => self.server.render_file(&rpc.params[0]),
+            }
+            .and_then(|()| self.opened(buffer.as_deref())),
+            "close_buffer" => {
+                if let Some(buffer) = &buffer {
+                    self.server.close_buffer(buffer);
+                }
+                Ok(())
+            }
+            "open_browser" => {
+                // The editor opened the browser itself, so it isn't opened again once markdown
+                // is sent.
+                self.open_on_first_send = false;
+                let anchor = rpc.params.iter().find(|param| param.starts_with('#'));
+                let force = rpc.params.iter().any(|param| param == "--force");
+                let opened =
+                    self.open_browser(buffer.as_deref(), anchor.map(|anchor| &anchor[..]), force)?;
+
+                // Without a display, such as over SSH, the editor should tell the user where the
+                // preview is instead.
+                let url = match &buffer {
+                    _ if opened => None,
+                    Some(buffer) => Some(self.server.buffer_url(buffer)),
+                    None => Some(self.server.url()),
+                };
+                respond(&mut writer, &rpc, url)
+            }
+            "close_browser" => {
+                self.server.close_browser();
+                Ok(())
+            }
+            "print_preview" => {
+                let printing = self.server.print_preview(buffer.as_deref());
+                respond(&mut writer, &rpc, printing)
+            }
+            "chdir" => {
+                let cwd = &rpc.params[0];
+                info!("changing working directory: {}", cwd);
+                self.server.set_static_root(cwd)
+            }
+            "set_git_path" => {
+                let path = Some(&rpc.params[0]).filter(|path| !path.is_empty());
+                self.server.set_git_path(buffer.as_deref(), path);
+                Ok(())
+            }
+            "set_title" => {
+                let title = mem::take(&mut rpc.params[0]);
+                self.server
+                    .set_title(Some(title).filter(|title| !title.is_empty()));
+                Ok(())
+            }
+            "scroll_to_line" => {
+                let line = rpc.params[0].parse().map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid line number {:?}: {}", rpc.params[0], e),
+                    )
+                })?;
+                match &buffer {
+                    Some(buffer) => self.server.scroll_buffer_to_line(buffer, line),
+                    None => self.server.scroll_to_line(line),
+                }
+                Ok(())
+            }
+            "toggle_auto_scroll" => {
+                let auto_scroll = !self.server.auto_scroll();
+                info!("setting auto-scroll: {}", auto_scroll);
+                self.server.set_auto_scroll(auto_scroll);
+                respond(&mut writer, &rpc, auto_scroll)
+            }
+            "toggle_presentation" => {
+                let presentation = !self.server.presentation();
+                info!("setting presentation mode: {}", presentation);
+                self.server.set_presentation(presentation)?;
+                respond(&mut writer, &rpc, presentation)
+            }
+            "set_zoom" => {
+                // Zooms starting with a sign are relative to the current zoom.
+                let zoom = &rpc.params[0];
+                let current = i64::from(self.server.zoom());
+                let percent = zoom.trim().parse::<i64>().map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid zoom {:?}: {}", zoom, e),
+                    )
+                })?;
+                if zoom.trim().starts_with(['+', '-']) {
+                    self.server.set_zoom((current + percent).max(0) as u32);
+                } else {
+                    self.server
+                        .set_zoom(percent.clamp(0, i64::from(u32::MAX)) as u32);
+                }
+                respond(&mut writer, &rpc, self.server.zoom())
+            }
+            "toggle_focus" => {
+                let focus = !self.server.focus();
+                info!("setting focus mode: {}", focus);
+                self.server.set_focus(focus);
+                respond(&mut writer, &rpc, focus)
+            }
+            "toggle_render_timings" => {
+                let render_timings = !self.server.render_timings();
+                info!("setting render timings: {}", render_timings);
+                self.server.set_render_timings(render_timings);
+                respond(&mut writer, &rpc, render_timings)
+            }
+            "toggle_side_by_side" => {
+                let side_by_side = !self.server.side_by_side();
+                info!("setting side-by-side layout: {}", side_by_side);
+                self.server.set_side_by_side(side_by_side);
+                respond(&mut writer, &rpc, side_by_side)
+            }
+            "toggle_spell_check" => {
+                let spell_check = !self.server.spell_check();
+                info!("setting spell checking: {}", spell_check);
+                self.server.set_spell_check(spell_check)?;
+                respond(&mut writer, &rpc, spell_check)
+            }
+            "toggle_toc" => {
+                let toc = !self.server.toc();
+                info!("setting table of contents: {}", toc);
+                self.server.set_toc(toc);
+                respond(&mut writer, &rpc, toc)
+            }
+            "set_color_scheme" => {
+                let color_scheme = rpc.params[0]
+                    .parse::<ColorScheme>()
+                    .map_err(|e| anyhow!(e))?;
+                self.server.set_color_scheme(color_scheme);
+                Ok(())
+            }
+            "set_typography" => {
+                let mut typography = self.server.typography();
+                let value = rpc.params.get(1).filter(|value| !value.is_empty()).cloned();
+                typography
+                    .set(&rpc.params[0], value)
+                    .map_err(|e| anyhow!(e))?;
+                self.server.set_typography(typography);
+                Ok(())
+            }
+            "set_flavor" => {
+                let flavor = rpc.params[0].parse::<Flavor>().map_err(|e| anyhow!(e))?;
+                self.server.set_extensions(flavor.extensions())
+            }
+            "enable_extension" | "disable_extension" => {
+                let extension = render::extension(&rpc.params[0])
+                    .ok_or_else(|| anyhow!("unknown markdown extension: {}", rpc.params[0]))?;
+                let mut extensions = self.server.extensions();
+                extensions.set(extension, rpc.method == "enable_extension");
+                self.server.set_extensions(extensions)
+            }
+            "export_html" => self.server.export_html(&rpc.params[0]),
+            "export_epub" => self.server.export_epub(&rpc.params[0]),
+            "export_pdf" => self
+                .server
+                .export_pdf(parse_command(&self.pdf_browser)?, &rpc.params[0]),
+            "get_url" => match &buffer {
+                Some(buffer) => respond(&mut writer, &rpc, self.server.buffer_url(buffer)),
+                None => respond(&mut writer, &rpc, self.server.url()),
+            },
+            "check_links" => {
+                let problems = self.server.check_links(buffer.as_deref())?;
+                respond(&mut writer, &rpc, problems)
+            }
+            "stats" => respond(&mut writer, &rpc, self.server.stats(buffer.as_deref())),
+            "status" => respond(&mut writer, &rpc, self.server.status()),
+            "get_port" => respond(
+                &mut writer,
+                &rpc,
+                self.server.addr().map(|addr| addr.port()),
+            ),
+            "shutdown" => return Ok(false),
+            method => return Err(anyhow!("unknown procedure: {}", method)),
+        };
+
+        res?;
+
+        Ok(true)
+    }
+}
+
+/// Replaces the lines of markdown from `first` up to (but not including) `last` with new lines,
+/// like the changes reported by Neovim's `nvim_buf_attach()`. Lines are numbered from zero, and
+/// each new line ends with a newline.
+fn apply_diff(markdown: &str, first: &str, last: &str, lines: &str) -> Result<String> {
+    let parse = |line: &str| {
+        line.parse::<usize>()
+            .map_err(|e| anyhow!("invalid line number {:?}: {}", line, e))
+    };
+    let (first, last) = (parse(first)?, parse(last)?);
+
+    let old = markdown.split('\n').collect::<Vec<_>>();
+    if first > last || last > old.len() {
+        return Err(anyhow!(
+            "lines {}..{} are outside of the {} lines of the markdown",
+            first,
+            last,
+            old.len()
+        ));
+    }
+
+    let new = match lines.strip_suffix('\n') {
+        Some(lines) => lines.split('\n').collect(),
+        None if lines.is_empty() => vec![],
+        None => vec![lines],
+    };
+
+    Ok([&old[..first], &new, &old[last..]].concat().join("\n"))
+}
+
+/// Sends the result of a procedure back to the client, if the client is expecting one.
+fn respond(writer: impl Write, rpc: &Rpc, result: impl Serialize) -> io::Result<()> {
+    match rpc.id {
+        Some(id) => rpc::write_response(writer, id, result),
+        None => {
+            warn!(
+                "{} was sent as a notification, discarding result",
+                rpc.method
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Parses a command line, such as `firefox --new-window`, into a command.
+///
+/// # Errors
+///
+/// This function fails if the command line has no words.
+pub fn parse_command(s: &str) -> io::Result<Command> {
+    let words = Shlex::new(s).collect::<Vec<_>>();
+    let (command, args) = words.split_first().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("not a command: {:?}", s),
+        )
+    })?;
+    let mut command = Command::new(command);
+    command.args(args);
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::error::Error;
+    use std::fs;
+    use std::process;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::rpc::{MessageType, Rpc};
+
+    use super::{apply_diff, Composer};
+
+    #[test]
+    fn call_procedures() -> Result<(), Box<dyn Error>> {
+        let mut composer = Composer::bind(&["localhost:0"])?;
+
+        let send_data = Rpc {
+            message_type: MessageType::Notification,
+            id: None,
+            method: String::from("send_data"),
+            params: vec![String::from("# Embedded")],
+        };
+        assert!(composer.call(send_data, vec![])?);
+        assert_eq!(composer.server().markdown(), Some("# Embedded"));
+
+        let mut response = vec![];
+        let toggle_toc = Rpc {
+            message_type: MessageType::Request,
+            id: Some(1),
+            method: String::from("toggle_toc"),
+            params: vec![],
+        };
+        assert!(composer.call(toggle_toc, &mut response)?);
+        assert!(composer.server().toc());
+        assert!(!response.is_empty());
+
+        let shutdown = Rpc {
+            message_type: MessageType::Notification,
+            id: None,
+            method: String::from("shutdown"),
+            params: vec![],
+        };
+        assert!(!composer.call(shutdown, vec![])?);
+        composer.shutdown();
+
+        Ok(())
+    }
+
+    #[test]
+    fn failed_procedures() -> Result<(), Box<dyn Error>> {
+        let mut composer = Composer::bind(&["localhost:0"])?;
+        composer.set_pdf_browser(String::from(" "));
+
+        for (method, params, error) in [
+            ("render_file", vec!["missing.md"], "render_file failed: "),
+            (
+                "send_diff",
+                vec!["5", "9", "x\n"],
+                "send_diff failed: lines 5..9 are outside of the 1 lines",
+            ),
+            ("set_title", vec![], "expected 1 parameters, but got 0"),
+            (
+                "scroll_to_line",
+                vec!["ten"],
+                "scroll_to_line failed: invalid line number",
+            ),
+            ("set_zoom", vec!["big"], "set_zoom failed: invalid zoom"),
+            (
+                "set_color_scheme",
+                vec!["sepia"],
+                "set_color_scheme failed: unknown color scheme: sepia",
+            ),
+            (
+                "set_typography",
+                vec!["font_family", "serif; color: red"],
+                "set_typography failed: invalid value for font_family",
+            ),
+            (
+                "set_typography",
+                vec!["line_height", "2"],
+                "set_typography failed: unknown typography property: line_height",
+            ),
+            (
+                "set_flavor",
+                vec!["textile"],
+                "set_flavor failed: unknown markdown flavor: textile",
+            ),
+            (
+                "enable_extension",
+                vec!["emoji"],
+                "enable_extension failed: unknown markdown extension: emoji",
+            ),
+            (
+                "export_pdf",
+                vec!["preview.pdf"],
+                "export_pdf failed: not a command",
+            ),
+            ("get_file", vec![], "unknown procedure: get_file"),
+        ] {
+            let rpc = Rpc {
+                message_type: MessageType::Request,
+                id: Some(1),
+                method: String::from(method),
+                params: params.into_iter().map(String::from).collect(),
+            };
+
+            // The composer keeps running, and the editor is told of the error.
+            let mut response = vec![];
+            assert!(composer.call(rpc, &mut response)?);
+            assert!(String::from_utf8_lossy(&response).contains(error));
+        }
+
+        composer.shutdown();
+
+        Ok(())
+    }
+
+    #[test]
+    fn open_on_first_send() -> Result<(), Box<dyn Error>> {
+        let mut composer = Composer::bind(&["localhost:0"])?;
+        let output = env::temp_dir().join(format!("composer-first-send-{}", process::id()));
+
+        // The URL is appended to the command, where the script receives it as `$0`.
+        composer.set_browser(Some(format!(
+            "sh -c 'printf %s \"$0\" > {}'",
+            output.display()
+        )));
+        composer.open_on_first_send(Some(String::from("#intro")))?;
+        assert!(!output.exists());
+
+        composer.send(String::from("# Intro"))?;
+        let mut url = None;
+        for _ in 0..100 {
+            match fs::read_to_string(&output) {
+                Ok(contents) if !contents.is_empty() => {
+                    url = Some(contents);
+                    break;
+                }
+                _ => thread::sleep(Duration::from_millis(50)),
+            }
+        }
+        let _ = fs::remove_file(&output);
+        assert_eq!(url, Some(format!("{}#intro", composer.server().url())));
+
+        Ok(())
+    }
+
+    #[test]
+    fn apply_diffs() {
+        assert_eq!(apply_diff("a\nb\nc", "1", "2", "B\n").unwrap(), "a\nB\nc");
+        assert_eq!(
+            apply_diff("a\nb\nc", "1", "1", "x\ny\n").unwrap(),
+            "a\nx\ny\nb\nc"
+        );
+        assert_eq!(apply_diff("a\nb\nc", "0", "2", "").unwrap(), "c");
+        assert_eq!(apply_diff("a", "1", "1", "\n").unwrap(), "a\n");
+        assert!(apply_diff("a", "0", "2", "").is_err());
+        assert!(apply_diff("a", "one", "1", "").is_err());
+    }
+}
@@ -0,0 +1,166 @@
+//! Rendering of CSV and TSV documents to HTML tables.
+//!
+//! The first record is the header of the table. Fields of CSV may be quoted as described by
+//! RFC 4180, so that they may contain delimiters, quotes and line breaks, while fields of TSV are
+//! only separated by tabs. Columns whose fields are all numbers are marked with
+//! `data-type="number"`, so that the preview sorts them numerically.
+//!
+//! Like markdown, the table and its rows are annotated with the `data-source-line` that they
+//! were rendered from.
+
+use std::fmt::Write;
+
+use handlebars::html_escape;
+
+/// A record of a document, and the line that it starts on.
+struct Record {
+    line: usize,
+    fields: Vec<String>,
+}
+
+/// Renders a CSV document, whose fields are separated by `delimiter`, to an HTML table.
+pub fn to_html(source: &str, delimiter: char) -> String {
+    // Only CSV has quoted fields.
+    let records = records(source, delimiter, delimiter != '\t');
+    let (header, rows) = match records.split_first() {
+        Some(records) => records,
+        None => return String::new(),
+    };
+
+    let columns = records
+        .iter()
+        .map(|record| record.fields.len())
+        .max()
+        .unwrap_or(0);
+    let numeric = (0..columns)
+        .map(|column| {
+            let mut fields = rows
+                .iter()
+                .filter_map(|row| row.fields.get(column))
+                .map(|field| field.trim())
+                .filter(|field| !field.is_empty())
+                .peekable();
+            fields.peek().is_some() && fields.all(|field| field.parse::<f64>().is_ok())
+        })
+        .collect::<Vec<_>>();
+
+    let mut html = format!(
+        "<table class=\"data-table\" data-source-line=\"{}\">\n<thead>\n<tr>",
+        header.line
+    );
+    for (column, &numeric) in numeric.iter().enumerate() {
+        let field = header.fields.get(column).map_or("", String::as_str);
+        if numeric {
+            html.push_str("<th data-type=\"number\">");
+        } else {
+            html.push_str("<th>");
+        }
+        html.push_str(&html_escape(field));
+        html.push_str("</th>");
+    }
+    html.push_str("</tr>\n</thead>\n<tbody>\n");
+
+    for row in rows {
+        write!(html, "<tr data-source-line=\"{}\">", row.line).unwrap();
+        for column in 0..columns {
+            let field = row.fields.get(column).map_or("", String::as_str);
+            write!(html, "<td>{}</td>", html_escape(field)).unwrap();
+        }
+        html.push_str("</tr>\n");
+    }
+
+    html.push_str("</tbody>\n</table>\n");
+    html
+}
+
+/// Splits a document into records, skipping blank lines.
+fn records(source: &str, delimiter: char, quoted: bool) -> Vec<Record> {
+    let mut records = vec![];
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut line = 1;
+    let mut start = 1;
+    let mut in_quotes = false;
+
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                c => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.push(c);
+                }
+            }
+            continue;
+        }
+
+        match c {
+            '"' if quoted && field.is_empty() => in_quotes = true,
+            '\r' if chars.peek() == Some(&'\n') => (),
+            '\n' => {
+                fields.push(std::mem::take(&mut field));
+                push_record(&mut records, start, std::mem::take(&mut fields));
+                line += 1;
+                start = line;
+            }
+            c if c == delimiter => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+
+    if !field.is_empty() || !fields.is_empty() {
+        fields.push(field);
+        push_record(&mut records, start, fields);
+    }
+
+    records
+}
+
+fn push_record(records: &mut Vec<Record>, line: usize, fields: Vec<String>) {
+    let blank = match &*fields {
+        [field] => field.trim().is_empty(),
+        _ => false,
+    };
+    if !blank {
+        records.push(Record { line, fields });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_html;
+
+    #[test]
+    fn csv() {
+        let html = to_html(
+            "name,count\r\n\"Smith, \"\"J\"\"\",3\n\nmulti\nline,-1.5\n\"a\nb\",\n",
+            ',',
+        );
+        assert_eq!(
+            html,
+            "<table class=\"data-table\" data-source-line=\"1\">\n\
+             <thead>\n<tr><th>name</th><th data-type=\"number\">count</th></tr>\n</thead>\n\
+             <tbody>\n\
+             <tr data-source-line=\"2\"><td>Smith, &quot;J&quot;</td><td>3</td></tr>\n\
+             <tr data-source-line=\"4\"><td>multi</td><td></td></tr>\n\
+             <tr data-source-line=\"5\"><td>line</td><td>-1.5</td></tr>\n\
+             <tr data-source-line=\"6\"><td>a\nb</td><td></td></tr>\n\
+             </tbody>\n</table>\n"
+        );
+    }
+
+    #[test]
+    fn tsv() {
+        let html = to_html("a\t\"b\"\n1\t\"x,y\"\t<z>", '\t');
+        assert!(html.contains("<th data-type=\"number\">a</th><th>&quot;b&quot;</th><th></th>"));
+        assert!(html.contains("<td>1</td><td>&quot;x,y&quot;</td><td>&lt;z&gt;</td>"));
+        assert_eq!(to_html("\n\n", '\t'), "");
+    }
+}
@@ -0,0 +1,327 @@
+//! Rendering diagrams in code blocks with external tools.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{self, prelude::*};
+use std::path::PathBuf;
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use log::*;
+use url::Url;
+
+/// The number of times the cache may be pruned while a diagram goes unused before the diagram is
+/// removed, so that diagrams aren't rendered again when a change is undone.
+const UNUSED_PRUNES: usize = 16;
+
+/// Renders the contents of diagram code blocks to SVG.
+///
+/// Rendered diagrams are cached by their contents, so unchanged diagrams are not re-rendered every
+/// time the markdown changes.
+#[derive(Debug, Default)]
+pub struct Diagrams {
+    mermaid: Option<Mermaid>,
+    plantuml: Option<PlantUml>,
+
+    /// Rendered diagrams, keyed by a hash of the language and contents of the code block, along
+    /// with the number of times the cache had been pruned when the diagram was last used. If the
+    /// diagram failed to render, the SVG is `None`.
+    cache: HashMap<u64, (Option<String>, usize)>,
+
+    /// The number of times the cache has been pruned.
+    prunes: usize,
+}
+
+/// Renders mermaid diagrams with [mermaid-cli](https://github.com/mermaid-js/mermaid-cli).
+#[derive(Debug)]
+struct Mermaid {
+    command: Command,
+    input: PathBuf,
+    output: PathBuf,
+}
+
+/// Renders [PlantUML](https://plantuml.com/) diagrams.
+#[derive(Debug)]
+pub enum PlantUml {
+    /// A local PlantUML command, such as `plantuml` or `java -jar plantuml.jar`.
+    Command(Command),
+
+    /// The URL of a PlantUML server, such as `https://www.plantuml.com/plantuml`.
+    Server(Url),
+}
+
+impl Diagrams {
+    /// Set the `mmdc` command used to render `mermaid` code blocks, or `None` to leave them as
+    /// code.
+    pub fn set_mermaid(&mut self, command: Option<Command>) {
+        self.mermaid = command.map(|mut command| {
+            // Each renderer needs its own files, since there may be more than one server running.
+            static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+            let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+            let input =
+                env::temp_dir().join(format!("composer-mermaid-{}-{}.mmd", process::id(), id));
+            let output = input.with_extension("svg");
+
+            command
+                .arg("--input")
+                .arg(&input)
+                .arg("--output")
+                .arg(&output)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null());
+
+            Mermaid {
+                command,
+                input,
+                output,
+            }
+        });
+        self.cache.clear();
+    }
+
+    /// Set how `plantuml` code blocks are rendered, or `None` to leave them as code.
+    pub fn set_plantuml(&mut self, plantuml: Option<PlantUml>) {
+        self.plantuml = plantuml.map(|plantuml| match plantuml {
+            PlantUml::Command(mut command) => {
+                command
+                    .args(["-tsvg", "-pipe"])
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::null());
+                PlantUml::Command(command)
+            }
+            server => server,
+        });
+        self.cache.clear();
+    }
+
+    /// Returns whether code blocks in the given language should be rendered as diagrams.
+    pub fn supports(&self, language: &str) -> bool {
+        match language {
+            "mermaid" => self.mermaid.is_some(),
+            "plantuml" => self.plantuml.is_some(),
+            _ => false,
+        }
+    }
+
+    /// Renders the source of a diagram to SVG, or returns `None` if the diagram couldn't be
+    /// rendered.
+    pub fn render(&mut self, language: &str, source: &str) -> Option<String> {
+        let mut hasher = DefaultHasher::new();
+        (language, source).hash(&mut hasher);
+        let key = hasher.finish();
+
+        if let Some((svg, used)) = self.cache.get_mut(&key) {
+            *used = self.prunes;
+            return svg.clone();
+        }
+
+        let result = match language {
+            "mermaid" => self.mermaid.as_mut().map(|mermaid| mermaid.render(source)),
+            "plantuml" => self
+                .plantuml
+                .as_mut()
+                .map(|plantuml| plantuml.render(source)),
+            _ => None,
+        };
+
+        let svg = match result {
+            Some(Ok(svg)) => Some(svg),
+            Some(Err(e)) => {
+                warn!("could not render {} diagram: {}", language, e);
+                None
+            }
+            None => None,
+        };
+
+        self.cache.insert(key, (svg.clone(), self.prunes));
+        svg
+    }
+
+    /// Removes every diagram from the cache, so that each diagram is rendered again.
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+    }
+
+    /// Removes diagrams from the cache that haven't been rendered recently.
+    pub fn prune_cache(&mut self) {
+        self.prunes += 1;
+
+        let prunes = self.prunes;
+        self.cache
+            .retain(|_, (_, used)| prunes - *used <= UNUSED_PRUNES);
+    }
+}
+
+impl Mermaid {
+    fn render(&mut self, source: &str) -> io::Result<String> {
+        fs::write(&self.input, source)?;
+
+        debug!("rendering mermaid diagram: {:?}", self.command);
+        let status = self.command.status();
+        let _ = fs::remove_file(&self.input);
+
+        let status = status?;
+        if !status.success() {
+            return Err(io::Error::other(format!("mmdc exited with {}", status)));
+        }
+
+        let svg = fs::read_to_string(&self.output);
+        let _ = fs::remove_file(&self.output);
+        svg
+    }
+}
+
+impl PlantUml {
+    fn render(&mut self, source: &str) -> io::Result<String> {
+        match self {
+            PlantUml::Command(command) => {
+                debug!("rendering PlantUML diagram: {:?}", command);
+                let mut child = command.spawn()?;
+
+                child.stdin.take().unwrap().write_all(source.as_bytes())?;
+
+                let mut svg = String::new();
+                child.stdout.take().unwrap().read_to_string(&mut svg)?;
+
+                let status = child.wait()?;
+                if !status.success() {
+                    return Err(io::Error::other(format!("plantuml exited with {}", status)));
+                }
+
+                Ok(svg)
+            }
+            PlantUml::Server(url) => {
+                let url = format!(
+                    "{}/svg/{}",
+                    url.as_str().trim_end_matches('/'),
+                    encode_plantuml(source)?
+                );
+
+                debug!("rendering PlantUML diagram: {}", url);
+                ureq::get(&url)
+                    .timeout(Duration::from_secs(10))
+                    .call()
+                    .map_err(io::Error::other)?
+                    .into_string()
+            }
+        }
+    }
+}
+
+/// Encodes PlantUML source for a server URL.
+///
+/// The source is compressed with deflate, then encoded with PlantUML's variant of base64. See
+/// <https://plantuml.com/text-encoding>.
+fn encode_plantuml(source: &str) -> io::Result<String> {
+    const ALPHABET: &[u8; 64] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz-_";
+
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(source.as_bytes())?;
+    let compressed = encoder.finish()?;
+
+    let mut encoded = String::with_capacity(compressed.len().div_ceil(3) * 4);
+
+    for chunk in compressed.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+
+        let indices = [
+            b[0] >> 2,
+            ((b[0] & 0x3) << 4) | (b[1] >> 4),
+            ((b[1] & 0xF) << 2) | (b[2] >> 6),
+            b[2] & 0x3F,
+        ];
+
+        encoded.extend(indices.iter().map(|&i| ALPHABET[i as usize] as char));
+    }
+
+    Ok(encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::prelude::*;
+
+    use flate2::read::DeflateDecoder;
+
+    use super::{encode_plantuml, Diagrams};
+
+    #[cfg(unix)]
+    #[test]
+    fn cache_rendered_diagrams() {
+        use std::env;
+        use std::fs;
+        use std::process::{self, Command};
+
+        let count = env::temp_dir().join(format!("composer-mmdc-count-{}", process::id()));
+
+        // Imitate mmdc by copying the input to the output.
+        let mut mmdc = Command::new("sh");
+        mmdc.arg("-c")
+            .arg(r#"cp "$2" "$4" && echo >> "$0""#)
+            .arg(&count);
+
+        let mut diagrams = Diagrams::default();
+        diagrams.set_mermaid(Some(mmdc));
+        assert!(diagrams.supports("mermaid"));
+
+        let render = |diagrams: &mut Diagrams, source| {
+            let svg = diagrams.render("mermaid", source);
+            diagrams.prune_cache();
+            svg
+        };
+
+        assert_eq!(render(&mut diagrams, "<svg/>").as_deref(), Some("<svg/>"));
+        assert_eq!(render(&mut diagrams, "<svg/>").as_deref(), Some("<svg/>"));
+        assert_eq!(fs::read_to_string(&count).unwrap().lines().count(), 1);
+
+        // Diagrams that were rendered recently are kept, such as when a change is undone.
+        render(&mut diagrams, "<svg></svg>");
+        render(&mut diagrams, "<svg/>");
+        assert_eq!(fs::read_to_string(&count).unwrap().lines().count(), 2);
+
+        for _ in 0..=super::UNUSED_PRUNES {
+            diagrams.prune_cache();
+        }
+        render(&mut diagrams, "<svg/>");
+        assert_eq!(fs::read_to_string(&count).unwrap().lines().count(), 3);
+
+        fs::remove_file(count).unwrap();
+    }
+
+    #[test]
+    fn plantuml_encoding() {
+        const ALPHABET: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz-_";
+
+        let source = "Bob -> Alice : hello";
+        let encoded = encode_plantuml(source).unwrap();
+
+        let compressed = encoded
+            .chars()
+            .map(|c| ALPHABET.find(c).unwrap() as u32)
+            .collect::<Vec<_>>()
+            .chunks(4)
+            .flat_map(|chunk| {
+                let n = chunk[0] << 18 | chunk[1] << 12 | chunk[2] << 6 | chunk[3];
+                vec![(n >> 16) as u8, (n >> 8) as u8, n as u8]
+            })
+            .collect::<Vec<_>>();
+
+        let mut decoded = String::new();
+        DeflateDecoder::new(&compressed[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, source);
+    }
+}
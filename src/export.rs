@@ -0,0 +1,258 @@
+//! Exporting the preview as a standalone document.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::{general_purpose::STANDARD, Engine};
+use log::*;
+use url::Url;
+
+pub mod epub;
+pub mod pdf;
+
+/// Replaces references to local images in the HTML with data URIs containing the image contents,
+/// so that the HTML may be viewed without access to the filesystem.
+///
+/// Relative image paths are resolved against `root`, matching how the server serves static files.
+/// Images that cannot be read are left as-is.
+pub fn inline_local_images(html: &str, root: Option<&Path>) -> String {
+    rewrite_local_images(html, root, |path| match data_uri(path) {
+        Ok(uri) => Some(uri),
+        Err(e) => {
+            warn!("could not inline image {}: {}", path.display(), e);
+            None
+        }
+    })
+}
+
+/// The data URIs of local images, so that HTML may be inlined repeatedly without reading images
+/// that haven't been modified since.
+#[derive(Debug, Default)]
+pub struct DataUris {
+    uris: HashMap<PathBuf, (SystemTime, String)>,
+}
+
+impl DataUris {
+    /// Replaces references to local images in the HTML with data URIs, like
+    /// [`inline_local_images`].
+    pub fn inline(&mut self, html: &str, root: Option<&Path>) -> String {
+        rewrite_local_images(html, root, |path| {
+            let modified = fs::metadata(path).and_then(|metadata| metadata.modified());
+
+            if let (Ok(modified), Some((read, uri))) = (&modified, self.uris.get(path)) {
+                if read == modified {
+                    return Some(uri.clone());
+                }
+            }
+
+            match data_uri(path) {
+                Ok(uri) => {
+                    if let Ok(modified) = modified {
+                        self.uris.insert(path.to_owned(), (modified, uri.clone()));
+                    }
+                    Some(uri)
+                }
+                Err(e) => {
+                    warn!("could not inline image {}: {}", path.display(), e);
+                    None
+                }
+            }
+        })
+    }
+}
+
+/// Replaces the source of each local image in the HTML with the result of `rewrite`.
+///
+/// `rewrite` is called with the path of the image on the local filesystem. If it returns `None`,
+/// the image is left as-is.
+pub fn rewrite_local_images(
+    html: &str,
+    root: Option<&Path>,
+    mut rewrite: impl FnMut(&Path) -> Option<String>,
+) -> String {
+    let mut inlined = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(start) = rest.find("<img ") {
+        let (before, tag) = rest.split_at(start);
+        inlined.push_str(before);
+
+        let tag_end = tag.find('>').map_or(tag.len(), |i| i + 1);
+        let (tag, after) = tag.split_at(tag_end);
+        inlined.push_str(&rewrite_image_tag(tag, root, &mut rewrite));
+
+        rest = after;
+    }
+
+    inlined.push_str(rest);
+    inlined
+}
+
+fn rewrite_image_tag<'a>(
+    tag: &'a str,
+    root: Option<&Path>,
+    rewrite: &mut impl FnMut(&Path) -> Option<String>,
+) -> Cow<'a, str> {
+    let src = match source_range(tag) {
+        Some(src) => src,
+        None => return Cow::Borrowed(tag),
+    };
+
+    match local_path(&unescape_attribute(&tag[src.clone()]), root).and_then(|path| rewrite(&path)) {
+        Some(new_src) => Cow::Owned(format!(
+            "{}{}{}",
+            &tag[..src.start],
+            new_src,
+            &tag[src.end..]
+        )),
+        None => Cow::Borrowed(tag),
+    }
+}
+
+/// Returns the sources of the local images in the HTML, along with their paths on the local
+/// filesystem.
+pub fn local_images(html: &str, root: Option<&Path>) -> Vec<(String, PathBuf)> {
+    let mut images = vec![];
+    let mut rest = html;
+
+    while let Some(start) = rest.find("<img ") {
+        let tag = &rest[start..];
+        let tag = &tag[..tag.find('>').map_or(tag.len(), |i| i + 1)];
+        rest = &rest[start + tag.len()..];
+
+        if let Some(src) = source_range(tag) {
+            let src = unescape_attribute(&tag[src]);
+            if let Some(path) = local_path(&src, root) {
+                images.push((src.into_owned(), path));
+            }
+        }
+    }
+
+    images
+}
+
+/// Returns the range of the value of the `src` attribute of an image tag.
+fn source_range(tag: &str) -> Option<Range<usize>> {
+    const SRC: &str = " src=\"";
+
+    let start = tag.find(SRC)? + SRC.len();
+    let end = start + tag[start..].find('"')?;
+    Some(start..end)
+}
+
+/// Returns the path of a link or image on the local filesystem, if the URL refers to one.
+pub fn local_path(src: &str, root: Option<&Path>) -> Option<PathBuf> {
+    match Url::parse(src) {
+        Ok(url) if url.scheme() == "file" => url.to_file_path().ok(),
+        // NB: Absolute paths on Windows will parse as URLs.
+        Ok(url) if url.scheme().len() == 1 => Some(PathBuf::from(src)),
+        Ok(_) => None,
+        Err(_) => {
+            let root = Url::from_directory_path(root?).ok()?;
+            root.join(src.trim_start_matches('/'))
+                .ok()?
+                .to_file_path()
+                .ok()
+        }
+    }
+}
+
+/// Reads a file into a data URI. The MIME type is guessed from the file's extension.
+pub fn data_uri(path: &Path) -> io::Result<String> {
+    let contents = fs::read(path)?;
+    let mime_type = mime_guess::from_path(path).first_or_octet_stream();
+
+    Ok(format!(
+        "data:{};base64,{}",
+        mime_type,
+        STANDARD.encode(contents)
+    ))
+}
+
+/// Reverses the escaping applied to attribute values by the HTML renderer.
+fn unescape_attribute(value: &str) -> Cow<'_, str> {
+    if !value.contains('&') {
+        return Cow::Borrowed(value);
+    }
+
+    Cow::Owned(
+        value
+            .replace("&quot;", "\"")
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&#39;", "'")
+            .replace("&amp;", "&"),
+    )
+}
+
+/// Formats the time as an ISO 8601 UTC timestamp, such as EPUB's `dcterms:modified`. The date
+/// is the first ten characters.
+pub(crate) fn timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+
+    let days = (secs / 86400) as i64;
+    let secs = secs % 86400;
+
+    // Converts days since the epoch to a civil date. See
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::{inline_local_images, DataUris};
+
+    #[test]
+    fn inline_images() {
+        let root = env::temp_dir().join(format!("composer-inline-images-{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a&b.png"), b"png").unwrap();
+
+        assert_eq!(
+            inline_local_images(
+                r#"<p><img src="a&amp;b.png" alt="local" /><img src="https://example.com/c.png" alt="remote" /><img src="missing.png" alt="missing" /></p>"#,
+                Some(&root),
+            ),
+            r#"<p><img src="data:image/png;base64,cG5n" alt="local" /><img src="https://example.com/c.png" alt="remote" /><img src="missing.png" alt="missing" /></p>"#,
+        );
+
+        let mut data_uris = DataUris::default();
+        for _ in 0..2 {
+            assert_eq!(
+                data_uris.inline(r#"<img src="a&amp;b.png">"#, Some(&root)),
+                r#"<img src="data:image/png;base64,cG5n">"#,
+            );
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
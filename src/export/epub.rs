@@ -0,0 +1,244 @@
+//! Packaging the preview as an EPUB.
+
+use std::collections::hash_map::{DefaultHasher, Entry, HashMap};
+use std::hash::{Hash, Hasher};
+use std::io::{self, prelude::*};
+use std::path::Path;
+use std::time::SystemTime;
+
+use handlebars::Handlebars;
+use log::*;
+use serde::Serialize;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use super::timestamp;
+use crate::front_matter::FrontMatter;
+
+/// The contents of an EPUB.
+#[derive(Debug)]
+pub struct Book<'a> {
+    /// The title of the book, if the front matter doesn't specify one.
+    pub title: &'a str,
+    pub front_matter: &'a FrontMatter,
+    /// The rendered HTML of the document. The HTML must also be valid XHTML.
+    pub html: &'a str,
+    pub stylesheet: &'a str,
+    /// The directory that relative image paths are resolved against.
+    pub root: Option<&'a Path>,
+}
+
+#[derive(Debug, Serialize)]
+struct Image {
+    href: String,
+    media_type: String,
+    #[serde(skip)]
+    contents: Vec<u8>,
+}
+
+/// Writes the book as an EPUB 3 container.
+///
+/// Local images referenced by the HTML are copied into the container.
+pub fn write(writer: impl Write + Seek, book: &Book) -> io::Result<()> {
+    #[derive(Debug, Serialize)]
+    struct Data<'a> {
+        identifier: String,
+        title: &'a str,
+        language: &'a str,
+        author: Option<&'a str>,
+        date: Option<&'a str>,
+        description: Option<&'a str>,
+        modified: String,
+        images: &'a [Image],
+        html: &'a str,
+    }
+
+    let mut images = Vec::new();
+    let mut image_hrefs = HashMap::new();
+
+    let html = super::rewrite_local_images(book.html, book.root, |path| {
+        match image_hrefs.entry(path.to_owned()) {
+            Entry::Occupied(entry) => Some(String::clone(entry.get())),
+            Entry::Vacant(entry) => {
+                let contents = match std::fs::read(path) {
+                    Ok(contents) => contents,
+                    Err(e) => {
+                        warn!("could not package image {}: {}", path.display(), e);
+                        return None;
+                    }
+                };
+
+                let extension = path
+                    .extension()
+                    .map(|ext| format!(".{}", ext.to_string_lossy()))
+                    .unwrap_or_default();
+                let href = format!("images/{}{}", images.len(), extension);
+
+                images.push(Image {
+                    href: href.clone(),
+                    media_type: mime_guess::from_path(path)
+                        .first_or_octet_stream()
+                        .to_string(),
+                    contents,
+                });
+
+                Some(entry.insert(href).clone())
+            }
+        }
+    });
+
+    let front_matter = book.front_matter;
+    let title = front_matter.title.as_deref().unwrap_or(book.title);
+
+    let data = Data {
+        identifier: front_matter
+            .identifier
+            .clone()
+            .unwrap_or_else(|| default_identifier(title)),
+        title,
+        language: front_matter.language.as_deref().unwrap_or("en"),
+        author: front_matter.author.as_deref(),
+        date: front_matter.date.as_deref(),
+        description: front_matter.description.as_deref(),
+        modified: timestamp(SystemTime::now()),
+        images: &images,
+        html: &html,
+    };
+
+    let render = |template| {
+        Handlebars::new()
+            .render_template(template, &data)
+            .expect("invalid template syntax")
+    };
+
+    let mut zip = ZipWriter::new(writer);
+    let deflated = SimpleFileOptions::default();
+
+    // The mimetype must be the first file in the container, and it must not be compressed.
+    zip.start_file(
+        "mimetype",
+        SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
+    )?;
+    zip.write_all(b"application/epub+zip")?;
+
+    let files = [
+        (
+            "META-INF/container.xml",
+            render(include_str!("../../templates/epub/container.xml")),
+        ),
+        (
+            "OEBPS/content.opf",
+            render(include_str!("../../templates/epub/content.opf")),
+        ),
+        (
+            "OEBPS/nav.xhtml",
+            render(include_str!("../../templates/epub/nav.xhtml")),
+        ),
+        (
+            "OEBPS/content.xhtml",
+            render(include_str!("../../templates/epub/content.xhtml")),
+        ),
+    ];
+
+    for (name, contents) in &files {
+        zip.start_file(*name, deflated)?;
+        zip.write_all(contents.as_bytes())?;
+    }
+
+    zip.start_file("OEBPS/styles.css", deflated)?;
+    zip.write_all(book.stylesheet.as_bytes())?;
+
+    for image in &images {
+        zip.start_file(format!("OEBPS/{}", image.href), deflated)?;
+        zip.write_all(&image.contents)?;
+    }
+
+    zip.finish()?;
+
+    Ok(())
+}
+
+/// Generates an identifier for books whose front matter doesn't specify one.
+fn default_identifier(title: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    title.hash(&mut hasher);
+    format!("urn:markdown-composer:{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::{Cursor, Read};
+    use std::process;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use zip::ZipArchive;
+
+    use super::{timestamp, write, Book};
+    use crate::front_matter::FrontMatter;
+
+    #[test]
+    fn modified_timestamp() {
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            timestamp(UNIX_EPOCH + Duration::from_secs(1_709_251_199)),
+            "2024-02-29T23:59:59Z"
+        );
+    }
+
+    #[test]
+    fn write_epub() {
+        let root = env::temp_dir().join(format!("composer-epub-{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("image.png"), b"png").unwrap();
+
+        let front_matter = FrontMatter {
+            title: Some(String::from("A & B")),
+            author: Some(String::from("Author")),
+            ..Default::default()
+        };
+
+        let mut epub = Cursor::new(Vec::new());
+        write(
+            &mut epub,
+            &Book {
+                title: "Untitled",
+                front_matter: &front_matter,
+                html: r#"<p><img src="image.png" alt="" /><img src="image.png" alt="" /></p>"#,
+                stylesheet: "",
+                root: Some(&root),
+            },
+        )
+        .unwrap();
+
+        fs::remove_dir_all(root).unwrap();
+
+        let epub = epub.into_inner();
+        assert_eq!(&epub[30..38], b"mimetype");
+        assert_eq!(&epub[38..58], b"application/epub+zip");
+
+        let mut archive = ZipArchive::new(Cursor::new(epub)).unwrap();
+        let mut read = |name| {
+            let mut contents = String::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            contents
+        };
+
+        let opf = read("OEBPS/content.opf");
+        assert!(opf.contains("<dc:title>A &amp; B</dc:title>"));
+        assert!(opf.contains("<dc:creator>Author</dc:creator>"));
+        assert!(opf.contains(r#"href="images/0.png" media-type="image/png""#));
+
+        let content = read("OEBPS/content.xhtml");
+        assert!(content.contains(
+            r#"<p><img src="images/0.png" alt="" /><img src="images/0.png" alt="" /></p>"#
+        ));
+
+        assert_eq!(read("OEBPS/images/0.png"), "png");
+    }
+}
@@ -0,0 +1,262 @@
+//! The page layout of exported PDFs, which also applies when the preview is printed.
+//!
+//! The layout is applied with CSS `@page` rules, which the browser follows when it prints the
+//! preview. Headers and footers are templates of the text in the margins of each page, such as
+//! `{title}|{date}|Page {page} of {pages}`, whose parts are separated by `|`: a single part is
+//! centered, two are placed on the left and the right, and three on the left, in the center and
+//! on the right.
+
+use std::mem;
+
+use log::*;
+use serde::{Deserialize, Deserializer};
+
+/// The page layout of exported PDFs. Properties that are `None` are left to the browser.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct PdfLayout {
+    /// The size of the paper, such as `A4`, `letter` or `8.5in 11in`.
+    pub paper_size: Option<String>,
+
+    /// The margins of the pages, such as `2cm` or `1in 0.75in`. Numbers are treated as
+    /// millimetres.
+    #[serde(deserialize_with = "string_or_number")]
+    pub margin: Option<String>,
+
+    /// Whether the pages are wider than they are tall.
+    pub landscape: Option<bool>,
+
+    /// The template of the header of each page.
+    pub header: Option<String>,
+
+    /// The template of the footer of each page.
+    pub footer: Option<String>,
+}
+
+impl PdfLayout {
+    /// Sets a property by name, such as `paper_size`, or resets it if the value is `None`.
+    ///
+    /// Values that could escape their CSS declaration are rejected.
+    pub fn set(&mut self, property: &str, value: Option<String>) -> Result<(), String> {
+        if let Some(value) = &value {
+            if !is_safe(value) && property != "header" && property != "footer" {
+                return Err(format!("invalid value for {}: {}", property, value));
+            }
+        }
+
+        match property {
+            "paper_size" => self.paper_size = value,
+            "margin" => self.margin = value,
+            "landscape" => {
+                self.landscape = match value.as_deref() {
+                    Some("true") | Some("1") => Some(true),
+                    Some("false") | Some("0") => Some(false),
+                    None => None,
+                    Some(value) => return Err(format!("invalid value for landscape: {}", value)),
+                }
+            }
+            "header" => self.header = value,
+            "footer" => self.footer = value,
+            _ => return Err(format!("unknown PDF layout property: {}", property)),
+        }
+
+        Ok(())
+    }
+
+    /// Returns the layout with the properties that are set in another layout, such as the front
+    /// matter of a document, replaced by theirs.
+    pub fn merge(&self, overrides: &PdfLayout) -> PdfLayout {
+        PdfLayout {
+            paper_size: overrides
+                .paper_size
+                .clone()
+                .or_else(|| self.paper_size.clone()),
+            margin: overrides.margin.clone().or_else(|| self.margin.clone()),
+            landscape: overrides.landscape.or(self.landscape),
+            header: overrides.header.clone().or_else(|| self.header.clone()),
+            footer: overrides.footer.clone().or_else(|| self.footer.clone()),
+        }
+    }
+
+    /// Returns CSS that lays out the printed pages of a document, or `None` if nothing is set.
+    /// `{title}` and `{date}` in the header and footer are replaced by the title of the document
+    /// and the date.
+    pub fn to_css(&self, title: &str, date: &str) -> Option<String> {
+        let mut body = String::new();
+
+        let paper_size = self.paper_size.as_deref().filter(|size| {
+            let safe = is_safe(size);
+            if !safe {
+                warn!("invalid paper size: {}", size);
+            }
+            safe
+        });
+        match (paper_size, self.landscape) {
+            (Some(size), Some(true)) => body.push_str(&format!("size: {} landscape;", size)),
+            (Some(size), _) => body.push_str(&format!("size: {};", size)),
+            (None, Some(true)) => body.push_str("size: landscape;"),
+            (None, _) => (),
+        }
+
+        match &self.margin {
+            Some(margin) if is_safe(margin) => {
+                let margin = match margin.parse::<f64>() {
+                    Ok(_) => format!("{}mm", margin),
+                    Err(_) => margin.clone(),
+                };
+                body.push_str(&format!("margin: {};", margin));
+            }
+            Some(margin) => warn!("invalid margin: {}", margin),
+            None => (),
+        }
+
+        for (edge, template) in [("top", &self.header), ("bottom", &self.footer)].iter() {
+            let template = match template {
+                Some(template) => template,
+                None => continue,
+            };
+
+            let parts = template.split('|').collect::<Vec<_>>();
+            let positions: &[&str] = match parts.len() {
+                1 => &["center"],
+                2 => &["left", "right"],
+                _ => &["left", "center", "right"],
+            };
+            for (position, part) in positions.iter().zip(parts) {
+                if !part.trim().is_empty() {
+                    body.push_str(&format!(
+                        " @{}-{} {{ content: {}; }}",
+                        edge,
+                        position,
+                        content(part.trim(), title, date)
+                    ));
+                }
+            }
+        }
+
+        if body.is_empty() {
+            None
+        } else {
+            Some(format!("@page {{ {} }}\n", body))
+        }
+    }
+}
+
+/// Converts a part of a header or footer template to the value of a CSS `content` property.
+fn content(template: &str, title: &str, date: &str) -> String {
+    let mut values = vec![];
+    let mut text = String::new();
+
+    let mut rest = template;
+    while let Some(c) = rest.chars().next() {
+        let counter = ["page", "pages"]
+            .iter()
+            .find(|counter| rest.starts_with(&format!("{{{}}}", counter)));
+        if let Some(counter) = counter {
+            if !text.is_empty() {
+                values.push(css_string(&mem::take(&mut text)));
+            }
+            values.push(format!("counter({})", counter));
+            rest = &rest[counter.len() + 2..];
+        } else if let Some(after) = rest.strip_prefix("{title}") {
+            text.push_str(title);
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("{date}") {
+            text.push_str(date);
+            rest = after;
+        } else {
+            text.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    if !text.is_empty() || values.is_empty() {
+        values.push(css_string(&text));
+    }
+
+    values.join(" ")
+}
+
+/// Quotes text as a CSS string, escaping characters that could end the string or the stylesheet.
+fn css_string(text: &str) -> String {
+    let mut string = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                string.push('\\');
+                string.push(c);
+            }
+            '\n' => string.push_str("\\A "),
+            '<' => string.push_str("\\3C "),
+            _ => string.push(c),
+        }
+    }
+    string.push('"');
+    string
+}
+
+/// Returns whether a value can't escape its CSS declaration.
+fn is_safe(value: &str) -> bool {
+    !value.contains([';', '{', '}', '<', '>'])
+}
+
+/// Deserializes a string, or a number as a string.
+fn string_or_number<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrNumber {
+        String(String),
+        Number(f64),
+    }
+
+    Ok(
+        Option::<StringOrNumber>::deserialize(deserializer)?.map(|value| match value {
+            StringOrNumber::String(value) => value,
+            StringOrNumber::Number(value) => value.to_string(),
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PdfLayout;
+
+    #[test]
+    fn to_css() {
+        let mut layout = PdfLayout::default();
+        assert_eq!(layout.to_css("Title", "2024-02-29"), None);
+
+        layout.set("paper_size", Some(String::from("A4"))).unwrap();
+        layout.set("landscape", Some(String::from("true"))).unwrap();
+        layout.set("margin", Some(String::from("20"))).unwrap();
+        layout
+            .set(
+                "footer",
+                Some(String::from("{title}||Page {page} of {pages}")),
+            )
+            .unwrap();
+        assert!(layout.set("margin", Some(String::from("1cm; }"))).is_err());
+        assert!(layout.set("color", Some(String::from("red"))).is_err());
+        assert_eq!(
+            layout
+                .to_css("A \"quoted\" <title>", "2024-02-29")
+                .as_deref(),
+            Some(
+                "@page { size: A4 landscape;margin: 20mm; \
+                 @bottom-left { content: \"A \\\"quoted\\\" \\3C title>\"; } \
+                 @bottom-right { content: \"Page \" counter(page) \" of \" counter(pages); } }\n"
+            )
+        );
+
+        let overrides = serde_yaml::from_str::<PdfLayout>("margin: 1in\nheader: '{date}'").unwrap();
+        let layout = layout.merge(&overrides);
+        assert_eq!(layout.margin.as_deref(), Some("1in"));
+        assert_eq!(layout.paper_size.as_deref(), Some("A4"));
+        assert!(layout
+            .to_css("", "2024-02-29")
+            .unwrap()
+            .contains("@top-center { content: \"2024-02-29\"; }"));
+    }
+}
@@ -0,0 +1,367 @@
+//! Rendering markdown with an external program, such as pandoc.
+//!
+//! By default, the program is run once for each render: it reads the markdown on stdin and prints
+//! the HTML on stdout before exiting. Programs that are slow to start may instead be kept running,
+//! with each document and its HTML framed on the program's stdin and stdout.
+
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::io::{self, prelude::*, BufReader};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::*;
+
+/// How documents are delimited when they're streamed through a long-lived renderer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// Each document and its HTML is followed by a NUL byte.
+    Null,
+
+    /// Each document and its HTML is preceded by its length in bytes, in decimal, and a newline.
+    Length,
+}
+
+impl FromStr for Framing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "null" => Ok(Framing::Null),
+            "length" => Ok(Framing::Length),
+            _ => Err(format!("unknown framing: {}", s)),
+        }
+    }
+}
+
+/// The encoding of the markdown written to the renderer's stdin. The HTML is always read as UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+
+    /// ISO-8859-1. Characters that it can't represent are written as `?`.
+    Latin1,
+}
+
+impl Encoding {
+    /// Encodes markdown to be written to the renderer.
+    pub fn encode<'a>(&self, markdown: &'a str) -> Cow<'a, [u8]> {
+        match self {
+            Encoding::Utf8 => Cow::Borrowed(markdown.as_bytes()),
+            Encoding::Utf16Le => markdown.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+            Encoding::Utf16Be => markdown.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+            Encoding::Latin1 => markdown
+                .chars()
+                .map(|c| u8::try_from(c).unwrap_or(b'?'))
+                .collect(),
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &*s.to_ascii_lowercase() {
+            "utf-8" | "utf8" => Ok(Encoding::Utf8),
+            "utf-16le" => Ok(Encoding::Utf16Le),
+            "utf-16be" => Ok(Encoding::Utf16Be),
+            "latin1" | "iso-8859-1" => Ok(Encoding::Latin1),
+            _ => Err(format!("unknown encoding: {}", s)),
+        }
+    }
+}
+
+/// An external program that renders markdown to HTML.
+#[derive(Debug)]
+pub struct ExternalRenderer {
+    command: Command,
+
+    /// How documents are framed, if the program is kept running between renders.
+    framing: Option<Framing>,
+
+    /// How long a render may take before the program is killed.
+    timeout: Option<Duration>,
+
+    encoding: Encoding,
+
+    /// The running program, if it's kept running.
+    process: Option<Process>,
+}
+
+#[derive(Debug)]
+struct Process {
+    child: Arc<Mutex<Child>>,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl ExternalRenderer {
+    /// Creates a renderer that runs the command once for each render.
+    pub fn new(mut command: Command) -> Self {
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+
+        ExternalRenderer {
+            command,
+            framing: None,
+            timeout: None,
+            encoding: Encoding::Utf8,
+            process: None,
+        }
+    }
+
+    /// Set how long a render may take before the program is killed and the render fails, or
+    /// `None` to wait for the program indefinitely.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Set the encoding of the markdown written to the program.
+    ///
+    /// Documents framed with [`Framing::Null`] should use an encoding without NUL bytes, such as
+    /// UTF-8 or ISO-8859-1.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    /// Set how documents are framed if the program should be kept running between renders, or
+    /// `None` to run the program once for each render.
+    pub fn set_framing(&mut self, framing: Option<Framing>) {
+        self.framing = framing;
+        self.stop();
+    }
+
+    /// Renders markdown to HTML.
+    ///
+    /// A long-lived program that crashed is restarted, and the markdown is sent to it again. A
+    /// program that timed out is killed, and the render fails with [`io::ErrorKind::TimedOut`].
+    pub fn render(&mut self, markdown: &str) -> io::Result<String> {
+        let framing = match self.framing {
+            Some(framing) => framing,
+            None => return self.render_once(markdown),
+        };
+
+        match self.render_framed(framing, markdown) {
+            Ok(html) => Ok(html),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                self.stop();
+                Err(e)
+            }
+            Err(e) => {
+                warn!("external renderer failed, restarting it: {}", e);
+                self.stop();
+                self.render_framed(framing, markdown)
+            }
+        }
+    }
+
+    fn render_once(&mut self, markdown: &str) -> io::Result<String> {
+        let mut child = self.command.spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let child = Arc::new(Mutex::new(child));
+
+        let markdown = self.encoding.encode(markdown);
+        let watchdog = self.timeout.map(|timeout| Watchdog::start(&child, timeout));
+        let html = (|| {
+            { stdin }.write_all(&markdown)?;
+
+            let mut html = String::with_capacity(markdown.len());
+            { stdout }.read_to_string(&mut html)?;
+            Ok(html)
+        })();
+        finish(
+            watchdog,
+            html.and_then(|html| {
+                child.lock().unwrap().wait()?;
+                Ok(html)
+            }),
+        )
+    }
+
+    fn render_framed(&mut self, framing: Framing, markdown: &str) -> io::Result<String> {
+        let process = match &mut self.process {
+            Some(process) => process,
+            None => {
+                let mut child = self.command.spawn()?;
+                let stdin = child.stdin.take().unwrap();
+                let stdout = BufReader::new(child.stdout.take().unwrap());
+                self.process.insert(Process {
+                    child: Arc::new(Mutex::new(child)),
+                    stdin,
+                    stdout,
+                })
+            }
+        };
+
+        let watchdog = self
+            .timeout
+            .map(|timeout| Watchdog::start(&process.child, timeout));
+        let markdown = self.encoding.encode(markdown);
+        finish(watchdog, Self::exchange(process, framing, &markdown))
+    }
+
+    /// Writes a document to a long-lived program, and reads its HTML.
+    fn exchange(process: &mut Process, framing: Framing, markdown: &[u8]) -> io::Result<String> {
+        match framing {
+            Framing::Null => {
+                process.stdin.write_all(markdown)?;
+                process.stdin.write_all(b"\0")?;
+            }
+            Framing::Length => {
+                writeln!(process.stdin, "{}", markdown.len())?;
+                process.stdin.write_all(markdown)?;
+            }
+        }
+        process.stdin.flush()?;
+
+        let html = match framing {
+            Framing::Null => {
+                let mut html = vec![];
+                process.stdout.read_until(b'\0', &mut html)?;
+                if html.pop() != Some(b'\0') {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                html
+            }
+            Framing::Length => {
+                let mut len = String::new();
+                process.stdout.read_line(&mut len)?;
+                let len = len.trim().parse::<usize>().map_err(|e| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("invalid length: {}", e))
+                })?;
+                let mut html = vec![0; len];
+                process.stdout.read_exact(&mut html)?;
+                html
+            }
+        };
+
+        String::from_utf8(html).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Stops the long-lived program, if it's running.
+    fn stop(&mut self) {
+        if let Some(process) = self.process.take() {
+            let mut child = process.child.lock().unwrap();
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Kills a program if a render takes longer than the timeout.
+struct Watchdog {
+    /// Dropped once the render finishes, which stops the watchdog.
+    done: Sender<()>,
+    timed_out: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    fn start(child: &Arc<Mutex<Child>>, timeout: Duration) -> Watchdog {
+        let (done, finished) = mpsc::channel::<()>();
+        let timed_out = Arc::new(AtomicBool::new(false));
+
+        let child = Arc::clone(child);
+        let killed = Arc::clone(&timed_out);
+        thread::spawn(move || {
+            if let Err(RecvTimeoutError::Timeout) = finished.recv_timeout(timeout) {
+                killed.store(true, Ordering::SeqCst);
+                let _ = child.lock().unwrap().kill();
+            }
+        });
+
+        Watchdog { done, timed_out }
+    }
+}
+
+/// Stops the watchdog of a render, if any, replacing the result of a render that was killed with
+/// a timeout error.
+fn finish(watchdog: Option<Watchdog>, result: io::Result<String>) -> io::Result<String> {
+    let watchdog = match watchdog {
+        Some(watchdog) => watchdog,
+        None => return result,
+    };
+
+    drop(watchdog.done);
+    if watchdog.timed_out.load(Ordering::SeqCst) {
+        return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "external renderer timed out",
+        ));
+    }
+    result
+}
+
+impl Drop for ExternalRenderer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::process::Command;
+    use std::time::{Duration, Instant};
+
+    use super::{Encoding, ExternalRenderer, Framing};
+
+    #[test]
+    fn render_once() {
+        let mut renderer = ExternalRenderer::new(Command::new("cat"));
+        assert_eq!(renderer.render("*Hello*").unwrap(), "*Hello*");
+    }
+
+    #[test]
+    fn render_framed() {
+        for framing in [Framing::Null, Framing::Length] {
+            let mut renderer = ExternalRenderer::new(Command::new("cat"));
+            renderer.set_framing(Some(framing));
+            assert_eq!(renderer.render("*One*").unwrap(), "*One*");
+            assert_eq!(renderer.render("*Two*").unwrap(), "*Two*");
+        }
+    }
+
+    #[test]
+    fn encodings() {
+        assert_eq!(&*Encoding::Utf8.encode("é"), "é".as_bytes());
+        assert_eq!(&*Encoding::Utf16Le.encode("é"), b"\xe9\x00");
+        assert_eq!(&*Encoding::Utf16Be.encode("é"), b"\x00\xe9");
+        assert_eq!(&*Encoding::Latin1.encode("é€"), b"\xe9?");
+    }
+
+    #[test]
+    fn timeout() {
+        let mut renderer = ExternalRenderer::new(Command::new("sleep"));
+        renderer.command.arg("10");
+        renderer.set_timeout(Some(Duration::from_millis(100)));
+
+        let start = Instant::now();
+        let err = renderer.render("*Hello*").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn restart_crashed_renderer() {
+        // Renders a single document, then exits.
+        let mut command = Command::new("sh");
+        command.args(["-c", "head -c 5"]);
+
+        let mut renderer = ExternalRenderer::new(command);
+        renderer.set_framing(Some(Framing::Null));
+        assert_eq!(renderer.render("1234").unwrap(), "1234");
+        assert_eq!(renderer.render("abcd").unwrap(), "abcd");
+    }
+}
@@ -0,0 +1,174 @@
+//! Document formats other than markdown.
+//!
+//! Markdown is rendered by the built-in renderer, or an external renderer if one is set. Org, CSV
+//! and TSV have built-in renderers of their own. Other formats have built-in pipelines around the tools that
+//! render them, whose output is trimmed to the fragment of HTML that the preview displays.
+
+use std::fmt;
+use std::process::Command;
+use std::str::FromStr;
+
+use crate::csv;
+use crate::external::ExternalRenderer;
+use crate::org;
+
+/// The format of a document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Format {
+    #[default]
+    Markdown,
+
+    /// AsciiDoc, rendered with `asciidoctor`.
+    AsciiDoc,
+
+    /// reStructuredText, rendered with docutils.
+    Rst,
+
+    /// Org, rendered by the built-in [`org`] renderer.
+    Org,
+
+    /// Comma-separated values, rendered as a table by the built-in [`csv`] renderer.
+    Csv,
+
+    /// Tab-separated values, rendered as a table by the built-in [`csv`] renderer.
+    Tsv,
+}
+
+impl Format {
+    /// Returns the format of a filetype, such as an editor's filetype or a file's extension, if
+    /// the format is known.
+    pub fn from_filetype(filetype: &str) -> Option<Format> {
+        match &*filetype.to_ascii_lowercase() {
+            "markdown" | "md" | "mkd" => Some(Format::Markdown),
+            "asciidoc" | "adoc" | "asc" => Some(Format::AsciiDoc),
+            "rst" | "rest" | "restructuredtext" => Some(Format::Rst),
+            "org" => Some(Format::Org),
+            "csv" => Some(Format::Csv),
+            "tsv" => Some(Format::Tsv),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Markdown => "markdown",
+            Format::AsciiDoc => "asciidoc",
+            Format::Rst => "rst",
+            Format::Org => "org",
+            Format::Csv => "csv",
+            Format::Tsv => "tsv",
+        }
+    }
+
+    /// Renders a document of a format that has a built-in renderer other than markdown's.
+    pub fn render(&self, source: &str) -> Option<String> {
+        match self {
+            Format::Org => Some(org::to_html(source)),
+            Format::Csv => Some(csv::to_html(source, ',')),
+            Format::Tsv => Some(csv::to_html(source, '\t')),
+            _ => None,
+        }
+    }
+
+    /// Returns the renderer of the format's pipeline, or `None` for formats that are rendered
+    /// by built-in renderers.
+    pub fn renderer(&self) -> Option<ExternalRenderer> {
+        let command = match self {
+            Format::Markdown | Format::Org | Format::Csv | Format::Tsv => return None,
+            Format::AsciiDoc => {
+                // Embedded documents have no header or footer, and `-` reads from stdin.
+                let mut command = Command::new("asciidoctor");
+                command.args(["--embedded", "--out-file", "-", "-"]);
+                command
+            }
+            Format::Rst => {
+                // docutils can't print a fragment, so its page is trimmed by `postprocess`.
+                let mut command = Command::new("docutils");
+                command.args([
+                    "--writer=html",
+                    "--no-generator",
+                    "--no-datestamp",
+                    "--no-source-link",
+                    "--link-stylesheet",
+                ]);
+                command
+            }
+        };
+
+        Some(ExternalRenderer::new(command))
+    }
+
+    /// Trims the HTML printed by the format's pipeline to the fragment that the preview displays.
+    pub fn postprocess(&self, html: String) -> String {
+        match self {
+            Format::Rst => body(&html).map(String::from).unwrap_or(html),
+            _ => html,
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "markdown" => Ok(Format::Markdown),
+            "asciidoc" => Ok(Format::AsciiDoc),
+            "rst" => Ok(Format::Rst),
+            "org" => Ok(Format::Org),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            _ => Err(format!("unknown format: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Returns the contents of the body of an HTML page, if it has one.
+fn body(html: &str) -> Option<&str> {
+    let start = html.find("<body")?;
+    let start = start + html[start..].find('>')? + 1;
+    let end = html.rfind("</body>")?;
+    html.get(start..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Format;
+
+    #[test]
+    fn formats_of_filetypes() {
+        assert_eq!(Format::from_filetype("ADOC"), Some(Format::AsciiDoc));
+        assert_eq!(Format::from_filetype("asciidoc"), Some(Format::AsciiDoc));
+        assert_eq!(Format::from_filetype("md"), Some(Format::Markdown));
+        assert_eq!(Format::from_filetype("txt"), None);
+
+        for format in [
+            Format::Markdown,
+            Format::AsciiDoc,
+            Format::Rst,
+            Format::Org,
+            Format::Csv,
+            Format::Tsv,
+        ] {
+            assert_eq!(format.name().parse::<Format>(), Ok(format));
+        }
+    }
+
+    #[test]
+    fn trim_pages() {
+        let page =
+            "<html><head><title>Doc</title></head>\n<body class=\"x\">\n<p>Hi</p>\n</body></html>";
+        assert_eq!(Format::Rst.postprocess(String::from(page)), "\n<p>Hi</p>\n");
+        assert_eq!(Format::AsciiDoc.postprocess(String::from(page)), page);
+        assert_eq!(
+            Format::Rst.postprocess(String::from("<p>Hi</p>")),
+            "<p>Hi</p>"
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! YAML front matter at the start of markdown documents.
+
+use handlebars::html_escape;
+use log::*;
+use serde::{Deserialize, Deserializer};
+use serde_yaml::{Mapping, Value};
+
+use crate::export::pdf::PdfLayout;
+
+/// Metadata read from a document's front matter.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct FrontMatter {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    #[serde(alias = "lang")]
+    pub language: Option<String>,
+    pub date: Option<String>,
+    pub description: Option<String>,
+    pub identifier: Option<String>,
+
+    /// Stylesheets that replace the server's custom CSS for this document.
+    #[serde(deserialize_with = "one_or_many")]
+    pub css: Vec<String>,
+    /// Whether math is typeset in this document, or the library used to typeset it.
+    pub math: Option<MathSetting>,
+    pub highlight_theme: Option<String>,
+    /// The layout of the document's pages when it's printed or exported as a PDF.
+    pub pdf: PdfLayout,
+}
+
+/// The value of the `math` key, which may either toggle math or name a math library.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum MathSetting {
+    Enabled(bool),
+    Renderer(String),
+}
+
+impl FrontMatter {
+    /// Parses the front matter of a markdown document.
+    ///
+    /// Returns `None` if the document has no front matter. Front matter that isn't valid YAML is
+    /// logged and ignored.
+    pub fn parse(markdown: &str) -> Option<FrontMatter> {
+        let (yaml, _) = split(markdown)?;
+
+        match serde_yaml::from_str(yaml) {
+            Ok(front_matter) => Some(front_matter),
+            Err(e) => {
+                warn!("invalid front matter: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Deserializes either a single string or a list of strings.
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+/// Renders YAML front matter as an HTML table with a row for each key.
+///
+/// Returns `None` if the front matter isn't a valid YAML mapping.
+pub fn html_table(yaml: &str) -> Option<String> {
+    match serde_yaml::from_str(yaml) {
+        Ok(Value::Mapping(mapping)) => {
+            let mut html = String::from("<table class=\"front-matter\">\n");
+            push_rows(&mut html, &mapping);
+            html.push_str("</table>\n");
+            Some(html)
+        }
+        Ok(_) => None,
+        Err(e) => {
+            warn!("invalid front matter: {}", e);
+            None
+        }
+    }
+}
+
+fn push_rows(html: &mut String, mapping: &Mapping) {
+    html.push_str("<tbody>\n");
+    for (key, value) in mapping {
+        html.push_str("<tr><th>");
+        push_value(html, key);
+        html.push_str("</th><td>");
+        push_value(html, value);
+        html.push_str("</td></tr>\n");
+    }
+    html.push_str("</tbody>\n");
+}
+
+fn push_value(html: &mut String, value: &Value) {
+    match value {
+        Value::Null => (),
+        Value::Bool(b) => html.push_str(&b.to_string()),
+        Value::Number(n) => html.push_str(&n.to_string()),
+        Value::String(s) => html.push_str(&html_escape(s)),
+        Value::Sequence(values) => {
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    html.push_str(", ");
+                }
+                push_value(html, value);
+            }
+        }
+        Value::Mapping(mapping) => {
+            html.push_str("<table>\n");
+            push_rows(html, mapping);
+            html.push_str("</table>");
+        }
+    }
+}
+
+/// Splits a markdown document into its front matter and the remaining markdown.
+///
+/// Front matter must begin on the first line with `---`, and end with a line containing either
+/// `---` or `...`.
+pub fn split(markdown: &str) -> Option<(&str, &str)> {
+    let mut lines = markdown.split_inclusive('\n');
+
+    if lines.next()?.trim_end() != "---" {
+        return None;
+    }
+
+    let start = markdown.find('\n')? + 1;
+    let mut end = start;
+
+    for line in lines {
+        if matches!(line.trim_end(), "---" | "...") {
+            return Some((&markdown[start..end], &markdown[end + line.len()..]));
+        }
+        end += line.len();
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{html_table, split, FrontMatter, MathSetting};
+
+    #[test]
+    fn split_front_matter() {
+        assert_eq!(
+            split("---\ntitle: Hello\n---\n# Body\n"),
+            Some(("title: Hello\n", "# Body\n"))
+        );
+        assert_eq!(
+            split("---\r\ntitle: Hello\r\n...\r\n"),
+            Some(("title: Hello\r\n", ""))
+        );
+        assert_eq!(split("# Body\n---\n"), None);
+        assert_eq!(split("---\nunterminated\n"), None);
+    }
+
+    #[test]
+    fn parse_front_matter() {
+        assert_eq!(
+            FrontMatter::parse("---\ntitle: Hello\nlang: fr\n---\n"),
+            Some(FrontMatter {
+                title: Some(String::from("Hello")),
+                language: Some(String::from("fr")),
+                ..Default::default()
+            })
+        );
+        assert_eq!(FrontMatter::parse("---\n[invalid\n---\n"), None);
+    }
+
+    #[test]
+    fn parse_settings() {
+        assert_eq!(
+            FrontMatter::parse("---\ncss: style.css\nmath: false\nhighlight_theme: nord\n---\n"),
+            Some(FrontMatter {
+                css: vec![String::from("style.css")],
+                math: Some(MathSetting::Enabled(false)),
+                highlight_theme: Some(String::from("nord")),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            FrontMatter::parse("---\ncss: [a.css, b.css]\nmath: mathjax\n---\n"),
+            Some(FrontMatter {
+                css: vec![String::from("a.css"), String::from("b.css")],
+                math: Some(MathSetting::Renderer(String::from("mathjax"))),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn front_matter_table() {
+        assert_eq!(
+            html_table("title: <Hello>\ntags: [a, b]\n").as_deref(),
+            Some(
+                "<table class=\"front-matter\">\n<tbody>\n\
+                 <tr><th>title</th><td>&lt;Hello&gt;</td></tr>\n\
+                 <tr><th>tags</th><td>a, b</td></tr>\n\
+                 </tbody>\n</table>\n"
+            )
+        );
+        assert_eq!(html_table("- a\n"), None);
+    }
+}
@@ -0,0 +1,158 @@
+//! Comparison of documents with the version of their file that was last committed to git.
+
+use std::ffi::OsString;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use log::*;
+
+/// Reads the contents of a file at `HEAD` of the git repository that contains it.
+///
+/// # Errors
+///
+/// This function fails if git can't be run, if the file isn't in a repository, or if the file
+/// isn't in the commit at `HEAD`, such as when it's new.
+pub fn head_contents(path: &Path) -> io::Result<String> {
+    let (dir, name) = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => (dir, name),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("not a file: {}", path.display()),
+            ))
+        }
+    };
+
+    // A path starting with `./` is relative to the working directory of git, rather than to the
+    // root of the repository.
+    let mut object = OsString::from("HEAD:./");
+    object.push(name);
+
+    let mut command = Command::new("git");
+    if !dir.as_os_str().is_empty() {
+        command.current_dir(dir);
+    }
+    let output = command
+        .arg("show")
+        .arg(object)
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+
+    String::from_utf8(output.stdout).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Returns the (one-based, inclusive) ranges of lines of `new` that were added or modified since
+/// `old`.
+pub fn changed_lines(old: &str, new: &str) -> Vec<(usize, usize)> {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+
+    // Edits are usually close together, so the lines that they share at the start and end are
+    // skipped before the rest are compared.
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for line in added_lines(old_middle, new_middle) {
+        let line = prefix + line + 1;
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == line => *end = line,
+            _ => ranges.push((line, line)),
+        }
+    }
+    ranges
+}
+
+/// The largest number of edits that are searched for before every line is considered added.
+const MAX_EDITS: usize = 500;
+
+/// Returns the (zero-based) indices of the lines of `new` that aren't in the longest common
+/// subsequence of `old` and `new`, as found by Myers' diff algorithm.
+fn added_lines(old: &[&str], new: &[&str]) -> Vec<usize> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = (n + m).min(MAX_EDITS as isize);
+    let offset = max + 1;
+
+    // The furthest x reached on each diagonal k = x - y, after each number of edits.
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    let mut trace = vec![];
+    let mut found = false;
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let index = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+                v[index + 1]
+            } else {
+                v[index - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index] = x;
+            if x >= n && y >= m {
+                found = true;
+                break 'search;
+            }
+        }
+    }
+
+    if !found {
+        debug!("too many differences to compare, marking every line as changed");
+        return (0..new.len()).collect();
+    }
+
+    // Walk back through the edits, collecting the lines that were inserted.
+    let mut added = vec![];
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().skip(1).rev() {
+        let d = d as isize;
+        let k = x - y;
+        let index = (k + offset) as usize;
+        let inserted = k == -d || (k != d && v[index - 1] < v[index + 1]);
+        let previous_k = if inserted { k + 1 } else { k - 1 };
+        let previous_x = v[(previous_k + offset) as usize];
+        let previous_y = previous_x - previous_k;
+
+        // Inserting a line moves down from the previous edit, and deleting one moves right.
+        if inserted {
+            added.push(previous_y as usize);
+        }
+        x = previous_x;
+        y = previous_y;
+    }
+    added.reverse();
+    added
+}
+
+#[cfg(test)]
+mod tests {
+    use super::changed_lines;
+
+    #[test]
+    fn changes() {
+        let old = "# Title\n\nOne\nTwo\nThree\n\nEnd\n";
+        assert_eq!(changed_lines(old, old), []);
+        assert_eq!(
+            changed_lines(old, "# Title\n\nOne\n2\nThree\nFour\n\nEnd\nMore\n"),
+            [(4, 4), (6, 6), (9, 9)]
+        );
+        assert_eq!(changed_lines(old, "Intro\n\n# Title\n\nTwo\n"), [(1, 2)]);
+        assert_eq!(changed_lines("", "a\nb\n"), [(1, 2)]);
+    }
+}
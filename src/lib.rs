@@ -0,0 +1,112 @@
+//! Markdown previews that update live as the markdown is edited.
+//!
+//! The [`Composer`] embeds the whole preview in another program: it wraps a [`server::Server`]
+//! that serves the preview page, and handles the procedures that editors call over [`rpc`]. The
+//! `markdown_composer` binary is a thin layer over it that reads RPCs from an editor.
+//!
+//! Markdown may also be rendered to HTML without a server with [`render()`].
+
+use std::io;
+use std::process::Command;
+
+use crate::diagram::Diagrams;
+use crate::external::ExternalRenderer;
+use crate::format::Format;
+use crate::render::RenderOptions;
+
+pub mod bench;
+pub mod browser;
+pub mod check;
+pub mod csv;
+pub mod diagram;
+pub mod export;
+pub mod external;
+pub mod format;
+pub mod front_matter;
+pub mod git;
+pub mod lint;
+pub mod man;
+pub mod org;
+pub mod qr;
+pub mod render;
+pub mod rpc;
+pub mod sanitize;
+pub mod search;
+pub mod server;
+pub mod session;
+pub mod spell;
+pub mod stats;
+
+mod composer;
+
+pub use crate::composer::{parse_command, Composer};
+
+/// Options for [`render()`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Options for the built-in renderer, such as the markdown extensions that are enabled.
+    pub render: RenderOptions,
+
+    /// A program that renders the markdown instead of the built-in renderer, followed by its
+    /// arguments. The program reads markdown on stdin and prints HTML on stdout.
+    pub external_renderer: Option<Vec<String>>,
+
+    /// The format of the document. Documents of formats other than markdown are rendered by
+    /// their format's pipeline, ignoring the other options.
+    pub format: Format,
+}
+
+/// Renders markdown to the HTML that the preview displays, without starting a server.
+///
+/// The HTML is the same for the same markdown and options. Code blocks are marked with their
+/// language for the preview to highlight, and diagrams are left as code blocks.
+///
+/// # Errors
+///
+/// This function fails if the external renderer, if any, or the pipeline of the document's format
+/// can't be run.
+pub fn render(markdown: &str, options: &Options) -> io::Result<String> {
+    if let Some(html) = options.format.render(markdown) {
+        return Ok(options.render.sanitize_html(html));
+    }
+    if let Some(mut renderer) = options.format.renderer() {
+        let html = options.format.postprocess(renderer.render(markdown)?);
+        return Ok(options.render.sanitize_html(html));
+    }
+
+    match &options.external_renderer {
+        Some(words) => {
+            let (program, args) = words
+                .split_first()
+                .ok_or_else(|| io::Error::other("external renderer was empty"))?;
+            let mut command = Command::new(program);
+            command.args(args);
+            let html = ExternalRenderer::new(command).render(markdown)?;
+            Ok(options.render.sanitize_html(html))
+        }
+        None => Ok(render::markdown_to_html(
+            markdown,
+            &options.render,
+            &mut Diagrams::default(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, Options};
+
+    #[test]
+    fn render_markdown() {
+        let options = Options::default();
+        let html = render("*Hello*", &options).unwrap();
+        assert_eq!(html, render("*Hello*", &options).unwrap());
+        assert!(html.contains("<em>Hello</em>"));
+
+        let options = Options {
+            external_renderer: Some(vec![String::from("cat")]),
+            ..Options::default()
+        };
+        assert_eq!(render("*Hello*", &options).unwrap(), "*Hello*");
+    }
+}
@@ -0,0 +1,163 @@
+//! Diagnostics from an external markdown linter, such as markdownlint or vale.
+//!
+//! The linter reads the markdown on stdin and reports each problem on its own line of stdout or
+//! stderr, starting with the name of the file and the line of the problem, such as
+//! `stdin:3:10 MD009/no-trailing-spaces Trailing spaces` or vale's
+//! `stdin.md:3:5:Vale.Spelling:Did you really mean 'wrold'?`. Other lines are ignored, as is the
+//! exit status of the linter, which usually fails when there are problems.
+
+use std::io::{self, prelude::*};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use serde::Serialize;
+
+/// A problem reported by the linter.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    /// The (one-based) line of the markdown that the problem is on.
+    pub line: usize,
+
+    /// The (one-based) column of the problem within its line, if the linter reported one.
+    pub column: Option<usize>,
+
+    pub message: String,
+}
+
+/// Runs a linter on markdown.
+#[derive(Debug)]
+pub struct Linter {
+    command: Command,
+}
+
+impl Linter {
+    /// Creates a linter that runs a command, such as `markdownlint --stdin`.
+    pub fn new(mut command: Command) -> Self {
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        Linter { command }
+    }
+
+    /// Runs the linter on markdown, returning its diagnostics ordered by line.
+    pub fn lint(&mut self, markdown: &str) -> io::Result<Vec<Diagnostic>> {
+        let mut child = self.command.spawn()?;
+
+        // The markdown is written from another thread, so that a linter that reports problems
+        // before reading all of its input doesn't block on a full pipe.
+        let mut stdin = child.stdin.take().unwrap();
+        let markdown = markdown.to_owned();
+        let writer = thread::spawn(move || stdin.write_all(markdown.as_bytes()));
+
+        let output = child.wait_with_output()?;
+        if let Err(e) = writer.join().unwrap() {
+            // Linters may exit without reading their input, such as when they're misconfigured.
+            if e.kind() != io::ErrorKind::BrokenPipe {
+                return Err(e);
+            }
+        }
+
+        let mut diagnostics = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .chain(String::from_utf8_lossy(&output.stderr).lines())
+            .filter_map(parse_diagnostic)
+            .collect::<Vec<_>>();
+        diagnostics.sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+        Ok(diagnostics)
+    }
+}
+
+/// Parses a line of the linter's output, such as `stdin:3:10 MD009 Trailing spaces`.
+fn parse_diagnostic(output: &str) -> Option<Diagnostic> {
+    // The file name may contain colons, such as on Windows, so the line is the first field after
+    // a colon that is a number.
+    let (line, rest) = output.match_indices(':').find_map(|(i, _)| {
+        let (number, rest) = leading_number(&output[i + 1..])?;
+        match rest.chars().next() {
+            None | Some(':') | Some(' ') => Some((number, rest)),
+            _ => None,
+        }
+    })?;
+
+    let (column, rest) = match rest.strip_prefix(':').and_then(leading_number) {
+        Some((column, rest)) if !rest.starts_with(|c: char| c != ':' && c != ' ') => {
+            (Some(column), rest)
+        }
+        _ => (None, rest),
+    };
+
+    let message = rest.trim_start_matches([':', ' ']).trim_end();
+    if message.is_empty() {
+        return None;
+    }
+
+    Some(Diagnostic {
+        line,
+        column,
+        message: message.to_owned(),
+    })
+}
+
+/// Splits the leading decimal number from a string.
+fn leading_number(s: &str) -> Option<(usize, &str)> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    Some((s[..end].parse().ok()?, &s[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::{parse_diagnostic, Diagnostic, Linter};
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            parse_diagnostic("stdin:3:10 MD009/no-trailing-spaces Trailing spaces"),
+            Some(Diagnostic {
+                line: 3,
+                column: Some(10),
+                message: String::from("MD009/no-trailing-spaces Trailing spaces"),
+            })
+        );
+        assert_eq!(
+            parse_diagnostic("C:\\notes\\a.md:1:5:Vale.Spelling:Did you really mean 'wrold'?"),
+            Some(Diagnostic {
+                line: 1,
+                column: Some(5),
+                message: String::from("Vale.Spelling:Did you really mean 'wrold'?"),
+            })
+        );
+        assert_eq!(
+            parse_diagnostic("stdin:1 MD041 First line should be a heading"),
+            Some(Diagnostic {
+                line: 1,
+                column: None,
+                message: String::from("MD041 First line should be a heading"),
+            })
+        );
+        assert_eq!(parse_diagnostic("Found 2 problems"), None);
+        assert_eq!(parse_diagnostic("stdin:2:"), None);
+    }
+
+    #[test]
+    fn lint() {
+        let mut command = Command::new("sh");
+        command.args([
+            "-c",
+            "grep -n TODO | sed 's/^\\([0-9]*\\):.*/stdin:\\1 unfinished/' >&2; exit 1",
+        ]);
+        let mut linter = Linter::new(command);
+
+        let diagnostics = linter.lint("# Title\n\nTODO\n").unwrap();
+        assert_eq!(
+            diagnostics,
+            [Diagnostic {
+                line: 3,
+                column: None,
+                message: String::from("unfinished"),
+            }]
+        );
+    }
+}
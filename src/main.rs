@@ -4,157 +4,698 @@
 //! in a browser. As new messages are received through stdin, the markdown is asynchronously
 //! rendered in the browser (no refresh is required).
 
+use std::collections::HashMap;
 use std::default::Default;
+use std::env;
 use std::fs;
 use std::io;
 use std::io::prelude::*;
+use std::io::IsTerminal;
 use std::mem;
-use std::process::Command;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
-use clap::{crate_authors, crate_version};
+use anyhow::{anyhow, Result};
+use clap::{crate_authors, crate_version, value_t};
 use log::*;
 
-use aurelius::Server;
-use clap::{App, Arg};
-use serde::Deserialize;
+use clap::{App, AppSettings, Arg, ArgGroup, ArgMatches, Shell, SubCommand};
+use log4rs::append::console::{ConsoleAppender, Target};
+use log4rs::append::file::FileAppender;
+use log4rs::append::rolling_file::policy::compound::roll::delete::DeleteRoller;
+use log4rs::append::rolling_file::policy::compound::roll::fixed_window::FixedWindowRoller;
+use log4rs::append::rolling_file::policy::compound::roll::Roll;
+use log4rs::append::rolling_file::policy::compound::trigger::size::SizeTrigger;
+use log4rs::append::rolling_file::policy::compound::CompoundPolicy;
+use log4rs::append::rolling_file::RollingFileAppender;
+use log4rs::append::Append;
+use log4rs::config::{Appender, Root};
+use log4rs::encode::json::JsonEncoder;
+use log4rs::encode::pattern::PatternEncoder;
+use log4rs::encode::Encode;
+use serde::Serialize;
 use shlex::Shlex;
 
-static ABOUT: &str = r"
-Creates a static server for serving markdown previews. Reads RPC requests from stdin.
+use markdown_composer::bench;
+use markdown_composer::browser::{app_browser, display_available};
+use markdown_composer::diagram::PlantUml;
+use markdown_composer::export::pdf::PdfLayout;
+use markdown_composer::external::{Encoding, ExternalRenderer, Framing};
+use markdown_composer::format::Format;
+use markdown_composer::lint::Linter;
+use markdown_composer::man;
+use markdown_composer::qr::QrCode;
+use markdown_composer::render::{self, Flavor, FrontMatterDisplay, SlugStyle, WikiLinkCase};
+use markdown_composer::rpc::{self, Rpc};
+use markdown_composer::sanitize::Sanitizer;
+use markdown_composer::server::{
+    self, ColorScheme, ContentSecurityPolicy, MathRenderer, PreviewRequest, Server, TitleSource,
+    Typography,
+};
+use markdown_composer::session::{self, Session, SessionFile};
+use markdown_composer::spell::SpellChecker;
+use markdown_composer::{parse_command, Composer};
+
+static ABOUT: &str = r#"
+Creates a static server for serving markdown previews. Reads RPC requests from stdin, or from
+editors connected to `--rpc-socket` or `--rpc-listen`. With `--stdin-format markdown`, stdin is
+rendered as markdown instead.
 
 Supported procedures:
 
     send_data(data: String)     Pushes a markdown string to the rendering server.
-    open_browser()              Opens the user default browser, or the browser specified by
-                                `--browser`.
+    send_data_as(filetype: String, data: String)
+                                Pushes a document of a filetype, such as `rst`, to be rendered
+                                with the renderer set for it by `--external-renderer-for`, or
+                                else as the format of the filetype, such as `asciidoc` or `adoc`
+                                for AsciiDoc, `rst` for reStructuredText, `org` for Org and
+                                `csv` or `tsv` for tables. Later pushes keep the filetype until
+                                another filetype or a file is pushed.
+    send_diff(first_line: String, last_line: String, lines: String)
+                                Replaces lines of the markdown that was last pushed, from
+                                `first_line` up to (but not including) `last_line`, and pushes
+                                the result. Lines are numbered from zero, and each new line in
+                                `lines` ends with a newline. Fails if the lines are outside of
+                                the markdown, in which case the editor should push the whole
+                                document again.
+    render_file(path: String)   Reads a markdown file and pushes it to the rendering server.
+                                Relative paths are resolved against the static root. Files of
+                                other formats, such as `.csv`, are rendered as the format of
+                                their extension.
+    open_browser(anchor: String) -> String | null
+                                Opens the user default browser, or the browser specified by
+                                `--browser`. The anchor is optional, and starts with `#`, such as
+                                `#installation`, to open the preview scrolled to the element with
+                                that id once it's rendered. If a preview is already open, it's
+                                brought to the front and scrolled to the anchor instead, unless
+                                `--force` is passed as a parameter too. Returns null, or the URL
+                                of the preview if there is no display to open the default browser
+                                on, such as over SSH.
+    close_browser()             Closes any open previews.
+    print_preview() -> bool     Opens the browser's print dialog in the open previews, after
+                                rendering any pending markdown, returning whether a preview was
+                                open. Printed previews use a print stylesheet, which hides the
+                                controls of the preview and starts each top-level section on a new
+                                page.
+    close_buffer(id: String)    Stops previewing a buffer. Its previews are notified that the
+                                preview has ended.
     chdir(path: String)         Changes the directory that the server serves static files from.
-";
+                                The directory must exist. If it doesn't, the procedure fails and
+                                the previous directory is kept.
+    set_title(title: String)    Sets the title of the preview. If the title is empty, the title
+                                is derived from `--title-source` instead.
+    set_git_path(path: String)  Sets the file in a git repository that the markdown is read from,
+                                so that the blocks that were added or modified since the file was
+                                committed at `HEAD` are highlighted. An empty path stops
+                                highlighting.
+    shutdown()                  Notifies open previews that the preview has ended, then exits.
+    scroll_to_line(line: String)
+                                Notifies the preview that the cursor moved to a line of the
+                                markdown. If auto-scroll is enabled, the preview will follow it.
+    toggle_auto_scroll() -> bool
+                                Toggles auto-scroll, returning whether it is now enabled.
+    toggle_toc() -> bool        Toggles the table of contents sidebar, returning whether it is now
+                                shown.
+    set_zoom(percent: String) -> u64
+                                Scales the preview by a percentage, such as `150`, or changes the
+                                current zoom by a signed amount, such as `+10` or `-10`, returning
+                                the new zoom. The zoom is between 25 and 500 percent. The base
+                                font size may be changed with `set_typography` instead.
+    toggle_focus() -> bool      Toggles focus mode, which dims everything but the block at the
+                                editor's cursor, returning whether it is now enabled.
+    toggle_render_timings() -> bool
+                                Toggles an overlay in the preview that shows how long each update
+                                took to render, send and display, returning whether it is now
+                                shown.
+    toggle_presentation() -> bool
+                                Toggles presentation mode, returning whether it is now enabled.
+    toggle_side_by_side() -> bool
+                                Toggles the display of the markdown beside the preview, returning
+                                whether it is now displayed.
+    toggle_spell_check() -> bool
+                                Toggles the marking of misspelled words, returning whether it is
+                                now enabled.
+    set_color_scheme(scheme: String)
+                                Sets the color scheme of the preview, either `light`, `dark`, or
+                                `auto`.
+    set_typography(property: String, value: String)
+                                Sets `font_family`, `code_font_family`, `font_size`, or
+                                `max_width` of the preview. An empty value resets the property.
+    set_flavor(flavor: String)  Resets the markdown extensions to the defaults of a flavor, either
+                                `gfm` or `commonmark`.
+    enable_extension(name: String)
+                                Enables a markdown extension. See `--enable` for the extensions.
+    disable_extension(name: String)
+                                Disables a markdown extension.
+    export_html(path: String)   Writes the preview to a file as a self-contained HTML page.
+                                Relative paths are resolved against the static root.
+    export_epub(path: String)   Writes the preview to a file as an EPUB. Metadata is taken from
+                                the document's front matter. Relative paths are resolved against
+                                the static root.
+    export_pdf(path: String)    Prints the preview to a PDF file using the browser specified by
+                                `--pdf-browser`, laid out as specified by the `--pdf-*` options or
+                                the `pdf` key of the front matter. Relative paths are resolved
+                                against the static root.
+    get_url() -> String         Returns the URL of the preview.
+    check_links() -> Array      Returns the relative links and images of the markdown that don't
+                                resolve under the static root, and any malformed URLs, as objects
+                                with the (one-based) `line` and byte `column` where the link
+                                starts, the `url`, and a `message`.
+    stats() -> Object           Returns the length of the preview's text as an object with the
+                                number of `words` and `characters`, and the
+                                `reading_time_minutes`. Code blocks and math aren't counted.
+    status() -> Object          Returns the version and uptime of the server, the number of
+                                connected previews, when the preview was last rendered and how
+                                long it took, and the current settings. The same status is served
+                                at `/healthz`. The last render has the milliseconds that parsing
+                                (`parse_ms`), an external renderer (`external_renderer_ms`) and
+                                the rest of rendering (`postprocess_ms`) took, and how long after
+                                rendering the slowest preview was sent the update
+                                (`broadcast_ms`). If render timings are shown, it also has how
+                                long the slowest preview took to display and highlight the update
+                                (`highlight_ms`).
+    get_port() -> u16           Returns the port that the server is listening on, or null if it is
+                                only listening on a unix domain socket.
+
+Each buffer of an editor may be previewed on its own page at `/buffer/<id>/`, served alongside the
+main document, by passing the ID of the buffer as the first parameter of `send_data`,
+`send_data_as`, `send_diff`, `render_file`, `scroll_to_line`, `open_browser`, `get_url`,
+`check_links`, `stats`, `set_git_path` and `print_preview`, such as
+`send_data(id: String, data: String)`. IDs may contain ASCII letters, digits, `-` and `_`. A tab
+bar on each preview links to the other documents, which are also listed at `/buffer/`.
+
+Other clients may push markdown to the preview with `POST /api/render`, whose body is either the
+markdown or a JSON object such as `{"content": "*Hello*", "path": "hello.md"}`.
+
+`GET /api/search?q=<text>` searches the text of every rendered document, ignoring case, and returns
+the documents that match as an array of objects with the `path` and `title` of the document and its
+`matches`, each with the `line` of the markdown that the match was rendered from and a `snippet`
+of the text around it. The search box of the preview lists the same matches.
+
+`GET /api/qr?path=<path>` returns an SVG image of a QR code of the URL of a page, such as
+`/buffer/<id>/`, on the network, so that it may be opened on a phone or tablet. It's only available
+when the server listens on an address other devices can reach, such as `0.0.0.0`, and is shown by
+the Phone button of the preview's toolbar. The URLs on the network are also printed on startup.
+
+Notifications are sent to the editor by asking it to call `MarkdownComposerNotify(method, params)`:
+
+    listening(url: String, urls: Array, lan_urls: Array)
+                                The server is listening, at the URL of the preview, on every
+                                address in `urls`, and on the network at `lan_urls`. Only sent,
+                                before anything else on stdout, with `--print-url`.
+    toggle_task(line: u64, buffer: String | null)
+                                The user clicked the checkbox of the task list item on a line of
+                                the markdown, or of a buffer that is previewed separately.
+    diagnostics(diagnostics: Array, buffer: String | null)
+                                The linter set by `--linter` finished linting the markdown, or a
+                                buffer that is previewed separately. Each diagnostic is an object
+                                with the (one-based) `line`, the `column` or null, and a
+                                `message`.
+    error(message: String)      A procedure that the editor called failed, such as rendering a file
+                                that can't be read. Requests over msgpack-rpc return the error
+                                instead. The error is also shown in the preview.
+"#;
+
+/// Identifies an editor that is sending RPCs.
+type EditorId = usize;
 
-/// Represents an RPC request.
+/// The editor that owns the process's stdin and stdout.
+const STDIO_EDITOR: EditorId = 0;
+
+/// The ID of the next editor to connect to the RPC socket.
+static NEXT_EDITOR: AtomicUsize = AtomicUsize::new(STDIO_EDITOR + 1);
+
+/// Whether a signal to exit is handled by shutting down the server gracefully, rather than by
+/// exiting immediately, such as while exporting.
+static GRACEFUL_EXIT: AtomicBool = AtomicBool::new(false);
+
+/// Notifies a sender when the process is asked to exit by a signal, such as when the terminal is
+/// closed, so that previews can be told that the preview has ended.
 ///
-/// Assumes that the request's parameters are always `String`s.
-#[derive(Debug)]
-pub struct Rpc {
-    /// The type of msgpack request. Should always be notification.
-    #[cfg(feature = "msgpack")]
-    msg_type: u64,
+/// The signals are blocked and waited for by a thread, so this must be called before any other
+/// thread is spawned, which inherit the blocked signals. Until [`GRACEFUL_EXIT`] is set, and if
+/// a second signal is received, the process exits immediately.
+#[cfg(unix)]
+fn notify_exit_signals(tx: mpsc::Sender<()>) -> Result<()> {
+    let signals = unsafe {
+        let mut signals = mem::zeroed::<libc::sigset_t>();
+        libc::sigemptyset(&mut signals);
+        for &signal in &[libc::SIGHUP, libc::SIGINT, libc::SIGTERM] {
+            libc::sigaddset(&mut signals, signal);
+        }
+        let res = libc::pthread_sigmask(libc::SIG_BLOCK, &signals, std::ptr::null_mut());
+        if res != 0 {
+            return Err(io::Error::from_raw_os_error(res).into());
+        }
+        signals
+    };
 
-    /// The ID of the JSON rpc request.
-    #[cfg(feature = "json-rpc")]
-    id: u64,
+    thread::spawn(move || {
+        let mut exiting = false;
+        loop {
+            let mut signal = 0;
+            if unsafe { libc::sigwait(&signals, &mut signal) } != 0 {
+                continue;
+            }
 
-    pub method: String,
-    pub params: Vec<String>,
+            if exiting || !GRACEFUL_EXIT.load(Ordering::SeqCst) || tx.send(()).is_err() {
+                process::exit(128 + signal);
+            }
+            info!("received signal {}, shutting down", signal);
+            exiting = true;
+        }
+    });
+
+    Ok(())
 }
 
-#[cfg(feature = "msgpack")]
-impl<'de> Deserialize<'de> for Rpc {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        use serde::de::{Error, Unexpected};
+/// Notifies a sender when the console is closed, or Ctrl+C is pressed, so that previews can be
+/// told that the preview has ended.
+///
+/// Windows ends the process once the handler returns from a console that is closed, or after a
+/// few seconds, so the handler waits for the server to shut down in the meantime. Until
+/// [`GRACEFUL_EXIT`] is set, the process exits immediately.
+#[cfg(windows)]
+fn notify_exit_signals(tx: mpsc::Sender<()>) -> Result<()> {
+    use std::sync::Mutex;
 
-        const NOTIFICATION_MESSAGE_TYPE: u64 = 2;
+    use winapi::shared::minwindef::{BOOL, DWORD, FALSE, TRUE};
+    use winapi::um::consoleapi::SetConsoleCtrlHandler;
+    use winapi::um::wincon::{CTRL_CLOSE_EVENT, CTRL_LOGOFF_EVENT, CTRL_SHUTDOWN_EVENT};
 
-        let (msg_type, method, params) = <(u64, String, Vec<String>)>::deserialize(deserializer)?;
+    static EXIT_TX: Mutex<Option<mpsc::Sender<()>>> = Mutex::new(None);
 
-        debug!("<- [{}, {}, {:?}]", msg_type, method, params);
+    unsafe extern "system" fn handler(event: DWORD) -> BOOL {
+        // The default handler exits the process.
+        let tx = EXIT_TX.lock().unwrap().take();
+        let notified =
+            GRACEFUL_EXIT.load(Ordering::SeqCst) && tx.is_some_and(|tx| tx.send(()).is_ok());
+        if !notified {
+            return FALSE;
+        }
 
-        if msg_type != NOTIFICATION_MESSAGE_TYPE {
-            return Err(Error::invalid_value(
-                Unexpected::Unsigned(msg_type),
-                &format!("notification message type ({})", NOTIFICATION_MESSAGE_TYPE).as_str(),
-            ));
+        info!("received console event {}, shutting down", event);
+        if [CTRL_CLOSE_EVENT, CTRL_LOGOFF_EVENT, CTRL_SHUTDOWN_EVENT].contains(&event) {
+            thread::sleep(Duration::from_secs(5));
         }
+        TRUE
+    }
 
-        Ok(Rpc {
-            msg_type,
-            method,
-            params,
-        })
+    *EXIT_TX.lock().unwrap() = Some(tx);
+    if unsafe { SetConsoleCtrlHandler(Some(handler), TRUE) } == 0 {
+        return Err(io::Error::last_os_error().into());
     }
+
+    Ok(())
 }
 
-// FIXME: Workaround for rust-lang/rust#55779. Move back to the impl when fixed.
-#[derive(Debug, Deserialize)]
-#[allow(unused)]
-struct InnerRpc {
-    method: String,
-    params: Vec<String>,
+#[cfg(not(any(unix, windows)))]
+fn notify_exit_signals(tx: mpsc::Sender<()>) -> Result<()> {
+    // The process is exited by the default handler of Ctrl+C, so the sender is never notified.
+    mem::forget(tx);
+    Ok(())
 }
 
-#[cfg(feature = "json-rpc")]
-impl<'de> Deserialize<'de> for Rpc {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        let (id, rpc): (u64, InnerRpc) = Deserialize::deserialize(deserializer)?;
+/// How often whether the parent process is running is checked.
+#[cfg(unix)]
+const PARENT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Notifies a sender when the process with an ID, such as the editor that started the composer,
+/// exits, so that a server isn't left behind if the editor crashes without closing stdin. Until
+/// [`GRACEFUL_EXIT`] is set, the process exits immediately.
+#[cfg(unix)]
+fn notify_parent_exit(pid: u32, tx: mpsc::Sender<()>) -> Result<()> {
+    use std::convert::TryFrom;
+
+    let pid = libc::pid_t::try_from(pid).map_err(|_| anyhow!("invalid process ID {}", pid))?;
+
+    // The ID of a process that exited may be reused, but a child is adopted by another process
+    // when its parent exits, so the parent is compared instead if it's the given process.
+    let is_parent = unsafe { libc::getppid() } == pid;
+    let running = move || {
+        if is_parent {
+            return unsafe { libc::getppid() } == pid;
+        }
+        let res = unsafe { libc::kill(pid, 0) };
+        res == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    };
 
-        debug!("<- [{}, {:?}]", id, rpc);
+    if !running() {
+        return Err(anyhow!("no process with ID {} is running", pid));
+    }
+
+    thread::spawn(move || {
+        while running() {
+            thread::sleep(PARENT_INTERVAL);
+        }
+
+        info!("parent process {} exited, shutting down", pid);
+        if !GRACEFUL_EXIT.load(Ordering::SeqCst) || tx.send(()).is_err() {
+            process::exit(1);
+        }
+    });
+
+    Ok(())
+}
 
-        Ok(Rpc {
-            id: id,
-            method: rpc.method,
-            params: rpc.params,
-        })
+#[cfg(windows)]
+fn notify_parent_exit(pid: u32, tx: mpsc::Sender<()>) -> Result<()> {
+    use winapi::shared::minwindef::FALSE;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::OpenProcess;
+    use winapi::um::synchapi::WaitForSingleObject;
+    use winapi::um::winbase::INFINITE;
+    use winapi::um::winnt::SYNCHRONIZE;
+
+    // The handle keeps the ID from being reused until it's closed.
+    let process = unsafe { OpenProcess(SYNCHRONIZE, FALSE, pid) };
+    if process.is_null() {
+        return Err(anyhow!("no process with ID {} is running", pid));
     }
+    // Handles aren't `Send`, but may be used from any thread.
+    let handle = process as usize;
+
+    thread::spawn(move || {
+        let handle = handle as winapi::um::winnt::HANDLE;
+        unsafe {
+            WaitForSingleObject(handle, INFINITE);
+            CloseHandle(handle);
+        }
+
+        info!("parent process {} exited, shutting down", pid);
+        if !GRACEFUL_EXIT.load(Ordering::SeqCst) || tx.send(()).is_err() {
+            process::exit(1);
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn notify_parent_exit(pid: u32, _tx: mpsc::Sender<()>) -> Result<()> {
+    Err(anyhow!(
+        "watching process {} is not supported on this platform",
+        pid
+    ))
+}
+
+/// Inputs to the main loop.
+enum Event {
+    /// An editor connected. Responses and notifications are written to the writer.
+    Connect(EditorId, Box<dyn Write + Send>),
+
+    /// An RPC was received from an editor.
+    Rpc(EditorId, Rpc),
+
+    /// An editor hung up.
+    Hangup(EditorId),
+
+    /// Markdown was piped to stdin.
+    Markdown(String),
+
+    /// The user made a request from a preview page.
+    Preview(PreviewRequest),
+
+    /// The process was asked to exit by a signal, or the parent process exited.
+    Exit,
 }
 
-fn read_rpc(reader: impl Read, mut server: Server, browser: Option<&str>) -> Result<()> {
+/// Decodes RPCs from the reader, sending them to the main loop until the editor hangs up.
+fn decode_rpc(reader: impl Read, editor: EditorId, events: mpsc::Sender<Event>) {
     #[cfg(feature = "msgpack")]
-    let mut deserializer = rmp_serde::Deserializer::new(std::io::BufReader::new(reader));
+    let rpcs = rpc::MessagePackDecoder::new(reader);
 
     #[cfg(feature = "json-rpc")]
-    let mut deserializer = serde_json::Deserializer::new(serde_json::de::IoRead::new(reader));
+    let rpcs = serde_json::Deserializer::from_reader(reader).into_iter::<Rpc>();
 
-    loop {
-        let mut rpc = match Rpc::deserialize(&mut deserializer) {
+    for rpc in rpcs {
+        let rpc = match rpc {
             Ok(rpc) => rpc,
-            #[cfg(feature = "msgpack")]
-            Err(rmp_serde::decode::Error::InvalidMarkerRead(_)) => {
-                // In this case, the remote client probably just hung up.
+            Err(err) => {
+                error!("could not decode RPC: {}", err);
                 break;
             }
-            #[cfg(feature = "json-rpc")]
-            Err(err) if err.is_eof() => {
+        };
+
+        if events.send(Event::Rpc(editor, rpc)).is_err() {
+            return;
+        }
+    }
+
+    let _ = events.send(Event::Hangup(editor));
+}
+
+/// Reads markdown piped to stdin, sending the document to the main loop each time more of it is
+/// read. A form feed ends a document, and the markdown that follows replaces it.
+fn decode_markdown(mut reader: impl Read, events: mpsc::Sender<Event>) {
+    const DELIMITER: u8 = b'\x0c';
+
+    let mut document = vec![];
+    let mut buf = [0; 8192];
+
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                error!("could not read markdown: {}", e);
                 break;
             }
-            Err(err) => panic!("{}", err),
         };
 
-        let res = match &rpc.method[..] {
-            "send_data" => {
-                let markdown = mem::replace(&mut rpc.params[0], String::new());
-                server.send(markdown)
+        let mut chunk = &buf[..len];
+        let mut completed = None;
+        while let Some(end) = chunk.iter().position(|&b| b == DELIMITER) {
+            document.extend_from_slice(&chunk[..end]);
+            completed = Some(mem::take(&mut document));
+            chunk = &chunk[end + 1..];
+        }
+        document.extend_from_slice(chunk);
+
+        // Until more of the next document is read, show the one that was just completed.
+        let markdown = match completed {
+            Some(completed) if document.is_empty() => completed,
+            _ => document.clone(),
+        };
+
+        let markdown = String::from_utf8_lossy(&markdown).into_owned();
+        if events.send(Event::Markdown(markdown)).is_err() {
+            return;
+        }
+    }
+
+    // The preview stays open after the markdown ends.
+    info!("stdin closed");
+}
+
+/// Accepts editor connections on a socket, decoding the RPCs of each editor on its own thread.
+fn accept_editors<S>(
+    incoming: impl Iterator<Item = io::Result<S>>,
+    try_clone: fn(&S) -> io::Result<S>,
+    events: mpsc::Sender<Event>,
+) where
+    S: Read + Write + Send + 'static,
+{
+    for conn in incoming {
+        let (reader, writer) = match conn.and_then(|conn| Ok((try_clone(&conn)?, conn))) {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("could not accept editor connection: {}", e);
+                continue;
+            }
+        };
+
+        let editor = NEXT_EDITOR.fetch_add(1, Ordering::SeqCst);
+        info!("editor {} connected", editor);
+
+        if events
+            .send(Event::Connect(editor, Box::new(writer)))
+            .is_err()
+        {
+            return;
+        }
+
+        let events = events.clone();
+        thread::spawn(move || decode_rpc(reader, editor, events));
+    }
+}
+
+/// Handles RPCs from editors until the editor on stdin hangs up, an editor requests shutdown or
+/// the process is asked to exit by a signal. Either way, previews are told that the preview has
+/// ended.
+///
+/// If editors connect to an RPC socket instead, the server keeps running after they hang up so
+/// that they may reconnect.
+///
+/// Markdown sent with `send_data` is rendered once no more has been sent for the debounce window,
+/// so that only the latest content is rendered while the user is typing.
+fn read_rpc(
+    events_tx: mpsc::Sender<Event>,
+    events: mpsc::Receiver<Event>,
+    mut composer: Composer,
+    mut session: Option<SessionFile>,
+) -> Result<()> {
+    let mut editors = HashMap::<EditorId, Box<dyn Write + Send>>::new();
+
+    if let Some(requests) = composer.server_mut().take_requests() {
+        thread::spawn(move || {
+            for request in requests {
+                if events_tx.send(Event::Preview(request)).is_err() {
+                    break;
+                }
             }
-            "open_browser" => match browser {
-                Some(browser) => server.open_specific_browser(Command::new(browser)),
-                None => server.open_browser(),
+        });
+    }
+
+    loop {
+        if let Some(session) = &mut session {
+            session.update(composer.server());
+        }
+
+        let event = match composer.deadline() {
+            Some(instant) => {
+                match events.recv_timeout(instant.saturating_duration_since(Instant::now())) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => {
+                        if let Err(e) = composer.render_pending() {
+                            composer.show_error(&format!("Rendering failed: {}", e));
+                        }
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            None => match events.recv() {
+                Ok(event) => event,
+                Err(_) => break,
             },
-            "chdir" => {
-                let cwd = &rpc.params[0];
-                info!("changing working directory: {}", cwd);
-                server.set_static_root(cwd);
-                Ok(())
+        };
+
+        let (editor, rpc) = match event {
+            Event::Connect(editor, writer) => {
+                editors.insert(editor, writer);
+                continue;
+            }
+            Event::Rpc(editor, rpc) => (editor, rpc),
+            Event::Markdown(markdown) => {
+                if let Err(e) = composer.send(markdown) {
+                    composer.show_error(&format!("Rendering failed: {}", e));
+                }
+                continue;
+            }
+            Event::Hangup(STDIO_EDITOR) | Event::Exit => break,
+            Event::Hangup(editor) => {
+                info!("editor {} hung up", editor);
+                editors.remove(&editor);
+                continue;
+            }
+            Event::Preview(PreviewRequest::ToggleTask { line, buffer }) => {
+                info!("toggling task on line {}", line);
+                notify_editors(&mut editors, "toggle_task", (line, &buffer));
+                continue;
+            }
+            Event::Preview(PreviewRequest::Diagnostics {
+                diagnostics,
+                buffer,
+            }) => {
+                notify_editors(&mut editors, "diagnostics", (&diagnostics, &buffer));
+                continue;
+            }
+            Event::Preview(request) => {
+                composer.handle_request(request);
+                continue;
             }
-            method => panic!("Received unknown command: {}", method),
         };
 
-        // TODO: Return error to the client instead of exiting the process.
-        res?;
+        // Responses are buffered so that an editor that hung up doesn't stop the server.
+        let mut writer = vec![];
+
+        if !composer.call(rpc, &mut writer)? {
+            break;
+        }
+
+        if writer.is_empty() {
+            continue;
+        }
+
+        if let Some(output) = editors.get_mut(&editor) {
+            if let Err(e) = output.write_all(&writer).and_then(|()| output.flush()) {
+                warn!("could not respond to editor {}: {}", editor, e);
+                editors.remove(&editor);
+            }
+        }
     }
 
+    composer.shutdown();
+    logger().flush();
     Ok(())
 }
 
-fn main() -> Result<()> {
-    log_panics::init();
-    log4rs::init_file("config/log.yaml", Default::default()).unwrap();
+/// Sends a notification to every editor, forgetting the editors that hung up.
+fn notify_editors(
+    editors: &mut HashMap<EditorId, Box<dyn Write + Send>>,
+    method: &str,
+    params: impl Serialize,
+) {
+    editors.retain(
+        |editor, writer| match rpc::write_notification(writer, method, &params) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("could not notify editor {}: {}", editor, e);
+                false
+            }
+        },
+    );
+}
 
-    let matches = App::new("markdown_composer")
+/// How often a watched markdown file is checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Renders a markdown file again whenever it's saved, instead of reading RPCs, until the process
+/// is interrupted.
+fn watch_file(mut server: Server, path: &Path, exits: mpsc::Receiver<()>) -> Result<()> {
+    let modified = || {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+
+    info!("watching file: {}", path.display());
+    let mut last_modified = modified();
+
+    loop {
+        if exits.recv_timeout(WATCH_INTERVAL) != Err(RecvTimeoutError::Timeout) {
+            break;
+        }
+
+        // Editors that save by replacing the file may briefly leave it missing.
+        let modified = modified();
+        if modified.is_none() || modified == last_modified {
+            continue;
+        }
+        last_modified = modified;
+
+        if let Err(e) = server.render_file(path) {
+            warn!("could not render {}: {}", path.display(), e);
+        }
+    }
+
+    server.shutdown();
+    Ok(())
+}
+
+/// The name of the binary, which completions and the manual page are generated for.
+const BIN_NAME: &str = env!("CARGO_PKG_NAME");
+
+/// Defines the command-line interface.
+fn app<'a>(extension_names: &'a [&'a str]) -> App<'a, 'a> {
+    App::new("markdown_composer")
         .author(crate_authors!())
         .version(crate_version!())
         .about(ABOUT)
@@ -163,13 +704,258 @@ fn main() -> Result<()> {
                 .long("no-auto-open")
                 .help("Don't open the web browser automatically."),
         )
+        .arg(
+            Arg::with_name("open-on-first-send")
+                .long("open-on-first-send")
+                .conflicts_with("watch")
+                .help(
+                    "Open the web browser once the editor first sends markdown, rather than at \
+                   startup, so that the preview isn't empty while the editor starts.",
+                ),
+        )
+        .arg(
+            Arg::with_name("close-on-shutdown")
+                .long("close-on-shutdown")
+                .help(
+                    "Close the preview tabs that the composer opened when it exits, if the browser \
+                   allows it. Other tabs only show that the preview has ended.",
+                ),
+        )
+        .arg(
+            Arg::with_name("open-to")
+                .long("open-to")
+                .value_name("anchor")
+                .help(
+                    "Open the preview scrolled to the element with an id, such as \"#installation\" \
+                   for the heading \"Installation\".",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no-auto-scroll")
+                .long("no-auto-scroll")
+                .help(
+                    "Don't scroll the preview to follow the editor's cursor. Auto-scroll may be \
+                   toggled at runtime with the `toggle_auto_scroll` procedure.",
+                ),
+        )
+        .arg(
+            Arg::with_name("zoom")
+                .long("zoom")
+                .value_name("percent")
+                .help(
+                    "Scale the preview by a percentage, such as 150. The zoom may be changed at \
+                   runtime with the `set_zoom` procedure.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("focus")
+                .long("focus")
+                .help(
+                    "Dim everything but the block at the editor's cursor, keeping it in the middle \
+                   of the window. Focus mode may be toggled at runtime with the `toggle_focus` \
+                   procedure.",
+                ),
+        )
+        .arg(
+            Arg::with_name("render-timings")
+                .long("render-timings")
+                .help(
+                    "Show how long each update took to render, send and display in an overlay in \
+                   the preview, to find out why the preview lags behind typing. The overlay may \
+                   be toggled at runtime with the `toggle_render_timings` procedure.",
+                ),
+        )
+        .arg(
+            Arg::with_name("toc")
+                .long("toc")
+                .help(
+                    "Show a table of contents sidebar generated from the headings of the preview. \
+                   The sidebar may be toggled at runtime with the `toggle_toc` procedure.",
+                ),
+        )
+        .arg(
+            Arg::with_name("spell-check")
+                .long("spell-check")
+                .help(
+                    "Underline misspelled words in the preview, as checked by `hunspell`. Spell \
+                   checking may be toggled at runtime with the `toggle_spell_check` procedure.",
+                ),
+        )
+        .arg(
+            Arg::with_name("spell-check-language")
+                .long("spell-check-language")
+                .value_name("language")
+                .help(
+                    "The language of the dictionary that words are checked against, such as \
+                   `en_US`. Defaults to hunspell's default dictionary.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("spell-check-dictionary")
+                .long("spell-check-dictionary")
+                .value_name("path")
+                .help("A personal dictionary of words that are spelled correctly, one per line.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("linter")
+                .long("linter")
+                .value_name("command")
+                .help(
+                    "A linter that is run on the markdown after each update, such as \
+                   `markdownlint --stdin`. The linter reads the markdown on stdin and reports \
+                   problems as `file:line[:column] message` lines. Its diagnostics are marked in \
+                   the preview and sent to the editor.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("offline")
+                .long("offline")
+                .help(
+                    "Don't load anything from CDNs, so that the preview works without internet \
+                   access. Math is converted to MathML, and presentations are displayed as \
+                   regular documents.",
+                ),
+        )
+        .arg(
+            Arg::with_name("csp")
+                .long("csp")
+                .value_name("policy")
+                .help(
+                    "The Content-Security-Policy of the preview page. `strict` only allows the \
+                   preview's own scripts, so scripts in the markdown can't run. `off` sends no \
+                   policy. Other values are sent as-is. Defaults to `strict` with `--offline`, and \
+                   `off` otherwise.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("csp-script-src")
+                .long("csp-script-src")
+                .value_name("source")
+                .help("An additional source of scripts allowed by the strict policy.")
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("csp-style-src")
+                .long("csp-style-src")
+                .value_name("source")
+                .help("An additional source of stylesheets allowed by the strict policy.")
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("presentation")
+                .long("presentation")
+                .help(
+                    "Present the document as slides with reveal.js. Slides are separated by \
+                   `---` and `<!-- slide -->`. Presentation mode may be toggled at runtime with \
+                   the `toggle_presentation` procedure.",
+                ),
+        )
+        .arg(
+            Arg::with_name("side-by-side")
+                .long("side-by-side")
+                .help(
+                    "Display the markdown beside the preview, scrolled along with it. The layout \
+                   may be toggled at runtime with the `toggle_side_by_side` procedure.",
+                ),
+        )
         .arg(
             Arg::with_name("browser")
                 .long("browser")
                 .value_name("executable")
                 .help(
-                    "Specify a browser that the program should open. If not supplied, the program \
-                   will determine the user's default browser.",
+                    "Specify a browser that the program should open, such as \"firefox \
+                   --new-window\". The URL of the preview is appended to its arguments, or \
+                   replaces `{url}` in them, such as \"chromium --app={url}\". If not supplied, \
+                   the program will determine the user's default browser.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("browser-args")
+                .long("browser-args")
+                .value_name("args")
+                .help(
+                    "Specify the arguments of the browser separately, such as \"--new-window \
+                   {url}\", in which case `--browser` is the path of the executable, which may \
+                   contain spaces.",
+                )
+                .requires("browser")
+                .allow_hyphen_values(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("browser-mode")
+                .long("browser-mode")
+                .help(
+                    "How the preview is opened. `app` opens it in a standalone window without \
+                   tabs or an address bar, with `--browser` or an installed Chromium-based \
+                   browser, which takes the URL with `--app`.",
+                )
+                .possible_values(&["tab", "app"])
+                .default_value("tab"),
+        )
+        .arg(
+            Arg::with_name("pdf-browser")
+                .long("pdf-browser")
+                .value_name("executable")
+                .help(
+                    "A Chromium-based browser that should be run headlessly to export PDFs with \
+                   the `export_pdf` procedure.",
+                )
+                .default_value("chromium"),
+        )
+        .arg(
+            Arg::with_name("pdf-paper-size")
+                .long("pdf-paper-size")
+                .value_name("size")
+                .help(
+                    "The paper size of exported PDFs and printed previews, such as `A4`, `letter` \
+                   or `8.5in 11in`.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pdf-margin")
+                .long("pdf-margin")
+                .value_name("margin")
+                .help(
+                    "The margins of the pages of exported PDFs and printed previews, such as `2cm` \
+                   or `1in 0.75in`. Numbers are treated as millimetres.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pdf-landscape")
+                .long("pdf-landscape")
+                .help("Lay out the pages of exported PDFs and printed previews in landscape."),
+        )
+        .arg(
+            Arg::with_name("pdf-header")
+                .long("pdf-header")
+                .value_name("template")
+                .help(
+                    "The header of each page of exported PDFs and printed previews. `{page}`, \
+                   `{pages}`, `{title}` and `{date}` are replaced by the page number, the number \
+                   of pages, the title of the document and the date, and `|` separates the parts \
+                   on the left, in the center and on the right, such as `{title}||{date}`.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pdf-footer")
+                .long("pdf-footer")
+                .value_name("template")
+                .help(
+                    "The footer of each page of exported PDFs and printed previews, like \
+                   `--pdf-header`, such as `Page {page} of {pages}`.",
                 )
                 .takes_value(true),
         )
@@ -183,98 +969,1616 @@ fn main() -> Result<()> {
                 .default_value("github"),
         )
         .arg(
-            Arg::with_name("working-directory")
-                .long("working-directory")
-                .value_name("dir")
-                .help(
-                    "The directory that static files should be served out of. All relative links \
-                   in the markdown will be served relative to this directory.",
-                )
-                .takes_value(true),
+            Arg::with_name("dark-theme")
+                .long("highlight-theme-dark")
+                .value_name("theme")
+                .help("The theme to use for syntax highlighting when the preview is dark.")
+                .default_value("github-dark"),
         )
         .arg(
-            Arg::with_name("css")
-                .long("custom-css")
-                .value_name("url/path")
+            Arg::with_name("color-scheme")
+                .long("color-scheme")
                 .help(
-                    "CSS that should be used to style the markdown output. Defaults to \
-                   GitHub-like CSS.",
+                    "The color scheme of the preview. `auto` follows the color scheme preferred \
+                   by the browser. The color scheme may be changed at runtime with the \
+                   `set_color_scheme` procedure.",
                 )
-                .takes_value(true)
-                .multiple(true),
+                .possible_values(&["light", "dark", "auto"])
+                .default_value("auto"),
         )
         .arg(
-            Arg::with_name("external-renderer")
-                .long("external-renderer")
-                .help("An external process that should be used for rendering markdown.")
+            Arg::with_name("font-family")
+                .long("font-family")
+                .value_name("fonts")
+                .help("The font family of the preview, such as `Georgia, serif`.")
                 .takes_value(true),
         )
         .arg(
-            Arg::with_name("markdown-file")
-                .help("A markdown file that should be rendered by the server on startup."),
+            Arg::with_name("code-font-family")
+                .long("code-font-family")
+                .value_name("fonts")
+                .help("The font family of code in the preview.")
+                .takes_value(true),
         )
         .arg(
-            Arg::with_name("address")
-                .long("address")
-                .help("The address that this server will listen on. The default value is `localhost`.")
+            Arg::with_name("font-size")
+                .long("font-size")
+                .value_name("size")
+                .help("The base font size of the preview. Numbers are treated as pixels.")
                 .takes_value(true),
         )
         .arg(
-            Arg::with_name("port")
-                .long("port")
-                .help("The port number that this server will listen on. The default value is `0 (ephemeral)`.")
+            Arg::with_name("max-width")
+                .long("max-width")
+                .value_name("width")
+                .help(
+                    "The maximum width of the content of the preview. Numbers are treated as \
+                   pixels.",
+                )
                 .takes_value(true),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("math")
+                .long("math")
+                .help(
+                    "The library used to typeset `$...$` and `$$...$$` math in the preview, or \
+                   `off` to disable math. If `mathml`, math is converted to MathML by the server, \
+                   so no scripts need to be loaded.",
+                )
+                .possible_values(&["katex", "mathjax", "mathml", "off"])
+                .default_value("katex"),
+        )
+        .arg(
+            Arg::with_name("flavor")
+                .long("flavor")
+                .help(
+                    "The flavor of markdown that the built-in renderer should use. Determines \
+                   which extensions are enabled by default.",
+                )
+                .possible_values(&["gfm", "commonmark"])
+                .default_value("gfm"),
+        )
+        .arg(
+            Arg::with_name("enable")
+                .long("enable")
+                .value_name("extensions")
+                .help("Markdown extensions to enable in addition to those of the flavor.")
+                .possible_values(extension_names)
+                .use_delimiter(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("disable")
+                .long("disable")
+                .value_name("extensions")
+                .help("Markdown extensions of the flavor to disable.")
+                .possible_values(extension_names)
+                .use_delimiter(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("front-matter")
+                .long("front-matter")
+                .help(
+                    "How YAML front matter at the start of the markdown should be displayed. If \
+                   `off`, front matter is rendered as markdown.",
+                )
+                .possible_values(&["strip", "table", "off"])
+                .default_value("strip"),
+        )
+        .arg(
+            Arg::with_name("wikilink-extension")
+                .long("wikilink-extension")
+                .value_name("extension")
+                .help(
+                    "The extension of the files that `[[Page Name]]` wiki links refer to. Wiki \
+                   links are only rendered if the `wikilinks` extension is enabled.",
+                )
+                .default_value("md"),
+        )
+        .arg(
+            Arg::with_name("wikilink-case")
+                .long("wikilink-case")
+                .help(
+                    "How the page names of wiki links are matched to files under the working \
+                   directory. `lower` converts page names to lowercase, and `insensitive` \
+                   matches existing files regardless of case.",
+                )
+                .possible_values(&["preserve", "lower", "insensitive"])
+                .default_value("preserve"),
+        )
+        .arg(
+            Arg::with_name("heading-slugs")
+                .long("heading-slugs")
+                .help(
+                    "How the ids of headings are generated from their text, for linking to \
+                   sections of the document. `gitlab` collapses consecutive hyphens.",
+                )
+                .possible_values(&["github", "gitlab"])
+                .default_value("github"),
+        )
+        .arg(
+            Arg::with_name("emoji")
+                .long("emoji")
+                .help("Replace GitHub emoji shortcodes such as `:smile:` with emoji."),
+        )
+        .arg(
+            Arg::with_name("code-line-numbers")
+                .long("code-line-numbers")
+                .help("Display line numbers next to code blocks."),
+        )
+        .arg(
+            Arg::with_name("sanitize")
+                .long("sanitize")
+                .help(
+                    "Sanitize the HTML of documents, for previewing documents that aren't \
+                   trusted. Scripts, event handlers, and links to URLs with schemes such as \
+                   `javascript:` are removed from raw HTML and from the output of external \
+                   renderers.",
+                ),
+        )
+        .arg(
+            Arg::with_name("sanitize-allow-tags")
+                .long("sanitize-allow-tags")
+                .value_name("tags")
+                .help("Tags to allow in sanitized HTML, in addition to the default tags.")
+                .requires("sanitize")
+                .use_delimiter(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("sanitize-allow-attributes")
+                .long("sanitize-allow-attributes")
+                .value_name("attributes")
+                .help(
+                    "Attributes to allow in sanitized HTML, in addition to the default \
+                   attributes. Event handlers are never allowed, and the values of `id` and \
+                   `name` are prefixed with `user-content-`.",
+                )
+                .requires("sanitize")
+                .use_delimiter(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("sanitize-allow-url-schemes")
+                .long("sanitize-allow-url-schemes")
+                .value_name("schemes")
+                .help(
+                    "URL schemes to allow in sanitized HTML, such as `data`, in addition to \
+                   `http`, `https`, `mailto` and `tel`.",
+                )
+                .requires("sanitize")
+                .use_delimiter(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("lazy-images")
+                .long("lazy-images")
+                .help("Only load images once they're scrolled near."),
+        )
+        .arg(
+            Arg::with_name("image-max-width")
+                .long("image-max-width")
+                .value_name("length")
+                .help("The largest width that images are displayed at, as a CSS length such as `100%`.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("image-max-height")
+                .long("image-max-height")
+                .value_name("length")
+                .help("The largest height that images are displayed at, as a CSS length such as `80vh`.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("inline-images")
+                .long("inline-images")
+                .help(
+                    "Inline local images into the preview as data URIs, so that the preview works \
+                   when viewed from another machine, such as over a forwarded port. Exports \
+                   always inline local images.",
+                ),
+        )
+        .arg(
+            Arg::with_name("thumbnail-command")
+                .long("thumbnail-command")
+                .value_name("command")
+                .help(
+                    "Serve downscaled copies of large local images to the preview, created by this \
+                   command. The command reads the image from stdin and should print the thumbnail \
+                   in the same format, e.g. `convert - -thumbnail 1600x1600> -`. \
+                   Exports always contain the full images.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("thumbnail-size")
+                .long("thumbnail-size")
+                .value_name("size")
+                .help(
+                    "The size of local images above which thumbnails are served, such as `500K`. \
+                   Defaults to 1M.",
+                )
+                .requires("thumbnail-command")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mermaid")
+                .long("mermaid")
+                .value_name("command")
+                .help(
+                    "Render `mermaid` code blocks to SVG with the given mermaid-cli (`mmdc`) \
+                   command, instead of displaying them as code.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("plantuml")
+                .long("plantuml")
+                .value_name("command")
+                .help(
+                    "Render `plantuml` code blocks to SVG with the given PlantUML command, such as \
+                   `plantuml` or `java -jar plantuml.jar`.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("plantuml-server")
+                .long("plantuml-server")
+                .value_name("url")
+                .help(
+                    "Render `plantuml` code blocks to SVG with the PlantUML server at the given \
+                   URL, such as `https://www.plantuml.com/plantuml`.",
+                )
+                .conflicts_with("plantuml")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("working-directory")
+                .long("working-directory")
+                .value_name("dir")
+                .help(
+                    "The directory that static files should be served out of. All relative links \
+                   in the markdown will be served relative to this directory.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("css")
+                .long("custom-css")
+                .value_name("url/path")
+                .help(
+                    "CSS that should be used to style the markdown output. Defaults to \
+                   GitHub-like CSS. Local files are reloaded by the preview when they change.",
+                )
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("js")
+                .long("custom-js")
+                .value_name("url/path")
+                .help(
+                    "JavaScript that should be loaded by the preview page, after the preview's \
+                   own scripts.",
+                )
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("highlight-language")
+                .long("highlight-language")
+                .value_name("url/path")
+                .help(
+                    "Extra highlight.js language definitions that should be loaded by the \
+                   preview, for highlighting code blocks in languages that aren't included.",
+                )
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("highlight-alias")
+                .long("highlight-alias")
+                .value_name("alias=language")
+                .help(
+                    "Highlight code blocks in a language as another language, such as \
+                   `viml=vim`.",
+                )
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("template")
+                .long("template")
+                .value_name("path")
+                .help(
+                    "A handlebars template that replaces the preview page. The template is given \
+                   the `title` and `html` of the preview, and the `styles` and `scripts` \
+                   partials. The HTML must be placed in an element with the `markdown-preview` \
+                   id.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("favicon")
+                .long("favicon")
+                .value_name("url/path")
+                .help(
+                    "The icon of the preview page. Accepts URLs and absolute paths. Defaults to \
+                   the Markdown mark.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("external-renderer")
+                .long("external-renderer")
+                .help("An external process that should be used for rendering markdown.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("external-renderer-for")
+                .long("external-renderer-for")
+                .value_name("filetype=command")
+                .help(
+                    "An external process that renders documents of a filetype, such as \
+                   `rst=pandoc -f rst -t html`, instead of `--external-renderer`. A document's \
+                   filetype is the one it was sent with by `send_data_as`, or else the extension \
+                   of its file. An empty command renders the filetype with the built-in \
+                   renderer. The other external renderer options apply to each process.",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .group(
+            ArgGroup::with_name("external-renderers")
+                .args(&["external-renderer", "external-renderer-for"])
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("external-renderer-framing")
+                .long("external-renderer-framing")
+                .help(
+                    "Keep the external renderer running, and stream each document through it \
+                   instead of starting it for every render. With `null`, each document and its \
+                   HTML ends with a NUL byte. With `length`, each is preceded by its length in \
+                   bytes and a newline. The renderer is restarted if it exits.",
+                )
+                .possible_values(&["null", "length"])
+                .requires("external-renderers")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("external-renderer-env")
+                .long("external-renderer-env")
+                .value_name("name=value")
+                .help("Set an environment variable of the external renderer.")
+                .requires("external-renderers")
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("external-renderer-cwd")
+                .long("external-renderer-cwd")
+                .value_name("path")
+                .help(
+                    "The working directory of the external renderer, so that it finds the \
+                   configuration of a project. Defaults to the working directory of the server.",
+                )
+                .requires("external-renderers")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("external-renderer-encoding")
+                .long("external-renderer-encoding")
+                .help(
+                    "The encoding of the markdown written to the external renderer's stdin. Its \
+                   HTML is always read as UTF-8.",
+                )
+                .possible_values(&["utf-8", "utf-16le", "utf-16be", "latin1"])
+                .requires("external-renderers")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("external-renderer-timeout")
+                .long("external-renderer-timeout")
+                .value_name("ms")
+                .help(
+                    "Kill the external renderer if a render takes longer than this many \
+                   milliseconds. The preview shows an error instead of the update.",
+                )
+                .requires("external-renderers")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("external-renderer-fallback")
+                .long("external-renderer-fallback")
+                .help(
+                    "Render an update with the built-in renderer if the external renderer fails \
+                   or times out.",
+                )
+                .requires("external-renderers"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .help(
+                    "The format of documents whose filetype or file extension doesn't determine \
+                   their format. AsciiDoc is rendered with `asciidoctor`, and reStructuredText \
+                   with the `docutils` command of docutils 0.19 or later. Org is rendered by a \
+                   built-in renderer that folds the sections of headlines, and CSV and TSV as \
+                   tables that may be sorted by their columns.",
+                )
+                .possible_values(&["markdown", "asciidoc", "rst", "org", "csv", "tsv"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("stdin-format")
+                .long("stdin-format")
+                .help(
+                    "What is read from stdin. With `markdown`, everything piped to stdin is \
+                   rendered as it's read, and a form feed starts a new document that replaces \
+                   the previous one.",
+                )
+                .possible_values(&["rpc", "markdown"])
+                .default_value("rpc")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("debounce-ms")
+                .long("debounce-ms")
+                .value_name("ms")
+                .help(
+                    "Wait until no markdown has been sent with `send_data` for this many \
+                   milliseconds before rendering, and render only the latest markdown. Defaults \
+                   to 0, which renders every update.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("virtualize-size")
+                .long("virtualize-size")
+                .value_name("size")
+                .help(
+                    "Only add the parts of the preview near the visible part of the page when the \
+                     rendered HTML is larger than this size, such as `5M`. By default, the whole \
+                     document is always shown.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("title-source")
+                .long("title-source")
+                .help(
+                    "Derive the title of the preview from the first level-one heading or from the \
+                   name of the file being previewed. The title may also be set by the `set_title` \
+                   procedure.",
+                )
+                .possible_values(&["heading", "file-name"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("export-html")
+                .long("export-html")
+                .value_name("path")
+                .help(
+                    "Render the markdown file as a self-contained HTML page at the given path, \
+                   then exit.",
+                )
+                .requires("markdown-file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("export-epub")
+                .long("export-epub")
+                .value_name("path")
+                .help(
+                    "Package the markdown file as an EPUB at the given path, then exit. Metadata \
+                   is taken from the file's front matter.",
+                )
+                .requires("markdown-file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("check")
+                .long("check")
+                .help(
+                    "Print a JSON array of the links and images of the markdown file that don't \
+                   resolve under the static root, and any malformed URLs, then exit. Exits with \
+                   status 1 if any were found.",
+                )
+                .requires("markdown-file"),
+        )
+        .arg(
+            Arg::with_name("render-only")
+                .long("render-only")
+                .help(
+                    "Render the markdown file, or markdown read from stdin, as a self-contained \
+                   HTML page on stdout, then exit without listening for connections.",
+                )
+                .conflicts_with_all(&[
+                    "watch",
+                    "check",
+                    "session",
+                    "print-url",
+                    "port-file",
+                    "export-html",
+                    "export-epub",
+                ]),
+        )
+        .arg(
+            Arg::with_name("bench")
+                .long("bench")
+                .value_name("path")
+                .help(
+                    "Render the markdown file at the given path repeatedly with the configured \
+                   renderer and settings, then print the latency percentiles and throughput of \
+                   the renders and exit. Caches are cleared before each render.",
+                )
+                .conflicts_with_all(&[
+                    "markdown-file",
+                    "watch",
+                    "check",
+                    "session",
+                    "print-url",
+                    "port-file",
+                    "render-only",
+                    "export-html",
+                    "export-epub",
+                ])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("iterations")
+                .long("iterations")
+                .value_name("count")
+                .help("The number of renders that `--bench` measures. Defaults to 100.")
+                .requires("bench")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .help(
+                    "Render the markdown file again whenever it changes on disk, instead of \
+                   reading RPCs. Useful for previewing files from any editor or the shell.",
+                )
+                .requires("markdown-file")
+                .conflicts_with_all(&["rpc-socket", "rpc-listen"]),
+        )
+        .arg(
+            Arg::with_name("markdown-file")
+                .help("A markdown file that should be rendered by the server on startup."),
+        )
+        .arg(
+            Arg::with_name("print-url")
+                .long("print-url")
+                .help(
+                    "Send a `listening` notification with the URL of the preview as the first \
+                   message on stdout, before any RPC responses. Useful for discovering an \
+                   ephemeral port.",
+                ),
+        )
+        .arg(
+            Arg::with_name("port-file")
+                .long("port-file")
+                .value_name("path")
+                .help(
+                    "Write the URL of the preview to a file on startup. The file is removed when the \
+                   server exits.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("parent-pid")
+                .long("parent-pid")
+                .value_name("pid")
+                .help(
+                    "Shut down the server when the process with this ID, such as the editor that \
+                   started the composer, exits, even if it crashed without closing stdin.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("session")
+                .long("session")
+                .value_name("name")
+                .help(
+                    "Restore the last rendered document, static root, and settings of the preview \
+                   from a named session on startup, and save them to the session as they change. \
+                   Sessions are stored in `$XDG_STATE_HOME/markdown-composer/sessions`. Without a \
+                   name, the session is named after `--port`.",
+                )
+                .takes_value(true)
+                .min_values(0)
+                .max_values(1),
+        )
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .help("The minimum level of messages that should be logged.")
+                .possible_values(&["off", "error", "warn", "info", "debug", "trace"])
+                .default_value("error"),
+        )
+        .arg(
+            Arg::with_name("log-file")
+                .long("log-file")
+                .value_name("path")
+                .help("A file that messages should be logged to. Defaults to stderr.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-max-size")
+                .long("log-max-size")
+                .value_name("size")
+                .help(
+                    "Rotate the log file once it grows larger than the given size. The size is in \
+                   bytes, with an optional `K`, `M`, or `G` suffix.",
+                )
+                .requires("log-file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-max-backups")
+                .long("log-max-backups")
+                .value_name("count")
+                .help("The number of rotated log files to keep when `--log-max-size` is set.")
+                .default_value("5"),
+        )
+        .arg(
+            Arg::with_name("log-format")
+                .long("log-format")
+                .help(
+                    "The format of logged messages. If `json`, each message is logged as a JSON \
+                   object on its own line.",
+                )
+                .possible_values(&["text", "json"])
+                .default_value("text"),
+        )
+        .arg(
+            Arg::with_name("log-config")
+                .long("log-config")
+                .value_name("path")
+                .help(
+                    "A log4rs configuration file. If supplied, `--log-level`, `--log-file`, and \
+                   `--log-format` are ignored.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("address")
+                .long("address")
+                .help(
+                    "An address that this server will listen on, such as `127.0.0.1` or `::1`. \
+                   May be given several times to listen on several addresses. Without an auth \
+                   token, requests must address the server by `localhost`, an IP address of the \
+                   server or one of these names. The default value is `localhost`.",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("port")
+                .long("port")
+                .help("The port number that this server will listen on. The default value is `0 (ephemeral)`.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rpc-socket")
+                .long("rpc-socket")
+                .value_name("path")
+                .help(
+                    "Read RPC requests from editors that connect to a unix domain socket at this \
+                   path, instead of from stdin. The server keeps running when editors disconnect, \
+                   until an editor requests shutdown.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rpc-listen")
+                .long("rpc-listen")
+                .value_name("address:port")
+                .help(
+                    "Read RPC requests from editors that connect to this TCP address, instead of \
+                   from stdin. Anyone who can connect may read and write files as the current \
+                   user, so the address must be a loopback address such as `127.0.0.1:8090`. \
+                   Editors on other machines may connect through SSH port forwarding.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("listen-unix")
+                .long("listen-unix")
+                .value_name("path")
+                .help(
+                    "Listen on a unix domain socket at this path instead of a TCP address, for \
+                   example behind a local proxy. Only the current user may connect to the socket.",
+                )
+                .conflicts_with_all(&["address", "port"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("auth-token")
+                .long("auth-token")
+                .value_name("token")
+                .help(
+                    "Require a secret token to access the preview. The token is included in the \
+                   URL of the preview. If the server isn't listening on a loopback address, a \
+                   random token is generated by default.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("allow-origin")
+                .long("allow-origin")
+                .value_name("origin")
+                .help(
+                    "An origin, such as `https://example.com`, whose pages may read from the \
+                   server and connect to its websocket. `*` allows any origin, but only origins \
+                   that are listed may read responses with the user's cookies.",
+                )
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("tls-cert")
+                .long("tls-cert")
+                .value_name("path")
+                .help(
+                    "Serve the preview over HTTPS with a PEM file containing a certificate chain. \
+                   Requires `--tls-key`.",
+                )
+                .requires("tls-key")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tls-key")
+                .long("tls-key")
+                .value_name("path")
+                .help("A PEM file containing the private key of the certificate.")
+                .requires("tls-cert")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("generate-man")
+                .long("generate-man")
+                .help("Print a manual page for the composer in roff format, then exit.")
+                .conflicts_with("markdown-file"),
+        )
+        .setting(AppSettings::DisableHelpSubcommand)
+        .subcommand(
+            SubCommand::with_name("completions")
+                .about("Prints a completion script for a shell, then exits.")
+                .arg(
+                    Arg::with_name("shell")
+                        .help("The shell to complete the arguments of the composer in.")
+                        .possible_values(&Shell::variants())
+                        .required(true),
+                ),
+        )
+}
+
+fn main() -> Result<()> {
+    let extension_names = render::EXTENSIONS
+        .iter()
+        .map(|&(name, _)| name)
+        .collect::<Vec<_>>();
+
+    let matches = app(&extension_names).get_matches();
+
+    if let Some(matches) = matches.subcommand_matches("completions") {
+        let shell = value_t!(matches, "shell", Shell).unwrap_or_else(|e| e.exit());
+        app(&extension_names).gen_completions_to(BIN_NAME, shell, &mut io::stdout());
+        return Ok(());
+    }
+
+    if matches.is_present("generate-man") {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        man::write(&app(&extension_names), BIN_NAME, &mut stdout)?;
+        stdout.flush()?;
+        return Ok(());
+    }
+
+    let (exit_tx, exits) = mpsc::channel();
+    notify_exit_signals(exit_tx.clone())?;
+    if let Some(pid) = matches.value_of("parent-pid") {
+        let pid = pid
+            .parse::<u32>()
+            .map_err(|e| anyhow!("invalid process ID {}: {}", pid, e))?;
+        notify_parent_exit(pid, exit_tx)?;
+    }
+
+    init_logging(&matches)?;
+    log_panics::init();
+
+    let port = match matches.value_of("port") {
+        Some(port) => port
+            .parse::<u16>()
+            .map_err(|e| anyhow!("invalid port {}: {}", port, e))?,
+        None => 0,
+    };
+    let rpc_addrs = match matches.value_of("rpc-listen") {
+        Some(address) => Some(loopback_addrs(address)?),
+        None => None,
+    };
+    let addresses = match matches.values_of("address") {
+        Some(addresses) => addresses
+            .map(|address| (parse_host(address), port))
+            .collect(),
+        None => vec![("localhost", port)],
+    };
+    let mut server = match matches.value_of("listen-unix") {
+        _ if matches.is_present("render-only") || matches.is_present("bench") => Server::unbound()?,
+        Some(path) => Server::bind_unix(path)?,
+        None => Server::bind(&addresses)?,
+    };
+
+    if let (Some(cert), Some(key)) = (matches.value_of("tls-cert"), matches.value_of("tls-key")) {
+        server.set_tls(cert, key)?;
+    }
+
+    // Other machines could read the user's documents, so require a token by default.
+    let auth_token = match matches.value_of("auth-token") {
+        Some(token) => Some(token.to_string()),
+        None if !server.addrs().iter().all(|addr| addr.ip().is_loopback()) => {
+            Some(generate_auth_token()?)
+        }
+        None => None,
+    };
+    server.set_auth_token(auth_token)?;
+
+    server.set_allowed_hosts(
+        addresses
+            .iter()
+            .map(|&(address, _)| address.to_string())
+            .collect(),
+    );
+
+    if let Some(origins) = matches.values_of("allow-origin") {
+        server.set_allowed_origins(origins.map(String::from).collect());
+    }
+
+    // Anything else on stdout would corrupt the RPCs, so the URLs are sent as a notification.
+    if matches.is_present("print-url") {
+        rpc::write_notification(
+            io::stdout().lock(),
+            "listening",
+            (server.url(), server.urls(), server.lan_urls()),
+        )?;
+    }
+
+    // The preview may be opened on other devices, such as a phone, by scanning the QR code.
+    let lan_urls = server.lan_urls();
+    if !lan_urls.is_empty() {
+        eprintln!("The preview is available on the network at:");
+        for url in &lan_urls {
+            eprintln!("  {}", url);
+        }
+        if io::stderr().is_terminal() {
+            if let Some(qr) = QrCode::encode(&lan_urls[0]) {
+                eprint!("{}", qr.to_terminal());
+            }
+        }
+    }
+
+    let _port_file = match matches.value_of("port-file") {
+        Some(path) => Some(PortFile::create(path, &server.url())?),
+        None => None,
+    };
 
-    let mut server = Server::bind(format!(
-        "{}:{}",
-        matches.value_of("address").unwrap_or("localhost"),
-        matches.value_of("port").unwrap_or("0")
-    ))?;
+    if let Some(size) = matches.value_of("virtualize-size") {
+        server.set_virtualize_size(Some(parse_size(size)? as usize));
+    }
+
+    if let Some(command) = matches.value_of("external-renderer") {
+        server.set_external_renderer(external_renderer(&matches, command)?);
+    }
+    for mapping in matches
+        .values_of("external-renderer-for")
+        .unwrap_or_default()
+    {
+        let (filetype, command) = mapping
+            .split_once('=')
+            .ok_or_else(|| anyhow!("invalid external renderer mapping: {}", mapping))?;
+        let renderer = match command.trim() {
+            "" => None,
+            command => Some(external_renderer(&matches, command)?),
+        };
+        server.set_filetype_renderer(filetype, renderer);
+    }
+    server.set_external_renderer_fallback(matches.is_present("external-renderer-fallback"));
 
-    if let Some(external_renderer) = matches.value_of("external-renderer") {
-        server.set_external_renderer(parse_command(external_renderer));
+    if let Some(format) = matches.value_of("format") {
+        server.set_format(format.parse::<Format>().map_err(|e| anyhow!(e))?);
     }
 
     if let Some(highlight_theme) = matches.value_of("theme") {
         server.set_highlight_theme(highlight_theme.to_string());
     }
 
+    let mut typography = Typography::default();
+    for property in ["font-family", "code-font-family", "font-size", "max-width"] {
+        if let Some(value) = matches.value_of(property) {
+            typography
+                .set(&property.replace('-', "_"), Some(value.to_string()))
+                .map_err(|e| anyhow!(e))?;
+        }
+    }
+    server.set_typography(typography);
+
+    let mut pdf_layout = PdfLayout::default();
+    for property in ["pdf-paper-size", "pdf-margin", "pdf-header", "pdf-footer"] {
+        if let Some(value) = matches.value_of(property) {
+            pdf_layout
+                .set(
+                    &property.trim_start_matches("pdf-").replace('-', "_"),
+                    Some(value.to_string()),
+                )
+                .map_err(|e| anyhow!(e))?;
+        }
+    }
+    if matches.is_present("pdf-landscape") {
+        pdf_layout.landscape = Some(true);
+    }
+    server.set_pdf_layout(pdf_layout);
+
+    server.set_dark_highlight_theme(matches.value_of("dark-theme").unwrap().to_string());
+    server.set_color_scheme(
+        matches
+            .value_of("color-scheme")
+            .unwrap()
+            .parse::<ColorScheme>()
+            .map_err(|e| anyhow!(e))?,
+    );
+
+    server.set_math(match matches.value_of("math").unwrap() {
+        "off" => None,
+        math => Some(math.parse::<MathRenderer>().map_err(|e| anyhow!(e))?),
+    });
+
+    let mut extensions = matches
+        .value_of("flavor")
+        .unwrap()
+        .parse::<Flavor>()
+        .map_err(|e| anyhow!(e))?
+        .extensions();
+    for name in matches.values_of("enable").into_iter().flatten() {
+        extensions.insert(render::extension(name).unwrap());
+    }
+    for name in matches.values_of("disable").into_iter().flatten() {
+        extensions.remove(render::extension(name).unwrap());
+    }
+    server.set_extensions(extensions)?;
+
+    server.set_front_matter_display(match matches.value_of("front-matter").unwrap() {
+        "strip" => Some(FrontMatterDisplay::Strip),
+        "table" => Some(FrontMatterDisplay::Table),
+        _ => None,
+    });
+
+    server.set_wiki_links(
+        matches.value_of("wikilink-extension").unwrap().to_string(),
+        matches
+            .value_of("wikilink-case")
+            .unwrap()
+            .parse::<WikiLinkCase>()
+            .map_err(|e| anyhow!(e))?,
+    );
+
+    server.set_heading_slugs(
+        matches
+            .value_of("heading-slugs")
+            .unwrap()
+            .parse::<SlugStyle>()
+            .map_err(|e| anyhow!(e))?,
+    );
+
+    if matches.is_present("emoji") {
+        server.set_emoji(true);
+    }
+
+    if matches.is_present("code-line-numbers") {
+        server.set_code_line_numbers(true);
+    }
+
+    if matches.is_present("sanitize") {
+        let mut sanitizer = Sanitizer::default();
+        for tag in matches
+            .values_of("sanitize-allow-tags")
+            .into_iter()
+            .flatten()
+        {
+            sanitizer.allow_tag(tag);
+        }
+        for attribute in matches
+            .values_of("sanitize-allow-attributes")
+            .into_iter()
+            .flatten()
+        {
+            sanitizer.allow_attribute(attribute);
+        }
+        for scheme in matches
+            .values_of("sanitize-allow-url-schemes")
+            .into_iter()
+            .flatten()
+        {
+            sanitizer.allow_url_scheme(scheme);
+        }
+        server.set_sanitizer(Some(sanitizer));
+    }
+
+    server.set_image_constraints(
+        matches.is_present("lazy-images"),
+        matches.value_of("image-max-width").map(String::from),
+        matches.value_of("image-max-height").map(String::from),
+    );
+
+    if matches.is_present("inline-images") {
+        server.set_inline_images(true);
+    }
+
+    if let Some(command) = matches.value_of("thumbnail-command") {
+        let min_size = match matches.value_of("thumbnail-size") {
+            Some(size) => parse_size(size)?,
+            None => 1024 * 1024,
+        };
+        server.set_thumbnail_command(Shlex::new(command).collect(), min_size);
+    }
+
+    if let Some(mermaid) = matches.value_of("mermaid") {
+        server.set_mermaid(Some(parse_command(mermaid)?));
+    }
+
+    if let Some(plantuml) = matches.value_of("plantuml") {
+        server.set_plantuml(Some(PlantUml::Command(parse_command(plantuml)?)));
+    }
+
+    if let Some(plantuml_server) = matches.value_of("plantuml-server") {
+        server.set_plantuml(Some(PlantUml::Server(plantuml_server.parse()?)));
+    }
+
     if let Some(working_directory) = matches.value_of("working-directory") {
-        server.set_static_root(working_directory);
+        server
+            .set_static_root(working_directory)
+            .map_err(|e| anyhow!("invalid working directory {}", e))?;
     }
 
     if let Some(custom_css) = matches.values_of("css") {
         server.set_custom_css(custom_css.map(String::from).collect())?;
     }
 
+    if let Some(custom_js) = matches.values_of("js") {
+        server.set_custom_js(custom_js.map(String::from).collect())?;
+    }
+
+    if let Some(template) = matches.value_of("template") {
+        server.set_template(template)?;
+    }
+
+    if let Some(favicon) = matches.value_of("favicon") {
+        server.set_favicon(favicon)?;
+    }
+
+    if let Some(languages) = matches.values_of("highlight-language") {
+        server.set_highlight_languages(languages.map(String::from).collect())?;
+    }
+
+    if let Some(aliases) = matches.values_of("highlight-alias") {
+        let aliases = aliases
+            .map(|alias| match alias.split_once('=') {
+                Some((alias, language)) => Ok((alias.to_string(), language.to_string())),
+                None => Err(anyhow!("invalid highlight alias: {}", alias)),
+            })
+            .collect::<Result<_, _>>()?;
+        server.set_language_aliases(aliases);
+    }
+
+    if matches.is_present("no-auto-scroll") {
+        server.set_auto_scroll(false);
+    }
+
+    if matches.is_present("close-on-shutdown") {
+        server.set_close_on_shutdown(true);
+    }
+
+    if matches.is_present("focus") {
+        server.set_focus(true);
+    }
+
+    if matches.is_present("render-timings") {
+        server.set_render_timings(true);
+    }
+
+    if let Some(zoom) = matches.value_of("zoom") {
+        server.set_zoom(
+            zoom.parse()
+                .map_err(|e| anyhow!("invalid zoom {:?}: {}", zoom, e))?,
+        );
+    }
+
+    if matches.is_present("toc") {
+        server.set_toc(true);
+    }
+
+    if matches.is_present("spell-check-language") || matches.is_present("spell-check-dictionary") {
+        server.set_spell_checker(SpellChecker::hunspell(
+            matches.value_of("spell-check-language"),
+            matches.value_of("spell-check-dictionary").map(Path::new),
+        ))?;
+    }
+    if matches.is_present("spell-check") {
+        server.set_spell_check(true)?;
+    }
+
+    if let Some(linter) = matches.value_of("linter") {
+        server.set_linter(Some(Linter::new(parse_command(linter)?)));
+    }
+
+    if matches.is_present("offline") {
+        server.set_offline(true);
+    }
+
+    if matches.is_present("presentation") {
+        server.set_presentation(true)?;
+    }
+
+    if matches.is_present("side-by-side") {
+        server.set_side_by_side(true);
+    }
+
+    if let Some(csp) = matches.value_of("csp") {
+        let csp = csp
+            .parse::<ContentSecurityPolicy>()
+            .map_err(|e| anyhow!(e))?;
+        server.set_content_security_policy(Some(csp));
+    }
+
+    let sources = |name| {
+        matches
+            .values_of(name)
+            .map(|sources| sources.map(String::from).collect())
+            .unwrap_or_default()
+    };
+    server.set_csp_sources(sources("csp-script-src"), sources("csp-style-src"))?;
+
+    if let Some(title_source) = matches.value_of("title-source") {
+        server.set_title_source(Some(match title_source {
+            "heading" => TitleSource::Heading,
+            "file-name" => TitleSource::FileName,
+            _ => unreachable!(),
+        }));
+    }
+
+    let session = if matches.is_present("session") {
+        let name = match matches.value_of("session") {
+            Some(name) => name.to_owned(),
+            None if port != 0 => format!("port-{}", port),
+            None => return Err(anyhow!("a session named after the port requires `--port`")),
+        };
+        let path = session::path(&name)?;
+        info!("restoring session: {}", path.display());
+        let session = Session::load(&path)?;
+        session.restore(&mut server)?;
+        Some(SessionFile::new(path, session))
+    } else {
+        None
+    };
+
     if let Some(file_name) = matches.value_of("markdown-file") {
-        server.send(fs::read_to_string(file_name)?)?;
+        server.render_file(env::current_dir()?.join(file_name))?;
+    }
+
+    if matches.is_present("render-only") {
+        if !matches.is_present("markdown-file") {
+            let mut markdown = String::new();
+            io::stdin().read_to_string(&mut markdown)?;
+            server.send(markdown)?;
+        }
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        stdout.write_all(server.standalone_html().as_bytes())?;
+        stdout.flush()?;
+        return Ok(());
+    }
+
+    if let Some(path) = matches.value_of("bench") {
+        let iterations = matches.value_of("iterations").unwrap_or("100");
+        let iterations = match iterations.parse::<usize>() {
+            Ok(iterations) if iterations > 0 => iterations,
+            _ => return Err(anyhow!("invalid number of iterations: {}", iterations)),
+        };
+
+        let path = env::current_dir()?.join(path);
+        let markdown = fs::read_to_string(&path)
+            .map_err(|e| anyhow!("could not read {}: {}", path.display(), e))?;
+        let report = bench::run(&mut server, &path, &markdown, iterations)?;
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        write!(stdout, "{}", report)?;
+        stdout.flush()?;
+        return Ok(());
+    }
+
+    if matches.is_present("check") {
+        let problems = server.check_links(None)?;
+        println!("{}", serde_json::to_string(&problems)?);
+        logger().flush();
+        process::exit(if problems.is_empty() { 0 } else { 1 });
+    }
+
+    if matches.is_present("export-html") || matches.is_present("export-epub") {
+        if let Some(export_path) = matches.value_of("export-html") {
+            server.export_html(env::current_dir()?.join(export_path))?;
+        }
+
+        if let Some(export_path) = matches.value_of("export-epub") {
+            server.export_epub(env::current_dir()?.join(export_path))?;
+        }
+
+        return Ok(());
+    }
+
+    let mut browser = match (
+        matches.value_of("browser"),
+        matches.value_of("browser-args"),
+    ) {
+        (Some(browser), Some(args)) => Some(format!("{} {}", shlex::quote(browser), args)),
+        (browser, _) => browser.map(String::from),
+    };
+    if matches.value_of("browser-mode") == Some("app") {
+        browser = match browser {
+            Some(browser) if browser.contains("{url}") => Some(browser),
+            Some(browser) => Some(format!("{} --app={{url}}", browser)),
+            None if !display_available() => None,
+            None => {
+                let app_browser = app_browser();
+                if app_browser.is_none() {
+                    warn!("no Chromium-based browser found, opening the preview in a tab");
+                }
+                app_browser
+            }
+        };
     }
+    // The browsers are only run once a preview is opened or printed, so check them now.
+    for browser in browser
+        .iter()
+        .map(String::as_str)
+        .chain(matches.value_of("pdf-browser"))
+    {
+        parse_command(browser)?;
+    }
+
+    let open_to = matches.value_of("open-to");
+
+    // Browsers can't connect to unix domain sockets.
+    let mut auto_open = !matches.is_present("no-auto-open") && !matches.is_present("listen-unix");
 
-    let browser = matches.value_of("browser");
+    // Without a display, such as over SSH, the preview may still be opened from another machine,
+    // such as by forwarding the port.
+    if auto_open && browser.is_none() && !display_available() {
+        eprintln!(
+            "There is no display to open a browser on. Open the preview at {}",
+            server.url()
+        );
+        auto_open = false;
+    }
 
-    if !matches.is_present("no-auto-open") {
-        if let Some(browser) = browser {
-            server.open_specific_browser(parse_command(browser))?;
+    let open_on_first_send = auto_open && matches.is_present("open-on-first-send");
+    if auto_open && !open_on_first_send {
+        if let Some(browser) = &browser {
+            server.open_specific_browser(parse_command(browser)?, None, open_to)?;
         } else {
-            server.open_browser()?;
+            server.open_browser(None, open_to)?;
         };
     }
 
-    let stdin = io::stdin();
-    let stdin_lock = stdin.lock();
+    if matches.is_present("watch") {
+        let file_name = matches.value_of("markdown-file").unwrap();
+        GRACEFUL_EXIT.store(true, Ordering::SeqCst);
+        return watch_file(server, &env::current_dir()?.join(file_name), exits);
+    }
+
+    let (events_tx, events) = mpsc::channel();
+
+    let exit_tx = events_tx.clone();
+    thread::spawn(move || {
+        if exits.recv().is_ok() {
+            let _ = exit_tx.send(Event::Exit);
+        }
+    });
+    GRACEFUL_EXIT.store(true, Ordering::SeqCst);
+
+    let _rpc_socket = match matches.value_of("rpc-socket") {
+        Some(path) => Some(RpcSocket::bind(path, events_tx.clone())?),
+        None => None,
+    };
+
+    if let Some(addrs) = rpc_addrs {
+        let listener = TcpListener::bind(&addrs[..])?;
+        info!("accepting editors on {}", listener.local_addr()?);
+
+        let rpc_tx = events_tx.clone();
+        thread::spawn(move || accept_editors(listener.incoming(), TcpStream::try_clone, rpc_tx));
+    }
+
+    if matches.value_of("stdin-format") == Some("markdown") {
+        let markdown_tx = events_tx.clone();
+        thread::spawn(move || decode_markdown(io::stdin(), markdown_tx));
+    } else if !matches.is_present("rpc-socket") && !matches.is_present("rpc-listen") {
+        events_tx
+            .send(Event::Connect(STDIO_EDITOR, Box::new(io::stdout())))
+            .unwrap();
+
+        let rpc_tx = events_tx.clone();
+        thread::spawn(move || decode_rpc(io::stdin(), STDIO_EDITOR, rpc_tx));
+    }
+
+    let debounce = match matches.value_of("debounce-ms") {
+        Some(ms) => Duration::from_millis(
+            ms.parse()
+                .map_err(|e| anyhow!("invalid debounce window {}: {}", ms, e))?,
+        ),
+        None => Duration::ZERO,
+    };
+
+    let mut composer = Composer::new(server);
+    composer.set_browser(browser);
+    composer.set_pdf_browser(matches.value_of("pdf-browser").unwrap().to_string());
+    composer.set_debounce(debounce);
+    if open_on_first_send {
+        composer.open_on_first_send(open_to.map(String::from))?;
+    }
+
+    read_rpc(events_tx, events, composer, session)?;
+
+    Ok(())
+}
+
+fn init_logging(matches: &ArgMatches) -> Result<()> {
+    if let Some(config) = matches.value_of("log-config") {
+        log4rs::init_file(config, Default::default())?;
+        return Ok(());
+    }
+
+    let level = matches
+        .value_of("log-level")
+        .unwrap()
+        .parse::<LevelFilter>()?;
+    let encoder: Box<dyn Encode> = match matches.value_of("log-format").unwrap() {
+        "json" => Box::new(JsonEncoder::new()),
+        _ => Box::new(PatternEncoder::new("{d} [{l}] {M}:{m}{n}")),
+    };
+
+    let appender: Box<dyn Append> = match matches.value_of("log-file") {
+        Some(path) => match matches.value_of("log-max-size") {
+            Some(max_size) => {
+                let max_size = parse_size(max_size)?;
+                let max_backups = matches.value_of("log-max-backups").unwrap().parse()?;
+
+                let roller: Box<dyn Roll> = if max_backups == 0 {
+                    Box::new(DeleteRoller::new())
+                } else {
+                    Box::new(
+                        FixedWindowRoller::builder()
+                            .base(1)
+                            .build(&format!("{}.{{}}", path), max_backups)
+                            .map_err(|e| anyhow!(e))?,
+                    )
+                };
+
+                let policy = CompoundPolicy::new(Box::new(SizeTrigger::new(max_size)), roller);
+
+                Box::new(
+                    RollingFileAppender::builder()
+                        .encoder(encoder)
+                        .build(path, Box::new(policy))?,
+                )
+            }
+            None => Box::new(FileAppender::builder().encoder(encoder).build(path)?),
+        },
+        None => Box::new(
+            ConsoleAppender::builder()
+                .target(Target::Stderr)
+                .encoder(encoder)
+                .build(),
+        ),
+    };
+
+    let config = log4rs::config::Config::builder()
+        .appender(Appender::builder().build("default", appender))
+        .build(Root::builder().appender("default").build(level))?;
 
-    read_rpc(stdin_lock, server, browser)?;
+    log4rs::init_config(config)?;
 
     Ok(())
 }
 
-fn parse_command(s: &str) -> Command {
-    let words = Shlex::new(s).collect::<Vec<_>>();
-    let (command, args) = words.split_first().expect("command was empty");
-    let mut command = Command::new(command);
-    command.args(args);
-    command
+/// A file containing the URL of the server, which is removed when dropped.
+struct PortFile {
+    path: PathBuf,
+}
+
+impl PortFile {
+    fn create(path: impl Into<PathBuf>, url: &str) -> io::Result<Self> {
+        let path = path.into();
+        fs::write(&path, format!("{}\n", url))?;
+        Ok(PortFile { path })
+    }
+}
+
+impl Drop for PortFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("could not remove port file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// A unix domain socket that editors connect to, which is removed when the server exits.
+struct RpcSocket {
+    path: PathBuf,
+}
+
+impl RpcSocket {
+    fn bind(path: impl AsRef<Path>, events: mpsc::Sender<Event>) -> Result<Self> {
+        let path = path.as_ref();
+
+        #[cfg(unix)]
+        {
+            let listener = server::bind_unix_socket(path)?;
+            info!("accepting editors on {}", path.display());
+            thread::spawn(move || {
+                accept_editors(
+                    listener.incoming(),
+                    std::os::unix::net::UnixStream::try_clone,
+                    events,
+                )
+            });
+
+            Ok(RpcSocket {
+                path: path.to_owned(),
+            })
+        }
+
+        #[cfg(not(unix))]
+        {
+            let _ = events;
+            Err(anyhow!(
+                "cannot listen on {}: RPC sockets are not supported on this platform",
+                path.display()
+            ))
+        }
+    }
+}
+
+impl Drop for RpcSocket {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("could not remove RPC socket {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Creates an external renderer that runs a command, with the external renderer options of the
+/// command line.
+fn external_renderer(matches: &ArgMatches, command: &str) -> Result<ExternalRenderer> {
+    let mut command = parse_command(command)?;
+    for var in matches
+        .values_of("external-renderer-env")
+        .unwrap_or_default()
+    {
+        match var.split_once('=') {
+            Some((name, value)) => command.env(name, value),
+            None => return Err(anyhow!("invalid environment variable: {}", var)),
+        };
+    }
+    if let Some(dir) = matches.value_of("external-renderer-cwd") {
+        command.current_dir(dir);
+    }
+
+    let mut renderer = ExternalRenderer::new(command);
+    if let Some(encoding) = matches.value_of("external-renderer-encoding") {
+        renderer.set_encoding(encoding.parse::<Encoding>().map_err(|e| anyhow!(e))?);
+    }
+    if let Some(framing) = matches.value_of("external-renderer-framing") {
+        renderer.set_framing(Some(framing.parse::<Framing>().map_err(|e| anyhow!(e))?));
+    }
+    if let Some(ms) = matches.value_of("external-renderer-timeout") {
+        renderer
+            .set_timeout(Some(Duration::from_millis(ms.parse().map_err(|e| {
+                anyhow!("invalid external renderer timeout {}: {}", ms, e)
+            })?)));
+    }
+    Ok(renderer)
+}
+
+/// Parses a size in bytes, with an optional `K`, `M`, or `G` suffix.
+fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim().to_ascii_uppercase();
+    let size = size.strip_suffix('B').unwrap_or(&size);
+
+    let (number, multiplier) = match size.as_bytes().last() {
+        Some(b'K') => (&size[..size.len() - 1], 1 << 10),
+        Some(b'M') => (&size[..size.len() - 1], 1 << 20),
+        Some(b'G') => (&size[..size.len() - 1], 1 << 30),
+        _ => (size, 1),
+    };
+
+    Ok(number.trim().parse::<u64>()? * multiplier)
+}
+
+/// Resolves the address that editors connect to with `--rpc-listen`.
+///
+/// RPCs aren't authenticated, and may read and write files as the current user, so addresses that
+/// other machines can connect to are refused.
+fn loopback_addrs(address: &str) -> Result<Vec<SocketAddr>> {
+    let addrs = address
+        .to_socket_addrs()
+        .map_err(|e| anyhow!("invalid address {}: {}", address, e))?
+        .collect::<Vec<_>>();
+
+    if let Some(addr) = addrs.iter().find(|addr| !addr.ip().is_loopback()) {
+        return Err(anyhow!(
+            "refusing to accept RPCs on {}, which other machines may connect to: use a loopback \
+             address such as 127.0.0.1, and forward it over SSH to reach other machines",
+            addr
+        ));
+    }
+
+    Ok(addrs)
+}
+
+/// Strips the brackets from an IPv6 address literal such as `[::1]`, so that it can be resolved
+/// alongside a separate port.
+fn parse_host(address: &str) -> &str {
+    address
+        .strip_prefix('[')
+        .and_then(|address| address.strip_suffix(']'))
+        .unwrap_or(address)
+}
+
+/// Generates a random token that is hard to guess, for access control.
+fn generate_auth_token() -> Result<String> {
+    let mut bytes = [0; 16];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| anyhow!("could not generate auth token: {}", e))?;
+
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::sync::mpsc;
+
+    use super::{decode_markdown, parse_size, Event};
+
+    #[test]
+    fn parse_sizes() {
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("10K").unwrap(), 10 * 1024);
+        assert_eq!(parse_size("5 MB").unwrap(), 5 * 1024 * 1024);
+        assert_eq!(parse_size("1g").unwrap(), 1024 * 1024 * 1024);
+        assert!(parse_size("big").is_err());
+    }
+
+    #[test]
+    fn decode_markdown_stream() {
+        let reader = b"# One".chain(&b"\x0c"[..]).chain(&b"# Two"[..]);
+        let (events_tx, events) = mpsc::channel();
+        decode_markdown(reader, events_tx);
+
+        let documents = events
+            .iter()
+            .map(|event| match event {
+                Event::Markdown(markdown) => markdown,
+                _ => panic!("unexpected event"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(documents, ["# One", "# One", "# Two"]);
+    }
 }
@@ -0,0 +1,199 @@
+//! Generation of a manual page from the command-line interface, so that the page documents the
+//! options that the binary actually accepts.
+//!
+//! clap renders the help of each section, without wrapping and with each description on the line
+//! after its argument, and the text is translated to roff. Text in backticks is set in bold.
+
+use std::io::{self, Write};
+
+use clap::{App, AppSettings};
+
+/// How far clap indents the description of an argument, on the line after the argument.
+const DESCRIPTION_INDENT: usize = 12;
+
+/// Writes a manual page for the binary with the given name, in section 1, to the writer.
+///
+/// The page has the usage, the about text of the app as its description, and a section for each
+/// kind of argument that the app has.
+///
+/// # Errors
+///
+/// This function fails if writing fails.
+pub fn write(app: &App, bin_name: &str, out: &mut impl Write) -> io::Result<()> {
+    let version = render(app, bin_name, "{version}")?;
+    writeln!(
+        out,
+        ".TH {} 1 \"\" \"{} {}\"",
+        escape(&bin_name.to_uppercase()),
+        escape(bin_name),
+        escape(&version)
+    )?;
+
+    writeln!(out, ".SH NAME")?;
+    writeln!(
+        out,
+        "{} \\- {}",
+        escape_literal(bin_name),
+        escape(env!("CARGO_PKG_DESCRIPTION"))
+    )?;
+
+    writeln!(out, ".SH SYNOPSIS")?;
+    let usage = render(app, bin_name, "{usage}")?;
+    writeln!(out, ".B {}", escape_literal(usage.trim()))?;
+
+    writeln!(out, ".SH DESCRIPTION")?;
+    write_text(out, &render(app, bin_name, "{about}")?)?;
+
+    let sections = [
+        ("ARGUMENTS", "{positionals}"),
+        ("OPTIONS", "{unified}"),
+        ("SUBCOMMANDS", "{subcommands}"),
+    ];
+    for (title, template) in sections {
+        let help = render(app, bin_name, template)?;
+        if help.trim().is_empty() {
+            continue;
+        }
+        writeln!(out, ".SH {}", title)?;
+        write_items(out, &help)?;
+    }
+
+    Ok(())
+}
+
+/// Renders a help template of the app, without wrapping.
+fn render(app: &App, bin_name: &str, template: &str) -> io::Result<String> {
+    let mut app = app
+        .clone()
+        .bin_name(bin_name)
+        .template(template)
+        .set_term_width(0)
+        .setting(AppSettings::NextLineHelp);
+
+    let mut help = Vec::new();
+    app.write_long_help(&mut help)
+        .map_err(|e| io::Error::other(e.message))?;
+    String::from_utf8(help).map_err(io::Error::other)
+}
+
+/// Writes the help of a section of arguments, where the description of each argument is indented
+/// further on the lines that follow it, as a list of tagged paragraphs.
+fn write_items(out: &mut impl Write, help: &str) -> io::Result<()> {
+    for line in help.lines() {
+        let indent = line.len() - line.trim_start().len();
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if indent < DESCRIPTION_INDENT {
+            writeln!(out, ".TP")?;
+            writeln!(out, "\\fB{}\\fR", escape_literal(line))?;
+        } else {
+            writeln!(out, "{}", text_line(line))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes prose as paragraphs. Blocks that are indented are lists of definitions, such as the
+/// procedures of the about text, whose terms are separated from their descriptions by at least
+/// two spaces, or are on a line of their own.
+fn write_text(out: &mut impl Write, text: &str) -> io::Result<()> {
+    let mut paragraph = true;
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            paragraph = true;
+            continue;
+        }
+
+        let indent = line.len() - line.trim_start().len();
+        let line = line.trim();
+        if indent == 0 {
+            if paragraph {
+                writeln!(out, ".PP")?;
+            }
+            writeln!(out, "{}", text_line(line))?;
+        } else if indent <= 4 {
+            writeln!(out, ".TP")?;
+            match line.split_once("  ") {
+                Some((term, description)) => {
+                    writeln!(out, "\\fB{}\\fR", escape_literal(term))?;
+                    writeln!(out, "{}", text_line(description.trim()))?;
+                }
+                None => writeln!(out, "\\fB{}\\fR", escape_literal(line))?,
+            }
+        } else {
+            writeln!(out, "{}", text_line(line))?;
+        }
+        paragraph = false;
+    }
+
+    Ok(())
+}
+
+/// Escapes a line of prose, setting text in backticks in bold. A period or apostrophe at the start
+/// of the line would start a request, so it's escaped too.
+fn text_line(text: &str) -> String {
+    let mut escaped = String::new();
+    if text.starts_with('.') || text.starts_with('\'') {
+        escaped.push_str("\\&");
+    }
+    for (i, part) in text.split('`').enumerate() {
+        if i % 2 == 0 {
+            escaped.push_str(&escape(part));
+        } else {
+            escaped.push_str("\\fB");
+            escaped.push_str(&escape_literal(part));
+            escaped.push_str("\\fR");
+        }
+    }
+    escaped
+}
+
+/// Escapes text that is typed literally, such as options, so that hyphens aren't rendered as
+/// dashes.
+fn escape_literal(text: &str) -> String {
+    escape(text).replace('-', "\\-")
+}
+
+/// Escapes backslashes, which start escape sequences.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\e")
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{App, Arg, SubCommand};
+
+    use super::write;
+
+    #[test]
+    fn man_page() {
+        let app = App::new("tool")
+            .version("1.0")
+            .about("Does things.\n\nProcedures:\n\n    run()   Runs `it`.\n.hidden\n")
+            .arg(Arg::with_name("file").help("The file to read.").index(1))
+            .arg(
+                Arg::with_name("dry-run")
+                    .long("dry-run")
+                    .help("Don't write anything."),
+            )
+            .subcommand(SubCommand::with_name("completions").about("Prints completions."));
+
+        let mut page = Vec::new();
+        write(&app, "my-tool", &mut page).unwrap();
+        let page = String::from_utf8(page).unwrap();
+
+        assert!(page.starts_with(".TH MY-TOOL 1 \"\" \"my-tool 1.0\"\n"));
+        assert!(page.contains("\n.SH SYNOPSIS\n.B my\\-tool [FLAGS] [file] [SUBCOMMAND]\n"));
+        assert!(page.contains("\n.PP\nDoes things.\n"));
+        assert!(page.contains("\n.TP\n\\fBrun()\\fR\nRuns \\fBit\\fR.\n"));
+        assert!(page.contains("\n\\&.hidden\n"));
+        assert!(page.contains("\n.TP\n\\fB<file>\\fR\nThe file to read.\n"));
+        assert!(page.contains("\n.TP\n\\fB\\-\\-dry\\-run\\fR\nDon't write anything.\n"));
+        assert!(page.contains("\n.SH SUBCOMMANDS\n"));
+        assert!(page.contains("\\fBcompletions\\fR\nPrints completions.\n"));
+    }
+}
@@ -0,0 +1,779 @@
+//! Rendering of Org documents to HTML.
+//!
+//! The common subset of Org syntax is supported: headlines with TODO keywords, priorities and
+//! tags, lists with checkboxes, tables, source, example and quote blocks, links and inline markup.
+//! Each headline and its section are rendered in a `<details>` element, so that the preview can
+//! fold them. Drawers, comments and keywords other than `#+TITLE` and `#+TODO` are not displayed.
+//!
+//! Like markdown, block-level elements are annotated with the `data-source-line` of the Org that
+//! they were rendered from.
+
+use handlebars::html_escape;
+
+/// The keywords of headlines that are still to be done, unless the document sets its own.
+const TODO_KEYWORDS: &[&str] = &["TODO", "NEXT", "WAITING", "HOLD"];
+
+/// The keywords of headlines that are done, unless the document sets its own.
+const DONE_KEYWORDS: &[&str] = &["DONE", "CANCELLED", "CANCELED"];
+
+/// File extensions of links that are displayed as images.
+const IMAGE_EXTENSIONS: &[&str] = &[".png", ".jpg", ".jpeg", ".gif", ".svg", ".webp"];
+
+/// Renders an Org document to HTML.
+pub fn to_html(org: &str) -> String {
+    let lines = org.lines().collect::<Vec<_>>();
+    let mut renderer = Renderer {
+        html: String::new(),
+        todo: TODO_KEYWORDS.iter().map(|&k| String::from(k)).collect(),
+        done: DONE_KEYWORDS.iter().map(|&k| String::from(k)).collect(),
+        sections: vec![],
+        lists: vec![],
+    };
+
+    for line in &lines {
+        if let Some(keywords) = keyword(line, "TODO") {
+            renderer.set_keywords(keywords);
+        }
+    }
+
+    renderer.render(&lines, 1);
+    renderer.close_lists(0);
+    while renderer.sections.pop().is_some() {
+        renderer.html.push_str("</details>\n");
+    }
+    renderer.html
+}
+
+struct Renderer {
+    html: String,
+
+    /// The TODO keywords of headlines that are still to be done.
+    todo: Vec<String>,
+
+    /// The TODO keywords of headlines that are done.
+    done: Vec<String>,
+
+    /// The levels of the headlines whose sections are open, innermost last.
+    sections: Vec<usize>,
+
+    /// The indentation of the lists that are open, and whether they're ordered, innermost last.
+    lists: Vec<(usize, bool)>,
+}
+
+impl Renderer {
+    /// Sets the TODO keywords from a `#+TODO:` line, such as `TODO NEXT | DONE`.
+    fn set_keywords(&mut self, keywords: &str) {
+        let (todo, done) = match keywords.split_once('|') {
+            Some((todo, done)) => (todo, done),
+            None => match keywords.rsplit_once(char::is_whitespace) {
+                Some((todo, done)) => (todo, done),
+                None => return,
+            },
+        };
+
+        // Keywords may declare fast access keys, such as `TODO(t)`.
+        let words = |s: &str| {
+            s.split_whitespace()
+                .map(|word| String::from(word.split('(').next().unwrap()))
+                .collect()
+        };
+        self.todo = words(todo);
+        self.done = words(done);
+    }
+
+    /// Renders lines of Org, the first of which is on the given line of the document.
+    fn render(&mut self, lines: &[&str], first_line: usize) {
+        let mut paragraph = vec![];
+        let mut i = 0;
+
+        while i < lines.len() {
+            let line = lines[i];
+            let number = first_line + i;
+            let trimmed = line.trim();
+
+            if !paragraph.is_empty() && (trimmed.is_empty() || !is_paragraph_line(line)) {
+                self.paragraph(&paragraph, number - paragraph.len());
+                paragraph.clear();
+            }
+
+            if trimmed.is_empty() {
+                i += 1;
+                continue;
+            }
+
+            if let Some((level, text)) = headline(line) {
+                self.close_lists(0);
+                while self.sections.last().is_some_and(|&open| open >= level) {
+                    self.sections.pop();
+                    self.html.push_str("</details>\n");
+                }
+                self.sections.push(level);
+                self.headline(level, text, number);
+                i += 1;
+                continue;
+            }
+
+            if let Some(item) = list_item(line) {
+                self.list_item(&item, number);
+                i += 1;
+
+                // Lines indented past the bullet continue the item.
+                while i < lines.len()
+                    && !lines[i].trim().is_empty()
+                    && indentation(lines[i]) > item.indent
+                    && list_item(lines[i]).is_none()
+                {
+                    self.html.push(' ');
+                    self.html.push_str(&inline(lines[i].trim()));
+                    i += 1;
+                }
+                continue;
+            }
+            self.close_lists(indentation(line));
+
+            if let Some(block) = block_name(trimmed) {
+                let end = lines[i + 1..]
+                    .iter()
+                    .position(|line| is_block_end(line.trim(), &block.name))
+                    .map_or(lines.len(), |end| i + 1 + end);
+                self.block(&block, &lines[i + 1..end], number);
+                i = end + 1;
+                continue;
+            }
+
+            if is_drawer_start(trimmed) {
+                i += lines[i..]
+                    .iter()
+                    .position(|line| line.trim().eq_ignore_ascii_case(":END:"))
+                    .map_or(lines.len() - i, |end| end + 1);
+                continue;
+            }
+
+            if let Some(title) = keyword(line, "TITLE") {
+                self.html.push_str(&format!(
+                    "<h1 data-source-line=\"{}\" class=\"org-title\">{}</h1>\n",
+                    number,
+                    inline(title)
+                ));
+            } else if trimmed.starts_with("#+") || trimmed == "#" || trimmed.starts_with("# ") {
+                // Other keywords and comments aren't displayed.
+            } else if trimmed.starts_with('|') {
+                let end = lines[i..]
+                    .iter()
+                    .position(|line| !line.trim().starts_with('|'))
+                    .map_or(lines.len(), |end| i + end);
+                self.table(&lines[i..end], number);
+                i = end;
+                continue;
+            } else if trimmed == ":" || trimmed.starts_with(": ") {
+                let end = lines[i..]
+                    .iter()
+                    .position(|line| {
+                        let line = line.trim();
+                        line != ":" && !line.starts_with(": ")
+                    })
+                    .map_or(lines.len(), |end| i + end);
+                let text = lines[i..end]
+                    .iter()
+                    .map(|line| line.trim().strip_prefix(':').unwrap().trim_start())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                self.html.push_str(&format!(
+                    "<pre data-source-line=\"{}\" class=\"org-example\">{}</pre>\n",
+                    number,
+                    html_escape(&text)
+                ));
+                i = end;
+                continue;
+            } else if is_rule(trimmed) {
+                self.html
+                    .push_str(&format!("<hr data-source-line=\"{}\">\n", number));
+            } else if is_planning(trimmed) {
+                self.html.push_str(&format!(
+                    "<p data-source-line=\"{}\" class=\"org-planning\">{}</p>\n",
+                    number,
+                    html_escape(trimmed)
+                ));
+            } else {
+                paragraph.push(trimmed);
+            }
+
+            i += 1;
+        }
+
+        if !paragraph.is_empty() {
+            self.paragraph(&paragraph, first_line + lines.len() - paragraph.len());
+        }
+    }
+
+    fn headline(&mut self, level: usize, text: &str, line: usize) {
+        let mut text = text.trim();
+        let mut heading = String::new();
+
+        if let Some((word, rest)) = split_word(text) {
+            let class = if self.todo.iter().any(|k| k == word) {
+                Some("org-todo")
+            } else if self.done.iter().any(|k| k == word) {
+                Some("org-done")
+            } else {
+                None
+            };
+            if let Some(class) = class {
+                heading.push_str(&format!(
+                    "<span class=\"org-keyword {}\">{}</span> ",
+                    class,
+                    html_escape(word)
+                ));
+                text = rest;
+            }
+        }
+
+        if let Some(rest) = text.strip_prefix("[#") {
+            if let Some((priority, rest)) = rest.split_once(']') {
+                heading.push_str(&format!(
+                    "<span class=\"org-priority\">[#{}]</span> ",
+                    html_escape(priority)
+                ));
+                text = rest.trim_start();
+            }
+        }
+
+        let (title, tags) = split_tags(text);
+        heading.push_str(&inline(title));
+        if !tags.is_empty() {
+            heading.push_str(" <span class=\"org-tags\">");
+            for tag in tags {
+                heading.push_str(&format!(
+                    "<span class=\"org-tag\">{}</span>",
+                    html_escape(tag)
+                ));
+            }
+            heading.push_str("</span>");
+        }
+
+        let tag = format!("h{}", level.min(6));
+        self.html.push_str(&format!(
+            "<details open class=\"org-section\">\n<summary><{} data-source-line=\"{}\">{}</{}></summary>\n",
+            tag, line, heading, tag
+        ));
+    }
+
+    fn paragraph(&mut self, lines: &[&str], line: usize) {
+        self.html.push_str(&format!(
+            "<p data-source-line=\"{}\">{}</p>\n",
+            line,
+            inline(&lines.join("\n"))
+        ));
+    }
+
+    fn list_item(&mut self, item: &ListItem, line: usize) {
+        // Items that are less indented end the items of deeper lists.
+        self.close_lists(item.indent + 1);
+
+        match self.lists.last() {
+            Some(&(indent, ordered)) if indent == item.indent && ordered == item.ordered => {
+                self.html.push_str("</li>\n");
+            }
+            Some(&(indent, _)) if indent == item.indent => {
+                self.close_lists(item.indent);
+                self.open_list(item, line);
+            }
+            _ => self.open_list(item, line),
+        }
+
+        self.html
+            .push_str(&format!("<li data-source-line=\"{}\">", line));
+        let text = match item.checkbox {
+            Some(checked) => {
+                self.html.push_str(&format!(
+                    "<input type=\"checkbox\" disabled{}> ",
+                    if checked { " checked" } else { "" }
+                ));
+                item.text
+            }
+            None => item.text,
+        };
+
+        match text.split_once(" :: ") {
+            Some((term, description)) => self.html.push_str(&format!(
+                "<strong>{}</strong>: {}",
+                inline(term),
+                inline(description)
+            )),
+            None => self.html.push_str(&inline(text)),
+        }
+    }
+
+    fn open_list(&mut self, item: &ListItem, line: usize) {
+        let tag = if item.ordered { "ol" } else { "ul" };
+        self.html
+            .push_str(&format!("<{} data-source-line=\"{}\">\n", tag, line));
+        self.lists.push((item.indent, item.ordered));
+    }
+
+    /// Closes the lists that are indented at least as much as the given indentation.
+    fn close_lists(&mut self, indent: usize) {
+        while let Some(&(open, ordered)) = self.lists.last() {
+            if open < indent {
+                break;
+            }
+            self.lists.pop();
+            self.html.push_str(if ordered {
+                "</li>\n</ol>\n"
+            } else {
+                "</li>\n</ul>\n"
+            });
+        }
+    }
+
+    fn block(&mut self, block: &Block, lines: &[&str], line: usize) {
+        match &*block.name {
+            "SRC" | "EXAMPLE" | "EXPORT" => {
+                let code = lines.join("\n");
+                let class = match block.parameters.split_whitespace().next() {
+                    Some(language) if block.name == "SRC" => {
+                        format!(" class=\"language-{}\"", html_escape(language))
+                    }
+                    _ => String::new(),
+                };
+                self.html.push_str(&format!(
+                    "<pre data-source-line=\"{}\"><code{}>{}\n</code></pre>\n",
+                    line,
+                    class,
+                    html_escape(&code)
+                ));
+            }
+            "QUOTE" => {
+                self.html
+                    .push_str(&format!("<blockquote data-source-line=\"{}\">\n", line));
+                self.render_nested(lines, line + 1);
+                self.html.push_str("</blockquote>\n");
+            }
+            "VERSE" => {
+                let verse = lines
+                    .iter()
+                    .map(|line| inline(line))
+                    .collect::<Vec<_>>()
+                    .join("<br>\n");
+                self.html.push_str(&format!(
+                    "<p data-source-line=\"{}\" class=\"org-verse\">{}</p>\n",
+                    line, verse
+                ));
+            }
+            name => {
+                self.html.push_str(&format!(
+                    "<div data-source-line=\"{}\" class=\"org-{}\">\n",
+                    line,
+                    html_escape(&name.to_ascii_lowercase())
+                ));
+                self.render_nested(lines, line + 1);
+                self.html.push_str("</div>\n");
+            }
+        }
+    }
+
+    /// Renders the contents of a block, which may not contain headlines that close the sections
+    /// around it.
+    fn render_nested(&mut self, lines: &[&str], first_line: usize) {
+        let sections = std::mem::take(&mut self.sections);
+        let lists = std::mem::take(&mut self.lists);
+        self.render(lines, first_line);
+        self.close_lists(0);
+        while self.sections.pop().is_some() {
+            self.html.push_str("</details>\n");
+        }
+        self.sections = sections;
+        self.lists = lists;
+    }
+
+    fn table(&mut self, lines: &[&str], line: usize) {
+        let is_rule = |line: &str| line.trim().starts_with("|-");
+
+        // Rows before the first horizontal rule are the header, if any rows follow it.
+        let header_rows = match lines.iter().position(|line| is_rule(line)) {
+            Some(rule) if rule > 0 && lines[rule..].iter().any(|line| !is_rule(line)) => rule,
+            _ => 0,
+        };
+
+        self.html
+            .push_str(&format!("<table data-source-line=\"{}\">\n", line));
+        for (i, row) in lines.iter().enumerate() {
+            if is_rule(row) {
+                continue;
+            }
+
+            let row = row.trim().trim_start_matches('|');
+            let row = row.strip_suffix('|').unwrap_or(row);
+            let tag = if i < header_rows { "th" } else { "td" };
+            self.html.push_str("<tr>");
+            for cell in row.split('|') {
+                self.html
+                    .push_str(&format!("<{}>{}</{}>", tag, inline(cell.trim()), tag));
+            }
+            self.html.push_str("</tr>\n");
+        }
+        self.html.push_str("</table>\n");
+    }
+}
+
+struct ListItem<'a> {
+    indent: usize,
+    ordered: bool,
+    checkbox: Option<bool>,
+    text: &'a str,
+}
+
+struct Block {
+    /// The name of the block, in uppercase, such as `SRC`.
+    name: String,
+    parameters: String,
+}
+
+/// Returns the level and text of a headline.
+fn headline(line: &str) -> Option<(usize, &str)> {
+    let level = line.bytes().take_while(|&b| b == b'*').count();
+    if level == 0 {
+        return None;
+    }
+
+    match line[level..].strip_prefix(' ') {
+        Some(text) => Some((level, text)),
+        None if line.len() == level => Some((level, "")),
+        None => None,
+    }
+}
+
+fn list_item(line: &str) -> Option<ListItem<'_>> {
+    let indent = indentation(line);
+    let rest = &line[indent..];
+
+    let (ordered, text) =
+        if let Some(text) = rest.strip_prefix("- ").or_else(|| rest.strip_prefix("+ ")) {
+            (false, text)
+        } else if let Some(text) = rest.strip_prefix("* ").filter(|_| indent > 0) {
+            // Unindented stars are headlines.
+            (false, text)
+        } else {
+            let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+            let text = rest[digits..]
+                .strip_prefix(". ")
+                .or_else(|| rest[digits..].strip_prefix(") "))
+                .filter(|_| digits > 0)?;
+            (true, text)
+        };
+
+    let text = text.trim_start();
+    let (checkbox, text) = match text.get(..3) {
+        Some("[ ]") => (Some(false), text[3..].trim_start()),
+        Some("[X]") | Some("[x]") | Some("[-]") => (Some(true), text[3..].trim_start()),
+        _ => (None, text),
+    };
+
+    Some(ListItem {
+        indent,
+        ordered,
+        checkbox,
+        text,
+    })
+}
+
+/// Returns the name and parameters of the block that a line begins, such as `#+BEGIN_SRC rust`.
+fn block_name(line: &str) -> Option<Block> {
+    let rest = strip_prefix_ignore_case(line, "#+BEGIN_")?;
+    let (name, parameters) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    if name.is_empty() {
+        return None;
+    }
+
+    Some(Block {
+        name: name.to_ascii_uppercase(),
+        parameters: String::from(parameters.trim()),
+    })
+}
+
+fn is_block_end(line: &str, name: &str) -> bool {
+    strip_prefix_ignore_case(line, "#+END_").is_some_and(|end| end.eq_ignore_ascii_case(name))
+}
+
+/// Returns whether a line starts a drawer, such as `:PROPERTIES:`.
+fn is_drawer_start(line: &str) -> bool {
+    line.len() > 2
+        && line.starts_with(':')
+        && line.ends_with(':')
+        && !line.eq_ignore_ascii_case(":END:")
+        && line[1..line.len() - 1]
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+/// Returns whether a line is a horizontal rule of five or more dashes.
+fn is_rule(line: &str) -> bool {
+    line.len() >= 5 && line.bytes().all(|b| b == b'-')
+}
+
+/// Returns whether a line schedules a headline, such as `SCHEDULED: <2024-01-01 Mon>`.
+fn is_planning(line: &str) -> bool {
+    ["SCHEDULED:", "DEADLINE:", "CLOSED:"]
+        .iter()
+        .any(|keyword| line.starts_with(keyword))
+}
+
+/// Returns whether a line may continue a paragraph.
+fn is_paragraph_line(line: &str) -> bool {
+    let trimmed = line.trim();
+    headline(line).is_none()
+        && list_item(line).is_none()
+        && !trimmed.starts_with('#')
+        && !is_rule(trimmed)
+        && !trimmed.starts_with('|')
+        && !trimmed.starts_with(": ")
+        && trimmed != ":"
+        && !is_drawer_start(trimmed)
+        && !is_planning(trimmed)
+}
+
+/// Returns the value of a keyword line, such as `#+TITLE: Notes`.
+fn keyword<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let rest = strip_prefix_ignore_case(line.trim(), "#+")?;
+    let rest = strip_prefix_ignore_case(rest, name)?;
+    Some(rest.strip_prefix(':')?.trim())
+}
+
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    match s.get(..prefix.len()) {
+        Some(start) if start.eq_ignore_ascii_case(prefix) => Some(&s[prefix.len()..]),
+        _ => None,
+    }
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// Splits the first word from the rest of a headline.
+fn split_word(text: &str) -> Option<(&str, &str)> {
+    match text.split_once(' ') {
+        Some((word, rest)) => Some((word, rest.trim_start())),
+        None if !text.is_empty() => Some((text, "")),
+        None => None,
+    }
+}
+
+/// Splits the tags from the end of a headline, such as `Title :work:urgent:`.
+fn split_tags(text: &str) -> (&str, Vec<&str>) {
+    let (title, tags) = match text.rsplit_once(char::is_whitespace) {
+        Some((title, tags)) => (title.trim_end(), tags),
+        None => ("", text),
+    };
+
+    let valid = tags.len() > 2
+        && tags.starts_with(':')
+        && tags.ends_with(':')
+        && tags[1..tags.len() - 1].split(':').all(|tag| {
+            !tag.is_empty()
+                && tag
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || "_@#%".contains(c))
+        });
+    if !valid {
+        return (text, vec![]);
+    }
+
+    (title, tags[1..tags.len() - 1].split(':').collect())
+}
+
+/// Renders the inline markup of Org text: links, and emphasis such as `*bold*` and `=verbatim=`.
+fn inline(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut html = String::new();
+    let mut plain = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if text[i..].starts_with("[[") {
+            if let Some((len, link)) = link(&text[i..]) {
+                html.push_str(&html_escape(&text[plain..i]));
+                html.push_str(&link);
+                i += len;
+                plain = i;
+                continue;
+            }
+        }
+
+        let marker = bytes[i];
+        let starts_emphasis = i == 0 || b" \t\n({'\"-".contains(&bytes[i - 1]);
+        if let (Some(tags), true) = (emphasis(marker), starts_emphasis) {
+            if let Some(end) = closing_marker(text, i, marker) {
+                let content = &text[i + 1..end];
+                let content = match marker {
+                    b'=' | b'~' => html_escape(content),
+                    _ => inline(content),
+                };
+                html.push_str(&html_escape(&text[plain..i]));
+                html.push_str(&format!("{}{}{}", tags.0, content, tags.1));
+                i = end + 1;
+                plain = i;
+                continue;
+            }
+        }
+
+        i += 1;
+    }
+
+    html.push_str(&html_escape(&text[plain..]));
+    html
+}
+
+/// Returns the tags that surround emphasized text with a marker.
+fn emphasis(marker: u8) -> Option<(&'static str, &'static str)> {
+    Some(match marker {
+        b'*' => ("<strong>", "</strong>"),
+        b'/' => ("<em>", "</em>"),
+        b'_' => ("<u>", "</u>"),
+        b'+' => ("<del>", "</del>"),
+        b'=' | b'~' => ("<code>", "</code>"),
+        _ => return None,
+    })
+}
+
+/// Returns the index of the marker that closes emphasis opened at an index.
+fn closing_marker(text: &str, start: usize, marker: u8) -> Option<usize> {
+    let bytes = text.as_bytes();
+    if bytes.get(start + 1).is_none_or(u8::is_ascii_whitespace) {
+        return None;
+    }
+
+    (start + 2..bytes.len()).find(|&end| {
+        bytes[end] == marker
+            && !bytes[end - 1].is_ascii_whitespace()
+            && bytes
+                .get(end + 1)
+                .is_none_or(|&b| b" \t\n-.,;:!?')}\"".contains(&b))
+    })
+}
+
+/// Renders a link, such as `[[https://example.com][Example]]`, returning its length.
+fn link(text: &str) -> Option<(usize, String)> {
+    let end = text.find("]]")?;
+    let inner = &text[2..end];
+    let (target, description) = match inner.split_once("][") {
+        Some((target, description)) => (target, Some(description)),
+        None => (inner, None),
+    };
+    let target = target.strip_prefix("file:").unwrap_or(target);
+
+    let is_image = IMAGE_EXTENSIONS
+        .iter()
+        .any(|extension| target.to_ascii_lowercase().ends_with(extension));
+    let html = match description {
+        None if is_image => format!("<img src=\"{}\" alt=\"\">", html_escape(target)),
+        Some(description) => format!(
+            "<a href=\"{}\">{}</a>",
+            html_escape(target),
+            inline(description)
+        ),
+        None => format!(
+            "<a href=\"{}\">{}</a>",
+            html_escape(target),
+            html_escape(target)
+        ),
+    };
+
+    Some((end + 2, html))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{inline, to_html};
+
+    #[test]
+    fn headlines() {
+        let html = to_html(
+            "\
+#+TITLE: Notes
+* TODO [#A] Write /docs/ :work:urgent:
+Some text.
+** DONE Review
+* Other",
+        );
+        assert_eq!(
+            html,
+            "\
+<h1 data-source-line=\"1\" class=\"org-title\">Notes</h1>
+<details open class=\"org-section\">
+<summary><h1 data-source-line=\"2\"><span class=\"org-keyword org-todo\">TODO</span> \
+<span class=\"org-priority\">[#A]</span> Write <em>docs</em> <span class=\"org-tags\">\
+<span class=\"org-tag\">work</span><span class=\"org-tag\">urgent</span></span></h1></summary>
+<p data-source-line=\"3\">Some text.</p>
+<details open class=\"org-section\">
+<summary><h2 data-source-line=\"4\"><span class=\"org-keyword org-done\">DONE</span> Review</h2></summary>
+</details>
+</details>
+<details open class=\"org-section\">
+<summary><h1 data-source-line=\"5\">Other</h1></summary>
+</details>
+"
+        );
+    }
+
+    #[test]
+    fn custom_keywords() {
+        let html = to_html("#+TODO: IDEA | SHIPPED\n* IDEA One\n* SHIPPED Two\n* TODO Three");
+        assert!(html.contains("org-todo\">IDEA</span>"));
+        assert!(html.contains("org-done\">SHIPPED</span>"));
+        assert!(html.contains("<h1 data-source-line=\"4\">TODO Three</h1>"));
+    }
+
+    #[test]
+    fn blocks() {
+        let html = to_html(
+            "\
+- [X] one
+  continued
+- [ ] two
+  1. nested
+
+#+BEGIN_SRC rust
+fn main() {}
+#+END_SRC
+:PROPERTIES:
+:ID: 1
+:END:
+| a | b |
+|---+---|
+| 1 | 2 |",
+        );
+        assert_eq!(
+            html,
+            "\
+<ul data-source-line=\"1\">
+<li data-source-line=\"1\"><input type=\"checkbox\" disabled checked> one continued</li>
+<li data-source-line=\"3\"><input type=\"checkbox\" disabled> two<ol data-source-line=\"4\">
+<li data-source-line=\"4\">nested</li>
+</ol>
+</li>
+</ul>
+<pre data-source-line=\"6\"><code class=\"language-rust\">fn main() {}
+</code></pre>
+<table data-source-line=\"12\">
+<tr><th>a</th><th>b</th></tr>
+<tr><td>1</td><td>2</td></tr>
+</table>
+"
+        );
+    }
+
+    #[test]
+    fn inline_markup() {
+        assert_eq!(
+            inline("*bold* /it/ =a<b= +del+ _u_ ~c~"),
+            "<strong>bold</strong> <em>it</em> <code>a&lt;b</code> <del>del</del> <u>u</u> <code>c</code>"
+        );
+        assert_eq!(inline("2*3*4 and a/b/c"), "2*3*4 and a/b/c");
+        assert_eq!(
+            inline("see [[https://example.com][the *site*]] or [[./cat.png]]"),
+            "see <a href=\"https://example.com\">the <strong>site</strong></a> or \
+             <img src=\"./cat.png\" alt=\"\">"
+        );
+    }
+}
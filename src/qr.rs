@@ -0,0 +1,548 @@
+//! QR codes of URLs, so that the preview may be opened on a phone or tablet by scanning it.
+//!
+//! Text is encoded as bytes with the medium error correction level, in the smallest version (size)
+//! of QR code that fits, following ISO/IEC 18004. Only what's needed for URLs is supported: other
+//! encoding modes, such as the compact alphanumeric mode, aren't used.
+
+use std::fmt::Write;
+
+/// The number of error correction codewords in each block, by version, at the medium error
+/// correction level. Index 0 is unused.
+const ECC_CODEWORDS_PER_BLOCK: [usize; 41] = [
+    0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26, 26, 28, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+];
+
+/// The number of error correction blocks, by version, at the medium error correction level.
+const ERROR_CORRECTION_BLOCKS: [usize; 41] = [
+    0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10, 10, 11, 13, 14, 16, 17, 17, 18, 20, 21, 23,
+    25, 26, 28, 29, 31, 33, 35, 37, 38, 40, 43, 45, 47, 49,
+];
+
+/// The width of the light border that scanners need around the code, in modules.
+const QUIET_ZONE: usize = 4;
+
+/// A QR code: a square grid of dark and light modules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QrCode {
+    size: usize,
+    modules: Vec<bool>,
+
+    /// Whether each module is part of a pattern, rather than of the data, so it isn't masked.
+    functions: Vec<bool>,
+}
+
+impl QrCode {
+    /// Encodes text, or returns `None` if it's too long to fit in a QR code.
+    pub fn encode(text: &str) -> Option<QrCode> {
+        let bytes = text.as_bytes();
+        let version = (1..=40).find(|&version| {
+            let count_bits = if version < 10 { 8 } else { 16 };
+            4 + count_bits + bytes.len() * 8 <= data_codewords(version) * 8
+        })?;
+
+        // The byte mode indicator, the length, and the bytes.
+        let mut bits = Bits::default();
+        bits.push(0b0100, 4);
+        bits.push(bytes.len() as u32, if version < 10 { 8 } else { 16 });
+        for &byte in bytes {
+            bits.push(u32::from(byte), 8);
+        }
+
+        // The data is terminated, padded to a whole byte, and then padded with alternating bytes
+        // to fill the capacity of the version.
+        let capacity = data_codewords(version) * 8;
+        bits.push(0, (capacity - bits.len).min(4));
+        bits.push(0, (8 - bits.len % 8) % 8);
+        let mut data = bits.bytes;
+        for &pad in [0xEC, 0x11].iter().cycle() {
+            if data.len() == data_codewords(version) {
+                break;
+            }
+            data.push(pad);
+        }
+
+        let size = version * 4 + 17;
+        let mut qr = QrCode {
+            size,
+            modules: vec![false; size * size],
+            functions: vec![false; size * size],
+        };
+        qr.draw_function_patterns(version);
+        qr.draw_codewords(&add_error_correction(version, &data));
+
+        // The mask that leaves the fewest patterns that could confuse scanners is used.
+        let mask = (0..8)
+            .min_by_key(|&mask| {
+                qr.apply_mask(mask);
+                qr.draw_format_bits(mask);
+                let penalty = qr.penalty();
+                qr.apply_mask(mask);
+                penalty
+            })
+            .unwrap();
+        qr.apply_mask(mask);
+        qr.draw_format_bits(mask);
+
+        Some(qr)
+    }
+
+    /// Returns the number of modules on each side of the code.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns whether the module at a column and row is dark.
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    /// Returns an SVG image of the code, with a light border.
+    pub fn to_svg(&self) -> String {
+        let size = self.size + QUIET_ZONE * 2;
+        let mut path = String::new();
+        for y in 0..self.size {
+            for x in 0..self.size {
+                if self.get(x, y) {
+                    let _ = write!(path, "M{},{}h1v1h-1z", x + QUIET_ZONE, y + QUIET_ZONE);
+                }
+            }
+        }
+
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {0} {0}\" \
+             shape-rendering=\"crispEdges\">\
+             <rect width=\"{0}\" height=\"{0}\" fill=\"#fff\"/>\
+             <path d=\"{1}\" fill=\"#000\"/></svg>\n",
+            size, path
+        )
+    }
+
+    /// Returns the code drawn with half blocks, two rows of modules to a line, in black on a white
+    /// background whatever the colors of the terminal are.
+    pub fn to_terminal(&self) -> String {
+        let size = self.size + QUIET_ZONE * 2;
+        let dark = |x: usize, y: usize| {
+            x >= QUIET_ZONE
+                && y >= QUIET_ZONE
+                && x < self.size + QUIET_ZONE
+                && y < self.size + QUIET_ZONE
+                && self.get(x - QUIET_ZONE, y - QUIET_ZONE)
+        };
+
+        let mut output = String::new();
+        for y in (0..size).step_by(2) {
+            output.push_str("\x1b[30;107m");
+            for x in 0..size {
+                output.push(match (dark(x, y), dark(x, y + 1)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            output.push_str("\x1b[0m\n");
+        }
+        output
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.functions[y * self.size + x] = true;
+    }
+
+    /// Draws the patterns that scanners use to find and orient the code, and reserves the space
+    /// of the format bits.
+    fn draw_function_patterns(&mut self, version: usize) {
+        let size = self.size;
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+
+        // Finder patterns, in each corner except the bottom right, with their separators.
+        for &(x, y) in [(3, 3), (size - 4, 3), (3, size - 4)].iter() {
+            for dy in -4..=4isize {
+                for dx in -4..=4isize {
+                    let (xx, yy) = (x as isize + dx, y as isize + dy);
+                    if xx < 0 || yy < 0 || xx >= size as isize || yy >= size as isize {
+                        continue;
+                    }
+                    let distance = dx.abs().max(dy.abs());
+                    self.set_function(xx as usize, yy as usize, distance != 2 && distance != 4);
+                }
+            }
+        }
+
+        // Alignment patterns, except where they would overlap the finder patterns.
+        let positions = alignment_positions(version);
+        let last = positions.len().saturating_sub(1);
+        for (i, &x) in positions.iter().enumerate() {
+            for (j, &y) in positions.iter().enumerate() {
+                let corner = (i == 0 || i == last) && (j == 0 || j == last);
+                if corner && (i == 0 || j == 0) {
+                    continue;
+                }
+                for dy in -2..=2isize {
+                    for dx in -2..=2isize {
+                        let dark = dx.abs().max(dy.abs()) != 1;
+                        self.set_function(
+                            (x as isize + dx) as usize,
+                            (y as isize + dy) as usize,
+                            dark,
+                        );
+                    }
+                }
+            }
+        }
+
+        self.draw_format_bits(0);
+
+        if version >= 7 {
+            let mut remainder = version as u32;
+            for _ in 0..12 {
+                remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1F25);
+            }
+            let bits = (version as u32) << 12 | remainder;
+            for i in 0..18 {
+                let dark = bit(bits, i);
+                let (a, b) = (size - 11 + i % 3, i / 3);
+                self.set_function(a, b, dark);
+                self.set_function(b, a, dark);
+            }
+        }
+    }
+
+    /// Draws the error correction level and the mask, twice.
+    fn draw_format_bits(&mut self, mask: u32) {
+        let bits = format_bits(mask);
+        let size = self.size;
+
+        for i in 0..6 {
+            self.set_function(8, i, bit(bits, i));
+        }
+        self.set_function(8, 7, bit(bits, 6));
+        self.set_function(8, 8, bit(bits, 7));
+        self.set_function(7, 8, bit(bits, 8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(bits, i));
+        }
+
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(bits, i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(bits, i));
+        }
+        self.set_function(8, size - 8, true);
+    }
+
+    /// Draws the codewords in the zigzag of pairs of columns from the bottom right, skipping the
+    /// patterns.
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size - 1;
+        loop {
+            // The vertical timing pattern is skipped.
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vertical in 0..size {
+                for j in 0..2 {
+                    let x = right - j;
+                    let y = if upward {
+                        size - 1 - vertical
+                    } else {
+                        vertical
+                    };
+                    if !self.functions[y * size + x] && i < codewords.len() * 8 {
+                        self.modules[y * size + x] = bit(u32::from(codewords[i / 8]), 7 - i % 8);
+                        i += 1;
+                    }
+                }
+            }
+            if right < 3 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    /// Inverts the data modules where the mask's condition holds. Applying a mask twice undoes it.
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                let index = y * self.size + x;
+                if invert && !self.functions[index] {
+                    self.modules[index] = !self.modules[index];
+                }
+            }
+        }
+    }
+
+    /// Scores the patterns of the code that make it harder to scan: long runs of one color,
+    /// blocks of one color, patterns that look like finder patterns, and an imbalance of dark and
+    /// light modules.
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let mut penalty = 0;
+
+        for transposed in [false, true].iter() {
+            let get = |a: usize, b: usize| {
+                if *transposed {
+                    self.get(b, a)
+                } else {
+                    self.get(a, b)
+                }
+            };
+            for b in 0..size {
+                let line = (0..size).map(|a| get(a, b)).collect::<Vec<_>>();
+
+                let mut run = 1;
+                for a in 1..=size {
+                    if a < size && line[a] == line[a - 1] {
+                        run += 1;
+                        continue;
+                    }
+                    if run >= 5 {
+                        penalty += run - 2;
+                    }
+                    run = 1;
+                }
+
+                // A dark, light, dark, dark, dark, light, dark pattern with four light modules
+                // (or the edge of the code) on either side.
+                let finder = [true, false, true, true, true, false, true];
+                for a in 0..=size.saturating_sub(7) {
+                    if line[a..a + 7] != finder {
+                        continue;
+                    }
+                    let light = |range: std::ops::Range<isize>| {
+                        range
+                            .filter(|&i| i >= 0 && (i as usize) < size)
+                            .all(|i| !line[i as usize])
+                    };
+                    let a = a as isize;
+                    if light(a - 4..a) || light(a + 7..a + 11) {
+                        penalty += 40;
+                    }
+                }
+            }
+        }
+
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let dark = self.get(x, y);
+                if dark == self.get(x + 1, y)
+                    && dark == self.get(x, y + 1)
+                    && dark == self.get(x + 1, y + 1)
+                {
+                    penalty += 3;
+                }
+            }
+        }
+
+        let dark = self.modules.iter().filter(|&&dark| dark).count();
+        let total = size * size;
+        let deviation = (dark * 20).max(total * 10) - (dark * 20).min(total * 10);
+        penalty += deviation.div_ceil(total).saturating_sub(1) * 10;
+
+        penalty
+    }
+}
+
+/// Bits that are packed into bytes, most significant bit first.
+#[derive(Default)]
+struct Bits {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl Bits {
+    fn push(&mut self, value: u32, count: usize) {
+        for i in (0..count).rev() {
+            if self.len.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if bit(value, i) {
+                *self.bytes.last_mut().unwrap() |= 0x80 >> (self.len % 8);
+            }
+            self.len += 1;
+        }
+    }
+}
+
+fn bit(value: u32, i: usize) -> bool {
+    (value >> i) & 1 != 0
+}
+
+/// Returns the number of modules of a version that hold codewords, rather than patterns.
+fn data_modules(version: usize) -> usize {
+    let mut modules = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let alignments = version / 7 + 2;
+        modules -= (25 * alignments - 10) * alignments - 55;
+        if version >= 7 {
+            modules -= 36;
+        }
+    }
+    modules
+}
+
+/// Returns the number of codewords of a version that hold data, rather than error correction.
+fn data_codewords(version: usize) -> usize {
+    data_modules(version) / 8 - ECC_CODEWORDS_PER_BLOCK[version] * ERROR_CORRECTION_BLOCKS[version]
+}
+
+/// Returns the rows and columns of the centers of the alignment patterns of a version.
+fn alignment_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return vec![];
+    }
+
+    let count = version / 7 + 2;
+    let step = (version * 8 + count * 3 + 5) / (count * 4 - 4) * 2;
+    let mut positions = (0..count - 1)
+        .map(|i| version * 4 + 10 - i * step)
+        .collect::<Vec<_>>();
+    positions.push(6);
+    positions.reverse();
+    positions
+}
+
+/// Returns the 15 format bits of the medium error correction level and a mask.
+fn format_bits(mask: u32) -> u32 {
+    // The medium error correction level is `00`.
+    let data = mask;
+    let mut remainder = data;
+    for _ in 0..10 {
+        remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+    }
+    (data << 10 | remainder) ^ 0x5412
+}
+
+/// Splits the data into blocks, appends the error correction codewords of each block, and
+/// interleaves the blocks.
+fn add_error_correction(version: usize, data: &[u8]) -> Vec<u8> {
+    let blocks_count = ERROR_CORRECTION_BLOCKS[version];
+    let ecc_len = ECC_CODEWORDS_PER_BLOCK[version];
+    let raw_codewords = data_modules(version) / 8;
+    let short_blocks = blocks_count - raw_codewords % blocks_count;
+    let short_block_len = raw_codewords / blocks_count;
+
+    let divisor = reed_solomon_divisor(ecc_len);
+    let mut blocks = vec![];
+    let mut start = 0;
+    for i in 0..blocks_count {
+        let len = short_block_len - ecc_len + usize::from(i >= short_blocks);
+        let mut block = data[start..start + len].to_vec();
+        start += len;
+        let ecc = reed_solomon_remainder(&block, &divisor);
+        // Short blocks are padded so that the blocks can be interleaved by index.
+        if i < short_blocks {
+            block.push(0);
+        }
+        block.extend(ecc);
+        blocks.push(block);
+    }
+
+    let mut codewords = vec![];
+    for i in 0..blocks[0].len() {
+        for (j, block) in blocks.iter().enumerate() {
+            if i != short_block_len - ecc_len || j >= short_blocks {
+                codewords.push(block[i]);
+            }
+        }
+    }
+    codewords
+}
+
+/// Returns the coefficients of the generator polynomial of a degree, without the leading term.
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut divisor = vec![0; degree];
+    divisor[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            divisor[j] = gf_multiply(divisor[j], root);
+            if j + 1 < degree {
+                divisor[j] ^= divisor[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+    divisor
+}
+
+/// Returns the error correction codewords of data.
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut remainder = vec![0; divisor.len()];
+    for &byte in data {
+        let factor = byte ^ remainder.remove(0);
+        remainder.push(0);
+        for (r, &d) in remainder.iter_mut().zip(divisor) {
+            *r ^= gf_multiply(d, factor);
+        }
+    }
+    remainder
+}
+
+/// Multiplies two elements of the Galois field GF(2^8) of QR codes.
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u32 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11D);
+        z ^= ((u32::from(y) >> i) & 1) * u32::from(x);
+    }
+    z as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_bits, reed_solomon_divisor, reed_solomon_remainder, QrCode};
+
+    #[test]
+    fn error_correction() {
+        // The example of ISO/IEC 18004: `01234567` in numeric mode, as version 1-M.
+        let data = [
+            0x10, 0x20, 0x0C, 0x56, 0x61, 0x80, 0xEC, 0x11, 0xEC, 0x11, 0xEC, 0x11, 0xEC, 0x11,
+            0xEC, 0x11,
+        ];
+        assert_eq!(
+            reed_solomon_remainder(&data, &reed_solomon_divisor(10)),
+            [0xA5, 0x24, 0xD4, 0xC1, 0xED, 0x36, 0xC7, 0x87, 0x2C, 0x55]
+        );
+
+        assert_eq!(format_bits(0), 0x5412);
+        assert_eq!(format_bits(7), 0x4AA0);
+    }
+
+    #[test]
+    fn encode() {
+        let qr = QrCode::encode("http://192.168.1.20:8080/").unwrap();
+        assert_eq!(qr.size(), 25);
+
+        // The finder pattern in the top left corner, and the dark module beside it.
+        assert!(qr.get(0, 0) && qr.get(6, 6) && qr.get(2, 2));
+        assert!(!qr.get(1, 1) && !qr.get(7, 7));
+        assert!(qr.get(8, qr.size() - 8));
+
+        // Versions from 7 have version information.
+        assert_eq!(QrCode::encode(&"x".repeat(120)).unwrap().size(), 45);
+        assert!(QrCode::encode(&"x".repeat(3000)).is_none());
+
+        assert!(qr.to_svg().starts_with("<svg"));
+    }
+}
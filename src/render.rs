@@ -0,0 +1,2021 @@
+//! Rendering markdown to HTML.
+
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use handlebars::html_escape;
+use latex2mathml::DisplayStyle;
+use log::*;
+use pulldown_cmark::{
+    BlockQuoteKind, CodeBlockKind, CowStr, Event, LinkType, MetadataBlockKind, Options, Parser,
+    Tag, TagEnd, TextMergeWithOffset,
+};
+use url::{Position, Url};
+
+use crate::diagram::Diagrams;
+use crate::sanitize::Sanitizer;
+
+/// Marks the position of a source line annotation in the rendered HTML.
+///
+/// NUL characters in the input are replaced before rendering, so this can never appear in HTML
+/// rendered from markdown.
+const SOURCE_LINE_MARKER: char = '\0';
+
+/// The beginnings of the attributes of rendered HTML that refer to lines of the markdown.
+pub const LINE_ATTRIBUTES: &[&str] = &["data-source-line=\"", "data-task-line=\""];
+
+/// The number of renders whose HTML is kept by a [`RenderCache`].
+const RENDER_CACHE_SIZE: usize = 16;
+
+/// Beginnings of HTML blocks that may contain blank lines, and the text that ends them.
+const MULTILINE_HTML_BLOCKS: &[(&str, &str)] = &[
+    ("<!--", "-->"),
+    ("<pre", "</pre>"),
+    ("<script", "</script>"),
+    ("<style", "</style>"),
+    ("<textarea", "</textarea>"),
+    ("<?", "?>"),
+    ("<![CDATA[", "]]>"),
+];
+
+/// Flavors of markdown supported by the built-in renderer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flavor {
+    /// Strict CommonMark, without any extensions.
+    CommonMark,
+
+    /// GitHub Flavored Markdown.
+    Gfm,
+}
+
+impl Flavor {
+    /// Returns the extensions that are enabled by default for the flavor.
+    pub fn extensions(self) -> Options {
+        match self {
+            Flavor::CommonMark => Options::empty(),
+            Flavor::Gfm => {
+                Options::ENABLE_FOOTNOTES
+                    | Options::ENABLE_TABLES
+                    | Options::ENABLE_STRIKETHROUGH
+                    | Options::ENABLE_TASKLISTS
+                    | Options::ENABLE_GFM
+            }
+        }
+    }
+}
+
+impl FromStr for Flavor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "commonmark" => Ok(Flavor::CommonMark),
+            "gfm" => Ok(Flavor::Gfm),
+            _ => Err(format!("unknown markdown flavor: {}", s)),
+        }
+    }
+}
+
+/// The names of the markdown extensions that may be toggled, and their parser options.
+pub const EXTENSIONS: &[(&str, Options)] = &[
+    ("tables", Options::ENABLE_TABLES),
+    ("footnotes", Options::ENABLE_FOOTNOTES),
+    ("strikethrough", Options::ENABLE_STRIKETHROUGH),
+    ("tasklists", Options::ENABLE_TASKLISTS),
+    ("alerts", Options::ENABLE_GFM),
+    ("smart-punctuation", Options::ENABLE_SMART_PUNCTUATION),
+    ("heading-attributes", Options::ENABLE_HEADING_ATTRIBUTES),
+    ("definition-lists", Options::ENABLE_DEFINITION_LIST),
+    ("superscript", Options::ENABLE_SUPERSCRIPT),
+    ("subscript", Options::ENABLE_SUBSCRIPT),
+    ("wikilinks", Options::ENABLE_WIKILINKS),
+];
+
+/// Returns the parser option for the extension with the given name.
+pub fn extension(name: &str) -> Option<Options> {
+    EXTENSIONS
+        .iter()
+        .find(|(extension, _)| *extension == name)
+        .map(|&(_, option)| option)
+}
+
+/// How YAML front matter at the start of a document should be displayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrontMatterDisplay {
+    /// The front matter is not displayed.
+    Strip,
+
+    /// The front matter is displayed as a table of metadata.
+    Table,
+}
+
+/// Algorithms for generating the ids of headings from their text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlugStyle {
+    /// Like GitHub: punctuation is removed and spaces become hyphens.
+    GitHub,
+
+    /// Like GitLab: like GitHub, but consecutive hyphens are collapsed into one.
+    GitLab,
+}
+
+impl FromStr for SlugStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "github" => Ok(SlugStyle::GitHub),
+            "gitlab" => Ok(SlugStyle::GitLab),
+            _ => Err(format!("unknown slug style: {}", s)),
+        }
+    }
+}
+
+impl SlugStyle {
+    /// Converts the text of a heading to an id.
+    pub fn slugify(self, text: &str) -> String {
+        let slug = text
+            .trim()
+            .to_lowercase()
+            .chars()
+            .filter(|&c| c.is_alphanumeric() || matches!(c, '_' | '-' | ' '))
+            .map(|c| if c == ' ' { '-' } else { c })
+            .collect::<String>();
+
+        match self {
+            SlugStyle::GitHub => slug,
+            SlugStyle::GitLab => slug
+                .split('-')
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join("-"),
+        }
+    }
+}
+
+/// How the page names of wiki links are matched to file names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WikiLinkCase {
+    /// The page name is used as the file name as-is.
+    Preserve,
+
+    /// The page name is converted to lowercase.
+    Lower,
+
+    /// The page name matches existing files regardless of case.
+    Insensitive,
+}
+
+impl FromStr for WikiLinkCase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(WikiLinkCase::Preserve),
+            "lower" => Ok(WikiLinkCase::Lower),
+            "insensitive" => Ok(WikiLinkCase::Insensitive),
+            _ => Err(format!("unknown wiki link case: {}", s)),
+        }
+    }
+}
+
+/// Options for resolving `[[Page Name]]` wiki links to markdown files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WikiLinks {
+    /// The directory that pages are resolved against.
+    pub root: Option<PathBuf>,
+
+    /// The extension of the files of pages, without the leading dot.
+    pub extension: String,
+
+    pub case: WikiLinkCase,
+}
+
+impl Default for WikiLinks {
+    fn default() -> Self {
+        WikiLinks {
+            root: None,
+            extension: String::from("md"),
+            case: WikiLinkCase::Preserve,
+        }
+    }
+}
+
+impl WikiLinks {
+    /// Resolves the target of a wiki link, such as `Page Name#Heading`, to a URL relative to the
+    /// root of the server.
+    pub fn resolve(&self, target: &str, slugs: SlugStyle) -> String {
+        let (page, heading) = match target.split_once('#') {
+            Some((page, heading)) => (page, Some(heading)),
+            None => (target, None),
+        };
+
+        let fragment = heading.map(|heading| slugs.slugify(heading));
+
+        let mut segments = page
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| match self.case {
+                WikiLinkCase::Lower => segment.to_lowercase(),
+                _ => segment.to_owned(),
+            })
+            .collect::<Vec<_>>();
+
+        let extension = format!(".{}", self.extension);
+        match segments.last_mut() {
+            Some(file_name) if !file_name.ends_with(&extension) => file_name.push_str(&extension),
+            Some(_) => (),
+            None => return format!("#{}", fragment.unwrap_or_default()),
+        }
+
+        if let (WikiLinkCase::Insensitive, Some(root)) = (self.case, &self.root) {
+            let mut dir = root.clone();
+            for segment in &mut segments {
+                if let Some(name) = find_case_insensitive(&dir, segment) {
+                    *segment = name;
+                }
+                dir.push(&*segment);
+            }
+        }
+
+        let mut url = Url::parse("http://localhost/").unwrap();
+        url.path_segments_mut()
+            .unwrap()
+            .pop_if_empty()
+            .extend(&segments);
+        url.set_fragment(fragment.as_deref());
+
+        url[Position::BeforePath..].to_owned()
+    }
+}
+
+/// Returns the name of the entry of the directory that matches the name regardless of case.
+fn find_case_insensitive(dir: &Path, name: &str) -> Option<String> {
+    let name = name.to_lowercase();
+
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .find(|entry| entry.to_lowercase() == name)
+}
+
+/// Options for the built-in renderer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderOptions {
+    /// The markdown extensions that are enabled. Defaults to the extensions of GitHub Flavored
+    /// Markdown.
+    pub extensions: Options,
+
+    /// Whether `$...$` and `$$...$$` spans should be rendered as math.
+    ///
+    /// Math is left untouched by markdown formatting, and is wrapped in `<span>`s with the `math`
+    /// class for the preview to typeset.
+    pub math: bool,
+
+    /// Whether math should be converted to MathML by the renderer, instead of being left for the
+    /// preview to typeset. Math that can't be converted is rendered as if this were `false`.
+    pub mathml: bool,
+
+    /// Whether GitHub emoji shortcodes such as `:smile:` should be replaced with emoji.
+    pub emoji: bool,
+
+    /// How YAML front matter should be displayed. If `None`, front matter is rendered as markdown.
+    /// Defaults to stripping front matter.
+    pub front_matter: Option<FrontMatterDisplay>,
+
+    /// How wiki links are resolved, if the `wikilinks` extension is enabled.
+    pub wiki_links: WikiLinks,
+
+    /// How the ids of headings are generated. Headings with an explicit id keep it.
+    pub heading_slugs: SlugStyle,
+
+    /// Whether code blocks should be rendered with line numbers.
+    pub code_line_numbers: bool,
+
+    /// Languages of code blocks that should be highlighted as another language, such as `viml` as
+    /// `vim`.
+    pub language_aliases: HashMap<String, String>,
+
+    /// Whether the document should be split into slides, wrapped in `<section>`s.
+    ///
+    /// Slides are separated by thematic breaks (`---`) and `<!-- slide -->` comments.
+    pub slides: bool,
+
+    /// Whether images should only be loaded once they're scrolled near, with `loading="lazy"`.
+    pub lazy_images: bool,
+
+    /// The largest width that images are displayed at, as a CSS length such as `100%`.
+    pub image_max_width: Option<String>,
+
+    /// The largest height that images are displayed at, as a CSS length such as `80vh`.
+    pub image_max_height: Option<String>,
+
+    /// Sanitizes the raw HTML of documents and the URLs of their links, if documents aren't
+    /// trusted. HTML generated by the renderer, such as diagrams, isn't sanitized.
+    pub sanitize: Option<Sanitizer>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            extensions: Flavor::Gfm.extensions(),
+            math: false,
+            mathml: false,
+            emoji: false,
+            front_matter: Some(FrontMatterDisplay::Strip),
+            wiki_links: WikiLinks::default(),
+            heading_slugs: SlugStyle::GitHub,
+            code_line_numbers: false,
+            language_aliases: HashMap::new(),
+            slides: false,
+            lazy_images: false,
+            image_max_width: None,
+            image_max_height: None,
+            sanitize: None,
+        }
+    }
+}
+
+impl RenderOptions {
+    /// Returns the options of the markdown parser, including the extensions that other options
+    /// depend on.
+    pub fn parser_options(&self) -> Options {
+        let mut parser_options = self.extensions;
+
+        if self.math {
+            parser_options |= Options::ENABLE_MATH;
+        }
+
+        if self.front_matter.is_some() {
+            parser_options |= Options::ENABLE_YAML_STYLE_METADATA_BLOCKS;
+        }
+
+        parser_options
+    }
+
+    /// Sanitizes HTML that wasn't rendered by the built-in renderer, if documents aren't trusted.
+    pub fn sanitize_html(&self, html: String) -> String {
+        match &self.sanitize {
+            Some(sanitizer) => sanitizer.clean(&html),
+            None => html,
+        }
+    }
+}
+
+/// Renders markdown to HTML with the built-in renderer.
+///
+/// Block-level elements are annotated with a `data-source-line` attribute containing the
+/// (one-based) line of the markdown that they were rendered from. The preview uses these to
+/// scroll to the editor's cursor.
+///
+/// Code blocks containing diagrams supported by `diagrams` are replaced with the rendered diagram.
+/// Footnote definitions are moved to a section at the end of the document, and alerts such as
+/// `> [!NOTE]` are rendered as callouts like on GitHub. Code blocks are given a button that copies
+/// their contents.
+pub fn markdown_to_html(
+    markdown: &str,
+    options: &RenderOptions,
+    diagrams: &mut Diagrams,
+) -> String {
+    let html = render_markdown(markdown, options, diagrams);
+    diagrams.prune_cache();
+    html
+}
+
+/// Renders markdown to HTML without pruning the diagram cache, so that a document may be rendered
+/// in parts.
+fn render_markdown(markdown: &str, options: &RenderOptions, diagrams: &mut Diagrams) -> String {
+    // CommonMark requires that NUL characters are replaced for security reasons.
+    let markdown = if markdown.contains('\0') {
+        Cow::Owned(markdown.replace('\0', "\u{FFFD}"))
+    } else {
+        Cow::Borrowed(markdown)
+    };
+
+    let line_starts = line_starts(&markdown);
+
+    let parser_options = options.parser_options();
+    let parser = Parser::new_ext(&markdown, parser_options);
+
+    // The language and contents of the diagram code block that is being read, if any.
+    let mut diagram: Option<(CowStr, String)> = None;
+    let mut in_code_block = false;
+
+    // The YAML front matter that is being read, if it should be displayed as a table.
+    let mut front_matter: Option<String> = None;
+
+    // The raw HTML block that is being read, if it should be sanitized. Tags may span the lines
+    // of the block, so the block is sanitized as a whole.
+    let mut html_block: Option<String> = None;
+    let sanitizer = options.sanitize.as_ref();
+
+    // Insert a marker containing the source line before each block. Once the HTML is rendered, the
+    // markers are moved into an attribute of the tag that follows them.
+    // The parser only recognizes subscripts and superscripts that are delimited like emphasis, so
+    // intraword scripts such as `H~2~O` are handled separately.
+    let subscript = parser_options.contains(Options::ENABLE_SUBSCRIPT);
+    let superscript = parser_options.contains(Options::ENABLE_SUPERSCRIPT);
+
+    let events = TextMergeWithOffset::new(parser.into_offset_iter()).flat_map(|(event, range)| {
+        let line = line_starts.partition_point(|&start| start <= range.start);
+
+        let marker = if is_annotated_block(&event) {
+            Some(Event::Html(
+                format!("{0}{1}{0}", SOURCE_LINE_MARKER, line).into(),
+            ))
+        } else {
+            None
+        };
+
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(TagEnd::CodeBlock) => in_code_block = false,
+            _ => (),
+        }
+
+        let events = match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref language)))
+                if diagrams.supports(language) =>
+            {
+                diagram = Some((language.clone(), String::new()));
+                vec![]
+            }
+            Event::Text(text) if diagram.is_some() => {
+                diagram.as_mut().unwrap().1.push_str(&text);
+                vec![]
+            }
+            Event::Start(Tag::MetadataBlock(MetadataBlockKind::YamlStyle))
+                if options.front_matter == Some(FrontMatterDisplay::Table) =>
+            {
+                front_matter = Some(String::new());
+                vec![]
+            }
+            Event::Text(text) if front_matter.is_some() => {
+                front_matter.as_mut().unwrap().push_str(&text);
+                vec![]
+            }
+            Event::End(TagEnd::MetadataBlock(_)) if front_matter.is_some() => {
+                let yaml = front_matter.take().unwrap();
+
+                // Front matter always begins on the first line.
+                crate::front_matter::html_table(&yaml)
+                    .map(|table| {
+                        Event::Html(format!("{0}1{0}{1}", SOURCE_LINE_MARKER, table).into())
+                    })
+                    .into_iter()
+                    .collect()
+            }
+            event @ Event::End(TagEnd::CodeBlock) if diagram.is_some() => {
+                let (language, source) = diagram.take().unwrap();
+
+                match diagrams.render(&language, &source) {
+                    Some(svg) => vec![Event::Html(
+                        format!("<div class=\"diagram\">{}</div>\n", svg).into(),
+                    )],
+                    None => vec![
+                        Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(language))),
+                        Event::Text(source.into()),
+                        event,
+                    ],
+                }
+            }
+            Event::Start(Tag::Link {
+                link_type: LinkType::WikiLink { .. },
+                dest_url,
+                ..
+            }) => {
+                let href = options
+                    .wiki_links
+                    .resolve(&dest_url, options.heading_slugs);
+                vec![Event::InlineHtml(
+                    format!("<a class=\"wikilink\" href=\"{}\">", html_escape(&href)).into(),
+                )]
+            }
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) if sanitizer.is_some_and(|sanitizer| !sanitizer.is_allowed_url(&dest_url)) => {
+                vec![Event::Start(Tag::Link {
+                    link_type,
+                    dest_url: CowStr::Borrowed(""),
+                    title,
+                    id,
+                })]
+            }
+            Event::Start(Tag::Image {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) if sanitizer.is_some_and(|sanitizer| !sanitizer.is_allowed_url(&dest_url)) => {
+                vec![Event::Start(Tag::Image {
+                    link_type,
+                    dest_url: CowStr::Borrowed(""),
+                    title,
+                    id,
+                })]
+            }
+            event @ Event::Start(Tag::HtmlBlock) if sanitizer.is_some() => {
+                html_block = Some(String::new());
+                vec![event]
+            }
+            Event::Html(html) if html_block.is_some() => {
+                html_block.as_mut().unwrap().push_str(&html);
+                vec![]
+            }
+            event @ Event::End(TagEnd::HtmlBlock) if html_block.is_some() => {
+                let html = sanitizer.unwrap().clean(&html_block.take().unwrap());
+                vec![Event::Html(html.into()), event]
+            }
+            Event::InlineHtml(html) if sanitizer.is_some() => {
+                vec![Event::InlineHtml(sanitizer.unwrap().clean(&html).into())]
+            }
+            Event::Start(Tag::BlockQuote(Some(kind))) => {
+                let (class, title, icon) = alert(kind);
+                vec![Event::Html(
+                    format!(
+                        "<div class=\"markdown-alert markdown-alert-{}\">\n\
+                         <p class=\"markdown-alert-title\">\
+                         <svg viewBox=\"0 0 16 16\" width=\"16\" height=\"16\" aria-hidden=\"true\">\
+                         <path d=\"{}\"></path></svg>{}</p>\n",
+                        class, icon, title
+                    )
+                    .into(),
+                )]
+            }
+            Event::End(TagEnd::BlockQuote(Some(_))) => vec![Event::Html("</div>\n".into())],
+            Event::Text(text) if (options.emoji || subscript || superscript) && !in_code_block => {
+                let text = if options.emoji {
+                    replace_emoji_shortcodes(text)
+                } else {
+                    text
+                };
+
+                split_scripts(text, subscript, superscript)
+            }
+            // Task list checkboxes can be clicked to toggle the task in the editor.
+            Event::TaskListMarker(checked) => vec![Event::InlineHtml(
+                format!(
+                    "<input type=\"checkbox\" class=\"task-list-item-checkbox\" \
+                     data-task-line=\"{}\"{}/>\n",
+                    line,
+                    if checked { " checked=\"\"" } else { "" }
+                )
+                .into(),
+            )],
+            Event::InlineMath(tex) if options.mathml => {
+                vec![math_to_mathml(tex, DisplayStyle::Inline)]
+            }
+            Event::DisplayMath(tex) if options.mathml => {
+                vec![math_to_mathml(tex, DisplayStyle::Block)]
+            }
+            event => vec![event],
+        };
+
+        marker.into_iter().chain(events)
+    });
+
+    let events = anchor_headings(collect_footnotes(events), options.heading_slugs);
+    let mut events = decorate_code_blocks(events, options);
+
+    if options.slides {
+        events = split_slides(events);
+    }
+
+    let mut html = String::with_capacity(markdown.len() * 3 / 2);
+    pulldown_cmark::html::push_html(&mut html, events.into_iter());
+
+    annotate_source_lines(&constrain_images(html, options))
+}
+
+/// Adds the attributes that load images lazily and limit their size to each `<img>` tag, unless
+/// the tag already sets them.
+fn constrain_images(html: String, options: &RenderOptions) -> String {
+    let style = [
+        ("max-width", &options.image_max_width),
+        ("max-height", &options.image_max_height),
+    ]
+    .iter()
+    .filter_map(|(property, value)| Some(format!("{}: {}", property, value.as_ref()?)))
+    .collect::<Vec<_>>()
+    .join("; ");
+
+    if !options.lazy_images && style.is_empty() {
+        return html;
+    }
+
+    let mut constrained = String::with_capacity(html.len());
+    let mut rest = &html[..];
+
+    while let Some(start) = rest.find("<img") {
+        let (before, tag) = rest.split_at(start);
+        constrained.push_str(before);
+
+        if !tag[4..].starts_with(|c: char| c.is_ascii_whitespace() || c == '/' || c == '>') {
+            constrained.push_str("<img");
+            rest = &tag[4..];
+            continue;
+        }
+
+        let tag_end = tag.find('>').map_or(tag.len(), |i| i + 1);
+        let (tag, after) = tag.split_at(tag_end);
+
+        constrained.push_str("<img");
+        if options.lazy_images && !tag.contains(" loading=") {
+            constrained.push_str(" loading=\"lazy\"");
+        }
+        if !style.is_empty() && !tag.contains(" style=") {
+            constrained.push_str(&format!(" style=\"{}\"", html_escape(&style)));
+        }
+        constrained.push_str(&tag[4..]);
+
+        rest = after;
+    }
+
+    constrained.push_str(rest);
+    constrained
+}
+
+/// Renders markdown to HTML like [`markdown_to_html`], reusing the HTML of blocks of the markdown
+/// that haven't changed since the last render.
+///
+/// The markdown is split at blank lines between top-level blocks, and each block is rendered on
+/// its own. Documents whose blocks affect each other, such as documents with footnotes, link
+/// reference definitions or duplicate headings, are rendered all at once.
+#[derive(Debug, Default)]
+pub struct BlockCache {
+    /// The options that the cached blocks were rendered with.
+    options: Option<RenderOptions>,
+
+    /// HTML rendered from blocks of markdown, keyed by a hash of the block. Source lines are
+    /// relative to the start of the block.
+    blocks: HashMap<u64, String>,
+}
+
+impl BlockCache {
+    /// Removes every block from the cache, such as when diagrams are rendered differently.
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+    }
+
+    /// Renders markdown to HTML, only rendering the blocks that aren't cached.
+    pub fn render(
+        &mut self,
+        markdown: &str,
+        options: &RenderOptions,
+        diagrams: &mut Diagrams,
+    ) -> String {
+        if self.options.as_ref() != Some(options) {
+            self.options = Some(options.clone());
+            self.blocks.clear();
+        }
+
+        // Footnotes and link reference definitions may be used from any block, and slides may
+        // span several blocks.
+        if options.slides || markdown.contains("[^") || has_link_definitions(markdown) {
+            self.blocks.clear();
+            return markdown_to_html(markdown, options, diagrams);
+        }
+
+        // Only the first block may begin with front matter.
+        let rest_options = RenderOptions {
+            front_matter: None,
+            ..options.clone()
+        };
+
+        let mut html = String::with_capacity(markdown.len() * 3 / 2);
+        let mut blocks = HashMap::new();
+        let mut rendered = 0;
+
+        let markdown_blocks = split_markdown(markdown, options.front_matter.is_some());
+        for (i, &(line, block)) in markdown_blocks.iter().enumerate() {
+            let key = hash((i == 0, block));
+
+            let block_html = match self
+                .blocks
+                .remove(&key)
+                .or_else(|| blocks.get(&key).cloned())
+            {
+                Some(block_html) => block_html,
+                None => {
+                    rendered += 1;
+                    let options = if i == 0 { options } else { &rest_options };
+                    render_markdown(block, options, diagrams)
+                }
+            };
+
+            push_moved_lines(&mut html, &block_html, line - 1);
+            blocks.insert(key, block_html);
+        }
+
+        self.blocks = blocks;
+        diagrams.prune_cache();
+
+        // Heading ids are only unique within each block.
+        let mut ids = HashSet::new();
+        if !html
+            .split("<a class=\"anchor\" href=\"#")
+            .skip(1)
+            .all(|rest| ids.insert(&rest[..rest.find('"').unwrap_or(rest.len())]))
+        {
+            self.blocks.clear();
+            return markdown_to_html(markdown, options, diagrams);
+        }
+
+        debug!("rendered {} of {} blocks", rendered, markdown_blocks.len());
+
+        html
+    }
+}
+
+/// The HTML of recent renders, keyed by a hash of the markdown, so that markdown that was rendered
+/// recently isn't rendered again, such as when a change is undone.
+#[derive(Debug, Default)]
+pub struct RenderCache {
+    /// The options that the cached HTML was rendered with.
+    options: Option<RenderOptions>,
+
+    /// Hashes of markdown and the HTML rendered from it, with the most recently used last.
+    renders: VecDeque<(u64, String)>,
+}
+
+impl RenderCache {
+    /// Removes every render from the cache, such as when the renderer changes.
+    pub fn clear(&mut self) {
+        self.renders.clear();
+    }
+
+    /// Returns the HTML rendered from some markdown with the given options, if it's cached. The
+    /// markdown may be keyed with anything else that the HTML depends on, such as its renderer.
+    pub fn get(&mut self, markdown: impl Hash, options: &RenderOptions) -> Option<String> {
+        if self.options.as_ref() != Some(options) {
+            self.options = Some(options.clone());
+            self.renders.clear();
+        }
+
+        let key = hash(markdown);
+        let index = self.renders.iter().position(|(k, _)| *k == key)?;
+        let render = self.renders.remove(index).unwrap();
+        let html = render.1.clone();
+        self.renders.push_back(render);
+        Some(html)
+    }
+
+    /// Caches the HTML rendered from some markdown, with the options last passed to `get`.
+    pub fn insert(&mut self, markdown: impl Hash, html: String) {
+        if self.renders.len() == RENDER_CACHE_SIZE {
+            self.renders.pop_front();
+        }
+        self.renders.push_back((hash(markdown), html));
+    }
+}
+
+/// Hashes a value to use as the key of a cache.
+fn hash(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Splits markdown into blocks that can be rendered separately, along with the (one-based) line
+/// that each block starts on.
+///
+/// Blocks are split at blank lines that are followed by an unindented line that doesn't continue a
+/// list or definition. Blank lines in fenced code, HTML blocks and front matter are skipped.
+fn split_markdown(markdown: &str, front_matter: bool) -> Vec<(usize, &str)> {
+    let body_start = match crate::front_matter::split(markdown) {
+        Some((_, body)) if front_matter => markdown.len() - body.len(),
+        _ => 0,
+    };
+
+    let mut blocks = vec![];
+    let mut start = 0;
+    let mut start_line = 1;
+
+    // The text that ends the fenced code or HTML block that is being read, if any.
+    let mut end: Option<&str> = None;
+    let mut blank = false;
+    let mut offset = 0;
+
+    for (i, line) in markdown.split_inclusive('\n').enumerate() {
+        let line_start = offset;
+        offset += line.len();
+
+        if line_start < body_start {
+            continue;
+        }
+
+        let text = line.trim_end();
+        let indent = text.len() - text.trim_start().len();
+        let unindented = &text[indent..];
+
+        if let Some(fence) = end {
+            let closed = if fence.starts_with(['`', '~']) {
+                indent <= 3
+                    && unindented.starts_with(fence)
+                    && unindented
+                        .trim_start_matches(fence.chars().next().unwrap())
+                        .is_empty()
+            } else {
+                text.to_ascii_lowercase().contains(fence)
+            };
+
+            if closed {
+                end = None;
+            }
+            continue;
+        }
+
+        if text.is_empty() {
+            blank = true;
+            continue;
+        }
+
+        if blank
+            && indent == 0
+            && line_start > start
+            && !is_list_item(text)
+            && !text.starts_with(':')
+        {
+            blocks.push((start_line, &markdown[start..line_start]));
+            start = line_start;
+            start_line = i + 1;
+        }
+        blank = false;
+
+        if indent <= 3 {
+            end = opened_block_end(unindented);
+        }
+    }
+
+    if start < markdown.len() || blocks.is_empty() {
+        blocks.push((start_line, &markdown[start..]));
+    }
+
+    blocks
+}
+
+/// If a line begins fenced code or an HTML block that may contain blank lines, returns the text
+/// that ends the block.
+fn opened_block_end(line: &str) -> Option<&str> {
+    for fence in ['`', '~'] {
+        let len = line.len() - line.trim_start_matches(fence).len();
+        if len >= 3 {
+            return Some(&line[..len]);
+        }
+    }
+
+    let lowercase = line.to_ascii_lowercase();
+    MULTILINE_HTML_BLOCKS
+        .iter()
+        .find(|(start, _)| lowercase.starts_with(start))
+        .filter(|(start, end)| !lowercase[start.len()..].contains(end))
+        .map(|(_, end)| *end)
+}
+
+/// Returns whether the markdown may contain link reference definitions, such as `[name]: url`.
+fn has_link_definitions(markdown: &str) -> bool {
+    markdown.lines().any(|line| {
+        // Definitions may also be in block quotes and list items.
+        let line = line.trim_start_matches(|c: char| {
+            c.is_whitespace()
+                || c.is_ascii_digit()
+                || matches!(c, '>' | '-' | '+' | '*' | '.' | ')')
+        });
+        line.starts_with('[') && line.contains("]:")
+    })
+}
+
+/// Returns whether a line begins with a list marker.
+fn is_list_item(line: &str) -> bool {
+    let rest = match line.strip_prefix(['-', '+', '*']) {
+        Some(rest) => rest,
+        None => {
+            let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            match line[digits..].strip_prefix(['.', ')']) {
+                Some(rest) if (1..=9).contains(&digits) => rest,
+                _ => return false,
+            }
+        }
+    };
+
+    rest.is_empty() || rest.starts_with([' ', '\t'])
+}
+
+/// Appends HTML, moving the lines of its line attributes down by some number of lines.
+fn push_moved_lines(output: &mut String, mut html: &str, lines: usize) {
+    if lines == 0 {
+        output.push_str(html);
+        return;
+    }
+
+    while let Some(start) = LINE_ATTRIBUTES
+        .iter()
+        .filter_map(|attribute| html.find(attribute).map(|i| i + attribute.len()))
+        .min()
+    {
+        let end = html[start..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(html.len(), |end| start + end);
+
+        output.push_str(&html[..start]);
+        match html[start..end].parse::<usize>() {
+            Ok(line) => output.push_str(&(line + lines).to_string()),
+            Err(_) => output.push_str(&html[start..end]),
+        }
+        html = &html[end..];
+    }
+
+    output.push_str(html);
+}
+
+/// Gives each heading an id, if it doesn't have one already, and an anchor linking to it.
+///
+/// Like on GitHub, duplicate ids are suffixed with an incrementing number.
+fn anchor_headings(events: Vec<Event>, slugs: SlugStyle) -> Vec<Event> {
+    let mut output = Vec::with_capacity(events.len());
+    let mut used = HashMap::new();
+    let mut events = events.into_iter();
+
+    while let Some(event) = events.next() {
+        let (level, id, classes, attrs) = match event {
+            Event::Start(Tag::Heading {
+                level,
+                id,
+                classes,
+                attrs,
+            }) => (level, id, classes, attrs),
+            event => {
+                output.push(event);
+                continue;
+            }
+        };
+
+        let mut contents = vec![];
+        for event in events.by_ref() {
+            let end = matches!(event, Event::End(TagEnd::Heading(_)));
+            contents.push(event);
+            if end {
+                break;
+            }
+        }
+
+        let id = id.unwrap_or_else(|| {
+            let text = contents
+                .iter()
+                .filter_map(|event| match event {
+                    Event::Text(text) | Event::Code(text) => Some(&**text),
+                    _ => None,
+                })
+                .collect::<String>();
+            let slug = slugs.slugify(&text);
+
+            let count = used.entry(slug.clone()).or_insert(0);
+            let id = match *count {
+                0 => slug,
+                n => format!("{}-{}", slug, n),
+            };
+            *count += 1;
+
+            id.into()
+        });
+
+        let anchor = format!(
+            "<a class=\"anchor\" href=\"#{}\" aria-hidden=\"true\"></a>",
+            html_escape(&id)
+        );
+
+        output.push(Event::Start(Tag::Heading {
+            level,
+            id: Some(id),
+            classes,
+            attrs,
+        }));
+        output.push(Event::InlineHtml(anchor.into()));
+        output.extend(contents);
+    }
+
+    output
+}
+
+/// Wraps code blocks in a container with a button that copies the code, and optionally adds a
+/// gutter of line numbers. Aliased languages are replaced with the language they refer to.
+fn decorate_code_blocks<'a>(events: Vec<Event<'a>>, options: &RenderOptions) -> Vec<Event<'a>> {
+    let mut output = Vec::with_capacity(events.len());
+    let mut events = events.into_iter();
+
+    while let Some(event) = events.next() {
+        let kind = match event {
+            Event::Start(Tag::CodeBlock(kind)) => kind,
+            event => {
+                output.push(event);
+                continue;
+            }
+        };
+
+        let mut contents = vec![];
+        for event in events.by_ref() {
+            if let Event::End(TagEnd::CodeBlock) = event {
+                break;
+            }
+            contents.push(event);
+        }
+
+        let mut start = String::from("<div class=\"code-block\"><pre>");
+
+        if options.code_line_numbers {
+            let lines = contents
+                .iter()
+                .map(|event| match event {
+                    Event::Text(text) => text.lines().count(),
+                    _ => 0,
+                })
+                .sum::<usize>();
+
+            start.push_str("<span class=\"line-numbers\" aria-hidden=\"true\">");
+            for line in 1..=lines {
+                start.push_str(&format!("{}\n", line));
+            }
+            start.push_str("</span>");
+        }
+
+        // Like pulldown-cmark, only the first word of the info string is used as the language.
+        match kind {
+            CodeBlockKind::Fenced(info) if !info.is_empty() => {
+                let language = info.split(' ').next().unwrap();
+                let language = options
+                    .language_aliases
+                    .get(language)
+                    .map_or(language, String::as_str);
+                start.push_str(&format!(
+                    "<code class=\"language-{}\">",
+                    html_escape(language)
+                ));
+            }
+            _ => start.push_str("<code>"),
+        }
+
+        output.push(Event::Html(start.into()));
+        output.extend(contents);
+        output.push(Event::Html(
+            "</code></pre>\
+             <button class=\"copy-code\" type=\"button\" aria-label=\"Copy code\">Copy</button>\
+             </div>\n"
+                .into(),
+        ));
+    }
+
+    output
+}
+
+/// Wraps each slide of the document in a `<section>`.
+///
+/// Slides are separated by top-level thematic breaks and `<!-- slide -->` comments. The separators
+/// themselves are removed.
+fn split_slides(events: Vec<Event>) -> Vec<Event> {
+    const START: &str = "<section>\n";
+    const BREAK: &str = "</section>\n<section>\n";
+
+    let mut output = Vec::with_capacity(events.len() + 2);
+    output.push(Event::Html(START.into()));
+
+    let mut depth = 0;
+    let mut events = events.into_iter().peekable();
+
+    while let Some(event) = events.next() {
+        match event {
+            Event::Start(Tag::HtmlBlock) if depth == 0 => {
+                // Skip the comment entirely, so that it doesn't end up in the next slide.
+                let is_separator = matches!(
+                    events.peek(),
+                    Some(Event::Html(html)) if html.trim() == "<!-- slide -->"
+                );
+
+                if is_separator {
+                    events.next();
+                    events.next();
+                    output.push(Event::Html(BREAK.into()));
+                } else {
+                    depth += 1;
+                    output.push(Event::Start(Tag::HtmlBlock));
+                }
+            }
+            Event::Rule if depth == 0 => {
+                // Move the source line of the break to the slide that it starts.
+                let marker = match output.last() {
+                    Some(Event::Html(html)) if html.starts_with(SOURCE_LINE_MARKER) => output.pop(),
+                    _ => None,
+                };
+
+                output.push(Event::Html("</section>\n".into()));
+                output.extend(marker);
+                output.push(Event::Html(START.into()));
+            }
+            event => {
+                match event {
+                    Event::Start(_) => depth += 1,
+                    Event::End(_) => depth -= 1,
+                    _ => (),
+                }
+                output.push(event);
+            }
+        }
+    }
+
+    output.push(Event::Html("</section>\n".into()));
+    output
+}
+
+/// Returns the class suffix, title, and octicon SVG path of an alert.
+fn alert(kind: BlockQuoteKind) -> (&'static str, &'static str, &'static str) {
+    match kind {
+        BlockQuoteKind::Note => (
+            "note",
+            "Note",
+            "M0 8a8 8 0 1 1 16 0A8 8 0 0 1 0 8Zm8-6.5a6.5 6.5 0 1 0 0 13 6.5 6.5 0 0 0 0-13ZM6.5 \
+             7.75A.75.75 0 0 1 7.25 7h1a.75.75 0 0 1 .75.75v2.75h.25a.75.75 0 0 1 0 1.5h-2a.75.75 \
+             0 0 1 0-1.5h.25v-2h-.25a.75.75 0 0 1-.75-.75ZM8 6a1 1 0 1 1 0-2 1 1 0 0 1 0 2Z",
+        ),
+        BlockQuoteKind::Tip => (
+            "tip",
+            "Tip",
+            "M8 1.5c-2.363 0-4 1.69-4 3.75 0 .984.424 1.625.984 2.304l.214.253c.223.264.47.556.673\
+             .848.284.411.537.896.621 1.49a.75.75 0 0 1-1.484.211c-.04-.282-.163-.547-.37-.847a8.456 \
+             8.456 0 0 0-.542-.68c-.084-.1-.173-.205-.268-.32C3.201 7.75 2.5 6.766 2.5 5.25 2.5 2.31 \
+             4.863 0 8 0s5.5 2.31 5.5 5.25c0 1.516-.701 2.5-1.328 3.259-.095.115-.184.22-.268.319-.207\
+             .245-.383.453-.541.681-.208.3-.33.565-.37.847a.751.751 0 0 1-1.485-.212c.084-.593.337-1.078\
+             .621-1.489.203-.292.45-.584.673-.848.075-.088.147-.173.213-.253.561-.679.985-1.32.985-2.304 \
+             0-2.06-1.637-3.75-4-3.75ZM5.75 12h4.5a.75.75 0 0 1 0 1.5h-4.5a.75.75 0 0 1 0-1.5ZM6 \
+             15.25a.75.75 0 0 1 .75-.75h2.5a.75.75 0 0 1 0 1.5h-2.5a.75.75 0 0 1-.75-.75Z",
+        ),
+        BlockQuoteKind::Important => (
+            "important",
+            "Important",
+            "M0 1.75C0 .784.784 0 1.75 0h12.5C15.216 0 16 .784 16 1.75v9.5A1.75 1.75 0 0 1 14.25 \
+             13H8.06l-2.573 2.573A1.458 1.458 0 0 1 3 14.543V13H1.75A1.75 1.75 0 0 1 0 11.25Zm1.75-.25a\
+             .25.25 0 0 0-.25.25v9.5c0 .138.112.25.25.25h2a.75.75 0 0 1 .75.75v2.19l2.72-2.72a.749.749 \
+             0 0 1 .53-.22h6.5a.25.25 0 0 0 .25-.25v-9.5a.25.25 0 0 0-.25-.25Zm7 2.25v2.5a.75.75 0 0 \
+             1-1.5 0v-2.5a.75.75 0 0 1 1.5 0ZM9 9a1 1 0 1 1-2 0 1 1 0 0 1 2 0Z",
+        ),
+        BlockQuoteKind::Warning => (
+            "warning",
+            "Warning",
+            "M6.457 1.047c.659-1.234 2.427-1.234 3.086 0l6.082 11.378A1.75 1.75 0 0 1 14.082 \
+             15H1.918a1.75 1.75 0 0 1-1.543-2.575Zm1.763.707a.25.25 0 0 0-.44 0L1.698 13.132a.25.25 0 \
+             0 0 .22.368h12.164a.25.25 0 0 0 .22-.368Zm.53 3.996v2.5a.75.75 0 0 1-1.5 0v-2.5a.75.75 0 \
+             0 1 1.5 0ZM9 11a1 1 0 1 1-2 0 1 1 0 0 1 2 0Z",
+        ),
+        BlockQuoteKind::Caution => (
+            "caution",
+            "Caution",
+            "M4.47.22A.749.749 0 0 1 5 0h6c.199 0 .389.079.53.22l4.25 4.25c.141.14.22.331.22.53v6a\
+             .749.749 0 0 1-.22.53l-4.25 4.25A.749.749 0 0 1 11 16H5a.749.749 0 0 1-.53-.22L.22 \
+             11.53A.749.749 0 0 1 0 11V5c0-.199.079-.389.22-.53Zm.84 1.28L1.5 5.31v5.38l3.81 3.81h5.38\
+             l3.81-3.81V5.31L10.69 1.5ZM8 4a.75.75 0 0 1 .75.75v3.5a.75.75 0 0 1-1.5 0v-3.5A.75.75 0 \
+             0 1 8 4Zm0 8a1 1 0 1 1 0-2 1 1 0 0 1 0 2Z",
+        ),
+    }
+}
+
+/// Moves footnote definitions to a section at the end of the document, numbered in the order that
+/// they are first referenced, and links each definition back to its references.
+///
+/// Definitions that are never referenced are dropped, like on GitHub.
+fn collect_footnotes<'a>(events: impl Iterator<Item = Event<'a>>) -> Vec<Event<'a>> {
+    fn reference_id(number: usize, occurrence: usize) -> String {
+        match occurrence {
+            1 => format!("fnref-{}", number),
+            _ => format!("fnref-{}-{}", number, occurrence),
+        }
+    }
+
+    fn is_marker(event: Option<&Event>) -> bool {
+        matches!(event, Some(Event::Html(html)) if html.starts_with(SOURCE_LINE_MARKER))
+    }
+
+    let mut output = vec![];
+
+    // The labels of the referenced footnotes in the order that they were first referenced, and the
+    // number of references to each.
+    let mut references: Vec<(String, usize)> = vec![];
+    let mut definitions = HashMap::new();
+
+    // The label and contents of the definition that is being read, if any.
+    let mut definition: Option<(String, Vec<Event>)> = None;
+
+    for event in events {
+        match event {
+            Event::Start(Tag::FootnoteDefinition(label)) => {
+                // Keep the source line marker of the definition with its contents.
+                let contents = if is_marker(output.last()) {
+                    output.pop().into_iter().collect()
+                } else {
+                    vec![]
+                };
+                definition = Some((label.to_lowercase(), contents));
+            }
+            Event::End(TagEnd::FootnoteDefinition) => {
+                if let Some((label, contents)) = definition.take() {
+                    definitions.entry(label).or_insert(contents);
+                }
+            }
+            Event::FootnoteReference(label) => {
+                let label = label.to_lowercase();
+                let index = match references.iter().position(|(l, _)| *l == label) {
+                    Some(index) => index,
+                    None => {
+                        references.push((label, 0));
+                        references.len() - 1
+                    }
+                };
+
+                references[index].1 += 1;
+                let number = index + 1;
+
+                let html = format!(
+                    "<sup class=\"footnote-ref\"><a href=\"#fn-{0}\" id=\"{1}\">{0}</a></sup>",
+                    number,
+                    reference_id(number, references[index].1)
+                );
+
+                match &mut definition {
+                    Some((_, contents)) => contents.push(Event::InlineHtml(html.into())),
+                    None => output.push(Event::InlineHtml(html.into())),
+                }
+            }
+            event => match &mut definition {
+                Some((_, contents)) => contents.push(event),
+                None => output.push(event),
+            },
+        }
+    }
+
+    if references.is_empty() {
+        return output;
+    }
+
+    output.push(Event::Html("<section class=\"footnotes\">\n<ol>\n".into()));
+
+    for (i, (label, count)) in references.into_iter().enumerate() {
+        let number = i + 1;
+        let mut contents = definitions.remove(&label).unwrap_or_default();
+
+        if is_marker(contents.first()) {
+            output.push(contents.remove(0));
+        }
+        output.push(Event::Html(format!("<li id=\"fn-{}\">\n", number).into()));
+
+        let backrefs = (1..=count)
+            .map(|occurrence| {
+                format!(
+                    " <a href=\"#{}\" class=\"footnote-backref\">\u{21A9}{}</a>",
+                    reference_id(number, occurrence),
+                    match occurrence {
+                        1 => String::new(),
+                        _ => format!("<sup>{}</sup>", occurrence),
+                    }
+                )
+            })
+            .collect::<String>();
+
+        // Place the links at the end of the last paragraph, if there is one.
+        match contents.pop() {
+            Some(end @ Event::End(TagEnd::Paragraph)) => {
+                contents.push(Event::InlineHtml(backrefs.into()));
+                contents.push(end);
+            }
+            last => {
+                contents.extend(last);
+                contents.push(Event::Html(
+                    format!("<p>{}</p>\n", backrefs.trim_start()).into(),
+                ));
+            }
+        }
+
+        output.extend(contents);
+        output.push(Event::Html("</li>\n".into()));
+    }
+
+    output.push(Event::Html("</ol>\n</section>\n".into()));
+
+    output
+}
+
+/// Converts a math event to MathML, falling back to the original event if the TeX is invalid.
+fn math_to_mathml(tex: CowStr, display: DisplayStyle) -> Event {
+    match latex2mathml::latex_to_mathml(&tex, display) {
+        Ok(mathml) => Event::InlineHtml(mathml.into()),
+        Err(e) => {
+            debug!("could not convert math to MathML: {}", e);
+            match display {
+                DisplayStyle::Inline => Event::InlineMath(tex),
+                DisplayStyle::Block => Event::DisplayMath(tex),
+            }
+        }
+    }
+}
+
+/// Replaces GitHub emoji shortcodes such as `:smile:` in the text with the emoji they represent.
+///
+/// Unknown shortcodes are left as-is.
+fn replace_emoji_shortcodes(text: CowStr) -> CowStr {
+    if !text.contains(':') {
+        return text;
+    }
+
+    let mut replaced = String::with_capacity(text.len());
+    let mut rest = &*text;
+
+    while let Some(start) = rest.find(':') {
+        let (before, candidate) = rest.split_at(start);
+        replaced.push_str(before);
+
+        let shortcode = candidate[1..].find(':').and_then(|len| {
+            let name = &candidate[1..1 + len];
+            let is_shortcode = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-'));
+
+            is_shortcode
+                .then(|| emojis::get_by_shortcode(name))
+                .flatten()
+                .map(|emoji| (emoji, len + 2))
+        });
+
+        match shortcode {
+            Some((emoji, len)) => {
+                replaced.push_str(emoji.as_str());
+                rest = &candidate[len..];
+            }
+            None => {
+                // The closing colon may begin another shortcode.
+                replaced.push(':');
+                rest = &candidate[1..];
+            }
+        }
+    }
+
+    replaced.push_str(rest);
+    replaced.into()
+}
+
+/// Converts `~subscripts~` and `^superscripts^` in the text to HTML, like pandoc.
+///
+/// Scripts may not be empty or contain whitespace.
+fn split_scripts(text: CowStr, subscript: bool, superscript: bool) -> Vec<Event> {
+    let is_delimiter = |c| (subscript && c == '~') || (superscript && c == '^');
+
+    if !text.contains(is_delimiter) {
+        return vec![Event::Text(text)];
+    }
+
+    let mut events = vec![];
+    let mut rest = &*text;
+
+    while let Some(start) = rest.find(is_delimiter) {
+        let delimiter = rest[start..].chars().next().unwrap();
+        let after = &rest[start + 1..];
+
+        let script = after
+            .find(delimiter)
+            .map(|len| &after[..len])
+            .filter(|script| !script.is_empty() && !script.contains(char::is_whitespace));
+
+        match script {
+            Some(script) => {
+                let tag = if delimiter == '~' { "sub" } else { "sup" };
+
+                events.push(Event::Text(rest[..start].to_owned().into()));
+                events.push(Event::InlineHtml(format!("<{}>", tag).into()));
+                events.push(Event::Text(script.to_owned().into()));
+                events.push(Event::InlineHtml(format!("</{}>", tag).into()));
+
+                rest = &after[script.len() + 1..];
+            }
+            None => {
+                events.push(Event::Text(rest[..start + 1].to_owned().into()));
+                rest = after;
+            }
+        }
+    }
+
+    events.push(Event::Text(rest.to_owned().into()));
+    events
+}
+
+/// Returns the byte offsets of the start of each line in the text.
+pub fn line_starts(text: &str) -> Vec<usize> {
+    Some(0)
+        .into_iter()
+        .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+        .collect()
+}
+
+/// Returns whether the event begins a block that should be annotated with its source line.
+fn is_annotated_block(event: &Event) -> bool {
+    match event {
+        Event::Start(tag) => matches!(
+            tag,
+            Tag::Paragraph
+                | Tag::Heading { .. }
+                | Tag::BlockQuote(_)
+                | Tag::CodeBlock(_)
+                | Tag::List(_)
+                | Tag::Item
+                | Tag::Table(_)
+                | Tag::FootnoteDefinition(_)
+                | Tag::DefinitionList
+                | Tag::DefinitionListTitle
+                | Tag::DefinitionListDefinition
+        ),
+        Event::Rule => true,
+        _ => false,
+    }
+}
+
+/// Replaces source line markers with `data-source-line` attributes on the following tag.
+fn annotate_source_lines(html: &str) -> String {
+    let mut annotated = String::with_capacity(html.len());
+    let mut parts = html.split(SOURCE_LINE_MARKER);
+
+    annotated.push_str(parts.next().unwrap_or_default());
+
+    while let (Some(line), Some(rest)) = (parts.next(), parts.next()) {
+        // The renderer starts blocks on a new line, but the marker itself doesn't end with one. If
+        // the output was already on a new line before the marker, the renderer will have emitted an
+        // extra newline.
+        let tag = match rest.strip_prefix('\n') {
+            Some(tag) => {
+                if !annotated.is_empty() && !annotated.ends_with('\n') {
+                    annotated.push('\n');
+                }
+                tag
+            }
+            None => rest,
+        };
+
+        let name_len = tag
+            .strip_prefix('<')
+            .map(|tag| {
+                tag.find(|c: char| !c.is_ascii_alphanumeric())
+                    .unwrap_or(tag.len())
+            })
+            .unwrap_or(0);
+
+        if name_len == 0 {
+            annotated.push_str(tag);
+            continue;
+        }
+
+        let (open, rest) = tag.split_at(1 + name_len);
+        annotated.push_str(open);
+        annotated.push_str(&format!(" data-source-line=\"{}\"", line));
+        annotated.push_str(rest);
+    }
+
+    annotated
+}
+
+#[cfg(test)]
+mod tests {
+    use pulldown_cmark::Options;
+
+    use std::collections::HashMap;
+
+    use super::{
+        markdown_to_html, BlockCache, Flavor, FrontMatterDisplay, RenderCache, RenderOptions,
+        SlugStyle, RENDER_CACHE_SIZE,
+    };
+    use crate::diagram::Diagrams;
+    use crate::sanitize::Sanitizer;
+
+    #[test]
+    fn source_lines() {
+        assert_eq!(
+            markdown_to_html(
+                "# Heading\n\nSome *text*\n\n---\n\n- a\n- b\n",
+                &RenderOptions::default(),
+                &mut Diagrams::default()
+            ),
+            "<h1 data-source-line=\"1\" id=\"heading\">\
+             <a class=\"anchor\" href=\"#heading\" aria-hidden=\"true\"></a>Heading</h1>\n\
+             <p data-source-line=\"3\">Some <em>text</em></p>\n\
+             <hr data-source-line=\"5\" />\n\
+             <ul data-source-line=\"7\">\n\
+             <li data-source-line=\"7\">a</li>\n\
+             <li data-source-line=\"8\">b</li>\n\
+             </ul>\n"
+        );
+
+        assert_eq!(
+            markdown_to_html(
+                "- a\n\n  b\n",
+                &RenderOptions::default(),
+                &mut Diagrams::default()
+            ),
+            "<ul data-source-line=\"1\">\n\
+             <li data-source-line=\"1\">\n\
+             <p data-source-line=\"1\">a</p>\n\
+             <p data-source-line=\"3\">b</p>\n\
+             </li>\n\
+             </ul>\n"
+        );
+    }
+
+    #[test]
+    fn nul_characters_are_not_markers() {
+        assert_eq!(
+            markdown_to_html("a\0b", &RenderOptions::default(), &mut Diagrams::default()),
+            "<p data-source-line=\"1\">a\u{FFFD}b</p>\n"
+        );
+    }
+
+    #[test]
+    fn math() {
+        let options = RenderOptions {
+            math: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            markdown_to_html("$a*b*c$ and\n\n$$\nx * y * z\n$$\n", &options, &mut Diagrams::default()),
+            "<p data-source-line=\"1\"><span class=\"math math-inline\">a*b*c</span> and</p>\n\
+             <p data-source-line=\"3\"><span class=\"math math-display\">\nx * y * z\n</span></p>\n"
+        );
+
+        assert_eq!(
+            markdown_to_html(
+                "$a*b*c$",
+                &RenderOptions::default(),
+                &mut Diagrams::default()
+            ),
+            "<p data-source-line=\"1\">$a<em>b</em>c$</p>\n"
+        );
+    }
+
+    #[test]
+    fn mathml() {
+        let options = RenderOptions {
+            math: true,
+            mathml: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            markdown_to_html("$x^2$ $\\left( x$", &options, &mut Diagrams::default()),
+            "<p data-source-line=\"1\">\
+             <math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"inline\">\
+             <msup><mi>x</mi><mn>2</mn></msup></math> \
+             <span class=\"math math-inline\">\\left( x</span></p>\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn diagrams() {
+        use std::process::Command;
+
+        let mut mmdc = Command::new("sh");
+        mmdc.arg("-c").arg(r#"cp "$2" "$4""#).arg("mmdc");
+
+        let mut diagrams = Diagrams::default();
+        diagrams.set_mermaid(Some(mmdc));
+
+        assert_eq!(
+            markdown_to_html(
+                "```mermaid\n<svg/>\n```\n",
+                &RenderOptions::default(),
+                &mut diagrams
+            ),
+            "<div data-source-line=\"1\" class=\"diagram\"><svg/>\n</div>\n"
+        );
+    }
+
+    #[test]
+    fn emoji_shortcodes() {
+        let options = RenderOptions {
+            emoji: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            markdown_to_html(
+                "10:30 :smile::+1: :not_an_emoji: `:smile:`\n\n    :smile:\n",
+                &options,
+                &mut Diagrams::default()
+            ),
+            "<p data-source-line=\"1\">10:30 \u{1F604}\u{1F44D} :not_an_emoji: <code>:smile:</code></p>\n\
+             <div data-source-line=\"3\" class=\"code-block\"><pre><code>:smile:\n</code></pre>\
+             <button class=\"copy-code\" type=\"button\" aria-label=\"Copy code\">Copy</button>\
+             </div>\n"
+        );
+    }
+
+    #[test]
+    fn extensions() {
+        let markdown = "~~a~~\n\n- [x] b\n";
+
+        assert_eq!(
+            markdown_to_html(
+                markdown,
+                &RenderOptions::default(),
+                &mut Diagrams::default()
+            ),
+            "<p data-source-line=\"1\"><del>a</del></p>\n\
+             <ul data-source-line=\"3\">\n\
+             <li data-source-line=\"3\"><input type=\"checkbox\" class=\"task-list-item-checkbox\" \
+             data-task-line=\"3\" checked=\"\"/>\nb</li>\n\
+             </ul>\n"
+        );
+
+        let commonmark = RenderOptions {
+            extensions: Flavor::CommonMark.extensions() | Options::ENABLE_STRIKETHROUGH,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            markdown_to_html(markdown, &commonmark, &mut Diagrams::default()),
+            "<p data-source-line=\"1\"><del>a</del></p>\n\
+             <ul data-source-line=\"3\">\n\
+             <li data-source-line=\"3\">[x] b</li>\n\
+             </ul>\n"
+        );
+    }
+
+    #[test]
+    fn heading_anchors() {
+        let markdown = "# A -- B\n\n## A -- B\n\n## Custom {#custom}\n";
+
+        let options = RenderOptions {
+            extensions: Options::ENABLE_HEADING_ATTRIBUTES,
+            ..Default::default()
+        };
+
+        let html = markdown_to_html(markdown, &options, &mut Diagrams::default());
+        assert!(html.starts_with(
+            "<h1 data-source-line=\"1\" id=\"a----b\">\
+             <a class=\"anchor\" href=\"#a----b\" aria-hidden=\"true\"></a>A -- B</h1>\n"
+        ));
+        assert!(html.contains("<h2 data-source-line=\"3\" id=\"a----b-1\">"));
+        assert!(html.contains("<h2 data-source-line=\"5\" id=\"custom\">"));
+
+        let gitlab = RenderOptions {
+            heading_slugs: SlugStyle::GitLab,
+            ..options
+        };
+
+        let html = markdown_to_html(markdown, &gitlab, &mut Diagrams::default());
+        assert!(html.contains("<h1 data-source-line=\"1\" id=\"a-b\">"));
+        assert!(html.contains("<h2 data-source-line=\"3\" id=\"a-b-1\">"));
+    }
+
+    #[test]
+    fn code_blocks() {
+        let markdown = "```rust\nfn main() {\n}\n```\n";
+
+        assert_eq!(
+            markdown_to_html(
+                markdown,
+                &RenderOptions::default(),
+                &mut Diagrams::default()
+            ),
+            "<div data-source-line=\"1\" class=\"code-block\">\
+             <pre><code class=\"language-rust\">fn main() {\n}\n</code></pre>\
+             <button class=\"copy-code\" type=\"button\" aria-label=\"Copy code\">Copy</button>\
+             </div>\n"
+        );
+
+        let line_numbers = RenderOptions {
+            code_line_numbers: true,
+            ..Default::default()
+        };
+
+        assert!(
+            markdown_to_html(markdown, &line_numbers, &mut Diagrams::default()).contains(
+                "<pre><span class=\"line-numbers\" aria-hidden=\"true\">1\n2\n</span>\
+                 <code class=\"language-rust\">"
+            )
+        );
+    }
+
+    #[test]
+    fn language_aliases() {
+        let options = RenderOptions {
+            language_aliases: HashMap::from([(String::from("viml"), String::from("vim"))]),
+            ..Default::default()
+        };
+
+        let html = markdown_to_html("```viml\nset nu\n```\n", &options, &mut Diagrams::default());
+        assert!(html.contains("<code class=\"language-vim\">"));
+    }
+
+    #[test]
+    fn image_constraints() {
+        let options = RenderOptions {
+            lazy_images: true,
+            image_max_width: Some(String::from("100%")),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            markdown_to_html(
+                "![Screenshot](shot.png)\n\n<img loading=\"eager\" src=\"a.png\">\n",
+                &options,
+                &mut Diagrams::default()
+            ),
+            "<p data-source-line=\"1\">\
+             <img loading=\"lazy\" style=\"max-width: 100%\" src=\"shot.png\" alt=\"Screenshot\" /></p>\n\
+             <img style=\"max-width: 100%\" loading=\"eager\" src=\"a.png\">\n"
+        );
+    }
+
+    #[test]
+    fn sanitize() {
+        let options = RenderOptions {
+            sanitize: Some(Sanitizer::default()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            markdown_to_html(
+                "<div\n  onclick=\"steal()\">\n<script>alert(1)</script>\n</div>\n\n\
+                 [a](javascript:alert(1)) <b onmouseover=\"steal()\">b</b>\n\n\
+                 - [ ] task\n",
+                &options,
+                &mut Diagrams::default()
+            ),
+            "<div>\n\n</div>\n\
+             <p data-source-line=\"6\"><a href=\"\">a</a> <b>b</b></p>\n\
+             <ul data-source-line=\"8\">\n<li data-source-line=\"8\">\
+             <input type=\"checkbox\" class=\"task-list-item-checkbox\" data-task-line=\"8\"/>\n\
+             task</li>\n</ul>\n"
+        );
+    }
+
+    #[test]
+    fn slides() {
+        let options = RenderOptions {
+            slides: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            markdown_to_html(
+                "One\n\n---\n\n- Two\n\n<!-- slide -->\n\nThree\n",
+                &options,
+                &mut Diagrams::default()
+            ),
+            "<section>\n\
+             <p data-source-line=\"1\">One</p>\n\
+             </section>\n\
+             <section data-source-line=\"3\">\n\
+             <ul data-source-line=\"5\">\n\
+             <li data-source-line=\"5\">Two</li>\n\
+             </ul>\n\
+             </section>\n<section>\n\
+             <p data-source-line=\"9\">Three</p>\n\
+             </section>\n"
+        );
+    }
+
+    #[test]
+    fn pandoc_extensions() {
+        let markdown = "Term\n: Definition of H~2~O and x^2^\n";
+
+        let pandoc = RenderOptions {
+            extensions: Options::ENABLE_DEFINITION_LIST
+                | Options::ENABLE_SUBSCRIPT
+                | Options::ENABLE_SUPERSCRIPT,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            markdown_to_html(markdown, &pandoc, &mut Diagrams::default()),
+            "<dl data-source-line=\"1\">\n\
+             <dt data-source-line=\"1\">Term</dt>\n\
+             <dd data-source-line=\"2\">Definition of H<sub>2</sub>O and x<sup>2</sup></dd>\n\
+             </dl>\n"
+        );
+
+        let strikethrough = RenderOptions {
+            extensions: pandoc.extensions | Options::ENABLE_STRIKETHROUGH,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            markdown_to_html(
+                "a ~b~ ~~c~~ 2^10^",
+                &strikethrough,
+                &mut Diagrams::default()
+            ),
+            "<p data-source-line=\"1\">a <sub>b</sub> <del>c</del> 2<sup>10</sup></p>\n"
+        );
+
+        assert_eq!(
+            markdown_to_html(
+                markdown,
+                &RenderOptions::default(),
+                &mut Diagrams::default()
+            ),
+            "<p data-source-line=\"1\">Term\n: Definition of H~2~O and x^2^</p>\n"
+        );
+    }
+
+    #[test]
+    fn footnotes() {
+        assert_eq!(
+            markdown_to_html(
+                "A[^b] and[^a] again[^b].\n\n[^a]: First.\n[^b]: Second.\n[^c]: Unused.\n",
+                &RenderOptions::default(),
+                &mut Diagrams::default()
+            ),
+            "<p data-source-line=\"1\">A<sup class=\"footnote-ref\"><a href=\"#fn-1\" id=\"fnref-1\">1</a></sup> \
+             and<sup class=\"footnote-ref\"><a href=\"#fn-2\" id=\"fnref-2\">2</a></sup> \
+             again<sup class=\"footnote-ref\"><a href=\"#fn-1\" id=\"fnref-1-2\">1</a></sup>.</p>\n\
+             <section class=\"footnotes\">\n<ol>\n\
+             <li data-source-line=\"4\" id=\"fn-1\">\n\
+             <p data-source-line=\"4\">Second. <a href=\"#fnref-1\" class=\"footnote-backref\">\u{21A9}</a> \
+             <a href=\"#fnref-1-2\" class=\"footnote-backref\">\u{21A9}<sup>2</sup></a></p>\n\
+             </li>\n\
+             <li data-source-line=\"3\" id=\"fn-2\">\n\
+             <p data-source-line=\"3\">First. <a href=\"#fnref-2\" class=\"footnote-backref\">\u{21A9}</a></p>\n\
+             </li>\n\
+             </ol>\n</section>\n"
+        );
+
+        let commonmark = RenderOptions {
+            extensions: Flavor::CommonMark.extensions(),
+            ..Default::default()
+        };
+
+        assert!(
+            !markdown_to_html("A[^a]\n\n[^a]: B\n", &commonmark, &mut Diagrams::default())
+                .contains("footnote")
+        );
+    }
+
+    #[test]
+    fn alerts() {
+        let html = markdown_to_html(
+            "> [!WARNING]\n> Careful\n",
+            &RenderOptions::default(),
+            &mut Diagrams::default(),
+        );
+
+        assert!(html.starts_with(
+            "<div data-source-line=\"1\" class=\"markdown-alert markdown-alert-warning\">\n\
+             <p class=\"markdown-alert-title\"><svg "
+        ));
+        assert!(html.ends_with("Warning</p>\n<p data-source-line=\"2\">Careful</p>\n</div>\n"));
+    }
+
+    #[test]
+    fn wiki_links() {
+        use std::env;
+        use std::fs;
+        use std::process;
+
+        use super::{WikiLinkCase, WikiLinks};
+
+        let root = env::temp_dir().join(format!("composer-wiki-{}", process::id()));
+        fs::create_dir_all(root.join("Notes")).unwrap();
+        fs::write(root.join("Notes").join("Some Page.md"), "").unwrap();
+
+        let mut wiki_links = WikiLinks {
+            root: Some(root.clone()),
+            ..Default::default()
+        };
+        assert_eq!(
+            wiki_links.resolve("Some Page", SlugStyle::GitHub),
+            "/Some%20Page.md"
+        );
+        assert_eq!(
+            wiki_links.resolve("a/b.md#A  Heading!", SlugStyle::GitLab),
+            "/a/b.md#a-heading"
+        );
+        assert_eq!(
+            wiki_links.resolve("#Heading", SlugStyle::GitHub),
+            "#heading"
+        );
+
+        wiki_links.case = WikiLinkCase::Lower;
+        wiki_links.extension = String::from("wiki");
+        assert_eq!(
+            wiki_links.resolve("Some Page", SlugStyle::GitHub),
+            "/some%20page.wiki"
+        );
+
+        wiki_links.case = WikiLinkCase::Insensitive;
+        wiki_links.extension = String::from("md");
+        assert_eq!(
+            wiki_links.resolve("notes/some page", SlugStyle::GitHub),
+            "/Notes/Some%20Page.md"
+        );
+
+        fs::remove_dir_all(root).unwrap();
+
+        let options = RenderOptions {
+            extensions: Options::ENABLE_WIKILINKS,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            markdown_to_html("[[Page|Text]]", &options, &mut Diagrams::default()),
+            "<p data-source-line=\"1\"><a class=\"wikilink\" href=\"/Page.md\">Text</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn front_matter() {
+        let markdown = "---\ntitle: Hello\n---\n\n# Heading\n";
+
+        assert_eq!(
+            markdown_to_html(
+                markdown,
+                &RenderOptions::default(),
+                &mut Diagrams::default()
+            ),
+            "<h1 data-source-line=\"5\" id=\"heading\">\
+             <a class=\"anchor\" href=\"#heading\" aria-hidden=\"true\"></a>Heading</h1>\n"
+        );
+
+        let table = RenderOptions {
+            front_matter: Some(FrontMatterDisplay::Table),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            markdown_to_html(markdown, &table, &mut Diagrams::default()),
+            "<table data-source-line=\"1\" class=\"front-matter\">\n<tbody>\n\
+             <tr><th>title</th><td>Hello</td></tr>\n\
+             </tbody>\n</table>\n\
+             <h1 data-source-line=\"5\" id=\"heading\">\
+             <a class=\"anchor\" href=\"#heading\" aria-hidden=\"true\"></a>Heading</h1>\n"
+        );
+    }
+
+    #[test]
+    fn block_cache() {
+        let options = RenderOptions {
+            front_matter: Some(FrontMatterDisplay::Table),
+            ..Default::default()
+        };
+        let mut cache = BlockCache::default();
+        let mut diagrams = Diagrams::default();
+
+        let markdown = "---\ntitle: A\n\nauthor: B\n---\n\n# Heading\n\nSome *text*\n\n\
+                        - a\n\n- [ ] b\n\n```\ncode\n\nnot a block\n```\n\n<!--\n\nhidden\n-->\n\n\
+                        1. one\n\n> quote\n\n## End\n";
+        let edited = markdown.replacen("Some *text*", "Some\n*more*\ntext", 1);
+
+        for markdown in [markdown, &edited, markdown] {
+            assert_eq!(
+                cache.render(markdown, &options, &mut diagrams),
+                markdown_to_html(markdown, &options, &mut diagrams)
+            );
+        }
+        assert_eq!(cache.blocks.len(), 7);
+
+        // Duplicate headings in different blocks are numbered like any other.
+        let markdown = "# Heading\n\n# Heading\n";
+        assert!(cache
+            .render(markdown, &options, &mut diagrams)
+            .contains("id=\"heading-1\""));
+    }
+
+    #[test]
+    fn render_cache() {
+        let options = RenderOptions::default();
+        let mut cache = RenderCache::default();
+
+        assert_eq!(cache.get("*a*", &options), None);
+        cache.insert("*a*", String::from("<p><em>a</em></p>\n"));
+        assert_eq!(
+            cache.get("*a*", &options).as_deref(),
+            Some("<p><em>a</em></p>\n")
+        );
+
+        // Renders with other options can't be reused.
+        let math = RenderOptions {
+            math: true,
+            ..Default::default()
+        };
+        assert_eq!(cache.get("*a*", &math), None);
+
+        // Only the most recently used renders are kept.
+        for i in 0..=RENDER_CACHE_SIZE {
+            cache.get(i.to_string(), &math);
+            cache.insert(i.to_string(), i.to_string());
+        }
+        assert_eq!(cache.get("0", &math), None);
+        assert_eq!(cache.get("1", &math).as_deref(), Some("1"));
+    }
+}
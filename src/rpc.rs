@@ -0,0 +1,556 @@
+//! RPC messages exchanged with the editor.
+//!
+//! Neovim communicates using [msgpack-rpc], while Vim uses its own JSON channel protocol. The
+//! protocol is selected at compile time with the `msgpack` and `json-rpc` features.
+//!
+//! [msgpack-rpc]: https://github.com/msgpack-rpc/msgpack-rpc/blob/master/spec.md
+
+use std::io::{self, prelude::*};
+
+use log::*;
+use serde::{Deserialize, Serialize};
+
+/// Represents an RPC request.
+///
+/// Assumes that the request's parameters are always `String`s.
+#[derive(Debug)]
+pub struct Rpc {
+    /// Whether the client may be expecting a response.
+    pub message_type: MessageType,
+
+    /// The ID of the request, if the client may be expecting a response.
+    ///
+    /// msgpack notifications do not have an ID. Every message sent over a Vim JSON channel has an
+    /// ID, but Vim ignores responses to messages that it did not wait on.
+    pub id: Option<u64>,
+
+    pub method: String,
+    pub params: Vec<String>,
+}
+
+/// The type of an RPC, which is given by the first element of a msgpack-rpc message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    /// A request that the client may be waiting on for a response.
+    Request,
+    /// A request whose result is discarded.
+    Notification,
+}
+
+#[cfg(feature = "msgpack")]
+const REQUEST_MESSAGE_TYPE: u64 = 0;
+
+#[cfg(feature = "msgpack")]
+const RESPONSE_MESSAGE_TYPE: u64 = 1;
+
+#[cfg(feature = "msgpack")]
+const NOTIFICATION_MESSAGE_TYPE: u64 = 2;
+
+/// A msgpack-rpc message from the client.
+#[cfg(feature = "msgpack")]
+#[derive(Debug)]
+enum Message {
+    Rpc(Rpc),
+
+    /// A response to a request with an ID. The server doesn't send requests, so responses are
+    /// unexpected.
+    Response(u64),
+}
+
+#[cfg(feature = "msgpack")]
+impl<'de> Deserialize<'de> for Message {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use std::fmt;
+
+        use serde::de::{Error, IgnoredAny, SeqAccess, Unexpected, Visitor};
+
+        struct MessageVisitor;
+
+        impl<'de> Visitor<'de> for MessageVisitor {
+            type Value = Message;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a msgpack-rpc request, response or notification")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Message, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let msg_type: u64 = seq
+                    .next_element()?
+                    .ok_or_else(|| Error::invalid_length(0, &self))?;
+
+                // Messages of each type are arrays of a fixed length.
+                let (len, expected) = match msg_type {
+                    REQUEST_MESSAGE_TYPE => (4, "an array of 4 elements for a request"),
+                    RESPONSE_MESSAGE_TYPE => (4, "an array of 4 elements for a response"),
+                    NOTIFICATION_MESSAGE_TYPE => (3, "an array of 3 elements for a notification"),
+                    _ => {
+                        return Err(Error::invalid_value(
+                            Unexpected::Unsigned(msg_type),
+                            &format!(
+                                "request ({}), response ({}) or notification ({}) message type",
+                                REQUEST_MESSAGE_TYPE,
+                                RESPONSE_MESSAGE_TYPE,
+                                NOTIFICATION_MESSAGE_TYPE
+                            )
+                            .as_str(),
+                        ))
+                    }
+                };
+                if let Some(remaining) = seq.size_hint().filter(|&remaining| remaining != len - 1) {
+                    return Err(Error::invalid_length(remaining + 1, &expected));
+                }
+
+                let id = match msg_type {
+                    NOTIFICATION_MESSAGE_TYPE => None,
+                    _ => Some(
+                        seq.next_element()?
+                            .ok_or_else(|| Error::invalid_length(1, &expected))?,
+                    ),
+                };
+
+                if msg_type == RESPONSE_MESSAGE_TYPE {
+                    for i in 2..4 {
+                        seq.next_element::<IgnoredAny>()?
+                            .ok_or_else(|| Error::invalid_length(i, &expected))?;
+                    }
+                    return Ok(Message::Response(id.unwrap()));
+                }
+
+                let method: String = seq
+                    .next_element()?
+                    .ok_or_else(|| Error::invalid_length(len - 2, &expected))?;
+                let params: Vec<String> = seq
+                    .next_element()?
+                    .ok_or_else(|| Error::invalid_length(len - 1, &expected))?;
+
+                debug!("<- [{}, {:?}, {}, {:?}]", msg_type, id, method, params);
+
+                let message_type = match id {
+                    Some(_) => MessageType::Request,
+                    None => MessageType::Notification,
+                };
+                Ok(Message::Rpc(Rpc {
+                    message_type,
+                    id,
+                    method,
+                    params,
+                }))
+            }
+        }
+
+        deserializer.deserialize_seq(MessageVisitor)
+    }
+}
+
+/// Responses are rejected, because the server doesn't send requests.
+#[cfg(feature = "msgpack")]
+impl<'de> Deserialize<'de> for Rpc {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        match Message::deserialize(deserializer)? {
+            Message::Rpc(rpc) => Ok(rpc),
+            Message::Response(id) => Err(serde::de::Error::custom(format!(
+                "unexpected response to request {}",
+                id
+            ))),
+        }
+    }
+}
+
+/// The most bytes that a string, binary or extension in a message may declare, and the most
+/// elements that an array or map may declare. Longer lengths are taken to be corrupted, rather than
+/// allocated and waited for.
+#[cfg(feature = "msgpack")]
+const MAX_DECLARED_LEN: usize = 64 * 1024 * 1024;
+
+/// Checks the lengths declared by the message at the start of the buffer, as far as it's been
+/// read.
+#[cfg(feature = "msgpack")]
+fn check_declared_lengths(buf: &[u8]) -> Result<(), rmp_serde::decode::Error> {
+    // The position of the next value, and how many values of the message are left.
+    let (mut pos, mut values) = (0, 1);
+    while values > 0 {
+        let marker = match buf.get(pos) {
+            Some(&marker) => marker,
+            None => return Ok(()),
+        };
+        values -= 1;
+
+        // Reads the big-endian length in the bytes after the marker.
+        let len = |size: usize| {
+            let bytes = buf.get(pos + 1..pos + 1 + size)?;
+            Some(bytes.iter().fold(0, |len, &b| len << 8 | usize::from(b)))
+        };
+
+        // The size of the marker and the length that follows it, the declared length, and
+        // whether the length counts elements rather than bytes.
+        let (header, declared, elements) = match marker {
+            0x80..=0x8f => (1, Some(usize::from(marker & 0x0f) * 2), true),
+            0x90..=0x9f => (1, Some(usize::from(marker & 0x0f)), true),
+            0xa0..=0xbf => (1, Some(usize::from(marker & 0x1f)), false),
+            0xc4 | 0xd9 => (2, len(1), false),
+            0xc5 | 0xda => (3, len(2), false),
+            0xc6 | 0xdb => (5, len(4), false),
+            // Extensions have a type after their length.
+            0xc7 => (3, len(1), false),
+            0xc8 => (4, len(2), false),
+            0xc9 => (6, len(4), false),
+            0xcc | 0xd0 => (2, Some(0), false),
+            0xcd | 0xd1 => (3, Some(0), false),
+            0xca | 0xce | 0xd2 => (5, Some(0), false),
+            0xcb | 0xcf | 0xd3 => (9, Some(0), false),
+            0xd4..=0xd8 => (2, Some(1 << (marker - 0xd4)), false),
+            0xdc => (3, len(2), true),
+            0xdd => (5, len(4), true),
+            0xde => (3, len(2).map(|len| len * 2), true),
+            0xdf => (5, len(4).map(|len| len * 2), true),
+            _ => (1, Some(0), false),
+        };
+
+        let declared = match declared {
+            Some(declared) => declared,
+            None => return Ok(()),
+        };
+        if declared > MAX_DECLARED_LEN {
+            return Err(rmp_serde::decode::Error::Syntax(format!(
+                "declared length of {} is longer than {}",
+                declared, MAX_DECLARED_LEN
+            )));
+        }
+
+        pos += header;
+        if elements {
+            values += declared;
+        } else {
+            pos += declared;
+        }
+    }
+
+    Ok(())
+}
+
+/// Decodes msgpack-rpc requests and notifications from a reader.
+///
+/// A corrupted message doesn't end the stream: the bytes up to the start of the next message are
+/// discarded, so that the messages that follow are still decoded. A message starts with an array
+/// of four elements and the request type, or of three elements and the notification type. So is a
+/// message that declares a string or array too long to be real, rather than waiting for it.
+/// Responses are skipped, since the server doesn't send requests.
+#[cfg(feature = "msgpack")]
+#[derive(Debug)]
+pub struct MessagePackDecoder<R> {
+    reader: R,
+    /// The bytes read but not yet decoded.
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+#[cfg(feature = "msgpack")]
+impl<R: Read> MessagePackDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        MessagePackDecoder {
+            reader,
+            buf: vec![],
+            eof: false,
+        }
+    }
+
+    /// Reads more bytes into the buffer. At least as many bytes as are buffered are read if
+    /// available, so that a large message is decoded again only a few times.
+    fn fill_buf(&mut self) -> io::Result<()> {
+        let len = self.buf.len();
+        self.buf.resize(len + len.max(8192), 0);
+        let res = loop {
+            match self.reader.read(&mut self.buf[len..]) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                res => break res,
+            }
+        };
+        let read = *res.as_ref().unwrap_or(&0);
+        self.buf.truncate(len + read);
+        self.eof = read == 0;
+        res.map(|_| ())
+    }
+
+    /// Discards the bytes up to the next byte that could start a message.
+    fn resync(&mut self, err: &rmp_serde::decode::Error) {
+        let start = (1..self.buf.len())
+            .find(|&i| {
+                let msg_type = self.buf.get(i + 1).map(|&msg_type| u64::from(msg_type));
+                match self.buf[i] {
+                    0x94 => msg_type.is_none() || msg_type == Some(REQUEST_MESSAGE_TYPE),
+                    0x93 => msg_type.is_none() || msg_type == Some(NOTIFICATION_MESSAGE_TYPE),
+                    _ => false,
+                }
+            })
+            .unwrap_or(self.buf.len());
+
+        let discarded = self.buf.drain(..start).collect::<Vec<_>>();
+        warn!(
+            "discarded {} bytes of a malformed RPC ({}): {:02x?}{}",
+            discarded.len(),
+            err,
+            &discarded[..discarded.len().min(64)],
+            if discarded.len() > 64 { "..." } else { "" }
+        );
+    }
+}
+
+#[cfg(feature = "msgpack")]
+impl<R: Read> Iterator for MessagePackDecoder<R> {
+    type Item = io::Result<Rpc>;
+
+    /// Decodes the next RPC, or returns `None` once the reader ends.
+    fn next(&mut self) -> Option<io::Result<Rpc>> {
+        use rmp_serde::decode::Error;
+
+        loop {
+            if !self.buf.is_empty() {
+                if let Err(e) = check_declared_lengths(&self.buf) {
+                    self.resync(&e);
+                    continue;
+                }
+
+                let mut deserializer = rmp_serde::Deserializer::new(io::Cursor::new(&self.buf));
+                match Message::deserialize(&mut deserializer) {
+                    Ok(message) => {
+                        let len = deserializer.position() as usize;
+                        self.buf.drain(..len);
+                        match message {
+                            Message::Rpc(rpc) => return Some(Ok(rpc)),
+                            Message::Response(id) => {
+                                warn!("ignoring unexpected response to request {}", id);
+                                continue;
+                            }
+                        }
+                    }
+                    // The rest of the message hasn't been read yet.
+                    Err(Error::InvalidMarkerRead(e)) | Err(Error::InvalidDataRead(e))
+                        if e.kind() == io::ErrorKind::UnexpectedEof && !self.eof => {}
+                    Err(e) => {
+                        self.resync(&e);
+                        continue;
+                    }
+                }
+            } else if self.eof {
+                return None;
+            }
+
+            if let Err(e) = self.fill_buf() {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+// FIXME: Workaround for rust-lang/rust#55779. Move back to the impl when fixed.
+#[derive(Debug, Deserialize)]
+#[allow(unused)]
+struct InnerRpc {
+    method: String,
+    params: Vec<String>,
+}
+
+#[cfg(feature = "json-rpc")]
+impl<'de> Deserialize<'de> for Rpc {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let (id, rpc): (u64, InnerRpc) = Deserialize::deserialize(deserializer)?;
+
+        debug!("<- [{}, {:?}]", id, rpc);
+
+        Ok(Rpc {
+            message_type: MessageType::Request,
+            id: Some(id),
+            method: rpc.method,
+            params: rpc.params,
+        })
+    }
+}
+
+/// Writes the result of a request back to the client.
+#[cfg(feature = "msgpack")]
+pub fn write_response(mut writer: impl Write, id: u64, result: impl Serialize) -> io::Result<()> {
+    let response = (RESPONSE_MESSAGE_TYPE, id, (), result);
+
+    rmp_serde::encode::write(&mut writer, &response).map_err(io::Error::other)?;
+    writer.flush()
+}
+
+/// Writes the result of a request back to the client.
+#[cfg(feature = "json-rpc")]
+pub fn write_response(mut writer: impl Write, id: u64, result: impl Serialize) -> io::Result<()> {
+    serde_json::to_writer(&mut writer, &(id, result))?;
+    writeln!(writer)?;
+    writer.flush()
+}
+
+/// Writes the error of a failed request back to the client.
+#[cfg(feature = "msgpack")]
+pub fn write_error(mut writer: impl Write, id: u64, message: &str) -> io::Result<()> {
+    let response = (RESPONSE_MESSAGE_TYPE, id, message, ());
+
+    rmp_serde::encode::write(&mut writer, &response).map_err(io::Error::other)?;
+    writer.flush()
+}
+
+/// Writes the error of a failed request back to the client.
+///
+/// Vim's channels have no errors, so the request returns null, and the client is sent an `error`
+/// notification with the message instead.
+#[cfg(feature = "json-rpc")]
+pub fn write_error(mut writer: impl Write, id: u64, message: &str) -> io::Result<()> {
+    write_response(&mut writer, id, ())?;
+    write_notification(writer, "error", [message])
+}
+
+/// The editor function that receives notifications from the server.
+const NOTIFY_FUNCTION: &str = "MarkdownComposerNotify";
+
+/// Sends a notification to the editor.
+///
+/// Neither editor dispatches messages from a job to plugins directly, so the editor is asked to
+/// call the plugin's notification function with the method and parameters instead.
+#[cfg(feature = "msgpack")]
+pub fn write_notification(
+    mut writer: impl Write,
+    method: &str,
+    params: impl Serialize,
+) -> io::Result<()> {
+    let notification = (
+        NOTIFICATION_MESSAGE_TYPE,
+        "nvim_call_function",
+        (NOTIFY_FUNCTION, (method, params)),
+    );
+
+    rmp_serde::encode::write(&mut writer, &notification).map_err(io::Error::other)?;
+    writer.flush()
+}
+
+/// Sends a notification to the editor.
+///
+/// Neither editor dispatches messages from a job to plugins directly, so the editor is asked to
+/// call the plugin's notification function with the method and parameters instead.
+#[cfg(feature = "json-rpc")]
+pub fn write_notification(
+    mut writer: impl Write,
+    method: &str,
+    params: impl Serialize,
+) -> io::Result<()> {
+    serde_json::to_writer(&mut writer, &("call", NOTIFY_FUNCTION, (method, params)))?;
+    writeln!(writer)?;
+    writer.flush()
+}
+
+#[cfg(all(test, feature = "msgpack"))]
+mod tests {
+    use std::io::{self, Read};
+
+    use super::{
+        check_declared_lengths, MessagePackDecoder, MessageType, NOTIFICATION_MESSAGE_TYPE,
+        REQUEST_MESSAGE_TYPE, RESPONSE_MESSAGE_TYPE,
+    };
+
+    #[test]
+    fn message_types() {
+        let mut input = vec![];
+        // A response is skipped whole.
+        input.extend(rmp_serde::to_vec(&(RESPONSE_MESSAGE_TYPE, 7, (), ["send_data"])).unwrap());
+        // A notification with an ID isn't mistaken for a request.
+        input.extend(
+            rmp_serde::to_vec(&(NOTIFICATION_MESSAGE_TYPE, 3, "toggle_toc", [""; 0])).unwrap(),
+        );
+        input.extend(rmp_serde::to_vec(&(REQUEST_MESSAGE_TYPE, 1, "get_url", [""; 0])).unwrap());
+        input.extend(
+            rmp_serde::to_vec(&(NOTIFICATION_MESSAGE_TYPE, "toggle_toc", [""; 0])).unwrap(),
+        );
+
+        let rpcs = MessagePackDecoder::new(&input[..])
+            .map(|rpc| {
+                let rpc = rpc.unwrap();
+                (rpc.message_type, rpc.id, rpc.method)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            rpcs,
+            [
+                (MessageType::Request, Some(1), String::from("get_url")),
+                (MessageType::Notification, None, String::from("toggle_toc")),
+            ]
+        );
+    }
+
+    #[test]
+    fn resynchronize() {
+        let mut input =
+            rmp_serde::to_vec(&(NOTIFICATION_MESSAGE_TYPE, "send_data", ["one"])).unwrap();
+        input.extend(&[0xc1, 0xff, 0x93]);
+        input.extend(rmp_serde::to_vec(&(REQUEST_MESSAGE_TYPE, 1, "get_url", [""; 0])).unwrap());
+        // A corrupted method name, followed by a message.
+        input.extend(&[0x93, 0x02, 0xc1]);
+        input
+            .extend(rmp_serde::to_vec(&(NOTIFICATION_MESSAGE_TYPE, "send_data", ["two"])).unwrap());
+        // A message that is cut off.
+        input.extend(
+            &rmp_serde::to_vec(&(NOTIFICATION_MESSAGE_TYPE, "send_data", ["three"])).unwrap()[..8],
+        );
+
+        // Messages are split across reads.
+        let rpcs = MessagePackDecoder::new((&input[..5]).chain(&input[5..]))
+            .map(|rpc| {
+                let rpc = rpc.unwrap();
+                (rpc.id, rpc.method, rpc.params)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            rpcs,
+            [
+                (None, String::from("send_data"), vec![String::from("one")]),
+                (Some(1), String::from("get_url"), vec![]),
+                (None, String::from("send_data"), vec![String::from("two")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn resynchronize_bogus_length() {
+        /// A reader whose input hasn't ended, but never has any more of it.
+        struct Open<'a>(&'a [u8]);
+
+        impl Read for Open<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                assert!(!self.0.is_empty(), "waited for bytes that never arrive");
+                self.0.read(buf)
+            }
+        }
+
+        // A method name that declares 4 GiB, followed by a message.
+        let mut input = vec![0x93, 0x02, 0xdb, 0xff, 0xff, 0xff, 0xff];
+        input.extend(
+            rmp_serde::to_vec(&(NOTIFICATION_MESSAGE_TYPE, "toggle_toc", [""; 0])).unwrap(),
+        );
+
+        let rpc = MessagePackDecoder::new(Open(&input))
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(rpc.method, "toggle_toc");
+
+        // Lengths that are cut off can't be checked yet.
+        assert!(check_declared_lengths(&[0x94, 0x00, 0x01, 0xdb, 0xff]).is_ok());
+        assert!(check_declared_lengths(&[0x94, 0x00, 0x01, 0xdb, 0xff, 0xff, 0xff, 0xff]).is_err());
+        assert!(check_declared_lengths(&[0x93, 0x02, 0xdd, 0x10, 0x00, 0x00, 0x00]).is_err());
+    }
+}
@@ -0,0 +1,505 @@
+//! Sanitization of HTML from untrusted documents.
+//!
+//! Tags, attributes and URL schemes that aren't allowed are removed, so that raw HTML in a
+//! document can't run scripts in the preview. Disallowed tags are removed but their contents are
+//! kept, except for the contents of `<script>` and `<style>` elements, which are removed too.
+//! Comments are removed, except for the markers that the renderer uses, such as `<!-- slide -->`.
+//!
+//! Elements with an `id` or `name` become properties of `window` and `document`, which could
+//! override the variables of the preview's scripts, so those attributes aren't allowed by default.
+//! If they are allowed, their values are prefixed with `user-content-`, like on GitHub.
+
+use std::collections::BTreeSet;
+
+use handlebars::html_escape;
+
+/// Tags that are allowed by default.
+const TAGS: &[&str] = &[
+    "a",
+    "abbr",
+    "b",
+    "bdi",
+    "bdo",
+    "blockquote",
+    "br",
+    "caption",
+    "center",
+    "cite",
+    "code",
+    "col",
+    "colgroup",
+    "dd",
+    "del",
+    "details",
+    "dfn",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "figcaption",
+    "figure",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "i",
+    "img",
+    "input",
+    "ins",
+    "kbd",
+    "li",
+    "mark",
+    "ol",
+    "p",
+    "picture",
+    "pre",
+    "q",
+    "rp",
+    "rt",
+    "ruby",
+    "s",
+    "samp",
+    "section",
+    "small",
+    "source",
+    "span",
+    "strike",
+    "strong",
+    "sub",
+    "summary",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "time",
+    "tr",
+    "tt",
+    "u",
+    "ul",
+    "var",
+    "wbr",
+];
+
+/// Attributes that are allowed on every allowed tag by default. Attributes beginning with
+/// `data-` and `aria-` are always allowed.
+const ATTRIBUTES: &[&str] = &[
+    "align", "alt", "checked", "cite", "class", "colspan", "datetime", "dir", "disabled", "height",
+    "href", "lang", "loading", "open", "rowspan", "span", "src", "start", "title", "type", "width",
+];
+
+/// URL schemes that are allowed by default. URLs without a scheme are always allowed.
+const URL_SCHEMES: &[&str] = &["http", "https", "mailto", "tel"];
+
+/// Attributes whose values are URLs.
+const URL_ATTRIBUTES: &[&str] = &["action", "cite", "formaction", "href", "poster", "src"];
+
+/// Attributes that name elements, whose values are prefixed.
+const NAME_ATTRIBUTES: &[&str] = &["id", "name"];
+
+/// The prefix of the values of `id` and `name` attributes.
+const NAME_PREFIX: &str = "user-content-";
+
+/// Tags whose contents are removed along with them, unless the tag is allowed.
+const CONTENT_TAGS: &[&str] = &["script", "style"];
+
+/// Comments that are kept, since the renderer uses them.
+const MARKERS: &[&str] = &["<!-- slide -->"];
+
+/// Removes the tags, attributes and URLs that aren't allowed from HTML.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sanitizer {
+    tags: BTreeSet<String>,
+    attributes: BTreeSet<String>,
+    url_schemes: BTreeSet<String>,
+}
+
+impl Default for Sanitizer {
+    fn default() -> Self {
+        let set = |names: &[&str]| names.iter().map(|&name| String::from(name)).collect();
+        Sanitizer {
+            tags: set(TAGS),
+            attributes: set(ATTRIBUTES),
+            url_schemes: set(URL_SCHEMES),
+        }
+    }
+}
+
+impl Sanitizer {
+    /// Allows a tag, in addition to the default tags.
+    pub fn allow_tag(&mut self, tag: &str) {
+        self.tags.insert(tag.to_ascii_lowercase());
+    }
+
+    /// Allows an attribute on every allowed tag, in addition to the default attributes. Event
+    /// handlers, such as `onclick`, are never allowed.
+    pub fn allow_attribute(&mut self, attribute: &str) {
+        self.attributes.insert(attribute.to_ascii_lowercase());
+    }
+
+    /// Allows URLs with a scheme, such as `data`, in addition to the default schemes.
+    pub fn allow_url_scheme(&mut self, scheme: &str) {
+        self.url_schemes.insert(scheme.to_ascii_lowercase());
+    }
+
+    /// Returns whether a URL may be linked to, because it has no scheme or an allowed one.
+    pub fn is_allowed_url(&self, url: &str) -> bool {
+        // Browsers ignore whitespace and control characters in URLs, such as in `java\tscript:`.
+        let url = url
+            .chars()
+            .filter(|c| !c.is_ascii_whitespace() && !c.is_ascii_control())
+            .collect::<String>();
+        let scheme = match url.find([':', '/', '?', '#']) {
+            Some(end) if url[end..].starts_with(':') => &url[..end],
+            _ => return true,
+        };
+        self.url_schemes.contains(&scheme.to_ascii_lowercase())
+    }
+
+    /// Sanitizes HTML.
+    pub fn clean(&self, html: &str) -> String {
+        let mut clean = String::with_capacity(html.len());
+        let mut rest = html;
+
+        while let Some(start) = rest.find('<') {
+            clean.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            if rest.starts_with("<!--") {
+                match MARKERS.iter().find(|marker| rest.starts_with(**marker)) {
+                    Some(marker) => {
+                        clean.push_str(marker);
+                        rest = &rest[marker.len()..];
+                    }
+                    None => rest = &rest[comment_len(rest)..],
+                }
+                continue;
+            }
+
+            let tag = match Tag::parse(rest) {
+                Some(tag) => tag,
+                None if rest[1..].starts_with(['!', '?', '/']) => {
+                    // Doctypes, processing instructions and malformed end tags are removed.
+                    rest = rest.find('>').map_or("", |end| &rest[end + 1..]);
+                    continue;
+                }
+                None => {
+                    clean.push_str("&lt;");
+                    rest = &rest[1..];
+                    continue;
+                }
+            };
+            rest = &rest[tag.len..];
+
+            if self.tags.contains(&tag.name) {
+                self.push_tag(&mut clean, &tag);
+            } else if !tag.end && CONTENT_TAGS.contains(&&*tag.name) {
+                rest = skip_element(rest, &tag.name);
+            }
+        }
+
+        clean.push_str(rest);
+        clean
+    }
+
+    fn push_tag(&self, html: &mut String, tag: &Tag) {
+        if tag.end {
+            html.push_str("</");
+            html.push_str(&tag.name);
+            html.push('>');
+            return;
+        }
+
+        html.push('<');
+        html.push_str(&tag.name);
+        for (name, value) in &tag.attributes {
+            let allowed = !name.starts_with("on")
+                && (self.attributes.contains(name)
+                    || name.starts_with("data-")
+                    || name.starts_with("aria-"));
+            let allowed_url = match value {
+                Some(url) if URL_ATTRIBUTES.contains(&&**name) => self.is_allowed_url(url),
+                _ => true,
+            };
+            if !allowed || !allowed_url {
+                continue;
+            }
+
+            match value {
+                Some(value) => {
+                    html.push(' ');
+                    html.push_str(name);
+                    html.push_str("=\"");
+                    if NAME_ATTRIBUTES.contains(&&**name) {
+                        html.push_str(NAME_PREFIX);
+                    }
+                    html.push_str(&html_escape(value));
+                    html.push('"');
+                }
+                None => {
+                    html.push(' ');
+                    html.push_str(name);
+                }
+            }
+        }
+        if tag.self_closing {
+            html.push_str(" /");
+        }
+        html.push('>');
+    }
+}
+
+/// A start or end tag.
+#[derive(Debug)]
+struct Tag {
+    /// The lowercase name of the tag.
+    name: String,
+
+    end: bool,
+    self_closing: bool,
+
+    /// The lowercase names of the attributes, and their values with character references
+    /// decoded.
+    attributes: Vec<(String, Option<String>)>,
+
+    /// The length of the tag in the HTML.
+    len: usize,
+}
+
+impl Tag {
+    /// Parses the tag at the beginning of HTML, if there is one.
+    fn parse(html: &str) -> Option<Tag> {
+        let (end, after_open) = match html.strip_prefix("</") {
+            Some(rest) => (true, rest),
+            None => (false, html.strip_prefix('<')?),
+        };
+        if !after_open.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return None;
+        }
+
+        let name_len = after_open
+            .find(|c: char| c.is_ascii_whitespace() || c == '/' || c == '>')
+            .unwrap_or(after_open.len());
+        let name = after_open[..name_len].to_ascii_lowercase();
+
+        let mut rest = &after_open[name_len..];
+        let mut attributes = vec![];
+        let mut self_closing = false;
+        loop {
+            rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace());
+            if let Some(after) = rest.strip_prefix('>') {
+                rest = after;
+                break;
+            } else if let Some(after) = rest.strip_prefix('/') {
+                self_closing = after.starts_with('>');
+                rest = after;
+                continue;
+            } else if rest.is_empty() {
+                // Tags that are cut off aren't tags.
+                return None;
+            }
+
+            let name_len = rest
+                .find(|c: char| c.is_ascii_whitespace() || matches!(c, '=' | '>' | '/'))
+                .unwrap_or(rest.len())
+                .max(1);
+            let attribute = rest[..name_len].to_ascii_lowercase();
+            rest = rest[name_len..].trim_start_matches(|c: char| c.is_ascii_whitespace());
+
+            let value = match rest.strip_prefix('=') {
+                Some(after) => {
+                    let after = after.trim_start_matches(|c: char| c.is_ascii_whitespace());
+                    let (value, after) = match after.chars().next() {
+                        Some(quote @ ('"' | '\'')) => {
+                            let value = &after[1..];
+                            let end = value.find(quote).unwrap_or(value.len());
+                            (&value[..end], value.get(end + 1..).unwrap_or(""))
+                        }
+                        _ => {
+                            let end = after
+                                .find(|c: char| c.is_ascii_whitespace() || c == '>')
+                                .unwrap_or(after.len());
+                            after.split_at(end)
+                        }
+                    };
+                    rest = after;
+                    Some(decode_references(value))
+                }
+                None => None,
+            };
+            attributes.push((attribute, value));
+        }
+
+        Some(Tag {
+            name,
+            end,
+            self_closing,
+            attributes,
+            len: html.len() - rest.len(),
+        })
+    }
+}
+
+/// Skips the contents and end tag of an element whose start tag was removed.
+fn skip_element<'a>(html: &'a str, name: &str) -> &'a str {
+    let end_tag = format!("</{}", name);
+    let lowercase = html.to_ascii_lowercase();
+    match lowercase.find(&end_tag) {
+        Some(start) => html[start..]
+            .find('>')
+            .map_or("", |end| &html[start + end + 1..]),
+        None => "",
+    }
+}
+
+/// Decodes the numeric character references in an attribute value, and the named references
+/// that may hide a URL's scheme, which include those that the renderers escape text with.
+pub(crate) fn decode_references(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = rest[1..]
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '#')
+            .map_or(rest.len(), |end| end + 1);
+        let reference = &rest[1..end];
+        let c = match reference.strip_prefix('#') {
+            Some(number) => match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => number.parse().ok(),
+            }
+            .map(|code| {
+                char::from_u32(code)
+                    .filter(|&c| c != '\0')
+                    .unwrap_or('\u{FFFD}')
+            }),
+            None => match reference {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "colon" => Some(':'),
+                "Tab" => Some('\t'),
+                "NewLine" => Some('\n'),
+                _ => None,
+            },
+        };
+
+        match c {
+            Some(c) => {
+                decoded.push(c);
+                rest = rest[end..].strip_prefix(';').unwrap_or(&rest[end..]);
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Returns the length of the comment at the start of the HTML, up to where a browser ends it, or
+/// the length of the HTML if the comment is unterminated.
+///
+/// Browsers end a comment at the first `-->` or `--!>`, and also at a `>` or `->` right after the
+/// `<!--`, so markup after any of them isn't part of the comment.
+fn comment_len(html: &str) -> usize {
+    let body = &html["<!--".len()..];
+    if body.starts_with('>') {
+        return "<!-->".len();
+    }
+    if body.starts_with("->") {
+        return "<!--->".len();
+    }
+
+    ["-->", "--!>"]
+        .iter()
+        .filter_map(|end| body.find(end).map(|i| "<!--".len() + i + end.len()))
+        .min()
+        .unwrap_or(html.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sanitizer;
+
+    #[test]
+    fn remove_scripts() {
+        let sanitizer = Sanitizer::default();
+        assert_eq!(
+            sanitizer.clean(
+                "<p onclick=\"steal()\" class=x>Hi<SCRIPT>alert('<p>')</script > there</p>\
+                 <style>p {}</style><!-- slide --><iframe src=\"x\">text</iframe>"
+            ),
+            "<p class=\"x\">Hi there</p><!-- slide -->text"
+        );
+        assert_eq!(sanitizer.clean("1 < 2 <!doctype html>"), "1 &lt; 2 ");
+        assert_eq!(
+            sanitizer.clean("<img src=x\nonerror=alert(1)>"),
+            "<img src=\"x\">"
+        );
+        assert_eq!(
+            sanitizer.clean("<!--><img src=x onerror=alert(1)>-->"),
+            "<img src=\"x\">-->"
+        );
+        assert_eq!(
+            sanitizer.clean("<!-- --!><script>alert(2)</script> -->"),
+            " -->"
+        );
+        assert_eq!(
+            sanitizer.clean("a<!---><b>b</b>-->c<!-- note -->d<!-- unterminated"),
+            "a<b>b</b>-->cd"
+        );
+    }
+
+    #[test]
+    fn names() {
+        let mut sanitizer = Sanitizer::default();
+        let html = "<img id=\"ws\" name=\"config\"><a href=\"#ws\">a</a>";
+        assert_eq!(sanitizer.clean(html), "<img><a href=\"#ws\">a</a>");
+
+        sanitizer.allow_attribute("id");
+        sanitizer.allow_attribute("NAME");
+        assert_eq!(
+            sanitizer.clean(html),
+            "<img id=\"user-content-ws\" name=\"user-content-config\"><a href=\"#ws\">a</a>"
+        );
+    }
+
+    #[test]
+    fn urls() {
+        let mut sanitizer = Sanitizer::default();
+        assert_eq!(
+            sanitizer.clean(
+                "<a href=\"java\tscript:alert(1)\">a</a>\
+                 <a href='javascript&#58;alert(1)'>b</a>\
+                 <a href=\"&#x6a;avascript:alert(1)\">c</a>\
+                 <a href=\"/docs/a:b\">d</a><a href=\"HTTPS://example.com?q=a&amp;b\">e</a>"
+            ),
+            "<a>a</a><a>b</a><a>c</a><a href=\"/docs/a:b\">d</a>\
+             <a href=\"HTTPS://example.com?q&#x3D;a&amp;b\">e</a>"
+        );
+        assert!(!sanitizer.is_allowed_url("data:text/html,<script>"));
+
+        sanitizer.allow_url_scheme("data");
+        sanitizer.allow_tag("svg");
+        sanitizer.allow_attribute("style");
+        assert!(sanitizer.is_allowed_url("data:image/png;base64,"));
+        assert_eq!(
+            sanitizer.clean("<svg style=\"color: red\" onload=\"x()\"/>"),
+            "<svg style=\"color: red\" />"
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! Full-text search of rendered documents.
+//!
+//! The text of the HTML is searched rather than the markdown, so that markup doesn't get in the
+//! way of matches, and each match is located by the source line of the block that contains it.
+//! Matching is case-insensitive, and runs of whitespace match any run of whitespace.
+
+use serde::Serialize;
+
+use crate::sanitize::decode_references;
+
+/// Elements whose text isn't searched.
+const SKIPPED_ELEMENTS: &[&str] = &["button", "script", "style", "svg", "template"];
+
+/// The number of characters of context on each side of a match in its snippet.
+const CONTEXT: usize = 40;
+
+/// The largest number of matches that are found in a document.
+pub const MAX_MATCHES: usize = 100;
+
+/// An occurrence of the query in a document.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Match {
+    /// The (one-based) line of the markdown that the block containing the match was rendered
+    /// from, if the renderer annotated it.
+    pub line: Option<usize>,
+
+    /// The text around the match.
+    pub snippet: String,
+}
+
+/// Searches the text of HTML for a query.
+pub fn search(html: &str, query: &str) -> Vec<Match> {
+    let query = normalize(query)
+        .trim()
+        .chars()
+        .map(fold)
+        .collect::<Vec<_>>();
+    if query.is_empty() {
+        return vec![];
+    }
+
+    let mut matches = vec![];
+    for (line, text) in blocks(html) {
+        let text = normalize(&text).chars().collect::<Vec<_>>();
+        let folded = text.iter().copied().map(fold).collect::<Vec<_>>();
+
+        let mut start = 0;
+        while start + query.len() <= folded.len() {
+            if folded[start..start + query.len()] != query[..] {
+                start += 1;
+                continue;
+            }
+
+            let end = start + query.len();
+            let before = start.saturating_sub(CONTEXT);
+            let after = (end + CONTEXT).min(text.len());
+            let mut snippet = String::new();
+            if before > 0 {
+                snippet.push('…');
+            }
+            snippet.extend(&text[before..after]);
+            if after < text.len() {
+                snippet.push('…');
+            }
+
+            matches.push(Match {
+                line,
+                snippet: snippet.trim().to_owned(),
+            });
+            if matches.len() == MAX_MATCHES {
+                return matches;
+            }
+            start = end;
+        }
+    }
+    matches
+}
+
+/// Splits the text of HTML into the text of each block that's annotated with a source line,
+/// with the line. Text before the first annotated block has no line.
+fn blocks(html: &str) -> Vec<(Option<usize>, String)> {
+    let mut blocks = vec![(None, String::new())];
+
+    // The element whose text isn't searched that is being skipped, if any, and the number of
+    // elements of the same name that are open.
+    let mut skipped: Option<(String, usize)> = None;
+
+    let mut rest = html;
+    while !rest.is_empty() {
+        if rest.starts_with('<') {
+            let end = if rest.starts_with("<!--") {
+                rest.find("-->").map_or(rest.len(), |end| end + 3)
+            } else {
+                rest.find('>').map_or(rest.len(), |end| end + 1)
+            };
+            let tag = &rest[..end];
+            rest = &rest[end..];
+
+            let (closing, name) = match tag.strip_prefix("</") {
+                Some(name) => (true, name),
+                None => (false, &tag[1..]),
+            };
+            let name = name
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            let self_closing = tag.ends_with("/>");
+
+            match &mut skipped {
+                Some((skipped_name, depth)) if *skipped_name == name => {
+                    if closing {
+                        *depth -= 1;
+                    } else if !self_closing {
+                        *depth += 1;
+                    }
+                    if *depth == 0 {
+                        skipped = None;
+                    }
+                }
+                Some(_) => (),
+                None if !closing && !self_closing && SKIPPED_ELEMENTS.contains(&&*name) => {
+                    skipped = Some((name, 1));
+                }
+                None if !closing => {
+                    if let Some(line) = source_line(tag) {
+                        blocks.push((Some(line), String::new()));
+                    }
+                }
+                None => {
+                    // Blocks are separated, so that words of adjacent blocks don't match together.
+                    let (_, text) = blocks.last_mut().unwrap();
+                    text.push(' ');
+                }
+            }
+            continue;
+        }
+
+        let end = rest.find('<').unwrap_or(rest.len());
+        if skipped.is_none() {
+            let (_, text) = blocks.last_mut().unwrap();
+            text.push_str(&decode_references(&rest[..end]));
+        }
+        rest = &rest[end..];
+    }
+
+    blocks
+}
+
+/// Returns the value of the `data-source-line` attribute of a start tag.
+fn source_line(tag: &str) -> Option<usize> {
+    let (_, value) = tag.split_once(" data-source-line=\"")?;
+    value.split('"').next()?.parse().ok()
+}
+
+/// Collapses runs of whitespace into single spaces.
+fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Folds the case of a character, keeping one character for each character so that positions
+/// in the folded text are positions in the text.
+fn fold(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{search, Match};
+
+    #[test]
+    fn matches() {
+        let html = "<h1 data-source-line=\"1\">Fish &amp; <em>Chips</em></h1>\n\
+                    <p data-source-line=\"3\">Some fish\nand more FISH.<button>fish</button></p>\n\
+                    <pre data-source-line=\"5\"><code>fish()</code></pre>";
+        assert_eq!(
+            search(html, " fish "),
+            [
+                Match {
+                    line: Some(1),
+                    snippet: String::from("Fish & Chips"),
+                },
+                Match {
+                    line: Some(3),
+                    snippet: String::from("Some fish and more FISH."),
+                },
+                Match {
+                    line: Some(3),
+                    snippet: String::from("Some fish and more FISH."),
+                },
+                Match {
+                    line: Some(5),
+                    snippet: String::from("fish()"),
+                },
+            ]
+        );
+        assert_eq!(search(html, "fish  and")[0].line, Some(3));
+        assert_eq!(search(html, "& chips")[0].line, Some(1));
+        assert!(search(html, "").is_empty());
+
+        let long = format!("<p>{}needle{}</p>", "a ".repeat(50), " b".repeat(50));
+        let snippet = &search(&long, "needle")[0].snippet;
+        assert!(snippet.starts_with('…') && snippet.ends_with('…'));
+    }
+}
@@ -0,0 +1,6630 @@
+//! Markdown preview server.
+//!
+//! Upon receiving an HTTP request, the server responds with an HTML page containing a rendering
+//! of the most recent markdown. Client-side JavaScript then initiates a WebSocket connection
+//! which allows the server to push new renderings and control messages to the page without a
+//! refresh.
+//!
+//! This module started life as the [aurelius](https://github.com/euclio/aurelius) crate.
+
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{self, prelude::*};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::{self, FromStr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use base64::engine::{general_purpose::STANDARD, Engine};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use handlebars::{html_escape, Handlebars, Template};
+use httparse::{Request, Status, EMPTY_HEADER};
+use include_dir::{include_dir, Dir};
+use log::*;
+use pulldown_cmark::{Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use serde::{Deserialize, Serialize};
+use tungstenite::protocol::frame::{coding::CloseCode, CloseFrame};
+use tungstenite::{handshake::derive_accept_key, protocol::Role, Message, WebSocket};
+use url::{form_urlencoded, Url};
+
+use self::id_map::IdMap;
+use self::queue::{SignalReceiver, SignalSender};
+use self::stream::{Listener, Stream};
+use self::thumbnail::Thumbnails;
+use crate::browser;
+use crate::check;
+use crate::diagram::{Diagrams, PlantUml};
+use crate::export::pdf::PdfLayout;
+use crate::export::{self, epub};
+use crate::external::ExternalRenderer;
+use crate::format::Format;
+use crate::front_matter::{FrontMatter, MathSetting};
+use crate::git;
+use crate::lint::{Diagnostic, Linter};
+use crate::qr::QrCode;
+use crate::render::{
+    BlockCache, FrontMatterDisplay, RenderCache, RenderOptions, SlugStyle, WikiLinkCase,
+};
+use crate::sanitize::Sanitizer;
+use crate::search::{self, Match};
+use crate::spell::{self, SpellChecker};
+use crate::stats::Stats;
+
+mod diff;
+mod id_map;
+mod network;
+mod queue;
+mod stream;
+mod thumbnail;
+
+static STATIC_FILES: Dir = include_dir!("$CARGO_MANIFEST_DIR/static");
+
+/// Responses and updates smaller than this are sent uncompressed, since compression would barely
+/// save anything.
+const MIN_COMPRESSED_SIZE: usize = 1024;
+
+/// The title of the preview page if no other title is available.
+const DEFAULT_TITLE: &str = "Markdown Composer";
+
+/// How often local images and stylesheets are checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long sending an update to a page may take before the page is considered unable to keep up,
+/// and is disconnected.
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The smallest and largest zoom of the preview, in percent.
+const MIN_ZOOM: u32 = 25;
+const MAX_ZOOM: u32 = 500;
+
+/// Sources that the title of the preview may be derived from, if no title is set explicitly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TitleSource {
+    /// The text of the first level-one heading in the markdown.
+    Heading,
+
+    /// The name of the file being previewed.
+    FileName,
+}
+
+/// Libraries that the preview may use to typeset math.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MathRenderer {
+    /// [KaTeX](https://katex.org/).
+    Katex,
+
+    /// [MathJax](https://www.mathjax.org/).
+    MathJax,
+
+    /// MathML, converted from TeX by the server. No scripts are required to display the math.
+    MathMl,
+}
+
+impl FromStr for MathRenderer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "katex" => Ok(MathRenderer::Katex),
+            "mathjax" => Ok(MathRenderer::MathJax),
+            "mathml" => Ok(MathRenderer::MathMl),
+            _ => Err(format!("unknown math renderer: {}", s)),
+        }
+    }
+}
+
+/// The color schemes that the preview may be displayed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorScheme {
+    Light,
+    Dark,
+
+    /// Follow the color scheme preferred by the browser.
+    Auto,
+}
+
+impl FromStr for ColorScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "light" => Ok(ColorScheme::Light),
+            "dark" => Ok(ColorScheme::Dark),
+            "auto" => Ok(ColorScheme::Auto),
+            _ => Err(format!("unknown color scheme: {}", s)),
+        }
+    }
+}
+
+/// The Content-Security-Policy of the preview page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContentSecurityPolicy {
+    /// Send no policy.
+    Off,
+
+    /// A policy generated from the preview's settings, which only allows the preview's own scripts
+    /// and scripts from the sources that the preview was configured with.
+    Strict,
+
+    /// A policy that is sent as-is.
+    Custom(String),
+}
+
+impl FromStr for ContentSecurityPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(ContentSecurityPolicy::Off),
+            "strict" => Ok(ContentSecurityPolicy::Strict),
+            _ if s.contains(['\r', '\n']) => Err(format!("invalid policy: {:?}", s)),
+            _ => Ok(ContentSecurityPolicy::Custom(s.to_string())),
+        }
+    }
+}
+
+/// Typography of the preview. Properties that are `None` are left to the stylesheets.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Typography {
+    pub font_family: Option<String>,
+    pub code_font_family: Option<String>,
+
+    /// The base font size. Numbers are treated as pixels.
+    pub font_size: Option<String>,
+
+    /// The maximum width of the content. Numbers are treated as pixels.
+    pub max_width: Option<String>,
+}
+
+impl Typography {
+    /// Sets a property by name, such as `font_family`, or resets it if the value is `None`.
+    ///
+    /// Values that could escape their CSS declaration are rejected.
+    pub fn set(&mut self, property: &str, value: Option<String>) -> Result<(), String> {
+        if let Some(value) = &value {
+            if value.contains([';', '{', '}', '<', '>']) {
+                return Err(format!("invalid value for {}: {}", property, value));
+            }
+        }
+
+        let length = |value: String| match value.parse::<f64>() {
+            Ok(_) => format!("{}px", value),
+            Err(_) => value,
+        };
+
+        match property {
+            "font_family" => self.font_family = value,
+            "code_font_family" => self.code_font_family = value,
+            "font_size" => self.font_size = value.map(length),
+            "max_width" => self.max_width = value.map(length),
+            _ => return Err(format!("unknown typography property: {}", property)),
+        }
+
+        Ok(())
+    }
+
+    /// Returns CSS that applies the typography to the preview, or `None` if nothing is set.
+    fn to_css(&self) -> Option<String> {
+        let mut body = String::new();
+        if let Some(font_family) = &self.font_family {
+            body.push_str(&format!("font-family: {};", font_family));
+        }
+        if let Some(font_size) = &self.font_size {
+            body.push_str(&format!("font-size: {};", font_size));
+        }
+        if let Some(max_width) = &self.max_width {
+            body.push_str(&format!("max-width: {};", max_width));
+        }
+
+        let mut css = String::new();
+        if !body.is_empty() {
+            css.push_str(&format!(".markdown-body {{ {} }}\n", body));
+        }
+        if let Some(code_font_family) = &self.code_font_family {
+            css.push_str(&format!(
+                ".markdown-body code, .markdown-body kbd, .markdown-body pre {{ font-family: {}; }}\n",
+                code_font_family
+            ));
+        }
+
+        Some(css).filter(|css| !css.is_empty())
+    }
+}
+
+/// Requests made by the user from a preview page, to be handled by the owner of the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PreviewRequest {
+    /// Render a markdown file, such as the target of a wiki link. The path is relative to the
+    /// static root. The file is rendered in the preview of the buffer, if the request was made
+    /// from one.
+    Open {
+        path: PathBuf,
+        buffer: Option<String>,
+    },
+
+    /// Go back to the document that was displayed before a file was opened, in the preview of the
+    /// buffer if the request was made from one.
+    Back { buffer: Option<String> },
+
+    /// Check or uncheck the task list item on a line of the markdown, or of the buffer if the
+    /// request was made from the preview of one.
+    ToggleTask { line: usize, buffer: Option<String> },
+
+    /// Render markdown that was pushed to the server over HTTP. The path of the document, if
+    /// given, names the preview; the file isn't read.
+    Render {
+        markdown: String,
+        path: Option<PathBuf>,
+    },
+
+    /// The linter reported new diagnostics for the markdown, or for the markdown of a buffer.
+    Diagnostics {
+        diagnostics: Vec<Diagnostic>,
+        buffer: Option<String>,
+    },
+}
+
+/// A summary of the state of the server, for diagnosing problems.
+#[derive(Debug, Serialize)]
+pub struct ServerStatus {
+    version: &'static str,
+    uptime_secs: u64,
+    /// The number of preview pages that are connected to the server.
+    clients: usize,
+    last_render: Option<RenderStatus>,
+    settings: StatusSettings,
+}
+
+/// When the preview was last rendered, and how long each stage of rendering took.
+#[derive(Debug, Clone, Copy, Serialize)]
+struct RenderStatus {
+    /// The number of the render, counting from one when the server starts.
+    id: u64,
+    /// Seconds since the Unix epoch.
+    timestamp: u64,
+    duration_ms: f64,
+    /// Whether the HTML was rendered recently, and was reused instead of rendered again.
+    cached: bool,
+    /// How long the built-in renderer, or the built-in pipeline of the document's format, took to
+    /// parse and render the markdown.
+    parse_ms: Option<f64>,
+    /// How long an external program took to render the markdown, such as the external renderer.
+    external_renderer_ms: Option<f64>,
+    /// How long the rest of rendering took, such as sanitizing the HTML, marking misspelled words
+    /// and inlining images.
+    postprocess_ms: f64,
+    /// How long after rendering the update was sent to the slowest of the connected pages.
+    broadcast_ms: Option<f64>,
+    /// How long the slowest of the pages that display render timings took to display the update,
+    /// including highlighting its code blocks.
+    highlight_ms: Option<f64>,
+    /// When rendering finished.
+    #[serde(skip)]
+    finished: Instant,
+}
+
+impl RenderStatus {
+    /// Returns the render with how long after rendering it was sent to a page.
+    fn sent_after(mut self, elapsed: Duration) -> Self {
+        self.broadcast_ms = Some(elapsed.as_secs_f64() * 1000.0);
+        self
+    }
+}
+
+/// The settings that the preview is currently displayed with.
+#[derive(Debug, Serialize)]
+struct StatusSettings {
+    title: String,
+    auto_scroll: bool,
+    focus: bool,
+    toc: bool,
+    presentation: bool,
+    zoom: u32,
+    color_scheme: ColorScheme,
+    highlight_theme: String,
+    dark_highlight_theme: String,
+    math: Option<MathRenderer>,
+    static_root: Option<PathBuf>,
+}
+
+impl ServerStatus {
+    fn new(config: &Config, title: &str, clients: usize) -> ServerStatus {
+        let page = config.page_settings();
+
+        ServerStatus {
+            version: env!("CARGO_PKG_VERSION"),
+            uptime_secs: config.started.elapsed().as_secs(),
+            clients,
+            last_render: config.last_render,
+            settings: StatusSettings {
+                title: title.to_owned(),
+                auto_scroll: config.auto_scroll,
+                focus: config.focus,
+                toc: config.toc,
+                presentation: config.presentation,
+                zoom: config.zoom,
+                color_scheme: page.color_scheme,
+                highlight_theme: page.highlight_theme,
+                dark_highlight_theme: page.dark_highlight_theme,
+                math: page.math,
+                static_root: config.static_root.clone(),
+            },
+        }
+    }
+}
+
+/// Markdown preview server.
+///
+/// Listens for HTTP connections and serves a page containing a live markdown preview. The page
+/// contains JavaScript to open a websocket connection back to the server for rendering updates.
+#[derive(Debug)]
+pub struct Server {
+    /// The TCP addresses that the server is listening on.
+    addrs: Vec<SocketAddr>,
+    /// The unix domain socket that the server is listening on, if any.
+    unix_socket: Option<PathBuf>,
+    config: Arc<Mutex<Config>>,
+    external_renderer: Option<ExternalRenderer>,
+    /// The renderers of documents by filetype, which override the external renderer. `None` is
+    /// the built-in renderer.
+    filetype_renderers: HashMap<String, Option<ExternalRenderer>>,
+    /// The format of documents whose filetype doesn't determine their format.
+    format: Format,
+    /// The renderers of the pipelines of formats other than markdown, once they're used.
+    format_renderers: HashMap<Format, Option<ExternalRenderer>>,
+    /// Whether markdown is rendered with the built-in renderer when the external renderer fails.
+    external_renderer_fallback: bool,
+    /// Whether the preview is showing an error from the external renderer.
+    external_renderer_failed: bool,
+    render_options: RenderOptions,
+    /// The settings of the preview page, before any overrides from the document's front matter.
+    page_settings: PageSettings,
+    diagrams: Diagrams,
+    /// Marks misspelled words in the preview, if spell checking is enabled.
+    spell_checker: SpellChecker,
+    spell_check: bool,
+    /// Sends markdown to be linted to the linter's thread, if a linter is set.
+    lint_jobs: Option<Sender<LintJob>>,
+    block_cache: BlockCache,
+    render_cache: RenderCache,
+    /// The number of renders so far.
+    renders: u64,
+    /// The custom stylesheets of the preview, as URLs and paths.
+    custom_css: Vec<String>,
+    /// The documents that were displayed before files were opened from the preview, by buffer,
+    /// most recent last.
+    history: HashMap<Option<String>, Vec<Visit>>,
+    /// The data URIs of local images, if they're inlined into the preview.
+    data_uris: Option<export::DataUris>,
+    /// The files in git repositories that the main document and buffers were read from, whose
+    /// changes since `HEAD` are highlighted.
+    git_paths: HashMap<Option<String>, PathBuf>,
+    documents: Arc<Documents>,
+    buffers: HashMap<String, Buffer>,
+    /// The tabs most recently sent to the previews.
+    tabs: Vec<Tab>,
+    requests: Option<Receiver<PreviewRequest>>,
+    /// Sends requests on behalf of the server itself, such as new diagnostics.
+    request_sender: Sender<PreviewRequest>,
+    /// The title set explicitly by the client, if any.
+    title: Option<String>,
+    title_source: Option<TitleSource>,
+    /// The most recently rendered markdown.
+    markdown: Option<String>,
+    /// The first heading of the most recently rendered markdown.
+    heading: Option<String>,
+    /// The name of the most recently rendered file.
+    file_name: Option<String>,
+    /// The filetype that the most recently rendered markdown was sent with, if any.
+    filetype: Option<String>,
+    /// The front matter of the most recently rendered markdown.
+    front_matter: Option<FrontMatter>,
+    /// Indicates whether the server should initiate shutdown.
+    ///
+    /// On drop, we want the server to clean up existing connections gracefully and stop listening
+    /// for new connections. Unfortunately, closing a socket that is currently blocking in an
+    /// `accept` loop is very platform-specific and potentially flaky. Instead, the `Drop` impl
+    /// sets this flag to signal that the server should shut down on on the next connection, and
+    /// then immediately opens a connection.
+    shutdown: Arc<AtomicBool>,
+    listener_join_handles: Vec<JoinHandle<()>>,
+}
+
+impl Server {
+    /// Binds the server to one or more addresses, such as an IPv4 and an IPv6 loopback address.
+    ///
+    /// Each address is bound to the first socket address that it resolves to that can be bound.
+    /// Binding on port 0 will request a port assignment from the OS, which is shared by the
+    /// remaining addresses with port 0 if it is available. Use `addrs()` to query the assigned
+    /// ports.
+    pub fn bind<A: ToSocketAddrs>(addrs: &[A]) -> io::Result<Self> {
+        let mut listeners: Vec<TcpListener> = vec![];
+
+        for addr in addrs {
+            let candidates = addr.to_socket_addrs()?.collect::<Vec<_>>();
+
+            let shared_port = match listeners.first() {
+                Some(first) => {
+                    let port = first.local_addr()?.port();
+                    let shared = candidates
+                        .iter()
+                        .map(|&candidate| match candidate.port() {
+                            0 => SocketAddr::new(candidate.ip(), port),
+                            _ => candidate,
+                        })
+                        .collect::<Vec<_>>();
+                    TcpListener::bind(&shared[..]).ok()
+                }
+                None => None,
+            };
+
+            let listener = match shared_port {
+                Some(listener) => listener,
+                None => TcpListener::bind(&candidates[..])?,
+            };
+            listeners.push(listener);
+        }
+
+        let addrs = listeners
+            .iter()
+            .map(TcpListener::local_addr)
+            .collect::<io::Result<Vec<_>>>()?;
+
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no addresses to listen on",
+            ));
+        }
+
+        for addr in &addrs {
+            info!("listening on {}", addr);
+        }
+
+        let lan_addrs = network::reachable_addrs(&addrs);
+        let listeners = listeners.into_iter().map(Listener::Tcp).collect();
+        let server = Server::listen(listeners, addrs, None)?;
+        server.config.lock().unwrap().lan_addrs = lan_addrs;
+        Ok(server)
+    }
+
+    /// Binds the server to a unix domain socket at the given path, instead of a TCP address.
+    ///
+    /// Only the user running the server may connect to the socket. A stale socket left behind by
+    /// a server that didn't exit cleanly is replaced, and the socket is removed when the server is
+    /// dropped. TLS is not used on unix domain sockets.
+    pub fn bind_unix(path: impl AsRef<Path>) -> io::Result<Self> {
+        #[cfg(unix)]
+        {
+            let path = path.as_ref();
+            let listener = bind_unix_socket(path)?;
+
+            info!("listening on {}", path.display());
+
+            Server::listen(
+                vec![Listener::Unix(listener)],
+                vec![],
+                Some(path.to_owned()),
+            )
+        }
+
+        #[cfg(not(unix))]
+        {
+            let _ = path;
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unix domain sockets are not supported on this platform",
+            ))
+        }
+    }
+
+    /// Creates a server that doesn't listen for connections, for rendering and exporting documents
+    /// without previewing them.
+    pub fn unbound() -> io::Result<Self> {
+        Server::listen(vec![], vec![], None)
+    }
+
+    /// Starts accepting connections on each listener.
+    fn listen(
+        listeners: Vec<Listener>,
+        addrs: Vec<SocketAddr>,
+        unix_socket: Option<PathBuf>,
+    ) -> io::Result<Self> {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let page_settings = PageSettings::default();
+        let render_options = RenderOptions {
+            math: page_settings.math.is_some(),
+            mathml: page_settings.math == Some(MathRenderer::MathMl),
+            ..Default::default()
+        };
+        let config = Arc::new(Mutex::new(Config::default()));
+        let documents = Arc::new(Documents::default());
+        documents.index.set_html(index_html(&[]), None);
+        let (requests_tx, requests) = mpsc::channel();
+
+        let listener_join_handles = listeners
+            .into_iter()
+            .map(|listener| {
+                let shutdown = Arc::clone(&shutdown);
+                let config = Arc::clone(&config);
+                let documents = Arc::clone(&documents);
+                let requests = requests_tx.clone();
+
+                thread::spawn(move || {
+                    accept_connections(listener, shutdown, config, documents, requests)
+                })
+            })
+            .collect();
+
+        {
+            let shutdown = Arc::clone(&shutdown);
+            let config = Arc::clone(&config);
+            let documents = Arc::clone(&documents);
+            thread::spawn(move || watch_files(&documents, &config, &shutdown));
+        }
+
+        Ok(Server {
+            addrs,
+            unix_socket,
+            config,
+            documents,
+            buffers: HashMap::new(),
+            tabs: vec![],
+            requests: Some(requests),
+            request_sender: requests_tx,
+            external_renderer: None,
+            filetype_renderers: HashMap::new(),
+            format: Format::Markdown,
+            format_renderers: HashMap::new(),
+            external_renderer_fallback: false,
+            external_renderer_failed: false,
+            render_options,
+            page_settings,
+            diagrams: Diagrams::default(),
+            spell_checker: SpellChecker::hunspell(None, None),
+            spell_check: false,
+            lint_jobs: None,
+            block_cache: BlockCache::default(),
+            render_cache: RenderCache::default(),
+            renders: 0,
+            custom_css: vec![],
+            history: HashMap::new(),
+            data_uris: None,
+            git_paths: HashMap::new(),
+            title: None,
+            title_source: None,
+            filetype: None,
+            heading: None,
+            markdown: None,
+            file_name: None,
+            front_matter: None,
+            shutdown,
+            listener_join_handles,
+        })
+    }
+
+    /// Returns the socket address that the server is listening on. If the server is listening on
+    /// several addresses, the first is returned. Returns `None` if the server is only listening on
+    /// a unix domain socket.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.addrs.first().copied()
+    }
+
+    /// Returns all of the TCP socket addresses that the server is listening on.
+    pub fn addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    /// Returns the URL of the preview.
+    pub fn url(&self) -> String {
+        self.urls().remove(0)
+    }
+
+    /// Returns the URL of the preview on each address that the server is listening on.
+    ///
+    /// A unix domain socket is given as an `http+unix` URL, which contains the percent-encoded
+    /// path of the socket.
+    pub fn urls(&self) -> Vec<String> {
+        self.urls_of("")
+    }
+
+    /// Returns the URL of the preview at each address that other devices on the network, such as a
+    /// phone, can open it at. There are none if the server is only listening on loopback
+    /// addresses.
+    pub fn lan_urls(&self) -> Vec<String> {
+        let config = self.config.lock().unwrap();
+        config
+            .lan_addrs
+            .iter()
+            .map(|&addr| config.url_of(addr, ""))
+            .collect()
+    }
+
+    /// Returns the URL of the preview of a buffer.
+    pub fn buffer_url(&self, buffer: &str) -> String {
+        self.urls_of(&format!("/buffer/{}/", buffer)).remove(0)
+    }
+
+    /// Returns the URL of a path on each address that the server is listening on, with the auth
+    /// token if one is required.
+    fn urls_of(&self, path: &str) -> Vec<String> {
+        let config = self.config.lock().unwrap();
+        let mut urls = self
+            .addrs
+            .iter()
+            .map(|&addr| config.url_of(addr, path))
+            .collect::<Vec<_>>();
+
+        if let Some(socket) = &self.unix_socket {
+            let socket = form_urlencoded::byte_serialize(socket.to_string_lossy().as_bytes())
+                .collect::<String>();
+            urls.push(format!(
+                "http+unix://{}{}",
+                socket,
+                config.authenticated_path(path)
+            ));
+        }
+
+        urls
+    }
+
+    /// Returns a receiver for the requests made from preview pages, such as following a wiki link.
+    ///
+    /// The requests may only be taken once. Subsequent calls return `None`.
+    pub fn take_requests(&mut self) -> Option<Receiver<PreviewRequest>> {
+        self.requests.take()
+    }
+
+    /// Publish new markdown to be rendered by the server.
+    ///
+    /// The new HTML will be sent to all connected websocket clients. If the front matter of the
+    /// markdown overrides the page settings, connected previews are reloaded with the new
+    /// settings.
+    ///
+    /// # Errors
+    ///
+    /// This method forwards errors from an external renderer, if set. Otherwise, the method is
+    /// infallible.
+    pub fn send(&mut self, markdown: String) -> io::Result<()> {
+        let front_matter = FrontMatter::parse(&markdown);
+        if front_matter != self.front_matter {
+            self.config.lock().unwrap().description = front_matter
+                .as_ref()
+                .and_then(|front_matter| front_matter.description.clone());
+            self.front_matter = front_matter;
+            self.update_page_settings();
+        }
+
+        let filetype = filetype(self.filetype.as_deref(), self.file_name.as_deref());
+        let (html, render) = self.render(&markdown, filetype.as_deref())?;
+        let root = self.config.lock().unwrap().static_root.clone();
+        self.documents.main.watch_images(&html, root.as_deref());
+        *self.documents.main.source.write().unwrap() = markdown.clone();
+        self.documents.main.set_html(html, Some(render));
+        self.set_history(None, vec![]);
+        self.lint(None, filetype.as_deref(), &markdown);
+
+        self.heading = first_heading(&markdown);
+        self.markdown = Some(markdown);
+        self.update_title();
+        self.update_changes(None);
+
+        Ok(())
+    }
+
+    /// Publish the markdown of a buffer to be rendered by the server.
+    ///
+    /// Each buffer is previewed on its own page, at `/buffer/<id>/`, alongside the main document.
+    /// The buffer is registered if it hasn't been sent before. Buffers share the settings of the
+    /// server, so their front matter may only set their title.
+    ///
+    /// # Errors
+    ///
+    /// This method fails if the ID of the buffer contains characters other than ASCII letters,
+    /// digits, `-` and `_`, or if an external renderer fails.
+    pub fn send_buffer(&mut self, buffer: &str, markdown: String) -> io::Result<()> {
+        self.update_buffer(buffer, markdown, None, None)
+    }
+
+    /// Publish the contents of a document of some filetype to be rendered, like
+    /// [`Server::send`], with the renderer set for the filetype by
+    /// [`Server::set_filetype_renderer`]. The filetype is kept for later markdown, until another
+    /// filetype or a file is sent.
+    pub fn send_as(&mut self, filetype: &str, markdown: String) -> io::Result<()> {
+        self.filetype = Some(filetype.to_owned());
+        self.send(markdown)
+    }
+
+    /// Publish the contents of a document of some filetype to be rendered in the preview of a
+    /// buffer, like [`Server::send_buffer`] and [`Server::send_as`].
+    pub fn send_buffer_as(
+        &mut self,
+        buffer: &str,
+        filetype: &str,
+        markdown: String,
+    ) -> io::Result<()> {
+        self.update_buffer(buffer, markdown, None, Some(filetype.to_owned()))
+    }
+
+    /// Reads markdown from a file and publishes it to be rendered in the preview of a buffer. The
+    /// name of the file may be used as the title of the preview.
+    ///
+    /// Relative paths are resolved against the static root, if set.
+    pub fn render_buffer_file(&mut self, buffer: &str, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = self.resolve_path(path);
+
+        info!("rendering file in buffer {}: {}", buffer, path.display());
+
+        let markdown = fs::read_to_string(&path)?;
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        self.update_buffer(buffer, markdown, file_name, None)
+    }
+
+    /// Renders a markdown file that was linked to from a preview, in the preview of a buffer if
+    /// given. The document that was displayed can be returned to with [`Server::go_back`], until
+    /// new markdown is sent for the document.
+    ///
+    /// Relative paths are resolved against the static root, if set.
+    pub fn open_file(&mut self, buffer: Option<&str>, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut history = self
+            .history
+            .get(&buffer.map(str::to_owned))
+            .cloned()
+            .unwrap_or_default();
+        let current = match buffer {
+            Some(buffer) => self.buffers.get(buffer).map(|state| Visit {
+                markdown: state.markdown.clone(),
+                file_name: state.file_name.clone(),
+                filetype: state.filetype.clone(),
+            }),
+            None => self.markdown.clone().map(|markdown| Visit {
+                markdown,
+                file_name: self.file_name.clone(),
+                filetype: self.filetype.clone(),
+            }),
+        };
+
+        match buffer {
+            Some(buffer) => self.render_buffer_file(buffer, path)?,
+            None => self.render_file(path)?,
+        }
+
+        history.extend(current);
+        self.set_history(buffer, history);
+        Ok(())
+    }
+
+    /// Returns to the document that was displayed before a file was opened with
+    /// [`Server::open_file`], in the preview of a buffer if given. Does nothing if no file was
+    /// opened.
+    pub fn go_back(&mut self, buffer: Option<&str>) -> io::Result<()> {
+        let mut history = self
+            .history
+            .get(&buffer.map(str::to_owned))
+            .cloned()
+            .unwrap_or_default();
+        let Visit {
+            markdown,
+            file_name,
+            filetype,
+        } = match history.pop() {
+            Some(previous) => previous,
+            None => return Ok(()),
+        };
+
+        match buffer {
+            Some(buffer) => self.update_buffer(buffer, markdown, file_name, filetype)?,
+            None => {
+                self.file_name = file_name;
+                self.filetype = filetype;
+                self.send(markdown)?;
+            }
+        }
+
+        self.set_history(buffer, history);
+        Ok(())
+    }
+
+    /// Replaces the documents that the preview of a buffer, or the main document, can go back to.
+    fn set_history(&mut self, buffer: Option<&str>, history: Vec<Visit>) {
+        let document = match buffer {
+            Some(buffer) => match self.buffers.get(buffer) {
+                Some(state) => Arc::clone(&state.document),
+                None => return,
+            },
+            None => Arc::clone(&self.documents.main),
+        };
+        document.set_can_go_back(!history.is_empty());
+
+        let buffer = buffer.map(str::to_owned);
+        if history.is_empty() {
+            self.history.remove(&buffer);
+        } else {
+            self.history.insert(buffer, history);
+        }
+    }
+
+    /// Stops previewing a buffer. Pages displaying the buffer are told that the preview has ended.
+    pub fn close_buffer(&mut self, buffer: &str) {
+        if self.buffers.remove(buffer).is_none() {
+            return;
+        }
+        self.history.remove(&Some(buffer.to_owned()));
+        self.git_paths.remove(&Some(buffer.to_owned()));
+
+        info!("closing buffer {}", buffer);
+
+        let document = {
+            let mut buffers = self.documents.buffers.write().unwrap();
+            let index = buffers.iter().position(|(id, _)| id == buffer);
+            index.map(|index| buffers.remove(index).1)
+        };
+        if let Some(document) = document {
+            document.close_clients(Signal::Shutdown);
+        }
+
+        self.update_tabs();
+    }
+
+    /// Renders the markdown of a buffer, registering the buffer if necessary. The name of the
+    /// buffer's file and its filetype are kept from the previous render unless new ones are given.
+    /// A new file replaces the filetype.
+    fn update_buffer(
+        &mut self,
+        buffer: &str,
+        markdown: String,
+        file_name: Option<String>,
+        filetype: Option<String>,
+    ) -> io::Result<()> {
+        if buffer.is_empty()
+            || !buffer
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid buffer ID: {:?}", buffer),
+            ));
+        }
+
+        let (file_name, filetype) = match self.buffers.get(buffer) {
+            Some(state) if file_name.is_none() => (
+                state.file_name.clone(),
+                filetype.or_else(|| state.filetype.clone()),
+            ),
+            _ => (file_name, filetype),
+        };
+
+        let renderer_filetype = self::filetype(filetype.as_deref(), file_name.as_deref());
+        let (html, render) = self.render(&markdown, renderer_filetype.as_deref())?;
+
+        match self.buffers.get_mut(buffer) {
+            Some(state) => {
+                state.markdown = markdown;
+                state.file_name = file_name;
+                state.filetype = filetype;
+            }
+            None => {
+                info!("registering buffer {}", buffer);
+
+                let document = Arc::new(Document::default());
+                self.documents
+                    .buffers
+                    .write()
+                    .unwrap()
+                    .push((buffer.to_owned(), Arc::clone(&document)));
+                self.buffers.insert(
+                    buffer.to_owned(),
+                    Buffer {
+                        document,
+                        markdown,
+                        file_name,
+                        filetype,
+                    },
+                );
+            }
+        }
+
+        let state = &self.buffers[buffer];
+        let root = self.config.lock().unwrap().static_root.clone();
+        state.document.watch_images(&html, root.as_deref());
+        *state.document.source.write().unwrap() = state.markdown.clone();
+        state.document.set_html(html, Some(render));
+        self.set_history(Some(buffer), vec![]);
+        self.lint(
+            Some(buffer),
+            renderer_filetype.as_deref(),
+            &self.buffers[buffer].markdown,
+        );
+
+        let state = &self.buffers[buffer];
+        state.document.set_title(&self.buffer_title(state));
+
+        self.update_tabs();
+        self.update_changes(Some(buffer));
+
+        Ok(())
+    }
+
+    /// Renders markdown with the renderer of its filetype, if set, the external renderer, if set,
+    /// or the built-in renderer. Markdown that was rendered recently is not rendered again.
+    ///
+    /// Returns the HTML, and how long each stage of rendering took.
+    fn render(
+        &mut self,
+        markdown: &str,
+        filetype: Option<&str>,
+    ) -> io::Result<(String, RenderStatus)> {
+        let start = Instant::now();
+        // How long the built-in renderer and an external program took, if they rendered the
+        // markdown.
+        let mut parse = None;
+        let mut external = None;
+
+        let format = filetype
+            .and_then(Format::from_filetype)
+            .unwrap_or(self.format);
+        let filetype_renderers = &mut self.filetype_renderers;
+        let (filetype, renderer) = match filetype
+            .and_then(|filetype| Some((filetype, filetype_renderers.get_mut(filetype)?)))
+        {
+            Some((filetype, renderer)) => (Some(filetype), renderer.as_mut()),
+            None => match format {
+                Format::Markdown => (None, self.external_renderer.as_mut()),
+                format => (
+                    None,
+                    self.format_renderers
+                        .entry(format)
+                        .or_insert_with(|| format.renderer())
+                        .as_mut(),
+                ),
+            },
+        };
+
+        // Each filetype and format may have its own renderer, so their renders are cached
+        // separately.
+        let source = (filetype, format, markdown);
+        let cached = self.render_cache.get(source, &self.render_options);
+        let was_cached = cached.is_some();
+        let html = if let Some(html) = cached {
+            html
+        } else if let Some(renderer) = renderer {
+            let started = Instant::now();
+            let result = renderer.render(markdown);
+            external = Some(started.elapsed());
+
+            match result {
+                Ok(html) => {
+                    // Only the output of the format's own pipeline needs trimming.
+                    let html = match filetype {
+                        Some(_) => html,
+                        None => format.postprocess(html),
+                    };
+                    let html = self.render_options.sanitize_html(html);
+                    if self.external_renderer_failed {
+                        self.external_renderer_failed = false;
+                        self.documents.broadcast(Signal::Error(None));
+                    }
+                    self.render_cache.insert(source, html.clone());
+                    html
+                }
+                Err(e) => {
+                    warn!("external renderer failed: {}", e);
+                    self.external_renderer_failed = true;
+                    self.documents.broadcast(Signal::Error(Some(format!(
+                        "External renderer failed: {}",
+                        e
+                    ))));
+                    if !self.external_renderer_fallback {
+                        return Err(e);
+                    }
+
+                    // The fallback isn't cached, so that the next update tries the external
+                    // renderer again.
+                    let started = Instant::now();
+                    let html =
+                        self.block_cache
+                            .render(markdown, &self.render_options, &mut self.diagrams);
+                    parse = Some(started.elapsed());
+                    html
+                }
+            }
+        } else {
+            let started = Instant::now();
+            let html = match format.render(markdown) {
+                Some(html) => {
+                    parse = Some(started.elapsed());
+                    self.render_options.sanitize_html(html)
+                }
+                None => {
+                    let html =
+                        self.block_cache
+                            .render(markdown, &self.render_options, &mut self.diagrams);
+                    parse = Some(started.elapsed());
+                    html
+                }
+            };
+            self.render_cache.insert(source, html.clone());
+            html
+        };
+
+        // Misspellings are marked after caching, since spell checking may be toggled.
+        let html = if self.spell_check {
+            self.spell_checker.mark(&html)
+        } else {
+            html
+        };
+
+        let html = match &mut self.data_uris {
+            Some(data_uris) => {
+                let root = self.config.lock().unwrap().static_root.clone();
+                data_uris.inline(&html, root.as_deref())
+            }
+            None => html,
+        };
+
+        let duration = start.elapsed();
+        let postprocess = duration
+            .saturating_sub(parse.unwrap_or_default())
+            .saturating_sub(external.unwrap_or_default());
+        debug!(
+            "rendered markdown in {:?} (parse: {:?}, external renderer: {:?}, postprocess: {:?})",
+            duration, parse, external, postprocess
+        );
+
+        self.renders += 1;
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let render = RenderStatus {
+            id: self.renders,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or(Duration::ZERO)
+                .as_secs(),
+            duration_ms: ms(duration),
+            cached: was_cached,
+            parse_ms: parse.map(ms),
+            external_renderer_ms: external.map(ms),
+            postprocess_ms: ms(postprocess),
+            broadcast_ms: None,
+            highlight_ms: None,
+            finished: Instant::now(),
+        };
+        self.config.lock().unwrap().last_render = Some(render);
+
+        Ok((html, render))
+    }
+
+    /// Reads markdown from a file and publishes it to be rendered by the server.
+    ///
+    /// Relative paths are resolved against the static root, if set.
+    pub fn render_file(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = self.resolve_path(path);
+
+        info!("rendering file: {}", path.display());
+
+        let markdown = fs::read_to_string(&path)?;
+        self.send_file(path, markdown)
+    }
+
+    /// Publishes the contents of a markdown file to be rendered by the server, without reading
+    /// the file. The name of the file may be used as the title of the preview.
+    pub fn send_file(&mut self, path: impl AsRef<Path>, markdown: String) -> io::Result<()> {
+        self.file_name = path
+            .as_ref()
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        self.filetype = None;
+        self.send(markdown)
+    }
+
+    /// Forgets the HTML of previous renders, including rendered blocks and diagrams, so that the
+    /// next document sent is rendered from scratch, such as to measure how long rendering takes.
+    pub fn clear_render_caches(&mut self) {
+        self.block_cache.clear();
+        self.render_cache.clear();
+        self.diagrams.clear_cache();
+    }
+
+    /// Writes the current document to a file as a self-contained HTML page.
+    ///
+    /// Relative paths are resolved against the static root, if set.
+    pub fn export_html(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = self.resolve_path(path);
+
+        info!("exporting HTML: {}", path.display());
+
+        fs::write(path, self.standalone_html())
+    }
+
+    /// Writes the current document to a file as an EPUB.
+    ///
+    /// The book's metadata is taken from the document's front matter. Local images are packaged
+    /// into the book. Relative paths are resolved against the static root, if set.
+    pub fn export_epub(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = self.resolve_path(path);
+
+        info!("exporting EPUB: {}", path.display());
+
+        let config = self.config.lock().unwrap();
+
+        let stylesheet = if config.page.custom_styles.is_empty() {
+            STATIC_FILES
+                .get_file("vendor/github-markdown-css/github-markdown.css")
+                .and_then(|file| file.contents_utf8())
+                .unwrap_or_default()
+                .to_owned()
+        } else {
+            config.page.custom_styles.join("\n")
+        };
+
+        let html = self.documents.main.html.read().unwrap();
+        let html = html.as_deref().map(spell::unmark);
+        let title = self.documents.main.title.read().unwrap();
+        let front_matter = self.front_matter.clone().unwrap_or_default();
+
+        let book = epub::Book {
+            title: &title,
+            front_matter: &front_matter,
+            html: html.as_deref().unwrap_or_default(),
+            stylesheet: &stylesheet,
+            root: config.static_root.as_deref(),
+        };
+
+        epub::write(io::BufWriter::new(fs::File::create(path)?), &book)
+    }
+
+    /// Prints the preview to a PDF file using a headless Chromium-based browser.
+    ///
+    /// The browser loads the live preview, so the PDF is styled exactly like the preview, including
+    /// the highlight theme and any custom CSS. Relative paths are resolved against the static root.
+    ///
+    /// # Errors
+    ///
+    /// This method fails if the browser could not be run, or if it exited unsuccessfully.
+    pub fn export_pdf(&self, mut command: Command, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = self.resolve_path(path);
+
+        command
+            .arg("--headless")
+            .arg("--disable-gpu")
+            .arg("--no-pdf-header-footer")
+            .arg("--virtual-time-budget=5000")
+            .arg(format!("--print-to-pdf={}", path.display()))
+            .arg(self.url());
+
+        command.stdout(Stdio::null()).stderr(Stdio::null());
+
+        info!("exporting PDF: {:?}", command);
+        let status = command.status()?;
+
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "PDF export failed: browser exited with {}",
+                status
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Renders the current document as a self-contained HTML page.
+    ///
+    /// Stylesheets, scripts, and local images are inlined into the page.
+    pub fn standalone_html(&self) -> String {
+        let config = self.config.lock().unwrap();
+        let title = self.documents.main.title.read().unwrap();
+        let html = self.documents.main.html.read().unwrap();
+        let html = html.as_deref().map(spell::unmark);
+        standalone_html(&config, &title, html.as_deref())
+    }
+
+    /// Set the title of the preview page.
+    ///
+    /// If the title is `None`, the title will be derived from the title source instead.
+    pub fn set_title(&mut self, title: Option<String>) {
+        self.title = title;
+        self.update_title();
+    }
+
+    /// Returns the title that was set explicitly, if any.
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// Set where the title of the preview page should be derived from, if no title is set
+    /// explicitly.
+    ///
+    /// By default, the page has a generic title.
+    pub fn set_title_source(&mut self, source: Option<TitleSource>) {
+        self.title_source = source;
+        self.update_title();
+
+        for buffer in self.buffers.values() {
+            buffer.document.set_title(&self.buffer_title(buffer));
+        }
+
+        self.update_tabs();
+    }
+
+    /// Set whether the preview should automatically scroll to follow the editor's cursor.
+    ///
+    /// Defaults to `true`.
+    pub fn set_auto_scroll(&mut self, auto_scroll: bool) {
+        self.config.lock().unwrap().auto_scroll = auto_scroll;
+        self.broadcast(Signal::AutoScroll);
+    }
+
+    /// Returns whether the preview automatically scrolls to follow the editor's cursor.
+    pub fn auto_scroll(&self) -> bool {
+        self.config.lock().unwrap().auto_scroll
+    }
+
+    /// Set whether the preview is in focus mode, which dims every block except the one at the
+    /// editor's cursor, and keeps that block in the middle of the window even if auto-scroll is
+    /// disabled.
+    ///
+    /// Defaults to `false`.
+    pub fn set_focus(&mut self, focus: bool) {
+        self.config.lock().unwrap().focus = focus;
+        self.broadcast(Signal::Focus);
+    }
+
+    /// Returns whether the preview is in focus mode.
+    pub fn focus(&self) -> bool {
+        self.config.lock().unwrap().focus
+    }
+
+    /// Set whether the preview displays how long each update took to render, to be sent to the
+    /// page, and to be displayed by the page, such as to find out why the preview lags behind
+    /// typing. Pages that display the timings also report how long they took to display updates
+    /// to the server's status.
+    ///
+    /// Defaults to `false`.
+    pub fn set_render_timings(&mut self, render_timings: bool) {
+        self.config.lock().unwrap().render_timings = render_timings;
+        self.broadcast(Signal::Timings);
+    }
+
+    /// Returns whether the preview displays how long each update took to render.
+    pub fn render_timings(&self) -> bool {
+        self.config.lock().unwrap().render_timings
+    }
+
+    /// Set whether the previews that [`Server::open_browser`] opened close themselves when the
+    /// preview ends, such as when the server shuts down, rather than staying open. Other previews
+    /// only show that the preview has ended.
+    ///
+    /// Browsers may not let pages close themselves, in which case they stay open.
+    ///
+    /// Defaults to `false`.
+    pub fn set_close_on_shutdown(&mut self, close_on_shutdown: bool) {
+        self.config.lock().unwrap().close_on_shutdown = close_on_shutdown;
+    }
+
+    /// Set how much the preview is scaled, in percent, such as to make it readable while sharing
+    /// the screen. The zoom is clamped between 25% and 500%.
+    ///
+    /// Defaults to 100%.
+    pub fn set_zoom(&mut self, percent: u32) {
+        self.config.lock().unwrap().zoom = percent.clamp(MIN_ZOOM, MAX_ZOOM);
+        self.broadcast(Signal::Zoom);
+    }
+
+    /// Returns how much the preview is scaled, in percent.
+    pub fn zoom(&self) -> u32 {
+        self.config.lock().unwrap().zoom
+    }
+
+    /// Set whether the preview should show a table of contents sidebar.
+    ///
+    /// The table of contents is generated from the headings of the preview. Defaults to `false`.
+    pub fn set_toc(&mut self, toc: bool) {
+        self.config.lock().unwrap().toc = toc;
+        self.broadcast(Signal::Toc);
+    }
+
+    /// Returns whether the preview shows a table of contents sidebar.
+    pub fn toc(&self) -> bool {
+        self.config.lock().unwrap().toc
+    }
+
+    /// Set whether the preview should present the document as slides with reveal.js.
+    ///
+    /// Slides are separated by thematic breaks (`---`) and `<!-- slide -->` comments, and the
+    /// presentation follows the editor's cursor. Only the built-in renderer splits documents into
+    /// slides. Connected previews are reloaded. Defaults to `false`.
+    pub fn set_presentation(&mut self, presentation: bool) -> io::Result<()> {
+        self.config.lock().unwrap().presentation = presentation;
+        self.render_options.slides = presentation;
+        self.rerender()?;
+        self.broadcast(Signal::Reload);
+        Ok(())
+    }
+
+    /// Returns whether the preview presents the document as slides.
+    pub fn presentation(&self) -> bool {
+        self.config.lock().unwrap().presentation
+    }
+
+    /// Set whether the markdown is displayed beside the preview, scrolled along with it. The
+    /// table of contents isn't shown beside the markdown, and presentations aren't displayed
+    /// beside it. Connected previews are reloaded. Defaults to `false`.
+    pub fn set_side_by_side(&mut self, side_by_side: bool) {
+        self.config.lock().unwrap().side_by_side = side_by_side;
+        self.broadcast(Signal::Reload);
+    }
+
+    /// Returns whether the markdown is displayed beside the preview.
+    pub fn side_by_side(&self) -> bool {
+        self.config.lock().unwrap().side_by_side
+    }
+
+    /// Notifies the preview that the editor's cursor moved to a (one-based) line of the markdown.
+    ///
+    /// If auto-scroll is enabled, the preview will scroll to the block rendered from that line.
+    /// Only the built-in renderer annotates the HTML with source lines, so this has no effect when
+    /// using an external renderer.
+    pub fn scroll_to_line(&mut self, line: usize) {
+        self.documents.main.broadcast(Signal::Scroll(line));
+    }
+
+    /// Notifies the preview of a buffer that the editor's cursor moved to a (one-based) line of
+    /// the buffer's markdown, like [`Server::scroll_to_line`].
+    pub fn scroll_buffer_to_line(&mut self, buffer: &str, line: usize) {
+        if let Some(state) = self.buffers.get(buffer) {
+            state.document.broadcast(Signal::Scroll(line));
+        }
+    }
+
+    /// Returns the markdown that was most recently rendered, if any.
+    pub fn markdown(&self) -> Option<&str> {
+        self.markdown.as_deref()
+    }
+
+    /// Returns the name of the file that was most recently rendered, if the markdown was read from
+    /// a file.
+    pub fn file_name(&self) -> Option<&str> {
+        self.file_name.as_deref()
+    }
+
+    /// Returns the markdown of a buffer that was most recently rendered, if the buffer is being
+    /// previewed.
+    pub fn buffer_markdown(&self, buffer: &str) -> Option<&str> {
+        self.buffers.get(buffer).map(|state| &*state.markdown)
+    }
+
+    /// Reports the relative links and images of the markdown that was most recently rendered, or
+    /// of a buffer, which don't resolve under the static root, and any malformed URLs.
+    pub fn check_links(&self, buffer: Option<&str>) -> io::Result<Vec<check::Problem>> {
+        let markdown = match buffer {
+            Some(buffer) => self.buffer_markdown(buffer),
+            None => self.markdown(),
+        };
+
+        let root = env::current_dir()?.join(self.resolve_path(""));
+        Ok(check::check_links(
+            markdown.unwrap_or_default(),
+            &self.render_options,
+            &root,
+        ))
+    }
+
+    /// Set the file in a git repository that the markdown, or the markdown of a buffer, is read
+    /// from, or `None` to stop highlighting changes. The blocks of the preview that were added or
+    /// modified since the file was last committed are highlighted.
+    ///
+    /// Relative paths are resolved against the static root, if set. Files that aren't in the
+    /// commit at `HEAD` aren't highlighted.
+    pub fn set_git_path(&mut self, buffer: Option<&str>, path: Option<impl AsRef<Path>>) {
+        let key = buffer.map(str::to_owned);
+        match path {
+            Some(path) => {
+                let path = self.resolve_path(path);
+                info!("highlighting changes since HEAD: {}", path.display());
+                self.git_paths.insert(key, path);
+            }
+            None => {
+                self.git_paths.remove(&key);
+            }
+        }
+        self.update_changes(buffer);
+    }
+
+    /// Compares the markdown of the main document or of a buffer with its file at `HEAD`, sending
+    /// the changed lines to the previews.
+    fn update_changes(&self, buffer: Option<&str>) {
+        let (document, markdown, file_name) = match buffer {
+            Some(buffer) => match self.buffers.get(buffer) {
+                Some(state) => (&state.document, &*state.markdown, &state.file_name),
+                None => return,
+            },
+            None => (
+                &self.documents.main,
+                self.markdown.as_deref().unwrap_or_default(),
+                &self.file_name,
+            ),
+        };
+
+        // Files that were opened from the preview aren't the file in git.
+        let path = self
+            .git_paths
+            .get(&buffer.map(str::to_owned))
+            .filter(|path| match file_name {
+                Some(file_name) => path.file_name() == Some(file_name.as_ref()),
+                None => true,
+            });
+
+        let changes = match path.map(|path| git::head_contents(path)) {
+            Some(Ok(head)) => git::changed_lines(&head, markdown),
+            Some(Err(e)) => {
+                debug!("could not read file at HEAD: {}", e);
+                vec![]
+            }
+            None => vec![],
+        };
+        document.set_changes(changes);
+    }
+
+    /// Returns the length of the document that was most recently rendered, or of a buffer, in
+    /// words and characters, and how long it takes to read.
+    pub fn stats(&self, buffer: Option<&str>) -> Stats {
+        let document = match buffer {
+            Some(buffer) => match self.buffers.get(buffer) {
+                Some(state) => &state.document,
+                None => return Stats::default(),
+            },
+            None => &self.documents.main,
+        };
+        *document.stats.read().unwrap()
+    }
+
+    /// Resolves a path against the static root, if set.
+    fn resolve_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        match &self.config.lock().unwrap().static_root {
+            Some(root) => root.join(path),
+            None => path.as_ref().to_owned(),
+        }
+    }
+
+    /// Renders the most recent markdown again, such as after the render options change.
+    fn rerender(&mut self) -> io::Result<()> {
+        if let Some(markdown) = self.markdown.take() {
+            self.send(markdown)?;
+        }
+
+        let buffers = self
+            .buffers
+            .iter()
+            .map(|(id, buffer)| (id.clone(), buffer.markdown.clone()))
+            .collect::<Vec<_>>();
+        for (id, markdown) in buffers {
+            self.send_buffer(&id, markdown)?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies the page settings, overridden by the front matter of the current document.
+    ///
+    /// If the settings of the page changed, connected previews are reloaded.
+    fn update_page_settings(&mut self) {
+        let mut settings = self.page_settings.clone();
+
+        // Local stylesheets are read again, since they're reloaded by the page when they change.
+        let mut stylesheets = &self.custom_css;
+        match load_resources(stylesheets, None) {
+            Ok((_, styles)) => settings.custom_styles = styles,
+            Err(e) => warn!("could not reload custom CSS: {}", e),
+        }
+        let mut root = None;
+
+        if let Some(front_matter) = &self.front_matter {
+            if let Some(theme) = &front_matter.highlight_theme {
+                settings.highlight_theme = theme.clone();
+                settings.dark_highlight_theme = theme.clone();
+            }
+
+            settings.pdf_layout = settings.pdf_layout.merge(&front_matter.pdf);
+
+            match &front_matter.math {
+                Some(MathSetting::Enabled(false)) => settings.math = None,
+                Some(MathSetting::Enabled(true)) => {
+                    settings.math.get_or_insert(MathRenderer::Katex);
+                }
+                Some(MathSetting::Renderer(name)) => match name.parse() {
+                    Ok(math) => settings.math = Some(math),
+                    Err(e) => warn!("invalid front matter: {}", e),
+                },
+                None => (),
+            }
+
+            if !front_matter.css.is_empty() {
+                // Relative stylesheets are resolved against the document, like images.
+                root = self.config.lock().unwrap().static_root.clone();
+                match load_resources(&front_matter.css, root.as_deref()) {
+                    Ok((links, styles)) => {
+                        settings.css_links = links;
+                        settings.custom_styles = styles;
+                        stylesheets = &front_matter.css;
+                    }
+                    Err(e) => warn!("could not load front matter CSS: {}", e),
+                }
+            }
+        }
+
+        // Math libraries are loaded from CDNs, so offline previews are typeset as MathML instead.
+        if settings.offline
+            && matches!(
+                settings.math,
+                Some(MathRenderer::Katex | MathRenderer::MathJax)
+            )
+        {
+            settings.math = Some(MathRenderer::MathMl);
+        }
+
+        self.render_options.math = settings.math.is_some();
+        self.render_options.mathml = settings.math == Some(MathRenderer::MathMl);
+
+        let mut config = self.config.lock().unwrap();
+        config.stylesheets = stylesheets
+            .iter()
+            .filter_map(|stylesheet| resource_path(stylesheet, root.as_deref()).ok())
+            .map(|path| {
+                let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+                (path, modified)
+            })
+            .collect();
+
+        if config.page != settings {
+            config.page = settings;
+            drop(config);
+
+            self.broadcast(Signal::Reload);
+        }
+    }
+
+    /// Recomputes the title of the preview page, notifying clients if it changed.
+    fn update_title(&mut self) {
+        let title = self
+            .title
+            .as_ref()
+            .or_else(|| {
+                self.derive_title(
+                    self.front_matter.as_ref(),
+                    self.heading.as_ref(),
+                    self.file_name.as_ref(),
+                )
+            })
+            .map(String::as_str)
+            .unwrap_or(DEFAULT_TITLE);
+
+        self.documents.main.set_title(title);
+
+        self.update_tabs();
+    }
+
+    /// Updates the index of the documents and the tab bar of every preview, if a document was
+    /// added, removed or retitled.
+    fn update_tabs(&mut self) {
+        let tabs = self.documents.tabs();
+
+        if tabs != self.tabs {
+            self.documents.index.set_html(index_html(&tabs), None);
+            self.tabs = tabs;
+
+            self.broadcast(Signal::Tabs);
+        }
+    }
+
+    /// Returns the title of the preview of a buffer.
+    fn buffer_title(&self, buffer: &Buffer) -> String {
+        let front_matter = FrontMatter::parse(&buffer.markdown);
+        let heading = first_heading(&buffer.markdown);
+
+        self.derive_title(
+            front_matter.as_ref(),
+            heading.as_ref(),
+            buffer.file_name.as_ref(),
+        )
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_TITLE)
+        .to_owned()
+    }
+
+    /// Derives the title of a document from its front matter, or else from the title source.
+    fn derive_title<'a>(
+        &self,
+        front_matter: Option<&'a FrontMatter>,
+        heading: Option<&'a String>,
+        file_name: Option<&'a String>,
+    ) -> Option<&'a String> {
+        let derived = match self.title_source {
+            Some(TitleSource::Heading) => heading,
+            Some(TitleSource::FileName) => file_name,
+            None => None,
+        };
+
+        front_matter
+            .and_then(|front_matter| front_matter.title.as_ref())
+            .or(derived)
+    }
+
+    /// Set the directory that static files will be served from.
+    ///
+    /// This can be thought of as the "working directory" of the server. Any HTTP requests with
+    /// non-root paths will be joined to this folder and used to serve files from the filesystem.
+    /// Typically this is used to serve image links relative to the markdown file.
+    ///
+    /// By default, the server will not serve static files.
+    ///
+    /// # Errors
+    ///
+    /// The directory must exist. Its canonical path is used, so that the root doesn't change if
+    /// the process's working directory does.
+    pub fn set_static_root(&mut self, root: impl AsRef<Path>) -> io::Result<()> {
+        let root = canonical_dir(root.as_ref())?;
+        self.render_options.wiki_links.root = Some(root.clone());
+        self.config.lock().unwrap().static_root = Some(root);
+        Ok(())
+    }
+
+    /// Returns the directory that static files are served from, if set.
+    pub fn static_root(&self) -> Option<PathBuf> {
+        self.config.lock().unwrap().static_root.clone()
+    }
+
+    /// Set the library used to typeset math in the preview, or `None` to disable math.
+    ///
+    /// Math is only recognized by the built-in renderer. External renderers should wrap math in
+    /// elements with the `math` class. Defaults to KaTeX.
+    pub fn set_math(&mut self, math: Option<MathRenderer>) {
+        self.page_settings.math = math;
+        self.update_page_settings();
+    }
+
+    /// Returns the markdown extensions enabled for the built-in renderer.
+    pub fn extensions(&self) -> Options {
+        self.render_options.extensions
+    }
+
+    /// Set the markdown extensions enabled for the built-in renderer.
+    ///
+    /// The current markdown is rendered again with the new extensions. Defaults to the extensions
+    /// of GitHub Flavored Markdown.
+    pub fn set_extensions(&mut self, extensions: Options) -> io::Result<()> {
+        self.render_options.extensions = extensions;
+        self.rerender()
+    }
+
+    /// Set how YAML front matter should be displayed, or `None` to render it as markdown.
+    ///
+    /// Defaults to stripping front matter from the preview.
+    pub fn set_front_matter_display(&mut self, display: Option<FrontMatterDisplay>) {
+        self.render_options.front_matter = display;
+    }
+
+    /// Set whether GitHub emoji shortcodes such as `:smile:` should be replaced with emoji.
+    ///
+    /// Shortcodes are only replaced by the built-in renderer. Defaults to `false`.
+    pub fn set_emoji(&mut self, emoji: bool) {
+        self.render_options.emoji = emoji;
+    }
+
+    /// Set whether code blocks should be rendered with line numbers.
+    ///
+    /// Line numbers are only added by the built-in renderer. Defaults to `false`.
+    pub fn set_code_line_numbers(&mut self, line_numbers: bool) {
+        self.render_options.code_line_numbers = line_numbers;
+    }
+
+    /// Set how the targets of wiki links are resolved to files under the static root.
+    ///
+    /// `extension` is appended to page names, without the leading dot. Wiki links are only
+    /// rendered if the `wikilinks` extension is enabled. Defaults to `md`, preserving case.
+    pub fn set_wiki_links(&mut self, extension: String, case: WikiLinkCase) {
+        self.render_options.wiki_links.extension = extension;
+        self.render_options.wiki_links.case = case;
+    }
+
+    /// Set the algorithm used to generate the ids of headings. Defaults to GitHub's.
+    pub fn set_heading_slugs(&mut self, slugs: SlugStyle) {
+        self.render_options.heading_slugs = slugs;
+    }
+
+    /// Set the `mmdc` command used to render `mermaid` code blocks to SVG.
+    ///
+    /// If `None`, mermaid code blocks are displayed as code. Diagrams are only rendered by the
+    /// built-in renderer.
+    pub fn set_mermaid(&mut self, command: Option<Command>) {
+        self.diagrams.set_mermaid(command);
+        self.block_cache.clear();
+        self.render_cache.clear();
+    }
+
+    /// Set how `plantuml` code blocks are rendered to SVG.
+    ///
+    /// If `None`, PlantUML code blocks are displayed as code. Diagrams are only rendered by the
+    /// built-in renderer.
+    pub fn set_plantuml(&mut self, plantuml: Option<PlantUml>) {
+        self.diagrams.set_plantuml(plantuml);
+        self.block_cache.clear();
+        self.render_cache.clear();
+    }
+
+    /// Set the highlight.js theme used for code blocks.
+    ///
+    /// Defaults to "github".
+    pub fn set_highlight_theme(&mut self, theme: String) {
+        self.page_settings.highlight_theme = theme;
+        self.update_page_settings();
+    }
+
+    /// Set the highlight.js theme used for code blocks when the preview is dark.
+    ///
+    /// Defaults to "github-dark".
+    pub fn set_dark_highlight_theme(&mut self, theme: String) {
+        self.page_settings.dark_highlight_theme = theme;
+        self.update_page_settings();
+    }
+
+    /// Set the color scheme of the preview. Connected previews are reloaded if it changed.
+    ///
+    /// Defaults to following the browser's preferred color scheme.
+    pub fn set_color_scheme(&mut self, color_scheme: ColorScheme) {
+        self.page_settings.color_scheme = color_scheme;
+        self.update_page_settings();
+    }
+
+    /// Returns the color scheme of the preview.
+    pub fn color_scheme(&self) -> ColorScheme {
+        self.page_settings.color_scheme
+    }
+
+    /// Set whether the preview should be usable without internet access.
+    ///
+    /// The preview's own scripts and stylesheets are always served by the server, but math and
+    /// presentations use libraries from CDNs. Offline previews typeset math as MathML instead, and
+    /// present slides as a regular document. URLs given explicitly, such as custom CSS, are still
+    /// loaded. Defaults to `false`.
+    pub fn set_offline(&mut self, offline: bool) {
+        self.page_settings.offline = offline;
+        self.update_page_settings();
+    }
+
+    /// Set the typography of the preview. Connected previews are reloaded if it changed.
+    pub fn set_typography(&mut self, typography: Typography) {
+        self.page_settings.typography = typography;
+        self.update_page_settings();
+    }
+
+    /// Returns the typography of the preview.
+    pub fn typography(&self) -> Typography {
+        self.page_settings.typography.clone()
+    }
+
+    /// Set the page layout of printed previews and exported PDFs, which the front matter of
+    /// documents may override. Connected previews are reloaded if it changed.
+    pub fn set_pdf_layout(&mut self, layout: PdfLayout) {
+        self.page_settings.pdf_layout = layout;
+        self.update_page_settings();
+    }
+
+    /// Returns the page layout of printed previews and exported PDFs.
+    pub fn pdf_layout(&self) -> PdfLayout {
+        self.page_settings.pdf_layout.clone()
+    }
+
+    /// Set custom CSS links and files to be served with the rendered HTML.
+    ///
+    /// Accepts URLs and absolute paths. URLs will be inserted as `<link>` tags. The contents of
+    /// the paths will be read from disk and served in `<style>` tags.
+    pub fn set_custom_css(&mut self, stylesheets: Vec<String>) -> io::Result<()> {
+        let (links, styles) = load_resources(&stylesheets, None)?;
+        self.page_settings.css_links = links;
+        self.page_settings.custom_styles = styles;
+        self.custom_css = stylesheets;
+        self.update_page_settings();
+
+        Ok(())
+    }
+
+    /// Set extra highlight.js language definitions to be loaded by the preview.
+    ///
+    /// Accepts URLs and absolute paths, like [`Server::set_custom_css`]. Each script should
+    /// register its language with `hljs.registerLanguage`.
+    pub fn set_highlight_languages(&mut self, languages: Vec<String>) -> io::Result<()> {
+        let (links, scripts) = load_resources(&languages, None)?;
+        let mut config = self.config.lock().unwrap();
+        config.language_links = links;
+        config.language_scripts = scripts;
+
+        Ok(())
+    }
+
+    /// Set custom JavaScript links and files to be loaded by the preview page.
+    ///
+    /// Accepts URLs and absolute paths, like [`Server::set_custom_css`]. Scripts are loaded after
+    /// the preview's own scripts, in order.
+    pub fn set_custom_js(&mut self, scripts: Vec<String>) -> io::Result<()> {
+        let (links, scripts) = load_resources(&scripts, None)?;
+        let mut config = self.config.lock().unwrap();
+        config.js_links = links;
+        config.custom_scripts = scripts;
+
+        Ok(())
+    }
+
+    /// Set languages of code blocks that should be highlighted as another language.
+    ///
+    /// The keys are aliases, such as `viml`, and the values are the names of highlight.js
+    /// languages, such as `vim`. Aliases are only resolved by the built-in renderer.
+    pub fn set_language_aliases(&mut self, aliases: HashMap<String, String>) {
+        self.render_options.language_aliases = aliases;
+    }
+
+    /// Set a handlebars template that replaces the preview page.
+    ///
+    /// The template is rendered with the `title` and `html` of the preview. The `{{> styles}}`
+    /// partial includes the page's stylesheets, and the `{{> scripts}}` partial includes the
+    /// scripts that connect the page to the server. The HTML should be placed in an element with
+    /// the `markdown-preview` id, so that it can be updated.
+    ///
+    /// # Errors
+    ///
+    /// This method fails if the template could not be read, or if it isn't a valid template.
+    pub fn set_template(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let template = fs::read_to_string(path)?;
+        Template::compile(&template).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.config.lock().unwrap().template = Some(template);
+        Ok(())
+    }
+
+    /// Serve the preview over HTTPS, with a certificate chain and private key in PEM files.
+    ///
+    /// Only connections made after this call are encrypted, so this should be called before the
+    /// preview is opened.
+    pub fn set_tls(&mut self, cert: impl AsRef<Path>, key: impl AsRef<Path>) -> io::Result<()> {
+        let tls = stream::load_tls_config(cert.as_ref(), key.as_ref())?;
+        self.config.lock().unwrap().tls = Some(Arc::new(tls));
+        Ok(())
+    }
+
+    /// Require a secret token to access the preview, or `None` to allow any client.
+    ///
+    /// The token is included in the URL of the preview. Pages loaded from that URL may connect
+    /// without it. Tokens may only contain ASCII letters, digits, `-`, `_` and `.`.
+    pub fn set_auth_token(&mut self, token: Option<String>) -> io::Result<()> {
+        if let Some(token) = &token {
+            if token.is_empty()
+                || !token
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid auth token: {}", token),
+                ));
+            }
+        }
+
+        self.config.lock().unwrap().auth = token.map(|token| AuthToken {
+            token,
+            cookie: match self.addr() {
+                Some(addr) => format!("composer_token_{}", addr.port()),
+                None => String::from("composer_token"),
+            },
+        });
+        Ok(())
+    }
+
+    /// Set the origins of other sites that may read from the server, such as
+    /// `https://example.com`, or `*` to allow any origin.
+    ///
+    /// Cross-origin requests from these origins are answered with CORS headers, and may open
+    /// websocket connections. Websocket connections from other origins are rejected. By default,
+    /// only the preview itself is allowed. Only origins that are listed may read responses with
+    /// credentials, such as the cookie of the auth token; `*` shares responses without them.
+    pub fn set_allowed_origins(&mut self, origins: Vec<String>) {
+        self.config.lock().unwrap().allowed_origins = origins;
+    }
+
+    /// Set the names, such as `preview.local`, that requests may address the server by, besides
+    /// `localhost` and the server's IP addresses.
+    ///
+    /// A page on another site could point its own domain name at this computer, and then read
+    /// the preview as if it were the same origin, so requests for other hosts are rejected. A
+    /// server that requires an auth token accepts any host, since such a page can't know the token.
+    pub fn set_allowed_hosts(&mut self, hosts: Vec<String>) {
+        self.config.lock().unwrap().allowed_hosts = hosts;
+    }
+
+    /// Set the Content-Security-Policy of the preview page, or `None` to use the default.
+    ///
+    /// By default, the strict policy is used when the preview is offline, and no policy is sent
+    /// otherwise. The strict policy blocks scripts in the markdown, including scripts in custom
+    /// templates that don't have the `nonce` attribute.
+    pub fn set_content_security_policy(&mut self, csp: Option<ContentSecurityPolicy>) {
+        self.config.lock().unwrap().csp = csp;
+    }
+
+    /// Set additional sources, such as `https://example.com`, that the strict
+    /// Content-Security-Policy allows scripts and stylesheets to be loaded from.
+    ///
+    /// The sources of custom CSS, custom JavaScript and highlight.js languages are always allowed.
+    pub fn set_csp_sources(
+        &mut self,
+        script_sources: Vec<String>,
+        style_sources: Vec<String>,
+    ) -> io::Result<()> {
+        if let Some(source) = script_sources.iter().chain(&style_sources).find(|source| {
+            source.is_empty() || source.contains(|c: char| c == ';' || c.is_whitespace())
+        }) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid CSP source: {:?}", source),
+            ));
+        }
+
+        let mut config = self.config.lock().unwrap();
+        config.csp_script_sources = script_sources;
+        config.csp_style_sources = style_sources;
+        Ok(())
+    }
+
+    /// Set the icon of the preview page.
+    ///
+    /// Accepts a URL or an absolute path. Local icons are embedded in the page. Defaults to the
+    /// Markdown mark.
+    pub fn set_favicon(&mut self, favicon: &str) -> io::Result<()> {
+        let favicon = match Url::parse(favicon) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => url.to_string(),
+            _ => export::data_uri(Path::new(favicon.trim_start_matches("file://")))?,
+        };
+
+        self.config.lock().unwrap().favicon = favicon;
+        Ok(())
+    }
+
+    /// Set the size of rendered HTML above which the preview is windowed, or `None` to never
+    /// window it. Windowed previews only add the blocks near the visible part of the page to it,
+    /// which keeps the browser responsive when the document is very large.
+    ///
+    /// Presentations are never windowed. Defaults to `None`.
+    pub fn set_virtualize_size(&mut self, size: Option<usize>) {
+        self.config.lock().unwrap().virtualize_size = size;
+    }
+
+    /// Set the sanitizer of documents that aren't trusted. Raw HTML in markdown and the HTML of
+    /// other renderers is sanitized, as are the URLs of markdown links. Defaults to `None`, which
+    /// trusts documents.
+    pub fn set_sanitizer(&mut self, sanitizer: Option<Sanitizer>) {
+        self.render_options.sanitize = sanitizer;
+    }
+
+    /// Set the spell checker that marks misspelled words in the preview, if spell checking is
+    /// enabled. Defaults to `hunspell` with its default dictionary.
+    pub fn set_spell_checker(&mut self, spell_checker: SpellChecker) -> io::Result<()> {
+        self.spell_checker = spell_checker;
+        if self.spell_check {
+            self.rerender()?;
+        }
+        Ok(())
+    }
+
+    /// Set whether misspelled words should be marked in the preview. Defaults to `false`.
+    pub fn set_spell_check(&mut self, spell_check: bool) -> io::Result<()> {
+        self.spell_check = spell_check;
+        self.rerender()
+    }
+
+    /// Returns whether misspelled words are marked in the preview.
+    pub fn spell_check(&self) -> bool {
+        self.spell_check
+    }
+
+    /// Set a linter that is run on each update of the markdown, or `None` to stop linting.
+    /// Defaults to `None`.
+    ///
+    /// The linter runs on its own thread, and only the latest markdown of each document is linted
+    /// when updates arrive faster than the linter finishes. Its diagnostics are displayed in the
+    /// preview, and sent as [`PreviewRequest::Diagnostics`]. Documents of other formats aren't
+    /// linted.
+    pub fn set_linter(&mut self, linter: Option<Linter>) {
+        // Dropping the sender of the previous linter's thread stops it.
+        self.lint_jobs = linter.map(|linter| {
+            let (jobs_tx, jobs) = mpsc::channel();
+            let requests = self.request_sender.clone();
+            thread::spawn(move || lint_documents(linter, &jobs, &requests));
+            jobs_tx
+        });
+    }
+
+    /// Lints the markdown of the main document or of a buffer, if a linter is set and the
+    /// markdown is rendered as markdown.
+    fn lint(&self, buffer: Option<&str>, filetype: Option<&str>, markdown: &str) {
+        let lint_jobs = match &self.lint_jobs {
+            Some(lint_jobs) => lint_jobs,
+            None => return,
+        };
+        let format = filetype
+            .and_then(Format::from_filetype)
+            .unwrap_or(self.format);
+        if format != Format::Markdown {
+            return;
+        }
+
+        let document = match buffer {
+            Some(buffer) => Arc::clone(&self.buffers[buffer].document),
+            None => Arc::clone(&self.documents.main),
+        };
+        let _ = lint_jobs.send(LintJob {
+            document,
+            buffer: buffer.map(String::from),
+            markdown: markdown.to_owned(),
+        });
+    }
+
+    /// Set whether images should only be loaded once they're scrolled near, and the largest
+    /// width and height that they're displayed at, as CSS lengths.
+    ///
+    /// Images are only constrained by the built-in renderer. Defaults to loading every image at
+    /// its full size.
+    pub fn set_image_constraints(
+        &mut self,
+        lazy: bool,
+        max_width: Option<String>,
+        max_height: Option<String>,
+    ) {
+        self.render_options.lazy_images = lazy;
+        self.render_options.image_max_width = max_width;
+        self.render_options.image_max_height = max_height;
+    }
+
+    /// Set whether local images should be inlined into the preview as data URIs, so that the
+    /// preview works when it's viewed from another machine, such as over a forwarded port.
+    /// Exports always inline local images. Defaults to `false`.
+    pub fn set_inline_images(&mut self, inline: bool) {
+        self.data_uris = if inline {
+            Some(export::DataUris::default())
+        } else {
+            None
+        };
+    }
+
+    /// Set a program that creates downscaled copies of local images larger than `min_size` bytes,
+    /// which are served to the preview instead of the images. The program and its arguments are
+    /// given as words. It's given the image on stdin, and should print a thumbnail in the same
+    /// format to stdout.
+    ///
+    /// Thumbnails are kept until the image is modified. Images are served as-is if their thumbnail
+    /// can't be created, and exports always contain the full images.
+    pub fn set_thumbnail_command(&mut self, command: Vec<String>, min_size: u64) {
+        self.config.lock().unwrap().thumbnails = Some(Arc::new(Thumbnails::new(command, min_size)));
+    }
+
+    /// Set an external program to use for rendering the markdown, instead of the built-in
+    /// renderer.
+    pub fn set_external_renderer(&mut self, renderer: ExternalRenderer) {
+        self.external_renderer = Some(renderer);
+        self.render_cache.clear();
+    }
+
+    /// Set the renderer of documents of a filetype, or `None` to render them with the built-in
+    /// renderer even if an external renderer is set.
+    ///
+    /// A document's filetype is the one it was sent with, such as with [`Server::send_as`], or
+    /// else the extension of its file. Filetypes are compared case-insensitively.
+    pub fn set_filetype_renderer(&mut self, filetype: &str, renderer: Option<ExternalRenderer>) {
+        self.filetype_renderers
+            .insert(filetype.to_lowercase(), renderer);
+    }
+
+    /// Set the format of documents whose filetype doesn't determine their format, such as
+    /// markdown that was sent without a filetype. Defaults to markdown.
+    pub fn set_format(&mut self, format: Format) {
+        self.format = format;
+    }
+
+    /// Set whether markdown is rendered with the built-in renderer when the external renderer
+    /// fails or times out. Either way, the preview shows the error.
+    pub fn set_external_renderer_fallback(&mut self, fallback: bool) {
+        self.external_renderer_fallback = fallback;
+    }
+
+    /// Opens the user's default browser with the server's URL in the background. If a buffer is
+    /// given, the URL of the buffer's preview is opened instead. If an anchor is given, such as
+    /// `#installation`, the preview is scrolled to the element with that id once it's rendered.
+    ///
+    /// This function uses platform-specific utilities to determine the browser, as described in
+    /// [`browser`](crate::browser). Returns `false` without opening a browser if there's no
+    /// display to open it on, such as over SSH, in which case the user should open the URL of the
+    /// preview themselves.
+    pub fn open_browser(&self, buffer: Option<&str>, anchor: Option<&str>) -> io::Result<bool> {
+        match browser::default_browser() {
+            Some(command) => {
+                self.open_specific_browser(command, buffer, anchor)?;
+                Ok(true)
+            }
+            None => {
+                let url = match buffer {
+                    Some(buffer) => self.buffer_url(buffer),
+                    None => self.url(),
+                };
+                warn!(
+                    "no display to open a browser on, open the preview at {}",
+                    url
+                );
+                Ok(false)
+            }
+        }
+    }
+
+    /// Opens a browser with a specified command. The HTTP address of the server, or of the preview
+    /// of a buffer, will be appended to the command as an argument, followed by the anchor, if
+    /// any. If any arguments contain `{url}`, such as `--app={url}`, it's replaced by the address
+    /// instead, for browsers that take it in the middle of their arguments.
+    pub fn open_specific_browser(
+        &self,
+        mut command: Command,
+        buffer: Option<&str>,
+        anchor: Option<&str>,
+    ) -> io::Result<()> {
+        let mut url = match buffer {
+            Some(buffer) => self.buffer_url(buffer),
+            None => self.url(),
+        };
+        // The preview only closes itself if it knows that it was opened by the server.
+        if self.config.lock().unwrap().close_on_shutdown {
+            url.push_str(if url.contains('?') {
+                "&opened"
+            } else {
+                "?opened"
+            });
+        }
+        if let Some(anchor) = anchor {
+            url.push('#');
+            url.push_str(&encode_fragment(anchor.trim_start_matches('#')));
+        }
+
+        let templated = command
+            .get_args()
+            .any(|arg| arg.to_str().is_some_and(|arg| arg.contains("{url}")));
+        if templated {
+            let mut templated = Command::new(command.get_program());
+            for arg in command.get_args() {
+                match arg.to_str() {
+                    Some(arg) => templated.arg(arg.replace("{url}", &url)),
+                    None => templated.arg(arg),
+                };
+            }
+            for (key, value) in command.get_envs() {
+                match value {
+                    Some(value) => templated.env(key, value),
+                    None => templated.env_remove(key),
+                };
+            }
+            if let Some(dir) = command.get_current_dir() {
+                templated.current_dir(dir);
+            }
+            command = templated;
+        } else {
+            command.arg(url);
+        }
+
+        command.stdout(Stdio::null()).stderr(Stdio::null());
+
+        info!("spawning browser: {:?}", command);
+        command.spawn()?;
+        Ok(())
+    }
+
+    /// Closes all connected preview pages.
+    ///
+    /// The server continues running, so a new preview may be opened afterwards.
+    pub fn close_browser(&self) {
+        info!("closing connected previews");
+
+        self.close_clients(Signal::CloseBrowser);
+    }
+
+    /// Opens the browser's print dialog in the connected previews of the main document, or of a
+    /// buffer. Returns whether any preview was connected.
+    pub fn print_preview(&self, buffer: Option<&str>) -> bool {
+        let document = match buffer {
+            Some(buffer) => match self.buffers.get(buffer) {
+                Some(state) => &state.document,
+                None => return false,
+            },
+            None => &self.documents.main,
+        };
+
+        info!("printing connected previews");
+        document.broadcast(Signal::Print);
+        document.clients.lock().unwrap().len() > 0
+    }
+
+    /// Brings the connected previews of the main document, or of a buffer, to the front, rather
+    /// than opening another one, scrolling them to an anchor if one is given. Returns whether any
+    /// preview was connected.
+    ///
+    /// Browsers may not let pages focus themselves, in which case the previews are only scrolled.
+    pub fn raise_preview(&self, buffer: Option<&str>, anchor: Option<&str>) -> bool {
+        let document = match buffer {
+            Some(buffer) => match self.buffers.get(buffer) {
+                Some(state) => &state.document,
+                None => return false,
+            },
+            None => &self.documents.main,
+        };
+
+        if document.clients.lock().unwrap().len() == 0 {
+            return false;
+        }
+
+        info!("raising connected previews");
+        let anchor = anchor.map(|anchor| anchor.trim_start_matches('#').to_owned());
+        document.broadcast(Signal::Raise(anchor));
+        true
+    }
+
+    /// Shows an error in the banner of every preview, such as a procedure that failed, or hides
+    /// the error if the message is `None`.
+    pub fn show_error(&self, message: Option<&str>) {
+        self.broadcast(Signal::Error(message.map(String::from)));
+    }
+
+    /// Gracefully shuts down the server.
+    ///
+    /// Connected preview pages are told that the preview has ended before their websocket
+    /// connections are closed, so that they can let the user know that the page will no longer
+    /// update.
+    pub fn shutdown(self) {
+        info!("shutting down server");
+
+        self.close_clients(Signal::Shutdown);
+    }
+
+    /// Returns a summary of the state of the server.
+    pub fn status(&self) -> ServerStatus {
+        let title = self.documents.main.title.read().unwrap();
+        let clients = self.documents.client_count();
+        ServerStatus::new(&self.config.lock().unwrap(), &title, clients)
+    }
+
+    /// Sends a signal to every connected websocket client, whichever document it displays.
+    fn broadcast(&self, signal: Signal) {
+        self.documents.broadcast(signal);
+    }
+
+    /// Disconnects all websocket clients, sending them a final signal.
+    fn close_clients(&self, signal: Signal) {
+        self.documents.close_clients(signal);
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        for addr in &self.addrs {
+            let _ = TcpStream::connect(addr);
+        }
+        #[cfg(unix)]
+        if let Some(path) = &self.unix_socket {
+            let _ = std::os::unix::net::UnixStream::connect(path);
+        }
+
+        // Shutdown all websocket connections.
+        self.close_clients(Signal::Close);
+
+        // Wait for connection threads to complete.
+        for join_handle in self.listener_join_handles.drain(..) {
+            join_handle.join().unwrap();
+        }
+
+        if let Some(path) = &self.unix_socket {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// A document that is previewed by the server, shared with the connections that display it.
+#[derive(Debug)]
+struct Document {
+    /// Shared, so that pages can send it without holding the lock while they write to sockets.
+    html: RwLock<Option<Arc<str>>>,
+    /// The markdown that the HTML was rendered from.
+    source: RwLock<String>,
+    title: RwLock<String>,
+    clients: Mutex<IdMap<SignalSender>>,
+    /// The local images referenced by the HTML, by their source, with their paths and when they
+    /// were last modified.
+    images: Mutex<HashMap<String, (PathBuf, Option<SystemTime>)>>,
+    /// Whether the document was opened from a link, so that the page can go back.
+    can_go_back: AtomicBool,
+    /// The problems that the linter found in the markdown.
+    diagnostics: RwLock<Vec<Diagnostic>>,
+    /// The length of the HTML's text.
+    stats: RwLock<Stats>,
+    /// The (one-based, inclusive) ranges of lines of the markdown that changed since `HEAD`.
+    changes: RwLock<Vec<(usize, usize)>>,
+    /// How long rendering the HTML took, if it was rendered from markdown.
+    render: RwLock<Option<RenderStatus>>,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Document {
+            html: RwLock::new(None),
+            source: RwLock::default(),
+            title: RwLock::new(String::from(DEFAULT_TITLE)),
+            clients: Mutex::new(IdMap::default()),
+            images: Mutex::default(),
+            can_go_back: AtomicBool::new(false),
+            diagnostics: RwLock::default(),
+            stats: RwLock::default(),
+            changes: RwLock::default(),
+            render: RwLock::default(),
+        }
+    }
+}
+
+impl Document {
+    /// Replaces the HTML of the document, sending it and its statistics to every client.
+    fn set_html(&self, html: String, render: Option<RenderStatus>) {
+        let stats = Stats::from_html(&html);
+        *self.render.write().unwrap() = render;
+        *self.html.write().unwrap() = Some(Arc::from(html));
+        self.broadcast(Signal::NewMarkdown);
+
+        let mut current = self.stats.write().unwrap();
+        if *current != stats {
+            *current = stats;
+            drop(current);
+
+            self.broadcast(Signal::Stats);
+        }
+    }
+
+    /// Watches the local images referenced by HTML for changes, instead of the images referenced
+    /// by the previous HTML.
+    fn watch_images(&self, html: &str, root: Option<&Path>) {
+        let mut images = self.images.lock().unwrap();
+        let mut old = std::mem::take(&mut *images);
+
+        for (src, path) in export::local_images(html, root) {
+            let modified = match old.remove(&src) {
+                Some((old_path, modified)) if old_path == path => modified,
+                _ => fs::metadata(&path).and_then(|m| m.modified()).ok(),
+            };
+            images.insert(src, (path, modified));
+        }
+    }
+
+    /// Returns the sources of the watched images that were modified since they were last checked.
+    fn modified_images(&self) -> Vec<String> {
+        let mut images = self.images.lock().unwrap();
+        let mut modified_images = vec![];
+
+        for (src, (path, modified)) in images.iter_mut() {
+            let current = fs::metadata(path).and_then(|m| m.modified()).ok();
+            if current != *modified {
+                *modified = current;
+                if current.is_some() {
+                    modified_images.push(src.clone());
+                }
+            }
+        }
+
+        modified_images
+    }
+
+    /// Changes whether the page can go back, notifying clients if it changed.
+    fn set_can_go_back(&self, can_go_back: bool) {
+        if self.can_go_back.swap(can_go_back, Ordering::SeqCst) != can_go_back {
+            self.broadcast(Signal::History);
+        }
+    }
+
+    /// Replaces the diagnostics of the document, notifying clients if they changed.
+    fn set_diagnostics(&self, diagnostics: Vec<Diagnostic>) {
+        let mut current = self.diagnostics.write().unwrap();
+        if *current != diagnostics {
+            *current = diagnostics;
+            drop(current);
+
+            self.broadcast(Signal::Diagnostics);
+        }
+    }
+
+    /// Replaces the lines that changed since `HEAD`, notifying clients if they changed.
+    fn set_changes(&self, changes: Vec<(usize, usize)>) {
+        let mut current = self.changes.write().unwrap();
+        if *current != changes {
+            *current = changes;
+            drop(current);
+
+            self.broadcast(Signal::Changes);
+        }
+    }
+
+    /// Changes the title of the document, notifying clients if it changed.
+    fn set_title(&self, title: &str) {
+        let mut current = self.title.write().unwrap();
+        if *current != title {
+            *current = title.to_owned();
+            drop(current);
+
+            self.broadcast(Signal::NewTitle);
+        }
+    }
+
+    /// Sends a signal to every client displaying the document. Clients whose queues are full are
+    /// disconnected, and load the document again when they reconnect.
+    fn broadcast(&self, signal: Signal) {
+        self.clients.lock().unwrap().retain(|client| {
+            let sent = client.send(signal.clone()).is_ok();
+            if !sent {
+                warn!("disconnecting a client that fell behind");
+            }
+            sent
+        });
+    }
+
+    /// Disconnects every client displaying the document, sending them a final signal.
+    fn close_clients(&self, signal: Signal) {
+        let clients = std::mem::take(&mut *self.clients.lock().unwrap());
+
+        for client in clients.values() {
+            let _ = client.send(signal.clone());
+        }
+    }
+}
+
+/// The documents that are previewed by the server.
+#[derive(Debug, Default)]
+struct Documents {
+    /// The main document, served at the root of the server.
+    main: Arc<Document>,
+    /// The documents of buffers that are previewed alongside the main document, by ID, in the
+    /// order that the buffers were registered.
+    buffers: RwLock<Vec<(String, Arc<Document>)>>,
+    /// A page listing the other documents, served at `/buffer/`.
+    index: Arc<Document>,
+}
+
+impl Documents {
+    /// Returns the document of a buffer.
+    fn buffer(&self, id: &str) -> Option<Arc<Document>> {
+        let buffers = self.buffers.read().unwrap();
+        buffers
+            .iter()
+            .find(|(buffer, _)| buffer == id)
+            .map(|(_, document)| Arc::clone(document))
+    }
+
+    /// Returns every document, including the index.
+    fn all(&self) -> Vec<Arc<Document>> {
+        let mut documents = vec![Arc::clone(&self.main), Arc::clone(&self.index)];
+        let buffers = self.buffers.read().unwrap();
+        documents.extend(buffers.iter().map(|(_, document)| Arc::clone(document)));
+        documents
+    }
+
+    /// Returns the tabs of the documents that have been rendered: the main document, if any, and
+    /// each buffer.
+    fn tabs(&self) -> Vec<Tab> {
+        let mut tabs = vec![];
+
+        if self.main.html.read().unwrap().is_some() {
+            tabs.push(Tab {
+                path: String::from("/"),
+                title: self.main.title.read().unwrap().clone(),
+            });
+        }
+
+        for (id, document) in self.buffers.read().unwrap().iter() {
+            tabs.push(Tab {
+                path: format!("/buffer/{}/", id),
+                title: document.title.read().unwrap().clone(),
+            });
+        }
+
+        tabs
+    }
+
+    /// Searches the text of the documents that have been rendered, in the order of their tabs,
+    /// returning those that match.
+    fn search(&self, query: &str) -> Vec<SearchResults> {
+        let mut documents = vec![(String::from("/"), Arc::clone(&self.main))];
+        for (id, document) in self.buffers.read().unwrap().iter() {
+            documents.push((format!("/buffer/{}/", id), Arc::clone(document)));
+        }
+
+        documents
+            .into_iter()
+            .filter_map(|(path, document)| {
+                let matches = search::search(document.html.read().unwrap().as_deref()?, query);
+                if matches.is_empty() {
+                    return None;
+                }
+                Some(SearchResults {
+                    path,
+                    title: document.title.read().unwrap().clone(),
+                    matches,
+                })
+            })
+            .collect()
+    }
+
+    /// Sends a signal to the clients of every document.
+    fn broadcast(&self, signal: Signal) {
+        for document in self.all() {
+            document.broadcast(signal.clone());
+        }
+    }
+
+    /// Disconnects the clients of every document, sending them a final signal.
+    fn close_clients(&self, signal: Signal) {
+        for document in self.all() {
+            document.close_clients(signal.clone());
+        }
+    }
+
+    /// Returns the number of clients displaying any document.
+    fn client_count(&self) -> usize {
+        self.all()
+            .iter()
+            .map(|document| document.clients.lock().unwrap().len())
+            .sum()
+    }
+}
+
+/// A link to a document, displayed in the tab bar of each preview.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct Tab {
+    /// The URL path of the document.
+    path: String,
+    title: String,
+}
+
+/// The matches of a search in a document.
+#[derive(Debug, Serialize)]
+struct SearchResults {
+    /// The URL path of the document.
+    path: String,
+    title: String,
+    matches: Vec<Match>,
+}
+
+/// The state of a buffer that is previewed alongside the main document.
+#[derive(Debug)]
+struct Buffer {
+    document: Arc<Document>,
+    /// The most recently rendered markdown of the buffer.
+    markdown: String,
+    /// The name of the file that was most recently rendered in the buffer.
+    file_name: Option<String>,
+    /// The filetype that the buffer's markdown was most recently sent with, if any.
+    filetype: Option<String>,
+}
+
+/// Markdown to be linted, and the document that displays its diagnostics.
+struct LintJob {
+    document: Arc<Document>,
+    buffer: Option<String>,
+    markdown: String,
+}
+
+/// Lints the markdown of each job until the server drops the sender of the jobs.
+///
+/// Jobs that arrived while the linter was running are replaced by later jobs for the same
+/// document, so that the diagnostics catch up with the markdown without linting every keystroke.
+fn lint_documents(mut linter: Linter, jobs: &Receiver<LintJob>, requests: &Sender<PreviewRequest>) {
+    while let Ok(job) = jobs.recv() {
+        let mut latest = vec![job];
+        for job in jobs.try_iter() {
+            latest.retain(|pending| pending.buffer != job.buffer);
+            latest.push(job);
+        }
+
+        for job in latest {
+            let diagnostics = match linter.lint(&job.markdown) {
+                Ok(diagnostics) => diagnostics,
+                Err(e) => {
+                    warn!("could not run linter: {}", e);
+                    continue;
+                }
+            };
+            debug!("linter reported {} diagnostics", diagnostics.len());
+
+            job.document.set_diagnostics(diagnostics.clone());
+            let _ = requests.send(PreviewRequest::Diagnostics {
+                diagnostics,
+                buffer: job.buffer,
+            });
+        }
+    }
+}
+
+/// A document that was displayed before a file was opened from the preview.
+#[derive(Debug, Clone)]
+struct Visit {
+    markdown: String,
+    file_name: Option<String>,
+    filetype: Option<String>,
+}
+
+/// Signals sent from the server to the websocket connection handlers.
+#[derive(Debug, Clone)]
+enum Signal {
+    /// New HTML is available.
+    NewMarkdown,
+
+    /// The title of the page changed.
+    NewTitle,
+
+    /// Auto-scroll was enabled or disabled.
+    AutoScroll,
+
+    /// Focus mode was enabled or disabled.
+    Focus,
+
+    /// The display of render timings was enabled or disabled.
+    Timings,
+
+    /// The zoom of the preview changed.
+    Zoom,
+
+    /// The table of contents was shown or hidden.
+    Toc,
+
+    /// The editor's cursor moved to a line.
+    Scroll(usize),
+
+    /// The server is shutting down. The client should be notified before the connection closes.
+    Shutdown,
+
+    /// The preview page should close itself before the connection closes.
+    CloseBrowser,
+
+    /// The page settings changed, so the page should be reloaded.
+    Reload,
+
+    /// The page should open the browser's print dialog.
+    Print,
+
+    /// The page should bring itself to the front, scrolling to the element with an id if one is
+    /// given.
+    Raise(Option<String>),
+
+    /// A document was added, removed or retitled.
+    Tabs,
+
+    /// The local stylesheets of the page were modified.
+    Styles,
+
+    /// The page became able or unable to go back to a previous document.
+    History,
+
+    /// Local images with these sources were modified.
+    Images(Vec<String>),
+
+    /// Rendering failed with this message, or succeeded again after failing.
+    Error(Option<String>),
+
+    /// The linter reported new diagnostics.
+    Diagnostics,
+
+    /// The length of the document changed.
+    Stats,
+
+    /// The lines of the markdown that changed since `HEAD` changed.
+    Changes,
+
+    /// The connection should be closed.
+    Close,
+}
+
+/// Messages sent to the preview page over the websocket or event stream.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage<'a> {
+    /// Replace the contents of the preview with blocks of HTML. If the preview is windowed, only
+    /// the blocks near the visible part of the page are added to it.
+    Html {
+        blocks: &'a [&'a str],
+        windowed: bool,
+    },
+
+    /// Replace some blocks of the preview with new ones. The lines of the blocks after them moved
+    /// by the line offset.
+    Patch {
+        start: usize,
+        delete: usize,
+        blocks: &'a [&'a str],
+        line_offset: i64,
+    },
+
+    /// Change the title of the page.
+    Title { title: &'a str },
+
+    /// Enable or disable scrolling to the editor's cursor.
+    AutoScroll { enabled: bool },
+
+    /// Enable or disable dimming every block except the one at the editor's cursor.
+    Focus { enabled: bool },
+
+    /// Display how long the update that was just sent took to render and send, or stop displaying
+    /// render timings if they're null.
+    Timings { render: Option<RenderStatus> },
+
+    /// Scale the preview by a percentage.
+    Zoom { percent: u32 },
+
+    /// Show or hide the table of contents.
+    Toc { enabled: bool },
+
+    /// The editor's cursor moved to a line.
+    Scroll { line: usize },
+
+    /// The preview has ended, and no further updates will be sent. The page should close itself
+    /// if it was opened by the server and `close` is set.
+    Shutdown { close: bool },
+
+    /// The page should close itself.
+    CloseBrowser,
+
+    /// The page should reload itself.
+    Reload,
+
+    /// The page should open the browser's print dialog.
+    Print,
+
+    /// The page should bring itself to the front, scrolling to the element with an id if one is
+    /// given.
+    Raise { anchor: Option<&'a str> },
+
+    /// Replace the tab bar, which links to every document.
+    Tabs { tabs: &'a [Tab] },
+
+    /// Reload the images with these sources, which were modified.
+    Images { sources: &'a [String] },
+
+    /// Replace the contents of the page's local stylesheets, which were modified.
+    Styles { styles: &'a [String] },
+
+    /// Show or hide the button that goes back to the previous document.
+    History { can_go_back: bool },
+
+    /// Show an error, or hide it if the message is null.
+    Error { message: Option<&'a str> },
+
+    /// Replace the diagnostics of the linter that are marked in the preview.
+    Diagnostics { diagnostics: &'a [Diagnostic] },
+
+    /// Update the length of the document shown in the status strip.
+    Stats { stats: Stats },
+
+    /// Replace the (one-based, inclusive) ranges of lines that are highlighted as changed since
+    /// `HEAD`.
+    Changes { lines: &'a [(usize, usize)] },
+
+    /// Replace the markdown that is displayed beside the preview.
+    Source { markdown: &'a str },
+}
+
+/// Messages received from the preview page over the websocket, or posted to the event stream.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum PageMessage {
+    /// The user followed a link to another markdown file. The path is a URL path.
+    Open { path: String },
+
+    /// The user went back to the document that was displayed before following a link.
+    Back,
+
+    /// The user clicked the checkbox of the task list item on a (one-based) line of the markdown.
+    ToggleTask { line: usize },
+
+    /// The page displayed a render, and highlighted its code blocks, in some milliseconds.
+    Displayed { render: u64, ms: f64 },
+}
+
+impl ClientMessage<'_> {
+    /// Encodes the message as JSON in a text frame, or gzipped in a binary frame if the page can
+    /// decompress it and it's large enough to be worth it.
+    fn to_message(&self, gzip: bool) -> io::Result<Message> {
+        let json = serde_json::to_string(self).expect("client message is always serializable");
+        if !gzip || json.len() < MIN_COMPRESSED_SIZE {
+            return Ok(Message::text(json));
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(json.as_bytes())?;
+        Ok(Message::binary(encoder.finish()?))
+    }
+}
+
+#[derive(Debug)]
+struct Config {
+    auto_scroll: bool,
+    /// Whether every block except the one at the editor's cursor is dimmed.
+    focus: bool,
+    /// Whether the preview displays how long each update took to render and display.
+    render_timings: bool,
+    /// How much the preview is scaled, in percent.
+    zoom: u32,
+    toc: bool,
+    presentation: bool,
+    /// Whether the markdown is displayed beside the preview.
+    side_by_side: bool,
+    /// Whether the previews that the browser was opened with close themselves when the preview
+    /// ends.
+    close_on_shutdown: bool,
+    static_root: Option<PathBuf>,
+    template: Option<String>,
+    tls: Option<Arc<rustls::ServerConfig>>,
+    auth: Option<AuthToken>,
+    /// The addresses that other devices on the network can reach the server at.
+    lan_addrs: Vec<SocketAddr>,
+    /// Origins that may read responses from the server, or `*` for any origin.
+    allowed_origins: Vec<String>,
+    /// Names other than `localhost` that requests may address the server by.
+    allowed_hosts: Vec<String>,
+    /// The Content-Security-Policy of the preview, or `None` to use a strict policy only when
+    /// offline.
+    csp: Option<ContentSecurityPolicy>,
+    /// Additional sources allowed by the strict Content-Security-Policy.
+    csp_script_sources: Vec<String>,
+    csp_style_sources: Vec<String>,
+    favicon: String,
+    description: Option<String>,
+    page: PageSettings,
+    /// Overrides of the page settings chosen from the preview's toolbar.
+    browser: BrowserSettings,
+    language_links: Vec<Url>,
+    language_scripts: Vec<String>,
+    js_links: Vec<Url>,
+    custom_scripts: Vec<String>,
+    /// The size of HTML above which the preview is windowed.
+    virtualize_size: Option<usize>,
+    /// Downscaled copies of large local images, if they're served instead of the images.
+    thumbnails: Option<Arc<Thumbnails>>,
+    /// The local stylesheets of the page, with when they were last modified.
+    stylesheets: Vec<(PathBuf, Option<SystemTime>)>,
+    /// When the server started.
+    started: Instant,
+    last_render: Option<RenderStatus>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            auto_scroll: true,
+            focus: false,
+            render_timings: false,
+            zoom: 100,
+            toc: false,
+            presentation: false,
+            side_by_side: false,
+            close_on_shutdown: false,
+            static_root: None,
+            template: None,
+            tls: None,
+            auth: None,
+            lan_addrs: vec![],
+            allowed_origins: vec![],
+            allowed_hosts: vec![],
+            csp: None,
+            csp_script_sources: vec![],
+            csp_style_sources: vec![],
+            favicon: String::from("/__/img/favicon.svg"),
+            description: None,
+            page: PageSettings::default(),
+            browser: BrowserSettings::default(),
+            language_links: vec![],
+            language_scripts: vec![],
+            js_links: vec![],
+            custom_scripts: vec![],
+            virtualize_size: None,
+            thumbnails: None,
+            stylesheets: vec![],
+            started: Instant::now(),
+            last_render: None,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the local stylesheets of the page again if any of them were modified, and returns
+    /// whether they were.
+    fn reload_stylesheets(&mut self) -> bool {
+        let mut modified = false;
+        for (path, last_modified) in &mut self.stylesheets {
+            let current = fs::metadata(&*path).and_then(|m| m.modified()).ok();
+            if current != *last_modified {
+                *last_modified = current;
+                modified = true;
+            }
+        }
+
+        if !modified {
+            return false;
+        }
+
+        let styles = self
+            .stylesheets
+            .iter()
+            .map(|(path, _)| fs::read_to_string(path))
+            .collect::<io::Result<Vec<_>>>();
+
+        match styles {
+            Ok(styles) => {
+                self.page.custom_styles = styles;
+                true
+            }
+            Err(e) => {
+                warn!("could not reload stylesheet: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Returns the URL of a path on an address, with the auth token if one is required.
+    fn url_of(&self, addr: SocketAddr, path: &str) -> String {
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+        format!("{}://{}{}", scheme, addr, self.authenticated_path(path))
+    }
+
+    /// Returns a path with the auth token if one is required.
+    fn authenticated_path(&self, path: &str) -> String {
+        match &self.auth {
+            Some(auth) if path.is_empty() => format!("/?token={}", auth.token),
+            Some(auth) => format!("{}?token={}", path, auth.token),
+            None => path.to_owned(),
+        }
+    }
+
+    /// Returns whether the preview of some HTML is windowed.
+    fn windowed(&self, html: &str) -> bool {
+        !self.presentation && self.virtualize_size.is_some_and(|size| html.len() > size)
+    }
+
+    /// Returns the settings of the preview page, including the choices made from the toolbar.
+    fn page_settings(&self) -> PageSettings {
+        let mut settings = self.page.clone();
+
+        if let Some(color_scheme) = self.browser.color_scheme {
+            settings.color_scheme = color_scheme;
+        }
+
+        if let Some(font_size) = &self.browser.font_size {
+            settings.typography.font_size = Some(font_size.clone());
+        }
+
+        if let Some(theme) = &self.browser.highlight_theme {
+            settings.highlight_theme = theme.clone();
+            settings.dark_highlight_theme = theme.clone();
+        }
+
+        settings
+    }
+}
+
+/// A secret that clients must present to access the preview.
+#[derive(Debug, Clone)]
+struct AuthToken {
+    token: String,
+
+    /// The name of the cookie that holds the token. Cookies are shared between ports, so each
+    /// server needs its own.
+    cookie: String,
+}
+
+impl AuthToken {
+    /// Compares a token with this one, in constant time.
+    fn matches(&self, token: &str) -> bool {
+        token.len() == self.token.len()
+            && token
+                .bytes()
+                .zip(self.token.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+/// Settings chosen from the preview's toolbar. They last until the server exits, and take
+/// precedence over the settings from the editor and front matter.
+#[derive(Debug, Default)]
+struct BrowserSettings {
+    color_scheme: Option<ColorScheme>,
+    font_size: Option<String>,
+    highlight_theme: Option<String>,
+}
+
+/// The settings served to the toolbar at `/__/settings`.
+///
+/// Settings may be changed by posting an object with only the settings that should change. An
+/// empty font size or highlight theme restores the default.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ToolbarSettings {
+    color_scheme: Option<ColorScheme>,
+    font_size: Option<String>,
+    /// The highlight theme chosen from the toolbar, which replaces both the light and dark themes.
+    highlight_theme: Option<String>,
+    toc: Option<bool>,
+}
+
+/// Settings of the preview page that may be overridden by a document's front matter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PageSettings {
+    highlight_theme: String,
+    dark_highlight_theme: String,
+    color_scheme: ColorScheme,
+    typography: Typography,
+    offline: bool,
+    css_links: Vec<Url>,
+    custom_styles: Vec<String>,
+    math: Option<MathRenderer>,
+    pdf_layout: PdfLayout,
+}
+
+impl Default for PageSettings {
+    fn default() -> Self {
+        PageSettings {
+            highlight_theme: String::from("github"),
+            dark_highlight_theme: String::from("github-dark"),
+            color_scheme: ColorScheme::Auto,
+            typography: Typography::default(),
+            offline: false,
+            css_links: vec![],
+            custom_styles: vec![],
+            math: Some(MathRenderer::Katex),
+            pdf_layout: PdfLayout::default(),
+        }
+    }
+}
+
+/// Splits stylesheets or scripts into links and the contents of local files.
+///
+/// URLs are returned as links. Paths are read from disk, resolved against the root if given.
+fn load_resources(
+    resources: &[String],
+    root: Option<&Path>,
+) -> io::Result<(Vec<Url>, Vec<String>)> {
+    let mut links = vec![];
+    let mut contents = vec![];
+
+    for resource in resources {
+        match resource_path(resource, root) {
+            Ok(path) => contents.push(fs::read_to_string(path)?),
+            Err(url) => links.push(url),
+        }
+    }
+
+    Ok((links, contents))
+}
+
+/// Returns the path of a local stylesheet or script, resolved against the root if given, or its
+/// URL if it's remote.
+fn resource_path(resource: &str, root: Option<&Path>) -> Result<PathBuf, Url> {
+    // NB: Absolute paths on Windows will parse as URLs.
+    match Url::parse(resource) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Err(url),
+        _ => {
+            let path = Path::new(resource.trim_start_matches("file://"));
+            Ok(match root {
+                Some(root) => root.join(path),
+                None => path.to_owned(),
+            })
+        }
+    }
+}
+
+/// The origin of a cross-origin request that may read the response.
+#[derive(Debug)]
+enum AllowedOrigin {
+    /// Any origin may read the response, without credentials, since `*` is allowed.
+    Any,
+
+    /// The origin was allowed by name, so it may read the response with credentials.
+    Listed(String),
+}
+
+#[derive(Debug)]
+struct Handler {
+    conn: Stream,
+    config: Arc<Mutex<Config>>,
+    documents: Arc<Documents>,
+    /// The document that the request is for.
+    document: Arc<Document>,
+    /// The ID of the buffer that the request is for, or `None` for the main document.
+    buffer: Option<String>,
+    requests: Sender<PreviewRequest>,
+    /// The origin of a cross-origin request that is allowed to read the response.
+    allowed_origin: Option<AllowedOrigin>,
+    /// Whether the client accepts gzip-compressed responses.
+    gzip: bool,
+}
+
+impl Handler {
+    fn handle(mut self) -> Result<(), Box<dyn Error>> {
+        let mut buf = Vec::new();
+
+        loop {
+            let mut chunk = [0; 4096];
+            let n = self.conn.read(&mut chunk)?;
+
+            if n == 0 {
+                break;
+            }
+
+            buf.extend_from_slice(&chunk[..n]);
+
+            let mut headers = [EMPTY_HEADER; 100];
+            let mut req = Request::new(&mut headers);
+
+            let header_len = match req.parse(&buf) {
+                Ok(Status::Partial) => continue,
+                Ok(Status::Complete(len)) => len,
+                Err(_) => {
+                    write!(self.conn, "HTTP/1.1 400 Bad Request\r\n\r\n")?;
+                    return Ok(());
+                }
+            };
+
+            let origin = req
+                .headers
+                .iter()
+                .find(|header| header.name.eq_ignore_ascii_case("Origin"))
+                .and_then(|header| str::from_utf8(header.value).ok());
+            let host = req
+                .headers
+                .iter()
+                .find(|header| header.name.eq_ignore_ascii_case("Host"))
+                .and_then(|header| str::from_utf8(header.value).ok());
+
+            if !self.is_allowed_host(host) {
+                debug!("rejecting request for host {:?}", host);
+                write!(self.conn, "HTTP/1.1 403 Forbidden\r\n\r\n")?;
+                return Ok(());
+            }
+
+            let cross_origin = origin.filter(|origin| {
+                let origin_host = origin.split_once("://").map(|(_, host)| host);
+                host.is_none() || origin_host != host
+            });
+
+            if let Some(origin) = cross_origin {
+                let config = self.config.lock().unwrap();
+                let allowed = &config.allowed_origins;
+                if allowed
+                    .iter()
+                    .any(|allowed| allowed.trim_end_matches('/') == origin)
+                {
+                    self.allowed_origin = Some(AllowedOrigin::Listed(origin.to_string()));
+                } else if allowed.iter().any(|allowed| allowed == "*") {
+                    self.allowed_origin = Some(AllowedOrigin::Any);
+                }
+            }
+
+            self.gzip = req
+                .headers
+                .iter()
+                .filter(|header| header.name.eq_ignore_ascii_case("Accept-Encoding"))
+                .filter_map(|header| str::from_utf8(header.value).ok())
+                .flat_map(|encodings| encodings.split(','))
+                .any(|encoding| {
+                    let mut params = encoding.split(';').map(str::trim);
+                    params.next() == Some("gzip") && params.all(|param| param != "q=0")
+                });
+
+            if !self.is_authorized(&req) {
+                debug!("rejecting unauthorized request for {:?}", req.path);
+                write!(self.conn, "HTTP/1.1 403 Forbidden\r\n\r\n")?;
+                return Ok(());
+            }
+
+            // Requests for the preview of a buffer, or for the index of the documents, are served
+            // like requests for the main document, relative to the buffer's path.
+            if let Some(path) = req.path.and_then(|path| path.strip_prefix("/buffer/")) {
+                if path.is_empty() || path.starts_with('?') {
+                    self.document = Arc::clone(&self.documents.index);
+                    req.path = Some("/");
+                } else {
+                    let buffer = path
+                        .find('/')
+                        .and_then(|end| Some((end, self.documents.buffer(&path[..end])?)));
+
+                    match buffer {
+                        Some((end, document)) => {
+                            self.buffer = Some(path[..end].to_owned());
+                            self.document = document;
+                            req.path = Some(&path[end..]);
+                        }
+                        None => {
+                            write!(self.conn, "HTTP/1.1 404 Not Found\r\n\r\n")?;
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+
+            let websocket = req.headers.iter().any(|header| {
+                header.name.eq_ignore_ascii_case("Upgrade") && header.value == b"websocket"
+            });
+
+            // Websockets aren't subject to CORS, and browsers may send simple POST requests to
+            // other origins without asking first, so pages on other origins must be rejected
+            // here.
+            if (websocket || req.method == Some("POST"))
+                && cross_origin.is_some()
+                && self.allowed_origin.is_none()
+            {
+                debug!("rejecting request from origin {:?}", cross_origin);
+                write!(self.conn, "HTTP/1.1 403 Forbidden\r\n\r\n")?;
+                return Ok(());
+            }
+
+            if websocket {
+                self.serve_markdown_on_websocket(req)?;
+                return Ok(());
+            }
+
+            let content_length = req
+                .headers
+                .iter()
+                .find(|header| header.name.eq_ignore_ascii_case("Content-Length"))
+                .and_then(|header| str::from_utf8(header.value).ok()?.trim().parse().ok())
+                .unwrap_or(0);
+
+            if buf.len() < header_len + content_length {
+                continue;
+            }
+
+            let body = &buf[header_len..header_len + content_length];
+            self.serve_http(req, body)?;
+            break;
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether a request addresses the server by `localhost`, one of its IP addresses or
+    /// one of the allowed hosts. See `Server::set_allowed_hosts`.
+    ///
+    /// Requests without a host and requests over a unix domain socket aren't sent by browsers, so
+    /// they're allowed.
+    fn is_allowed_host(&self, host: Option<&str>) -> bool {
+        let host = match host {
+            Some(host) if !self.conn.is_unix() => host,
+            _ => return true,
+        };
+
+        let config = self.config.lock().unwrap();
+        if config.auth.is_some() {
+            return true;
+        }
+
+        let name = match host.strip_prefix('[') {
+            Some(host) => host.split(']').next().unwrap_or_default(),
+            None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+        };
+        let name = name.trim_end_matches('.');
+
+        match name.parse::<IpAddr>() {
+            Ok(ip) => ip.is_loopback() || config.lan_addrs.iter().any(|addr| addr.ip() == ip),
+            Err(_) => {
+                name.eq_ignore_ascii_case("localhost")
+                    || config
+                        .allowed_hosts
+                        .iter()
+                        .any(|allowed| allowed.eq_ignore_ascii_case(name))
+            }
+        }
+    }
+
+    /// Returns whether the request carries the server's auth token, if one is required.
+    ///
+    /// The token may be given by the `token` query parameter or the cookie set when the preview
+    /// page is loaded with it.
+    fn is_authorized(&self, req: &Request) -> bool {
+        let config = self.config.lock().unwrap();
+        let auth = match &config.auth {
+            Some(auth) => auth,
+            None => return true,
+        };
+
+        let query = req
+            .path
+            .and_then(|path| path.split_once('?'))
+            .map(|(_, query)| query)
+            .unwrap_or_default();
+        let query_token = form_urlencoded::parse(query.as_bytes())
+            .any(|(key, value)| key == "token" && auth.matches(&value));
+
+        let cookie_token = req
+            .headers
+            .iter()
+            .filter(|header| header.name.eq_ignore_ascii_case("Cookie"))
+            .filter_map(|header| str::from_utf8(header.value).ok())
+            .flat_map(|cookies| cookies.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .any(|(name, value)| name == auth.cookie && auth.matches(value));
+
+        query_token || cookie_token
+    }
+
+    fn serve_markdown_on_websocket(mut self, req: Request) -> Result<(), Box<dyn Error>> {
+        let key = req.headers.iter().find_map(|header| {
+            if header.name.eq_ignore_ascii_case("Sec-WebSocket-Key") {
+                Some(header.value)
+            } else {
+                None
+            }
+        });
+
+        let key = match key {
+            Some(key) => key,
+            None => {
+                write!(self.conn, "HTTP/1.1 400 Bad Request\r\n\r\n")?;
+                return Ok(());
+            }
+        };
+
+        // Browsers only compress websockets with permessage-deflate, which tungstenite doesn't
+        // support, so pages that can decompress updates ask for them to be gzipped instead.
+        let gzip = req
+            .path
+            .and_then(|path| path.split_once('?'))
+            .is_some_and(|(_, query)| {
+                form_urlencoded::parse(query.as_bytes())
+                    .any(|(key, value)| key == "compress" && value == "gzip")
+            });
+
+        // Register the client before completing the handshake, so that any updates sent after the
+        // client observes the connection are delivered.
+        let (md_tx, md_rx) = queue::channel();
+
+        let client_id = self.document.clients.lock().unwrap().insert(md_tx);
+        debug!("websocket client {} connected", client_id);
+
+        write!(self.conn, "HTTP/1.1 101 Switching Protocols\r\n")?;
+        write!(self.conn, "Upgrade: websocket\r\n")?;
+        write!(self.conn, "Connection: upgrade\r\n")?;
+        write!(
+            self.conn,
+            "Sec-WebSocket-Accept: {}\r\n",
+            derive_accept_key(key)
+        )?;
+        write!(self.conn, "\r\n")?;
+        self.conn.flush()?;
+        self.conn.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT))?;
+
+        let mut writer = WebSocket::from_raw_socket(self.conn.try_clone()?, Role::Server, None);
+        let mut reader = WebSocket::from_raw_socket(self.conn, Role::Server, None);
+
+        let document = Arc::clone(&self.document);
+        let buffer = self.buffer.clone();
+        let requests = self.requests.clone();
+        let config = Arc::clone(&self.config);
+        thread::spawn(move || loop {
+            match reader.read() {
+                // TLS connections time out reads, so that the connection can be written to.
+                Err(tungstenite::Error::Io(e))
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) => {}
+                Err(_) => break,
+                Ok(Message::Close(_)) => {
+                    debug!("websocket client {} disconnected", client_id);
+
+                    // The client may already be dropped by the time we get here.
+                    document.clients.lock().unwrap().remove(client_id);
+                    break;
+                }
+                Ok(Message::Text(text)) => match serde_json::from_str(text.as_str()) {
+                    Ok(message) => {
+                        handle_page_message(&requests, &config, buffer.as_deref(), message)
+                    }
+                    Err(e) => warn!("invalid message from websocket client {}: {}", client_id, e),
+                },
+                Ok(_) => (),
+            }
+        });
+
+        let res = send_updates(
+            &self.document,
+            &self.documents,
+            &self.config,
+            &md_rx,
+            |message| {
+                writer.send(message.to_message(gzip)?).map_err(|e| match e {
+                    tungstenite::Error::Io(e) => e,
+                    e => io::Error::other(e),
+                })
+            },
+        );
+
+        // Ask pages that fell behind to reconnect, rather than treating the connection as ended.
+        let (res, frame) = match res {
+            Err(e) if fell_behind(&e) => {
+                debug!("websocket client {} fell behind", client_id);
+                let frame = CloseFrame {
+                    code: CloseCode::Again,
+                    reason: "fell behind".into(),
+                };
+                (Ok(()), Some(frame))
+            }
+            res => (res, None),
+        };
+
+        // Ignore errors, since the socket may already be closed.
+        let _ = writer.close(frame);
+        let _ = writer.flush();
+
+        Ok(res?)
+    }
+
+    /// Streams updates to the preview page as server-sent events, for browsers that can't open a
+    /// websocket to the server.
+    fn serve_markdown_as_events(&mut self) -> io::Result<()> {
+        let (md_tx, md_rx) = queue::channel();
+
+        let client_id = self.document.clients.lock().unwrap().insert(md_tx);
+        debug!("event stream client {} connected", client_id);
+
+        self.write_status("200 OK")?;
+        write!(self.conn, "Content-Type: text/event-stream\r\n")?;
+        write!(self.conn, "Cache-Control: no-cache\r\n")?;
+        // Ask proxies not to buffer the stream.
+        write!(self.conn, "X-Accel-Buffering: no\r\n")?;
+        write!(self.conn, "Connection: close\r\n")?;
+        write!(self.conn, "\r\n")?;
+        write!(self.conn, "retry: 5000\n\n")?;
+        self.conn.flush()?;
+        self.conn.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT))?;
+
+        let conn = &mut self.conn;
+        let res = send_updates(
+            &self.document,
+            &self.documents,
+            &self.config,
+            &md_rx,
+            |message| {
+                let json =
+                    serde_json::to_string(&message).expect("client message is always serializable");
+                write!(conn, "data: {}\n\n", json)?;
+                conn.flush()
+            },
+        );
+
+        debug!("event stream client {} disconnected", client_id);
+        self.document.clients.lock().unwrap().remove(client_id);
+
+        // The page reconnects by itself once the stream ends.
+        match res {
+            Err(e) if fell_behind(&e) => Ok(()),
+            res => res,
+        }
+    }
+
+    fn serve_http(&mut self, req: Request, body: &[u8]) -> io::Result<()> {
+        let (path, query) = match req.path.unwrap().split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (req.path.unwrap(), None),
+        };
+
+        if req.method == Some("OPTIONS") {
+            // Answer CORS preflight requests.
+            self.write_status("204 No Content")?;
+            if self.allowed_origin.is_some() {
+                write!(self.conn, "Access-Control-Allow-Methods: GET, POST\r\n")?;
+                write!(self.conn, "Access-Control-Allow-Headers: Content-Type\r\n")?;
+            }
+            write!(self.conn, "\r\n")?;
+        } else if path == "/__/settings" {
+            if req.method == Some("POST") {
+                self.update_settings(body)?;
+            } else {
+                self.serve_settings()?;
+            }
+        } else if path == "/__/events" {
+            if req.method == Some("POST") {
+                match serde_json::from_slice(body) {
+                    Ok(message) => {
+                        handle_page_message(
+                            &self.requests,
+                            &self.config,
+                            self.buffer.as_deref(),
+                            message,
+                        );
+                        self.write_empty_response("204 No Content")?;
+                    }
+                    Err(e) => {
+                        warn!("invalid message from event stream client: {}", e);
+                        self.write_empty_response("400 Bad Request")?;
+                    }
+                }
+            } else {
+                self.serve_markdown_as_events()?;
+            }
+        } else if path == "/healthz" {
+            let status = {
+                let title = self.documents.main.title.read().unwrap();
+                let clients = self.documents.client_count();
+                ServerStatus::new(&self.config.lock().unwrap(), &title, clients)
+            };
+            let json = serde_json::to_string(&status).expect("status is always serializable");
+
+            self.write_json(&json)?;
+        } else if path == "/api/render" {
+            if req.method == Some("POST") {
+                let json = req.headers.iter().any(|header| {
+                    header.name.eq_ignore_ascii_case("Content-Type")
+                        && header.value.starts_with(b"application/json")
+                });
+                self.push_markdown(body, json)?;
+            } else {
+                self.write_status("405 Method Not Allowed")?;
+                write!(self.conn, "Allow: POST\r\n\r\n")?;
+            }
+        } else if path == "/api/search" {
+            if req.method == Some("GET") {
+                let search = query
+                    .into_iter()
+                    .flat_map(|query| form_urlencoded::parse(query.as_bytes()))
+                    .find(|(key, _)| key == "q")
+                    .map(|(_, search)| search.into_owned())
+                    .unwrap_or_default();
+                let results = self.documents.search(&search);
+                let json = serde_json::to_string(&results).expect("results are serializable");
+
+                self.write_json(&json)?;
+            } else {
+                self.write_status("405 Method Not Allowed")?;
+                write!(self.conn, "Allow: GET\r\n\r\n")?;
+            }
+        } else if path == "/api/qr" {
+            if req.method == Some("GET") {
+                // The code is of the URL that other devices can open the page at, if there's one.
+                let page = query
+                    .into_iter()
+                    .flat_map(|query| form_urlencoded::parse(query.as_bytes()))
+                    .find(|(key, _)| key == "path")
+                    .map(|(_, path)| path.into_owned())
+                    .filter(|path| path.starts_with('/'))
+                    .unwrap_or_else(|| String::from("/"));
+                let url = {
+                    let config = self.config.lock().unwrap();
+                    config
+                        .lan_addrs
+                        .first()
+                        .map(|&addr| config.url_of(addr, &page))
+                };
+
+                match url.and_then(|url| QrCode::encode(&url)) {
+                    Some(qr) => {
+                        self.write_status("200 OK")?;
+                        write!(self.conn, "Connection: close\r\n")?;
+                        self.write_body(Some("image/svg+xml"), qr.to_svg().as_bytes())?;
+                    }
+                    None => self.write_empty_response("404 Not Found")?,
+                }
+            } else {
+                self.write_status("405 Method Not Allowed")?;
+                write!(self.conn, "Allow: GET\r\n\r\n")?;
+            }
+        } else if path == "/__/highlight-themes" {
+            let json = serde_json::to_string(&highlight_themes()).expect("themes are serializable");
+
+            self.write_json(&json)?;
+        } else if path == "/__/export" {
+            let (title, html) = {
+                let config = self.config.lock().unwrap();
+                let title = self.document.title.read().unwrap().clone();
+                let html = self.document.html.read().unwrap();
+                let html = standalone_html(&config, &title, html.as_deref());
+                (title, html)
+            };
+
+            let file_name = title
+                .chars()
+                .map(|c| match c {
+                    'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | ' ' => c,
+                    _ => '_',
+                })
+                .collect::<String>();
+
+            self.write_status("200 OK")?;
+            write!(self.conn, "Connection: close\r\n")?;
+            write!(
+                self.conn,
+                "Content-Disposition: attachment; filename=\"{}.html\"\r\n",
+                file_name
+            )?;
+            self.write_body(Some("text/html; charset=UTF-8"), html.as_bytes())?;
+        } else if path.starts_with("/__/") {
+            let path = path.trim_start_matches("/__/");
+
+            match STATIC_FILES.get_file(path) {
+                Some(file) => self.write_file_contents(file.path(), file.contents())?,
+                None => self.write_empty_response("404 Not Found")?,
+            }
+        } else if path == "/" {
+            #[derive(Debug, Serialize)]
+            struct HighlightStyle<'a> {
+                theme: &'a str,
+                media: &'a str,
+            }
+
+            #[derive(Debug, Serialize)]
+            struct Data<'a> {
+                title: &'a str,
+                favicon: &'a str,
+                description: Option<&'a str>,
+                remote_custom_css: &'a [Url],
+                local_custom_css: &'a [String],
+                highlight_styles: Vec<HighlightStyle<'a>>,
+                dark_media: Option<&'a str>,
+                typography: Option<String>,
+                page_layout: Option<String>,
+                remote_languages: &'a [Url],
+                local_languages: &'a [String],
+                remote_custom_js: &'a [Url],
+                local_custom_js: &'a [String],
+                toc: bool,
+                presentation: bool,
+                side_by_side: bool,
+                /// Whether other devices on the network can open the preview.
+                lan: bool,
+                katex: bool,
+                mathjax: bool,
+                nonce: &'a str,
+                html: Option<&'a str>,
+            }
+
+            // Inline scripts of the page are marked with a nonce, so that the page's
+            // Content-Security-Policy can tell them apart from scripts in the markdown.
+            let mut nonce = [0; 16];
+            getrandom::getrandom(&mut nonce)?;
+            let nonce = STANDARD.encode(nonce);
+
+            let (html, csp) = {
+                let config = self.config.lock().unwrap();
+                let title = self.document.title.read().unwrap();
+                let html = self.document.html.read().unwrap();
+                let mut page = config.page_settings();
+
+                // Themes may be previewed by loading the page with a `highlight_theme` parameter.
+                let theme = query
+                    .into_iter()
+                    .flat_map(|query| form_urlencoded::parse(query.as_bytes()))
+                    .find(|(key, _)| key == "highlight_theme")
+                    .map(|(_, theme)| theme.into_owned());
+                if let Some(theme) =
+                    theme.filter(|theme| highlight_themes().contains(&theme.as_str()))
+                {
+                    page.highlight_theme = theme.clone();
+                    page.dark_highlight_theme = theme;
+                }
+
+                let light = &page.highlight_theme;
+                let dark = &page.dark_highlight_theme;
+                let (highlight_styles, dark_media) = match page.color_scheme {
+                    ColorScheme::Light => (vec![(light, "all")], None),
+                    ColorScheme::Dark => (vec![(dark, "all")], Some("all")),
+                    ColorScheme::Auto => (
+                        vec![
+                            (light, "not all and (prefers-color-scheme: dark)"),
+                            (dark, "(prefers-color-scheme: dark)"),
+                        ],
+                        Some("(prefers-color-scheme: dark)"),
+                    ),
+                };
+
+                let data = Data {
+                    title: &title,
+                    favicon: &config.favicon,
+                    description: config.description.as_deref(),
+                    remote_custom_css: &config.page.css_links,
+                    local_custom_css: &config.page.custom_styles,
+                    highlight_styles: highlight_styles
+                        .into_iter()
+                        .map(|(theme, media)| HighlightStyle { theme, media })
+                        .collect(),
+                    dark_media,
+                    typography: page.typography.to_css(),
+                    page_layout: page.pdf_layout.to_css(&title, &today()),
+                    remote_languages: &config.language_links,
+                    local_languages: &config.language_scripts,
+                    remote_custom_js: &config.js_links,
+                    local_custom_js: &config.custom_scripts,
+                    toc: config.toc && !config.presentation && !config.side_by_side,
+                    presentation: config.presentation && !config.page.offline,
+                    side_by_side: config.side_by_side && !config.presentation,
+                    lan: !config.lan_addrs.is_empty(),
+                    katex: config.page.math == Some(MathRenderer::Katex),
+                    mathjax: config.page.math == Some(MathRenderer::MathJax),
+                    nonce: &nonce,
+                    html: html.as_deref(),
+                };
+
+                let mut handlebars = Handlebars::new();
+                handlebars
+                    .register_partial("styles", include_str!("../templates/partials/styles.html"))
+                    .expect("invalid template syntax");
+                handlebars
+                    .register_partial(
+                        "scripts",
+                        include_str!("../templates/partials/scripts.html"),
+                    )
+                    .expect("invalid template syntax");
+
+                let template = config
+                    .template
+                    .as_deref()
+                    .unwrap_or(include_str!("../templates/markdown_view.html"));
+
+                let csp = match &config.csp {
+                    Some(ContentSecurityPolicy::Off) => None,
+                    Some(ContentSecurityPolicy::Custom(policy)) => Some(policy.clone()),
+                    Some(ContentSecurityPolicy::Strict) => {
+                        Some(strict_content_security_policy(&config, &nonce))
+                    }
+                    None if config.page.offline => {
+                        Some(strict_content_security_policy(&config, &nonce))
+                    }
+                    None => None,
+                };
+
+                (handlebars.render_template(template, &data), csp)
+            };
+
+            let html = match html {
+                Ok(html) => html,
+                Err(e) => {
+                    // Only custom templates can fail to render.
+                    warn!("could not render template: {}", e);
+                    self.write_empty_response("500 Internal Server Error")?;
+                    return Ok(());
+                }
+            };
+
+            let auth = self.config.lock().unwrap().auth.clone();
+
+            self.write_status("200 OK")?;
+            write!(self.conn, "Connection: close\r\n")?;
+            if let Some(csp) = csp {
+                write!(self.conn, "Content-Security-Policy: {}\r\n", csp)?;
+            }
+            if let Some(auth) = auth {
+                // Let the page's resources and websocket connection through without the token.
+                write!(
+                    self.conn,
+                    "Set-Cookie: {}={}; Path=/; HttpOnly; SameSite=Strict\r\n",
+                    auth.cookie, auth.token
+                )?;
+            }
+            self.write_body(Some("text/html; charset=UTF-8"), html.as_bytes())?;
+        } else {
+            let file_path = self
+                .config
+                .lock()
+                .unwrap()
+                .static_root
+                .clone()
+                .and_then(|root| static_file(&root, path));
+
+            match file_path {
+                Some(file_path) => self.write_file(&file_path)?,
+                None => self.write_empty_response("404 Not Found")?,
+            }
+        }
+
+        self.conn.flush()?;
+
+        Ok(())
+    }
+
+    /// Responds with the current settings of the toolbar.
+    fn serve_settings(&mut self) -> io::Result<()> {
+        let settings = {
+            let config = self.config.lock().unwrap();
+            let page = config.page_settings();
+
+            ToolbarSettings {
+                color_scheme: Some(page.color_scheme),
+                font_size: page.typography.font_size,
+                highlight_theme: config.browser.highlight_theme.clone(),
+                toc: Some(config.toc),
+            }
+        };
+
+        let json = serde_json::to_string(&settings).expect("settings are always serializable");
+
+        self.write_json(&json)
+    }
+
+    /// Asks the owner of the server to render markdown from the body of a request.
+    ///
+    /// The body is either the markdown itself, or a JSON object with the markdown as `content`
+    /// and an optional `path` of the document.
+    fn push_markdown(&mut self, body: &[u8], json: bool) -> io::Result<()> {
+        #[derive(Debug, Deserialize)]
+        struct Document {
+            content: String,
+            path: Option<PathBuf>,
+        }
+
+        let document = if json {
+            serde_json::from_slice::<Document>(body).map_err(|e| e.to_string())
+        } else {
+            String::from_utf8(body.to_vec())
+                .map(|content| Document {
+                    content,
+                    path: None,
+                })
+                .map_err(|e| e.to_string())
+        };
+
+        match document {
+            Ok(Document { content, path }) => {
+                let _ = self.requests.send(PreviewRequest::Render {
+                    markdown: content,
+                    path,
+                });
+                self.write_empty_response("202 Accepted")
+            }
+            Err(e) => {
+                warn!("invalid markdown: {}", e);
+                self.write_empty_response("400 Bad Request")
+            }
+        }
+    }
+
+    /// Applies settings changed from the toolbar, and updates every connected preview.
+    fn update_settings(&mut self, body: &[u8]) -> io::Result<()> {
+        let settings = match serde_json::from_slice::<ToolbarSettings>(body) {
+            Ok(settings) => settings,
+            Err(e) => {
+                warn!("invalid settings: {}", e);
+                self.write_empty_response("400 Bad Request")?;
+                return Ok(());
+            }
+        };
+
+        let mut typography = Typography::default();
+        if let Some(font_size) = settings.font_size.clone().filter(|size| !size.is_empty()) {
+            if let Err(e) = typography.set("font_size", Some(font_size)) {
+                warn!("invalid settings: {}", e);
+                self.write_empty_response("400 Bad Request")?;
+                return Ok(());
+            }
+        }
+
+        if let Some(theme) = settings.highlight_theme.as_deref() {
+            if !theme.is_empty() && !highlight_themes().contains(&theme) {
+                warn!("invalid settings: unknown highlight theme: {}", theme);
+                self.write_empty_response("400 Bad Request")?;
+                return Ok(());
+            }
+        }
+
+        let mut config = self.config.lock().unwrap();
+        let page = config.page_settings();
+
+        if let Some(color_scheme) = settings.color_scheme {
+            config.browser.color_scheme = Some(color_scheme);
+        }
+
+        if settings.font_size.is_some() {
+            config.browser.font_size = typography.font_size;
+        }
+
+        if let Some(theme) = settings.highlight_theme {
+            config.browser.highlight_theme = Some(theme).filter(|theme| !theme.is_empty());
+        }
+
+        let reload = config.page_settings() != page;
+
+        let toc = settings.toc.filter(|&toc| toc != config.toc);
+        if let Some(toc) = toc {
+            config.toc = toc;
+        }
+
+        drop(config);
+
+        if reload {
+            self.documents.broadcast(Signal::Reload);
+        }
+
+        if toc.is_some() {
+            self.documents.broadcast(Signal::Toc);
+        }
+
+        self.write_empty_response("204 No Content")?;
+
+        Ok(())
+    }
+
+    /// Writes the status line of a response, followed by the CORS headers of the request.
+    fn write_status(&mut self, status: &str) -> io::Result<()> {
+        write!(self.conn, "HTTP/1.1 {}\r\n", status)?;
+
+        match &self.allowed_origin {
+            Some(AllowedOrigin::Any) => write!(self.conn, "Access-Control-Allow-Origin: *\r\n")?,
+            Some(AllowedOrigin::Listed(origin)) => {
+                write!(self.conn, "Access-Control-Allow-Origin: {}\r\n", origin)?;
+                write!(self.conn, "Access-Control-Allow-Credentials: true\r\n")?;
+                write!(self.conn, "Vary: Origin\r\n")?;
+            }
+            None => (),
+        }
+
+        Ok(())
+    }
+
+    fn write_empty_response(&mut self, status: &str) -> io::Result<()> {
+        self.write_status(status)?;
+        write!(self.conn, "\r\n")
+    }
+
+    fn write_json(&mut self, json: &str) -> io::Result<()> {
+        self.write_status("200 OK")?;
+        write!(self.conn, "Connection: close\r\n")?;
+        self.write_body(Some("application/json"), json.as_bytes())
+    }
+
+    fn write_file_contents(&mut self, path: impl AsRef<Path>, contents: &[u8]) -> io::Result<()> {
+        self.write_status("200 OK")?;
+        write!(self.conn, "Connection: close\r\n")?;
+
+        let mime_type = mime_guess::from_path(path.as_ref()).first();
+        self.write_body(mime_type.as_ref().map(|mime| mime.as_ref()), contents)
+    }
+
+    /// Writes the content type and body of a response, after its other headers.
+    ///
+    /// Text is compressed if the client accepts gzip.
+    fn write_body(&mut self, content_type: Option<&str>, body: &[u8]) -> io::Result<()> {
+        if let Some(content_type) = content_type {
+            write!(self.conn, "Content-Type: {}\r\n", content_type)?;
+        }
+
+        let compressible = content_type.is_some_and(|content_type| {
+            content_type.starts_with("text/")
+                || ["javascript", "json", "xml"]
+                    .iter()
+                    .any(|subtype| content_type.contains(subtype))
+        });
+
+        if !compressible {
+            write!(self.conn, "\r\n")?;
+            return self.conn.write_all(body);
+        }
+
+        write!(self.conn, "Vary: Accept-Encoding\r\n")?;
+
+        if !self.gzip || body.len() < MIN_COMPRESSED_SIZE {
+            write!(self.conn, "\r\n")?;
+            return self.conn.write_all(body);
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body)?;
+        let body = encoder.finish()?;
+
+        write!(self.conn, "Content-Encoding: gzip\r\n")?;
+        write!(self.conn, "Content-Length: {}\r\n", body.len())?;
+        write!(self.conn, "\r\n")?;
+        self.conn.write_all(&body)
+    }
+
+    fn write_file(&mut self, path: &Path) -> io::Result<()> {
+        let thumbnails = self.config.lock().unwrap().thumbnails.clone();
+        if let Some(thumbnail) = thumbnails.and_then(|thumbnails| thumbnails.get(path)) {
+            return self.write_file_contents(path, &thumbnail);
+        }
+
+        if let Ok(contents) = fs::read(path) {
+            self.write_file_contents(path, &contents)?;
+        } else {
+            self.write_empty_response("404 Not Found")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the strict Content-Security-Policy of the preview page.
+///
+/// Inline scripts are only allowed with the nonce, so scripts in the markdown can't run. Offline
+/// previews may only load media from the server.
+fn strict_content_security_policy(config: &Config, nonce: &str) -> String {
+    fn push(sources: &mut Vec<String>, source: impl Into<String>) {
+        let source = source.into();
+        if !sources.contains(&source) {
+            sources.push(source);
+        }
+    }
+
+    let mut cdns = vec![];
+    match config.page.math {
+        Some(MathRenderer::Katex) => cdns.push("https://cdnjs.cloudflare.com"),
+        Some(MathRenderer::MathJax) => cdns.push("https://cdn.jsdelivr.net"),
+        _ => (),
+    }
+    if config.presentation && !config.page.offline {
+        cdns.push("https://cdn.jsdelivr.net");
+    }
+
+    let mut scripts = vec![String::from("'self'"), format!("'nonce-{}'", nonce)];
+    let mut styles = vec![String::from("'self'"), String::from("'unsafe-inline'")];
+    let mut fonts = vec![String::from("'self'"), String::from("data:")];
+
+    for cdn in cdns {
+        push(&mut scripts, cdn);
+        push(&mut styles, cdn);
+        push(&mut fonts, cdn);
+    }
+
+    for url in config.language_links.iter().chain(&config.js_links) {
+        push(&mut scripts, url.origin().ascii_serialization());
+    }
+    for url in &config.page.css_links {
+        push(&mut styles, url.origin().ascii_serialization());
+    }
+    for source in &config.csp_script_sources {
+        push(&mut scripts, source.as_str());
+    }
+    for source in &config.csp_style_sources {
+        push(&mut styles, source.as_str());
+    }
+
+    let media = if config.page.offline {
+        "'self' data:"
+    } else {
+        "* data: blob:"
+    };
+
+    format!(
+        "default-src 'self'; script-src {}; style-src {}; font-src {}; img-src {media}; \
+         media-src {media}; connect-src 'self'; object-src 'none'; base-uri 'none'",
+        scripts.join(" "),
+        styles.join(" "),
+        fonts.join(" "),
+        media = media,
+    )
+}
+
+/// Returns the names of the highlight.js themes served by the preview, in alphabetical order.
+fn highlight_themes() -> Vec<&'static str> {
+    let mut themes = STATIC_FILES
+        .get_dir("vendor/highlight.js/build/styles")
+        .map(|dir| {
+            dir.files()
+                .filter_map(|file| file.path().file_name()?.to_str()?.strip_suffix(".min.css"))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    themes.sort_unstable();
+    themes
+}
+
+/// Binds a unix domain socket that only the current user may connect to.
+///
+/// A stale socket left behind by a process that didn't exit cleanly is replaced.
+#[cfg(unix)]
+pub fn bind_unix_socket(path: &Path) -> io::Result<std::os::unix::net::UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let is_socket = fs::metadata(path)
+        .map(|metadata| metadata.file_type().is_socket())
+        .unwrap_or(false);
+    if is_socket && UnixStream::connect(path).is_err() {
+        fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Accepts connections until the server shuts down, handling each connection on its own thread.
+fn accept_connections(
+    listener: Listener,
+    shutdown: Arc<AtomicBool>,
+    config: Arc<Mutex<Config>>,
+    documents: Arc<Documents>,
+    requests: Sender<PreviewRequest>,
+) {
+    thread::scope(|s| {
+        loop {
+            let conn = listener.accept();
+
+            if shutdown.load(Ordering::SeqCst) {
+                break;
+            }
+
+            let conn = match conn {
+                Ok(conn) => conn,
+                Err(_) => break,
+            };
+
+            let tls = config.lock().unwrap().tls.clone();
+            let conn = match (conn, tls) {
+                (Stream::Tcp(conn), Some(tls)) => match Stream::tls(conn, tls) {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        warn!("could not accept TLS connection: {}", e);
+                        continue;
+                    }
+                },
+                (conn, _) => conn,
+            };
+
+            let handler = Handler {
+                conn,
+                config: Arc::clone(&config),
+                document: Arc::clone(&documents.main),
+                documents: Arc::clone(&documents),
+                buffer: None,
+                requests: requests.clone(),
+                allowed_origin: None,
+                gzip: false,
+            };
+
+            s.spawn(move || {
+                if let Err(e) = handler.handle() {
+                    match e.downcast_ref::<io::Error>() {
+                        // MacOS may return EPROTOTYPE if a write occurs while the socket
+                        // is being torn down. We could retry, but it's easier to just
+                        // ignore it.
+                        #[cfg(target_os = "macos")]
+                        Some(e) if e.raw_os_error() == Some(41) => (),
+                        Some(e)
+                            if e.kind() == io::ErrorKind::ConnectionReset
+                                || e.kind() == io::ErrorKind::BrokenPipe => {}
+                        _ => error!("unexpected error occurred: {}", e),
+                    }
+                }
+            });
+        }
+    });
+}
+
+/// Tells pages to reload the local images of their documents and the local stylesheets of the
+/// preview when they're modified, until the server shuts down.
+fn watch_files(documents: &Documents, config: &Mutex<Config>, shutdown: &AtomicBool) {
+    while !shutdown.load(Ordering::SeqCst) {
+        thread::sleep(WATCH_INTERVAL);
+
+        for document in documents.all() {
+            let modified = document.modified_images();
+            if !modified.is_empty() {
+                debug!("images were modified: {:?}", modified);
+                document.broadcast(Signal::Images(modified));
+            }
+        }
+
+        if config.lock().unwrap().reload_stylesheets() {
+            debug!("stylesheets were modified");
+            documents.broadcast(Signal::Styles);
+        }
+    }
+}
+
+/// Sends the state of the preview to a page that just connected, and then the updates of each
+/// signal until the preview is closed.
+///
+/// The page may be reconnecting, so it's sent the latest settings as well as any HTML. Once the
+/// page has the HTML, it's only sent the blocks that changed.
+///
+/// Returns an error if the page fell behind, and was disconnected because too many signals were
+/// waiting for it.
+fn send_updates(
+    document: &Document,
+    documents: &Documents,
+    config: &Mutex<Config>,
+    signals: &SignalReceiver,
+    mut send: impl FnMut(ClientMessage) -> io::Result<()>,
+) -> io::Result<()> {
+    // The blocks of HTML that the page is displaying, and whether the page is windowed.
+    let mut blocks = None;
+    // The markdown that the page is displaying beside the preview, if any.
+    let mut source = None;
+
+    // A page that doesn't read its updates blocks the writes to it, so everything it's sent is
+    // copied out of the locks first, rather than holding up renders and other callers.
+    {
+        let html = document.html.read().unwrap().clone();
+        if let Some(html) = html {
+            let windowed = config.lock().unwrap().windowed(&html);
+            send_html(&mut blocks, &html, windowed, &mut send)?;
+        }
+
+        let (render_timings, auto_scroll, focus, zoom, toc, side_by_side) = {
+            let config = config.lock().unwrap();
+            (
+                config.render_timings,
+                config.auto_scroll,
+                config.focus,
+                config.zoom,
+                config.toc,
+                config.side_by_side,
+            )
+        };
+
+        if render_timings {
+            let render = *document.render.read().unwrap();
+            send(ClientMessage::Timings { render })?;
+        }
+
+        let title = document.title.read().unwrap().clone();
+        send(ClientMessage::Title { title: &title })?;
+
+        send(ClientMessage::AutoScroll {
+            enabled: auto_scroll,
+        })?;
+        send(ClientMessage::Focus { enabled: focus })?;
+        send(ClientMessage::Zoom { percent: zoom })?;
+        send(ClientMessage::Toc { enabled: toc })?;
+        send(ClientMessage::Tabs {
+            tabs: &documents.tabs(),
+        })?;
+        send(ClientMessage::History {
+            can_go_back: document.can_go_back.load(Ordering::SeqCst),
+        })?;
+        let diagnostics = document.diagnostics.read().unwrap().clone();
+        send(ClientMessage::Diagnostics {
+            diagnostics: &diagnostics,
+        })?;
+        let stats = *document.stats.read().unwrap();
+        send(ClientMessage::Stats { stats })?;
+        let lines = document.changes.read().unwrap().clone();
+        send(ClientMessage::Changes { lines: &lines })?;
+        if side_by_side {
+            let markdown = document.source.read().unwrap().clone();
+            send_source(&mut source, &markdown, &mut send)?;
+        }
+    }
+
+    while let Some(signal) = signals.recv() {
+        match signal {
+            Signal::NewMarkdown => {
+                let html = document.html.read().unwrap().clone();
+                let html = html.expect("no HTML present");
+                let (windowed, side_by_side) = {
+                    let config = config.lock().unwrap();
+                    (config.windowed(&html), config.side_by_side)
+                };
+                send_html(&mut blocks, &html, windowed, &mut send)?;
+                if side_by_side {
+                    let markdown = document.source.read().unwrap().clone();
+                    send_source(&mut source, &markdown, &mut send)?;
+                }
+
+                let render = *document.render.read().unwrap();
+                if let Some(render) = render {
+                    send_timings(render, config, &mut send)?;
+                }
+            }
+            Signal::NewTitle => {
+                let title = document.title.read().unwrap().clone();
+                send(ClientMessage::Title { title: &title })?;
+            }
+            Signal::AutoScroll => {
+                let enabled = config.lock().unwrap().auto_scroll;
+                send(ClientMessage::AutoScroll { enabled })?;
+            }
+            Signal::Focus => {
+                let enabled = config.lock().unwrap().focus;
+                send(ClientMessage::Focus { enabled })?;
+            }
+            Signal::Timings => {
+                let render = match config.lock().unwrap().render_timings {
+                    true => *document.render.read().unwrap(),
+                    false => None,
+                };
+                send(ClientMessage::Timings { render })?;
+            }
+            Signal::Zoom => {
+                let percent = config.lock().unwrap().zoom;
+                send(ClientMessage::Zoom { percent })?;
+            }
+            Signal::Toc => {
+                let enabled = config.lock().unwrap().toc;
+                send(ClientMessage::Toc { enabled })?;
+            }
+            Signal::Scroll(line) => send(ClientMessage::Scroll { line })?,
+            Signal::Reload => send(ClientMessage::Reload)?,
+            Signal::Print => send(ClientMessage::Print)?,
+            Signal::Raise(anchor) => send(ClientMessage::Raise {
+                anchor: anchor.as_deref(),
+            })?,
+            Signal::Tabs => send(ClientMessage::Tabs {
+                tabs: &documents.tabs(),
+            })?,
+            Signal::Images(sources) => send(ClientMessage::Images { sources: &sources })?,
+            Signal::Error(message) => send(ClientMessage::Error {
+                message: message.as_deref(),
+            })?,
+            Signal::History => send(ClientMessage::History {
+                can_go_back: document.can_go_back.load(Ordering::SeqCst),
+            })?,
+            Signal::Diagnostics => {
+                let diagnostics = document.diagnostics.read().unwrap().clone();
+                send(ClientMessage::Diagnostics {
+                    diagnostics: &diagnostics,
+                })?
+            }
+            Signal::Stats => {
+                let stats = *document.stats.read().unwrap();
+                send(ClientMessage::Stats { stats })?
+            }
+            Signal::Changes => {
+                let lines = document.changes.read().unwrap().clone();
+                send(ClientMessage::Changes { lines: &lines })?
+            }
+            Signal::Styles => {
+                let styles = config.lock().unwrap().page.custom_styles.clone();
+                send(ClientMessage::Styles { styles: &styles })?;
+            }
+            Signal::Shutdown | Signal::CloseBrowser | Signal::Close => {
+                let message = match signal {
+                    Signal::Shutdown => Some(ClientMessage::Shutdown {
+                        close: config.lock().unwrap().close_on_shutdown,
+                    }),
+                    Signal::CloseBrowser => Some(ClientMessage::CloseBrowser),
+                    _ => None,
+                };
+
+                // Ignore errors, since the connection may already be closed.
+                if let Some(message) = message {
+                    let _ = send(message);
+                }
+                break;
+            }
+        }
+    }
+
+    if signals.fell_behind() {
+        return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "too many updates are waiting to be sent",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Returns whether sending updates to a page failed because the page couldn't keep up with them,
+/// either because too many were waiting or because sending one timed out.
+fn fell_behind(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    )
+}
+
+/// Sends new HTML to a page. If the page is already displaying some blocks, only the blocks that
+/// changed are sent. The whole page is replaced if it switches between being windowed or not.
+fn send_html(
+    blocks: &mut Option<(Vec<String>, bool)>,
+    html: &str,
+    windowed: bool,
+    send: &mut impl FnMut(ClientMessage) -> io::Result<()>,
+) -> io::Result<()> {
+    let new = diff::split_blocks(html);
+
+    match blocks {
+        Some((old, was_windowed)) if *was_windowed == windowed => {
+            let patch = diff::diff(old, &new);
+            if !patch.is_empty() {
+                send(ClientMessage::Patch {
+                    start: patch.start,
+                    delete: patch.delete,
+                    blocks: &new[patch.insert],
+                    line_offset: patch.line_offset,
+                })?;
+            }
+        }
+        _ => send(ClientMessage::Html {
+            blocks: &new,
+            windowed,
+        })?,
+    }
+
+    *blocks = Some((new.into_iter().map(str::to_owned).collect(), windowed));
+    Ok(())
+}
+
+/// Records how long after a render its HTML was sent to a page, and sends how long each stage of
+/// the render took to the page, if it displays render timings.
+fn send_timings(
+    render: RenderStatus,
+    config: &Mutex<Config>,
+    send: &mut impl FnMut(ClientMessage) -> io::Result<()>,
+) -> io::Result<()> {
+    let elapsed = render.finished.elapsed();
+    debug!(
+        "sent render {} to a page {:?} after rendering",
+        render.id, elapsed
+    );
+    let render = render.sent_after(elapsed);
+
+    let mut config = config.lock().unwrap();
+    if let Some(last) = config
+        .last_render
+        .as_mut()
+        .filter(|last| last.id == render.id)
+    {
+        last.broadcast_ms = Some(slowest(last.broadcast_ms, render.broadcast_ms.unwrap()));
+    }
+
+    if config.render_timings {
+        drop(config);
+        send(ClientMessage::Timings {
+            render: Some(render),
+        })?;
+    }
+    Ok(())
+}
+
+/// Sends the markdown to display beside the preview to a page, unless it's already displayed.
+fn send_source(
+    source: &mut Option<String>,
+    markdown: &str,
+    send: &mut impl FnMut(ClientMessage) -> io::Result<()>,
+) -> io::Result<()> {
+    if source.as_deref() != Some(markdown) {
+        send(ClientMessage::Source { markdown })?;
+        *source = Some(markdown.to_owned());
+    }
+    Ok(())
+}
+
+/// Passes a message from a preview page on to the owner of the server. The buffer is the one
+/// displayed by the page, if it isn't displaying the main document.
+fn handle_page_message(
+    requests: &Sender<PreviewRequest>,
+    config: &Mutex<Config>,
+    buffer: Option<&str>,
+    message: PageMessage,
+) {
+    let buffer = buffer.map(str::to_owned);
+
+    match message {
+        PageMessage::Open { path } => {
+            // Don't allow pages to read files outside of the static root.
+            match url_path_to_file_path(&path) {
+                Some(path) => {
+                    let _ = requests.send(PreviewRequest::Open { path, buffer });
+                }
+                None => warn!("refusing to open path outside of root: {}", path),
+            }
+        }
+        PageMessage::ToggleTask { line } => {
+            let _ = requests.send(PreviewRequest::ToggleTask { line, buffer });
+        }
+        PageMessage::Back => {
+            let _ = requests.send(PreviewRequest::Back { buffer });
+        }
+        PageMessage::Displayed { render, ms } => {
+            debug!("a page displayed render {} in {:.1} ms", render, ms);
+
+            let mut config = config.lock().unwrap();
+            if let Some(last) = config.last_render.as_mut().filter(|last| last.id == render) {
+                last.highlight_ms = Some(slowest(last.highlight_ms, ms));
+            }
+        }
+    }
+}
+
+/// Returns the longer of two durations in milliseconds, if there's a first one.
+fn slowest(ms: Option<f64>, other: f64) -> f64 {
+    ms.map_or(other, |ms| ms.max(other))
+}
+
+/// Returns the current date in UTC, such as `2024-02-29`.
+fn today() -> String {
+    export::timestamp(SystemTime::now())[..10].to_owned()
+}
+
+/// Renders HTML as a self-contained page, styled with the settings of the preview.
+fn standalone_html(config: &Config, title: &str, html: Option<&str>) -> String {
+    #[derive(Debug, Serialize)]
+    struct Data<'a> {
+        title: &'a str,
+        remote_custom_css: &'a [Url],
+        styles: Vec<&'a str>,
+        remote_scripts: &'a [Url],
+        scripts: Vec<&'a str>,
+        html: &'a str,
+    }
+
+    fn static_file(path: &str) -> Option<&'static str> {
+        STATIC_FILES
+            .get_file(path)
+            .and_then(|file| file.contents_utf8())
+    }
+
+    let mut styles = config
+        .page
+        .custom_styles
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+
+    // Default to GitHub CSS if no custom CSS is set.
+    if config.page.css_links.is_empty() && styles.is_empty() {
+        styles.extend(static_file(
+            "vendor/github-markdown-css/github-markdown.css",
+        ));
+    }
+
+    let theme_path = format!(
+        "vendor/highlight.js/build/styles/{}.min.css",
+        config.page.highlight_theme
+    );
+    styles.extend(static_file(&theme_path));
+    styles.extend(static_file("css/styles.css"));
+    styles.extend(static_file("css/print.css"));
+
+    let page = config.page_settings();
+    let typography = page.typography.to_css();
+    styles.extend(typography.as_deref());
+    let page_layout = page.pdf_layout.to_css(title, &today());
+    styles.extend(page_layout.as_deref());
+
+    let mut scripts = [
+        "vendor/highlight.js/build/highlight.min.js",
+        "vendor/highlight.js/build/languages/vim.min.js",
+    ]
+    .iter()
+    .filter_map(|path| static_file(path))
+    .collect::<Vec<_>>();
+    scripts.extend(config.language_scripts.iter().map(String::as_str));
+
+    let html = export::inline_local_images(html.unwrap_or_default(), config.static_root.as_deref());
+
+    let data = Data {
+        title,
+        remote_custom_css: &config.page.css_links,
+        styles,
+        remote_scripts: &config.language_links,
+        scripts,
+        html: &html,
+    };
+
+    Handlebars::new()
+        .render_template(include_str!("../templates/export.html"), &data)
+        .expect("invalid template syntax")
+}
+
+/// Renders the list of documents displayed by the index page.
+fn index_html(tabs: &[Tab]) -> String {
+    if tabs.is_empty() {
+        return String::from("<p>No documents are being previewed.</p>\n");
+    }
+
+    let mut html = String::from("<ul class=\"composer-documents\">\n");
+    for tab in tabs {
+        html.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            html_escape(&tab.path),
+            html_escape(&tab.title)
+        ));
+    }
+    html.push_str("</ul>\n");
+
+    html
+}
+
+/// Returns the filetype of a document: the one it was sent with, or else the extension of its file.
+fn filetype(filetype: Option<&str>, file_name: Option<&str>) -> Option<String> {
+    let filetype = filetype.or_else(|| Path::new(file_name?).extension()?.to_str())?;
+    Some(filetype.to_lowercase())
+}
+
+/// Returns the text of the first level-one heading in the markdown, if any.
+fn first_heading(markdown: &str) -> Option<String> {
+    let mut events = Parser::new(markdown).skip_while(|event| {
+        !matches!(
+            event,
+            Event::Start(Tag::Heading {
+                level: HeadingLevel::H1,
+                ..
+            })
+        )
+    });
+
+    events.next()?;
+
+    let heading = events
+        .take_while(|event| !matches!(event, Event::End(TagEnd::Heading(_))))
+        .filter_map(|event| match event {
+            Event::Text(text) | Event::Code(text) => Some(text.into_string()),
+            _ => None,
+        })
+        .collect::<String>();
+
+    Some(heading.trim().to_owned()).filter(|heading| !heading.is_empty())
+}
+
+/// Returns the canonical path of a directory, or an error if the path doesn't exist or isn't a
+/// directory.
+fn canonical_dir(path: &Path) -> io::Result<PathBuf> {
+    let canonical = fs::canonicalize(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    if !canonical.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{}: not a directory", path.display()),
+        ));
+    }
+
+    if cfg!(windows) {
+        Ok(strip_verbatim_prefix(canonical))
+    } else {
+        Ok(canonical)
+    }
+}
+
+/// Removes the `\\?\` prefix of the canonical paths of Windows from paths of drives and UNC
+/// shares, such as `\\?\C:\Users` and `\\?\UNC\server\share`. Verbatim paths may not contain
+/// `/`, so relative paths of URLs couldn't be joined to them, and many programs don't support
+/// them.
+fn strip_verbatim_prefix(path: PathBuf) -> PathBuf {
+    let verbatim = match path.to_str().and_then(|path| path.strip_prefix(r"\\?\")) {
+        Some(verbatim) => verbatim,
+        None => return path,
+    };
+
+    if let Some(unc) = verbatim.strip_prefix(r"UNC\") {
+        PathBuf::from(format!(r"\\{}", unc))
+    } else if verbatim.as_bytes().get(1) == Some(&b':') {
+        PathBuf::from(verbatim)
+    } else {
+        path
+    }
+}
+
+/// Converts the path of a URL to a relative file path, decoding percent-encoded bytes.
+///
+/// Returns `None` if a segment isn't a plain file name, such as `..` or a name containing an
+/// encoded `/`, so that the path can't refer to files outside of the directory it's joined to.
+fn url_path_to_file_path(path: &str) -> Option<PathBuf> {
+    let mut file_path = PathBuf::new();
+    for segment in path.trim_start_matches('/').split('/') {
+        let segment = percent_decode(segment)?;
+        if segment.is_empty() {
+            continue;
+        }
+
+        let mut components = Path::new(&segment).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), None) if name == &*segment => file_path.push(name),
+            _ => return None,
+        }
+    }
+
+    Some(file_path)
+}
+
+/// Decodes the percent-encoded bytes of a segment of a URL. Returns `None` if an escape is
+/// malformed or the segment isn't UTF-8.
+fn percent_decode(segment: &str) -> Option<String> {
+    let mut decoded = Vec::with_capacity(segment.len());
+    let mut bytes = segment.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let hex = [bytes.next()?, bytes.next()?];
+            decoded.push(u8::from_str_radix(str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            decoded.push(byte);
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+/// Returns the file under the static root that the path of a URL refers to. Returns `None` if the
+/// file doesn't exist, or if it's outside of the root, such as through a symbolic link.
+fn static_file(root: &Path, path: &str) -> Option<PathBuf> {
+    let path = root.join(url_path_to_file_path(path)?);
+
+    let canonical = fs::canonicalize(&path).ok()?;
+    let root = fs::canonicalize(root).ok()?;
+    if canonical.starts_with(&root) {
+        Some(path)
+    } else {
+        warn!(
+            "refusing to serve file outside of root: {}",
+            canonical.display()
+        );
+        None
+    }
+}
+
+/// Percent-encodes the characters of an anchor that aren't allowed in the fragment of a URL.
+fn encode_fragment(anchor: &str) -> String {
+    let mut fragment = String::new();
+    for byte in anchor.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                fragment.push(char::from(byte))
+            }
+            _ => fragment.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    fragment
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::error::Error;
+    use std::fs;
+    use std::io::prelude::*;
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::path::{Path, PathBuf};
+    use std::process::{self, Command};
+    use std::thread;
+    use std::time::{Duration, Instant, SystemTime};
+
+    use serde_json::{json, Value};
+    use tungstenite::{Message, WebSocket};
+
+    use crate::export::pdf::PdfLayout;
+    use crate::external::ExternalRenderer;
+    use crate::lint::{Diagnostic, Linter};
+
+    use super::{
+        strip_verbatim_prefix, ColorScheme, ContentSecurityPolicy, PreviewRequest, Server,
+        TitleSource, Typography,
+    };
+
+    fn connect(server: &Server) -> Result<WebSocket<TcpStream>, Box<dyn Error>> {
+        let stream = TcpStream::connect(server.addr().unwrap())?;
+        let (websocket, _) =
+            tungstenite::client(format!("ws://{}", server.addr().unwrap()), stream)?;
+        Ok(websocket)
+    }
+
+    /// Reads messages from the websocket until a message of the given type is received.
+    fn read_json(
+        websocket: &mut WebSocket<impl Read + Write>,
+        message_type: &str,
+    ) -> Result<Value, Box<dyn Error>> {
+        loop {
+            let message = websocket.read()?;
+            let message: Value = serde_json::from_str(message.to_text()?)?;
+
+            if message["type"] == message_type {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Joins the blocks of HTML sent in an `html` or `patch` message.
+    fn blocks(message: &Value) -> String {
+        message["blocks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|block| block.as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn uri_path_to_file_path() {
+        assert_eq!(
+            super::url_path_to_file_path("/file.txt"),
+            Some(PathBuf::from("file.txt"))
+        );
+        assert_eq!(
+            super::url_path_to_file_path("/a/b/c/d"),
+            Some(["a", "b", "c", "d"].iter().collect::<PathBuf>()),
+        );
+        assert_eq!(
+            super::url_path_to_file_path("/my%20notes/caf%C3%A9.png"),
+            Some(["my notes", "café.png"].iter().collect::<PathBuf>()),
+        );
+
+        for path in [
+            "/../etc/passwd",
+            "/a/../../b",
+            "/%2e%2e/etc/passwd",
+            "/a%2F..%2F..%2Fb",
+            "/./a",
+            "/a%zz",
+            "/%ff",
+        ] {
+            assert_eq!(super::url_path_to_file_path(path), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn first_heading() {
+        assert_eq!(super::first_heading("# Title"), Some(String::from("Title")));
+        assert_eq!(
+            super::first_heading("Some text\n\n## Subtitle\n\nTitle `code`\n===="),
+            Some(String::from("Title code"))
+        );
+        assert_eq!(super::first_heading("## Subtitle"), None);
+    }
+
+    #[test]
+    fn send_with_no_clients() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+
+        server.send(String::from("This shouldn't hang"))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn send_markdown() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        let mut websocket = connect(&server)?;
+
+        server.send(String::from("*Hello*"))?;
+        let message = read_json(&mut websocket, "html")?;
+        assert_eq!(
+            blocks(&message).trim(),
+            "<p data-source-line=\"1\"><em>Hello</em></p>"
+        );
+
+        // Only the blocks that changed are sent once the page has the HTML.
+        server.send(String::from("*Hello*\n\nWorld"))?;
+        let message = read_json(&mut websocket, "patch")?;
+        assert_eq!(message["start"], 1);
+        assert_eq!(message["delete"], 0);
+        assert_eq!(
+            blocks(&message).trim(),
+            "<p data-source-line=\"3\">World</p>"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn window_large_documents() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.set_virtualize_size(Some(100));
+        let mut websocket = connect(&server)?;
+
+        server.send(String::from("Short"))?;
+        assert_eq!(read_json(&mut websocket, "html")?["windowed"], false);
+
+        // The whole page is replaced once it becomes windowed.
+        server.send("Long\n\n".repeat(20))?;
+        assert_eq!(read_json(&mut websocket, "html")?["windowed"], true);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reload_modified_images() -> Result<(), Box<dyn Error>> {
+        let root = env::temp_dir().join(format!("composer-modified-images-{}", process::id()));
+        fs::create_dir_all(&root)?;
+        fs::write(root.join("plot.png"), "old")?;
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.set_static_root(&root)?;
+        let mut websocket = connect(&server)?;
+
+        server.send(String::from("![Plot](plot.png)"))?;
+        read_json(&mut websocket, "html")?;
+
+        fs::File::options()
+            .write(true)
+            .open(root.join("plot.png"))?
+            .set_modified(SystemTime::now() + Duration::from_secs(60))?;
+        assert_eq!(
+            read_json(&mut websocket, "images")?["sources"],
+            json!(["plot.png"])
+        );
+
+        fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn stats() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        let mut websocket = connect(&server)?;
+        assert_eq!(read_json(&mut websocket, "stats")?["stats"]["words"], 0);
+
+        server.send(String::from(
+            "# Hello\n\nSome *words*.\n\n```\nnot counted\n```",
+        ))?;
+        assert_eq!(
+            read_json(&mut websocket, "stats")?["stats"],
+            json!({ "words": 3, "characters": 17, "reading_time_minutes": 1 })
+        );
+        assert_eq!(server.stats(None).words, 3);
+        assert_eq!(server.stats(Some("missing")).words, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn git_changes() -> Result<(), Box<dyn Error>> {
+        let root = env::temp_dir().join(format!("composer-git-changes-{}", process::id()));
+        fs::create_dir_all(&root)?;
+        fs::write(root.join("notes.md"), "# Notes\n\nOld\n")?;
+        for args in [
+            &["init", "-q"][..],
+            &["add", "notes.md"],
+            &[
+                "-c",
+                "user.name=a",
+                "-c",
+                "user.email=a@b",
+                "commit",
+                "-qm",
+                "a",
+            ],
+        ] {
+            assert!(Command::new("git")
+                .args(args)
+                .current_dir(&root)
+                .status()?
+                .success());
+        }
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        let mut websocket = connect(&server)?;
+        assert_eq!(read_json(&mut websocket, "changes")?["lines"], json!([]));
+
+        server.set_git_path(None, Some(root.join("notes.md")));
+        server.send(String::from("# Notes\n\nNew\n\nMore\n"))?;
+        assert_eq!(
+            read_json(&mut websocket, "changes")?["lines"],
+            json!([[3, 5]])
+        );
+
+        server.set_git_path(None, None::<&Path>);
+        assert_eq!(read_json(&mut websocket, "changes")?["lines"], json!([]));
+
+        fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn lint() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        let requests = server.take_requests().unwrap();
+        let mut command = Command::new("sh");
+        command.args([
+            "-c",
+            "grep -n TODO | sed 's/:.*/:1 unfinished/; s/^/stdin:/'",
+        ]);
+        server.set_linter(Some(Linter::new(command)));
+        let mut websocket = connect(&server)?;
+        assert_eq!(
+            read_json(&mut websocket, "diagnostics")?["diagnostics"],
+            json!([])
+        );
+
+        server.send(String::from("# Title\n\nTODO"))?;
+        assert_eq!(
+            read_json(&mut websocket, "diagnostics")?["diagnostics"],
+            json!([{ "line": 3, "column": 1, "message": "unfinished" }])
+        );
+        assert_eq!(
+            requests.recv()?,
+            PreviewRequest::Diagnostics {
+                diagnostics: vec![Diagnostic {
+                    line: 3,
+                    column: Some(1),
+                    message: String::from("unfinished"),
+                }],
+                buffer: None,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reload_modified_stylesheets() -> Result<(), Box<dyn Error>> {
+        let path = env::temp_dir().join(format!("composer-stylesheet-{}.css", process::id()));
+        fs::write(&path, "body { color: red; }")?;
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.set_custom_css(vec![path.to_string_lossy().into_owned()])?;
+        let mut websocket = connect(&server)?;
+        read_json(&mut websocket, "tabs")?;
+
+        fs::write(&path, "body { color: blue; }")?;
+        fs::File::options()
+            .write(true)
+            .open(&path)?
+            .set_modified(SystemTime::now() + Duration::from_secs(60))?;
+        assert_eq!(
+            read_json(&mut websocket, "styles")?["styles"],
+            json!(["body { color: blue; }"])
+        );
+
+        fs::remove_file(path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn open_file_and_go_back() -> Result<(), Box<dyn Error>> {
+        let root = env::temp_dir().join(format!("composer-open-file-{}", process::id()));
+        fs::create_dir_all(&root)?;
+        fs::write(root.join("other.md"), "Other")?;
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.set_static_root(&root)?;
+        server.send(String::from("Buffer"))?;
+        let mut websocket = connect(&server)?;
+        assert_eq!(read_json(&mut websocket, "history")?["can_go_back"], false);
+
+        server.open_file(None, "other.md")?;
+        assert!(blocks(&read_json(&mut websocket, "patch")?).contains("Other"));
+        assert_eq!(read_json(&mut websocket, "history")?["can_go_back"], true);
+
+        server.go_back(None)?;
+        assert!(blocks(&read_json(&mut websocket, "patch")?).contains("Buffer"));
+        assert_eq!(read_json(&mut websocket, "history")?["can_go_back"], false);
+
+        fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn queue_html_if_no_clients() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+
+        server.send(String::from("# Markdown"))?;
+
+        let mut websocket = connect(&server)?;
+        let message = read_json(&mut websocket, "html")?;
+        assert_eq!(
+            blocks(&message).trim(),
+            "<h1 data-source-line=\"1\" id=\"markdown\">\
+             <a class=\"anchor\" href=\"#markdown\" aria-hidden=\"true\"></a>Markdown</h1>"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_static_root() -> Result<(), Box<dyn Error>> {
+        let root = env::temp_dir().join(format!("composer-static-root-{}", process::id()));
+        fs::create_dir_all(&root)?;
+        fs::write(root.join("file.md"), "")?;
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.set_static_root(root.join("missing")).unwrap_err();
+        server.set_static_root(root.join("file.md")).unwrap_err();
+        assert_eq!(server.static_root(), None);
+
+        // The root is canonicalized when it is set.
+        server.set_static_root(root.join("."))?;
+        assert_eq!(server.static_root(), Some(fs::canonicalize(&root)?));
+
+        assert_eq!(
+            strip_verbatim_prefix(PathBuf::from(r"\\?\C:\Users\notes")),
+            PathBuf::from(r"C:\Users\notes")
+        );
+        assert_eq!(
+            strip_verbatim_prefix(PathBuf::from(r"\\?\UNC\server\share\notes")),
+            PathBuf::from(r"\\server\share\notes")
+        );
+        assert_eq!(
+            strip_verbatim_prefix(PathBuf::from(r"\\?\Volume{0}\notes")),
+            PathBuf::from(r"\\?\Volume{0}\notes")
+        );
+
+        fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn static_files_stay_under_root() -> Result<(), Box<dyn Error>> {
+        let dir = env::temp_dir().join(format!("composer-static-files-{}", process::id()));
+        let root = dir.join("root");
+        fs::create_dir_all(&root)?;
+        fs::write(root.join("image.txt"), "served")?;
+        fs::write(dir.join("secret.txt"), "secret")?;
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.set_static_root(&root)?;
+
+        let get = |path: &str| -> Result<String, Box<dyn Error>> {
+            let mut stream = TcpStream::connect(server.addr().unwrap())?;
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path)?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response)
+        };
+
+        assert!(get("/image.txt")?.ends_with("served"));
+        for path in ["/../secret.txt", "/%2e%2e/secret.txt", "/..%2fsecret.txt"] {
+            let response = get(path)?;
+            assert!(
+                response.starts_with("HTTP/1.1 404"),
+                "{}: {}",
+                path,
+                response
+            );
+        }
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.join("secret.txt"), root.join("link.txt"))?;
+            assert!(get("/link.txt")?.starts_with("HTTP/1.1 404"));
+        }
+
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_file_relative_to_static_root() -> Result<(), Box<dyn Error>> {
+        let root = env::temp_dir().join(format!("composer-render-file-{}", process::id()));
+        fs::create_dir_all(&root)?;
+        fs::write(root.join("file.md"), "# From disk")?;
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.set_static_root(&root)?;
+        server.render_file("file.md")?;
+
+        let mut websocket = connect(&server)?;
+        let message = read_json(&mut websocket, "html")?;
+        assert_eq!(
+            blocks(&message).trim(),
+            "<h1 data-source-line=\"1\" id=\"from-disk\">\
+             <a class=\"anchor\" href=\"#from-disk\" aria-hidden=\"true\"></a>From disk</h1>"
+        );
+
+        fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn index_includes_html() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.send(String::from("---\ndescription: A <greeting>\n---\n*Hello*"))?;
+
+        let mut stream = TcpStream::connect(server.addr().unwrap())?;
+        write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.contains("<p data-source-line=\"4\"><em>Hello</em></p>"));
+        assert!(response.contains("<link rel=\"icon\" href=\"/__/img/favicon.svg\">"));
+        assert!(response.contains("<meta name=\"description\" content=\"A &lt;greeting&gt;\">"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn index_loads_scripts() -> Result<(), Box<dyn Error>> {
+        let language = env::temp_dir().join(format!("composer-language-{}.js", process::id()));
+        fs::write(&language, "hljs.registerLanguage('zig', zig);")?;
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.set_highlight_languages(vec![
+            String::from("https://example.com/lang.min.js"),
+            language.to_string_lossy().into_owned(),
+        ])?;
+
+        let mut stream = TcpStream::connect(server.addr().unwrap())?;
+        write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.contains("<script src=\"https://example.com/lang.min.js\"></script>"));
+        let inline = response
+            .find("hljs.registerLanguage('zig', zig);</script>")
+            .unwrap();
+        assert!(response[..inline].ends_with("\">"));
+        assert!(response[..inline].contains("<script nonce=\""));
+
+        fs::remove_file(language)?;
+
+        server.set_custom_js(vec![String::from("https://example.com/custom.js")])?;
+
+        let mut stream = TcpStream::connect(server.addr().unwrap())?;
+        write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        let client = response.find("/__/js/markdown_client.js").unwrap();
+        let custom = response.find("https://example.com/custom.js").unwrap();
+        assert!(client < custom);
+
+        Ok(())
+    }
+
+    #[test]
+    fn color_scheme() -> Result<(), Box<dyn Error>> {
+        fn index(server: &Server) -> Result<String, Box<dyn Error>> {
+            let mut stream = TcpStream::connect(server.addr().unwrap())?;
+            write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response)
+        }
+
+        let mut server = Server::bind(&["localhost:0"])?;
+
+        let auto = index(&server)?;
+        assert!(auto.contains(
+            "github-dark.min.css\" rel=\"stylesheet\" media=\"(prefers-color-scheme: dark)\""
+        ));
+        assert!(
+            auto.contains("dark.css\" rel=\"stylesheet\" media=\"(prefers-color-scheme: dark)\"")
+        );
+
+        server.set_color_scheme(ColorScheme::Light);
+        let light = index(&server)?;
+        assert!(light.contains("github.min.css\" rel=\"stylesheet\" media=\"all\""));
+        assert!(!light.contains("github-dark"));
+        assert!(!light.contains("css/dark.css"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn custom_template() -> Result<(), Box<dyn Error>> {
+        let template = env::temp_dir().join(format!("composer-template-{}.html", process::id()));
+        fs::write(
+            &template,
+            "<title>{{ title }}</title><main id=\"markdown-preview\">{{{ html }}}</main>\
+             {{> scripts }}",
+        )?;
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.set_template(&template)?;
+        server.send(String::from("*Hello*"))?;
+
+        let mut stream = TcpStream::connect(server.addr().unwrap())?;
+        write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.contains(
+            "<main id=\"markdown-preview\"><p data-source-line=\"1\"><em>Hello</em></p>\n</main>"
+        ));
+        assert!(response.contains("<script src=\"/__/js/markdown_client.js\"></script>"));
+
+        fs::write(&template, "{{#if}}")?;
+        assert!(server.set_template(&template).is_err());
+
+        fs::remove_file(template)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn typography() {
+        let mut typography = Typography::default();
+        typography
+            .set("font_size", Some(String::from("18")))
+            .unwrap();
+        typography
+            .set("max_width", Some(String::from("60em")))
+            .unwrap();
+        typography
+            .set("code_font_family", Some(String::from("\"Fira Code\"")))
+            .unwrap();
+        assert!(typography
+            .set("font_family", Some(String::from("a; } body { color: red")))
+            .is_err());
+        assert!(typography.set("color", Some(String::from("red"))).is_err());
+
+        assert_eq!(
+            typography.to_css().as_deref(),
+            Some(
+                ".markdown-body { font-size: 18px;max-width: 60em; }\n\
+                 .markdown-body code, .markdown-body kbd, .markdown-body pre { font-family: \"Fira Code\"; }\n"
+            )
+        );
+
+        typography.set("font_size", None).unwrap();
+        typography.set("max_width", None).unwrap();
+        typography.set("code_font_family", None).unwrap();
+        assert_eq!(typography.to_css(), None);
+    }
+
+    #[test]
+    fn offline() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.set_offline(true);
+        server.set_presentation(true)?;
+        server.send(String::from("$x$"))?;
+        assert!(server.render_options.mathml);
+
+        let mut stream = TcpStream::connect(server.addr().unwrap())?;
+        write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.contains("<math"));
+        assert!(!response.contains("https://"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn content_security_policy() -> Result<(), Box<dyn Error>> {
+        fn csp(server: &Server) -> Result<Option<String>, Box<dyn Error>> {
+            let mut stream = TcpStream::connect(server.addr().unwrap())?;
+            write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response
+                .lines()
+                .find_map(|line| line.strip_prefix("Content-Security-Policy: "))
+                .map(String::from))
+        }
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        assert_eq!(csp(&server)?, None);
+
+        server.set_offline(true);
+        let offline = csp(&server)?.unwrap();
+        assert!(offline.contains("script-src 'self' 'nonce-"));
+        assert!(offline.contains("img-src 'self' data:;"));
+        assert!(!offline.contains("https://"));
+
+        server.set_offline(false);
+        server.set_content_security_policy(Some(ContentSecurityPolicy::Strict));
+        assert!(server
+            .set_csp_sources(vec![String::from("https://a.com; x")], vec![])
+            .is_err());
+        server.set_csp_sources(vec![String::from("https://example.com")], vec![])?;
+        let strict = csp(&server)?.unwrap();
+        assert!(strict.contains("https://cdnjs.cloudflare.com https://example.com;"));
+        assert!(strict.contains("img-src * data: blob:;"));
+
+        server.set_content_security_policy(Some("default-src 'none'".parse()?));
+        assert_eq!(csp(&server)?.as_deref(), Some("default-src 'none'"));
+
+        server.set_offline(true);
+        server.set_content_security_policy(Some(ContentSecurityPolicy::Off));
+        assert_eq!(csp(&server)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn front_matter_overrides_page_settings() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        let mut websocket = connect(&server)?;
+
+        server.send(String::from(
+            "---\ntitle: Overridden\nmath: false\nhighlight_theme: nord\n---\n$x$",
+        ))?;
+        read_json(&mut websocket, "reload")?;
+        assert_eq!(
+            blocks(&read_json(&mut websocket, "html")?).trim(),
+            "<p data-source-line=\"6\">$x$</p>"
+        );
+        assert_eq!(read_json(&mut websocket, "title")?["title"], "Overridden");
+        assert_eq!(server.config.lock().unwrap().page.highlight_theme, "nord");
+
+        let mut layout = PdfLayout::default();
+        layout.set("margin", Some(String::from("1cm")))?;
+        layout.set("footer", Some(String::from("{title}")))?;
+        server.set_pdf_layout(layout);
+        read_json(&mut websocket, "reload")?;
+        server.send(String::from(
+            "---\ntitle: Overridden\npdf:\n  margin: 20\n---\n",
+        ))?;
+        read_json(&mut websocket, "reload")?;
+        let mut stream = TcpStream::connect(server.addr().unwrap())?;
+        write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response
+            .contains("@page { margin: 20mm; @bottom-center { content: \"Overridden\"; } }"));
+
+        server.send(String::from("$x$"))?;
+        read_json(&mut websocket, "reload")?;
+        assert_eq!(server.config.lock().unwrap().page.highlight_theme, "github");
+        assert!(server.render_options.math);
+
+        Ok(())
+    }
+
+    #[test]
+    fn preview_requests() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        let requests = server.take_requests().unwrap();
+        let mut websocket = connect(&server)?;
+
+        for path in ["/../secret.md", "/notes/Some Page.md"] {
+            websocket.send(Message::text(
+                json!({ "type": "open", "path": path }).to_string(),
+            ))?;
+        }
+
+        assert_eq!(
+            requests.recv()?,
+            PreviewRequest::Open {
+                path: ["notes", "Some Page.md"].iter().collect(),
+                buffer: None,
+            }
+        );
+
+        websocket.send(Message::text(
+            json!({ "type": "toggle_task", "line": 3 }).to_string(),
+        ))?;
+        assert_eq!(
+            requests.recv()?,
+            PreviewRequest::ToggleTask {
+                line: 3,
+                buffer: None
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn event_stream() -> Result<(), Box<dyn Error>> {
+        use std::io::BufReader;
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        let requests = server.take_requests().unwrap();
+
+        let mut stream = TcpStream::connect(server.addr().unwrap())?;
+        write!(stream, "GET /__/events HTTP/1.1\r\n\r\n")?;
+        let mut lines = BufReader::new(stream).lines();
+
+        let mut read_event = |kind: &str| -> Result<Value, Box<dyn Error>> {
+            loop {
+                let line = lines.next().ok_or("event stream ended")??;
+                if let Some(data) = line.strip_prefix("data: ") {
+                    let event: Value = serde_json::from_str(data)?;
+                    if event["type"] == kind {
+                        return Ok(event);
+                    }
+                }
+            }
+        };
+
+        assert_eq!(read_event("toc")?["enabled"], false);
+
+        server.send(String::from("# Hello"))?;
+        assert!(blocks(&read_event("html")?).contains(">Hello</h1>"));
+
+        // Pages post their messages instead.
+        let mut stream = TcpStream::connect(server.addr().unwrap())?;
+        let body = json!({ "type": "toggle_task", "line": 3 }).to_string();
+        write!(
+            stream,
+            "POST /__/events HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.starts_with("HTTP/1.1 204"));
+        assert_eq!(
+            requests.recv()?,
+            PreviewRequest::ToggleTask {
+                line: 3,
+                buffer: None
+            }
+        );
+
+        server.shutdown();
+        read_event("shutdown")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn health() -> Result<(), Box<dyn Error>> {
+        fn get_status(server: &Server) -> Result<Value, Box<dyn Error>> {
+            let mut stream = TcpStream::connect(server.addr().unwrap())?;
+            write!(stream, "GET /healthz HTTP/1.1\r\n\r\n")?;
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            assert!(response.starts_with("HTTP/1.1 200"));
+            let (_, body) = response.split_once("\r\n\r\n").unwrap();
+            Ok(serde_json::from_str(body)?)
+        }
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        let status = get_status(&server)?;
+        assert_eq!(status["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(status["clients"], 0);
+        assert_eq!(status["last_render"], Value::Null);
+        assert_eq!(status["settings"]["math"], "katex");
+
+        let mut websocket = connect(&server)?;
+        server.send(String::from("# Hello"))?;
+        read_json(&mut websocket, "html")?;
+
+        let status = get_status(&server)?;
+        assert_eq!(status["clients"], 1);
+        assert!(status["last_render"]["duration_ms"].is_number());
+        assert_eq!(
+            serde_json::to_value(server.status())?["last_render"]["timestamp"],
+            status["last_render"]["timestamp"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_timings() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.send(String::from("# Hello"))?;
+        let mut websocket = connect(&server)?;
+        read_json(&mut websocket, "html")?;
+
+        server.set_render_timings(true);
+        let timings = read_json(&mut websocket, "timings")?;
+        assert_eq!(timings["render"]["id"], 1);
+        assert!(timings["render"]["parse_ms"].is_number());
+        assert_eq!(timings["render"]["external_renderer_ms"], Value::Null);
+
+        // Renders that are sent to pages have how long sending them took.
+        server.send(String::from("# Hello, world"))?;
+        let timings = read_json(&mut websocket, "timings")?;
+        assert_eq!(timings["render"]["id"], 2);
+        assert!(timings["render"]["broadcast_ms"].is_number());
+
+        websocket.send(Message::text(
+            json!({ "type": "displayed", "render": 2, "ms": 12.5 }).to_string(),
+        ))?;
+        for _ in 0..100 {
+            let status = serde_json::to_value(server.status())?;
+            if status["last_render"]["highlight_ms"] == 12.5 {
+                assert!(status["last_render"]["broadcast_ms"].is_number());
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            serde_json::to_value(server.status())?["last_render"]["highlight_ms"],
+            12.5
+        );
+
+        // Renders of markdown that was rendered recently are reused.
+        server.send(String::from("# Hello"))?;
+        let timings = read_json(&mut websocket, "timings")?;
+        assert_eq!(timings["render"]["cached"], true);
+        assert_eq!(timings["render"]["parse_ms"], Value::Null);
+
+        server.set_render_timings(false);
+        assert_eq!(read_json(&mut websocket, "timings")?["render"], Value::Null);
+
+        Ok(())
+    }
+
+    #[test]
+    fn qr_code() -> Result<(), Box<dyn Error>> {
+        fn get(server: &Server, path: &str) -> Result<String, Box<dyn Error>> {
+            let mut stream = TcpStream::connect(server.addr().unwrap())?;
+            write!(stream, "GET {} HTTP/1.1\r\n\r\n", path)?;
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response)
+        }
+
+        let server = Server::bind(&["localhost:0"])?;
+        assert!(server.lan_urls().is_empty());
+        assert!(get(&server, "/api/qr")?.starts_with("HTTP/1.1 404"));
+
+        let lan_addr = SocketAddr::from(([192, 168, 1, 20], 8080));
+        server.config.lock().unwrap().lan_addrs = vec![lan_addr];
+        assert_eq!(server.lan_urls(), ["http://192.168.1.20:8080"]);
+
+        let response = get(&server, "/api/qr?path=%2Fbuffer%2F3%2F")?;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Content-Type: image/svg+xml\r\n"));
+        assert!(response.contains("<svg"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn push_markdown() -> Result<(), Box<dyn Error>> {
+        fn post(server: &Server, headers: &str, body: &str) -> Result<String, Box<dyn Error>> {
+            let mut stream = TcpStream::connect(server.addr().unwrap())?;
+            write!(
+                stream,
+                "POST /api/render HTTP/1.1\r\n{}Content-Length: {}\r\n\r\n{}",
+                headers,
+                body.len(),
+                body
+            )?;
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response)
+        }
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        let requests = server.take_requests().unwrap();
+
+        assert!(post(&server, "", "# Hello")?.starts_with("HTTP/1.1 202"));
+        assert_eq!(
+            requests.recv()?,
+            PreviewRequest::Render {
+                markdown: String::from("# Hello"),
+                path: None,
+            }
+        );
+
+        let json = json!({ "content": "# Hello", "path": "notes/hello.md" }).to_string();
+        let response = post(&server, "Content-Type: application/json\r\n", &json)?;
+        assert!(response.starts_with("HTTP/1.1 202"));
+        assert_eq!(
+            requests.recv()?,
+            PreviewRequest::Render {
+                markdown: String::from("# Hello"),
+                path: Some(["notes", "hello.md"].iter().collect()),
+            }
+        );
+
+        let response = post(&server, "Content-Type: application/json\r\n", "# Hello")?;
+        assert!(response.starts_with("HTTP/1.1 400"));
+
+        // Pages on other origins may not push markdown.
+        let response = post(&server, "Origin: https://example.com\r\n", "# Hello")?;
+        assert!(response.starts_with("HTTP/1.1 403"));
+        assert!(requests.try_recv().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn search() -> Result<(), Box<dyn Error>> {
+        fn get(server: &Server, query: &str) -> Result<Value, Box<dyn Error>> {
+            let mut stream = TcpStream::connect(server.addr().unwrap())?;
+            write!(stream, "GET /api/search?q={} HTTP/1.1\r\n\r\n", query)?;
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            assert!(response.starts_with("HTTP/1.1 200"));
+            let (_, body) = response.split_once("\r\n\r\n").unwrap();
+            Ok(serde_json::from_str(body)?)
+        }
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.set_title_source(Some(TitleSource::Heading));
+        assert_eq!(get(&server, "fish")?, json!([]));
+
+        server.send(String::from("# Main\n\nOne fish, two fish"))?;
+        server.send_buffer("1", String::from("# Other\n\nRed\n\nFish"))?;
+        assert_eq!(
+            get(&server, "Red+FISH")?,
+            json!([]),
+            "matches don't span blocks"
+        );
+        assert_eq!(
+            get(&server, "two%20fish")?,
+            json!([{
+                "path": "/",
+                "title": "Main",
+                "matches": [{ "line": 3, "snippet": "One fish, two fish" }],
+            }])
+        );
+
+        let results = get(&server, "fish")?;
+        assert_eq!(results[0]["matches"].as_array().unwrap().len(), 2);
+        assert_eq!(results[1]["path"], "/buffer/1/");
+        assert_eq!(results[1]["matches"][0]["line"], 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn toggle_toc() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        let mut websocket = connect(&server)?;
+        assert_eq!(read_json(&mut websocket, "toc")?["enabled"], false);
+
+        server.set_toc(true);
+        assert!(server.toc());
+        assert_eq!(read_json(&mut websocket, "toc")?["enabled"], true);
+
+        Ok(())
+    }
+
+    #[test]
+    fn toolbar_settings() -> Result<(), Box<dyn Error>> {
+        fn request(server: &Server, request: &str) -> Result<String, Box<dyn Error>> {
+            let mut stream = TcpStream::connect(server.addr().unwrap())?;
+            write!(stream, "{}", request)?;
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response)
+        }
+
+        fn settings(server: &Server) -> Result<Value, Box<dyn Error>> {
+            let response = request(server, "GET /__/settings HTTP/1.1\r\n\r\n")?;
+            let (_, body) = response.split_once("\r\n\r\n").unwrap();
+            Ok(serde_json::from_str(body)?)
+        }
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.set_color_scheme(ColorScheme::Light);
+        let mut websocket = connect(&server)?;
+
+        assert_eq!(
+            settings(&server)?,
+            json!({
+                "color_scheme": "light",
+                "font_size": null,
+                "highlight_theme": null,
+                "toc": false,
+            })
+        );
+
+        let body =
+            r#"{"color_scheme":"dark","font_size":"18","highlight_theme":"nord","toc":true}"#;
+        let response = request(
+            &server,
+            &format!(
+                "POST /__/settings HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            ),
+        )?;
+        assert!(response.starts_with("HTTP/1.1 204"));
+        read_json(&mut websocket, "reload")?;
+        assert_eq!(read_json(&mut websocket, "toc")?["enabled"], true);
+
+        assert_eq!(
+            settings(&server)?,
+            json!({
+                "color_scheme": "dark",
+                "font_size": "18px",
+                "highlight_theme": "nord",
+                "toc": true,
+            })
+        );
+
+        // Toolbar choices take precedence over the editor's settings.
+        server.set_color_scheme(ColorScheme::Auto);
+        let index = request(&server, "GET / HTTP/1.1\r\n\r\n")?;
+        assert!(index.contains("nord.min.css\" rel=\"stylesheet\" media=\"all\""));
+        assert!(index.contains("font-size: 18px;"));
+
+        let response = request(
+            &server,
+            "POST /__/settings HTTP/1.1\r\nContent-Length: 21\r\n\r\n{\"font_size\":\"1;x{}\"}",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 400"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn highlight_theme_gallery() -> Result<(), Box<dyn Error>> {
+        let server = Server::bind(&["localhost:0"])?;
+
+        let mut stream = TcpStream::connect(server.addr().unwrap())?;
+        write!(stream, "GET /__/highlight-themes HTTP/1.1\r\n\r\n")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        let themes: Vec<String> = serde_json::from_str(body)?;
+        assert!(themes.iter().any(|theme| theme == "github"));
+        assert!(themes.iter().any(|theme| theme == "nord"));
+
+        let mut stream = TcpStream::connect(server.addr().unwrap())?;
+        write!(stream, "GET /?highlight_theme=nord HTTP/1.1\r\n\r\n")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.contains("/styles/nord.min.css"));
+        assert!(!response.contains("/styles/github.min.css"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn auth_token() -> Result<(), Box<dyn Error>> {
+        use tungstenite::client::IntoClientRequest;
+
+        fn get(server: &Server, path: &str, cookie: &str) -> Result<String, Box<dyn Error>> {
+            let mut stream = TcpStream::connect(server.addr().unwrap())?;
+            write!(
+                stream,
+                "GET {} HTTP/1.1\r\nCookie: {}\r\n\r\n",
+                path, cookie
+            )?;
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response)
+        }
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        assert!(server.set_auth_token(Some(String::from("a;b"))).is_err());
+        server.set_auth_token(Some(String::from("secret")))?;
+        assert_eq!(
+            server.url(),
+            format!("http://{}/?token=secret", server.addr().unwrap())
+        );
+
+        assert!(get(&server, "/", "")?.starts_with("HTTP/1.1 403"));
+        assert!(get(&server, "/?token=guess", "")?.starts_with("HTTP/1.1 403"));
+        assert!(connect(&server).is_err());
+
+        let cookie = format!("composer_token_{}=secret", server.addr().unwrap().port());
+        let page = get(&server, "/?token=secret", "")?;
+        assert!(page.starts_with("HTTP/1.1 200"));
+        assert!(page.contains(&format!("Set-Cookie: {};", cookie)));
+        assert!(get(&server, "/__/css/styles.css", &cookie)?.starts_with("HTTP/1.1 200"));
+
+        let mut request = format!("ws://{}", server.addr().unwrap()).into_client_request()?;
+        request.headers_mut().insert("Cookie", cookie.parse()?);
+        let stream = TcpStream::connect(server.addr().unwrap())?;
+        let (mut websocket, _) = tungstenite::client(request, stream)?;
+        read_json(&mut websocket, "title")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn allowed_hosts() -> Result<(), Box<dyn Error>> {
+        fn status(server: &Server, host: &str) -> Result<String, Box<dyn Error>> {
+            let mut stream = TcpStream::connect(server.addr().unwrap())?;
+            write!(
+                stream,
+                "GET /__/settings?token=secret HTTP/1.1\r\nHost: {}\r\n\r\n",
+                host
+            )?;
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response.lines().next().unwrap_or_default().to_owned())
+        }
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        let port = server.addr().unwrap().port();
+
+        // A page on another site that points its domain name at this computer.
+        for host in [
+            "attacker.example",
+            "attacker.example:80",
+            "localhost.attacker.example",
+        ] {
+            assert_eq!(status(&server, host)?, "HTTP/1.1 403 Forbidden", "{}", host);
+        }
+
+        for host in [
+            format!("localhost:{}", port),
+            format!("LOCALHOST.:{}", port),
+            format!("127.0.0.1:{}", port),
+            format!("[::1]:{}", port),
+        ] {
+            assert_eq!(status(&server, &host)?, "HTTP/1.1 200 OK", "{}", host);
+        }
+
+        server.set_allowed_hosts(vec![String::from("preview.local")]);
+        assert_eq!(status(&server, "preview.local:8080")?, "HTTP/1.1 200 OK");
+        assert_eq!(
+            status(&server, "attacker.example")?,
+            "HTTP/1.1 403 Forbidden"
+        );
+
+        // Servers that require a token are protected by it instead.
+        server.set_auth_token(Some(String::from("secret")))?;
+        assert_eq!(status(&server, "preview.example:8080")?, "HTTP/1.1 200 OK");
+
+        Ok(())
+    }
+
+    #[test]
+    fn allowed_origins() -> Result<(), Box<dyn Error>> {
+        use tungstenite::client::IntoClientRequest;
+
+        fn request(server: &Server, request: &str) -> Result<String, Box<dyn Error>> {
+            let mut stream = TcpStream::connect(server.addr().unwrap())?;
+            write!(stream, "{}", request)?;
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response)
+        }
+
+        fn connect_from(server: &Server, origin: &str) -> Result<(), Box<dyn Error>> {
+            let mut request = format!("ws://{}", server.addr().unwrap()).into_client_request()?;
+            request.headers_mut().insert("Origin", origin.parse()?);
+            tungstenite::client(request, TcpStream::connect(server.addr().unwrap())?)?;
+            Ok(())
+        }
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        let same_origin = format!("http://{}", server.addr().unwrap());
+
+        let response = request(
+            &server,
+            "GET /__/settings HTTP/1.1\r\nHost: localhost\r\nOrigin: https://example.com\r\n\r\n",
+        )?;
+        assert!(!response.contains("Access-Control-Allow-Origin"));
+        assert!(connect_from(&server, "https://example.com").is_err());
+        assert!(connect_from(&server, &same_origin).is_ok());
+
+        server.set_allowed_origins(vec![String::from("https://example.com/")]);
+
+        let response = request(
+            &server,
+            "GET /__/settings HTTP/1.1\r\nHost: localhost\r\nOrigin: https://example.com\r\n\r\n",
+        )?;
+        assert!(response.contains("Access-Control-Allow-Origin: https://example.com\r\n"));
+        assert!(response.contains("Access-Control-Allow-Credentials: true\r\n"));
+
+        let response = request(
+            &server,
+            "OPTIONS /__/settings HTTP/1.1\r\nHost: localhost\r\nOrigin: https://example.com\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 204"));
+        assert!(response.contains("Access-Control-Allow-Methods: GET, POST\r\n"));
+
+        assert!(connect_from(&server, "https://example.com").is_ok());
+        assert!(connect_from(&server, "https://example.org").is_err());
+
+        // Only origins that are listed may read responses with the user's cookies.
+        server.set_allowed_origins(vec![String::from("*"), String::from("https://example.com")]);
+
+        let response = request(
+            &server,
+            "GET /__/settings HTTP/1.1\r\nHost: localhost\r\nOrigin: https://example.org\r\n\r\n",
+        )?;
+        assert!(response.contains("Access-Control-Allow-Origin: *\r\n"));
+        assert!(!response.contains("Access-Control-Allow-Credentials"));
+        assert!(!response.contains("https://example.org"));
+
+        let response = request(
+            &server,
+            "GET /__/settings HTTP/1.1\r\nHost: localhost\r\nOrigin: https://example.com\r\n\r\n",
+        )?;
+        assert!(response.contains("Access-Control-Allow-Origin: https://example.com\r\n"));
+        assert!(response.contains("Access-Control-Allow-Credentials: true\r\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn gzip_responses() -> Result<(), Box<dyn Error>> {
+        use flate2::read::GzDecoder;
+
+        fn get(server: &Server, path: &str, accept: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+            let mut stream = TcpStream::connect(server.addr().unwrap())?;
+            write!(
+                stream,
+                "GET {} HTTP/1.1\r\nAccept-Encoding: {}\r\n\r\n",
+                path, accept
+            )?;
+
+            let mut response = vec![];
+            stream.read_to_end(&mut response)?;
+            Ok(response)
+        }
+
+        fn split(response: &[u8]) -> (String, &[u8]) {
+            let end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+            (
+                String::from_utf8_lossy(&response[..end]).into_owned(),
+                &response[end + 4..],
+            )
+        }
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.send("Hello, world!\n\n".repeat(100))?;
+
+        let response = get(&server, "/", "br, gzip;q=0.8")?;
+        let (headers, body) = split(&response);
+        assert!(headers.contains("Content-Encoding: gzip"));
+
+        let mut html = String::new();
+        GzDecoder::new(body).read_to_string(&mut html)?;
+        assert!(html.contains("<p data-source-line=\"99\">Hello, world!</p>"));
+
+        let response = get(&server, "/", "gzip;q=0")?;
+        let (headers, body) = split(&response);
+        assert!(!headers.contains("Content-Encoding"));
+        assert!(String::from_utf8_lossy(body).contains("Hello, world!"));
+
+        let response = get(&server, "/__/img/favicon.svg", "gzip")?;
+        let (headers, _) = split(&response);
+        assert!(!headers.contains("Content-Encoding"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn stalled_pages() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.set_side_by_side(true);
+
+        // The HTML doesn't fit in the socket buffers, so writing it to a page that doesn't read
+        // its updates blocks.
+        server.send("word ".repeat(2 * 1024 * 1024))?;
+        let _page = connect(&server)?;
+        thread::sleep(Duration::from_secs(1));
+
+        let start = Instant::now();
+        server.send(String::from("# Title"))?;
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // Reference definitions aren't rendered, so only the markdown beside the preview blocks.
+        server.send("[link]: /\n".repeat(1024 * 1024))?;
+        let _page = connect(&server)?;
+        thread::sleep(Duration::from_secs(1));
+
+        let start = Instant::now();
+        server.set_zoom(150);
+        server.send(String::from("# Title"))?;
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        Ok(())
+    }
+
+    #[test]
+    fn gzip_updates() -> Result<(), Box<dyn Error>> {
+        use flate2::read::GzDecoder;
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.send("Hello, world!\n\n".repeat(100))?;
+
+        let url = format!("ws://{}/?compress=gzip", server.addr().unwrap());
+        let stream = TcpStream::connect(server.addr().unwrap())?;
+        let (mut websocket, _) = tungstenite::client(url, stream)?;
+
+        // Large updates are gzipped, and the rest are sent as text.
+        let message = websocket.read()?;
+        assert!(message.is_binary());
+        let mut json = String::new();
+        GzDecoder::new(&message.into_data()[..]).read_to_string(&mut json)?;
+        let message: Value = serde_json::from_str(&json)?;
+        assert_eq!(message["type"], "html");
+        assert!(blocks(&message).contains("<p data-source-line=\"99\">Hello, world!</p>"));
+
+        server.set_title(Some(String::from("Greeting")));
+        read_json(&mut websocket, "title")?;
+
+        // Pages that don't ask for gzip are sent text.
+        let mut websocket = connect(&server)?;
+        assert!(websocket.read()?.is_text());
+
+        Ok(())
+    }
+
+    #[test]
+    fn tls() -> Result<(), Box<dyn Error>> {
+        use std::convert::TryFrom;
+        use std::sync::Arc;
+
+        use rustls::crypto::ring;
+        use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+        use rustls_pki_types::pem::PemObject;
+        use rustls_pki_types::{CertificateDer, ServerName};
+
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/tls");
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.set_tls(fixtures.join("cert.pem"), fixtures.join("key.pem"))?;
+        server.send(String::from("*Hello*"))?;
+        assert_eq!(server.url(), format!("https://{}", server.addr().unwrap()));
+
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file(fixtures.join("cert.pem"))?)?;
+        let config = Arc::new(
+            ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                .with_safe_default_protocol_versions()?
+                .with_root_certificates(roots)
+                .with_no_client_auth(),
+        );
+        let connect = || -> Result<_, Box<dyn Error>> {
+            let conn =
+                ClientConnection::new(Arc::clone(&config), ServerName::try_from("localhost")?)?;
+            Ok(StreamOwned::new(
+                conn,
+                TcpStream::connect(server.addr().unwrap())?,
+            ))
+        };
+
+        let mut stream = connect()?;
+        write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.contains("<em>Hello</em>"));
+
+        let url = format!("wss://localhost:{}", server.addr().unwrap().port());
+        let (mut websocket, _) = tungstenite::client(url, connect()?)?;
+        assert!(blocks(&read_json(&mut websocket, "html")?).contains("<em>Hello</em>"));
+
+        server.send(String::from("*Goodbye*"))?;
+        assert!(blocks(&read_json(&mut websocket, "patch")?).contains("<em>Goodbye</em>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn export_download() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.set_title(Some(String::from("My notes: draft")));
+        server.send(String::from("*Hello*"))?;
+
+        let mut stream = TcpStream::connect(server.addr().unwrap())?;
+        write!(stream, "GET /__/export HTTP/1.1\r\n\r\n")?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(
+            response.contains("Content-Disposition: attachment; filename=\"My notes_ draft.html\"")
+        );
+        assert!(response.contains("<em>Hello</em>"));
+        assert!(!response.contains("/__/"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn side_by_side() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.set_side_by_side(true);
+        assert!(server.side_by_side());
+
+        let mut stream = TcpStream::connect(server.addr().unwrap())?;
+        write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.contains("id=\"composer-source\""));
+
+        let mut websocket = connect(&server)?;
+        assert_eq!(read_json(&mut websocket, "source")?["markdown"], "");
+        server.send(String::from("# Title\n\nText"))?;
+        assert_eq!(
+            read_json(&mut websocket, "source")?["markdown"],
+            "# Title\n\nText"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn toggle_presentation() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.send(String::from("One\n\n---\n\nTwo"))?;
+        server.set_presentation(true)?;
+        assert!(server.presentation());
+
+        let mut stream = TcpStream::connect(server.addr().unwrap())?;
+        write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.contains("reveal.js"));
+        assert!(response.contains("<section data-source-line=\"3\">"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn standalone_html() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.set_title(Some(String::from("Exported")));
+        server.send(String::from("*Hello*"))?;
+
+        let html = server.standalone_html();
+        assert!(html.contains("<title>Exported</title>"));
+        assert!(html.contains("<p data-source-line=\"1\"><em>Hello</em></p>"));
+        assert!(html.contains(".markdown-body"));
+        assert!(!html.contains("/__/"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn filetype_renderers() -> Result<(), Box<dyn Error>> {
+        let mut upper = Command::new("tr");
+        upper.args(["a-z", "A-Z"]);
+
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.set_external_renderer(ExternalRenderer::new(Command::new("cat")));
+        server.set_filetype_renderer("RST", Some(ExternalRenderer::new(upper)));
+        server.set_filetype_renderer("md", None);
+
+        server.send_as("rst", String::from("*hello*"))?;
+        assert!(server.standalone_html().contains("*HELLO*"));
+
+        // The filetype is kept until a file is sent.
+        server.send(String::from("*again*"))?;
+        assert!(server.standalone_html().contains("*AGAIN*"));
+
+        server.send_file("notes.md", String::from("*hello*"))?;
+        assert!(server.standalone_html().contains("<em>hello</em>"));
+
+        server.send_file("notes.txt", String::from("*hello*"))?;
+        assert!(server.standalone_html().contains("*hello*"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn title() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        let mut websocket = connect(&server)?;
+        let title = read_json(&mut websocket, "title")?;
+        assert_eq!(title["title"], "Markdown Composer");
+
+        server.set_title_source(Some(TitleSource::Heading));
+        server.send(String::from("# Heading"))?;
+        assert_eq!(read_json(&mut websocket, "title")?["title"], "Heading");
+
+        server.set_title(Some(String::from("Explicit")));
+        assert_eq!(read_json(&mut websocket, "title")?["title"], "Explicit");
+
+        Ok(())
+    }
+
+    #[test]
+    fn buffers() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        let requests = server.take_requests().unwrap();
+        server.set_title_source(Some(TitleSource::Heading));
+        server.send(String::from("# Main"))?;
+        server.send_buffer("2", String::from("# Buffer"))?;
+        assert!(server.send_buffer("../2", String::new()).is_err());
+
+        let url = server.buffer_url("2");
+        assert_eq!(url, format!("http://{}/buffer/2/", server.addr().unwrap()));
+
+        let stream = TcpStream::connect(server.addr().unwrap())?;
+        let (mut websocket, _) = tungstenite::client(url.replacen("http", "ws", 1), stream)?;
+        let html = read_json(&mut websocket, "html")?;
+        assert!(blocks(&html).contains("Buffer"));
+        assert_eq!(read_json(&mut websocket, "title")?["title"], "Buffer");
+
+        websocket.send(Message::text(
+            json!({ "type": "toggle_task", "line": 3 }).to_string(),
+        ))?;
+        assert_eq!(
+            requests.recv()?,
+            PreviewRequest::ToggleTask {
+                line: 3,
+                buffer: Some(String::from("2"))
+            }
+        );
+
+        // Updates to the main document aren't sent to the preview of the buffer.
+        server.send(String::from("# Main again"))?;
+        server.scroll_buffer_to_line("2", 4);
+        loop {
+            let message: Value = serde_json::from_str(websocket.read()?.to_text()?)?;
+            assert_ne!(message["type"], "html");
+            assert_ne!(message["type"], "patch");
+            if message["type"] == "scroll" {
+                assert_eq!(message["line"], 4);
+                break;
+            }
+        }
+
+        server.close_buffer("2");
+        read_json(&mut websocket, "shutdown")?;
+
+        let mut stream = TcpStream::connect(server.addr().unwrap())?;
+        write!(stream, "GET /buffer/2/ HTTP/1.1\r\n\r\n")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn tabs() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.set_title_source(Some(TitleSource::Heading));
+        let mut websocket = connect(&server)?;
+        assert_eq!(read_json(&mut websocket, "tabs")?["tabs"], json!([]));
+
+        server.send_buffer("1", String::from("# One"))?;
+        assert_eq!(
+            read_json(&mut websocket, "tabs")?["tabs"],
+            json!([{ "path": "/buffer/1/", "title": "One" }])
+        );
+
+        let mut stream = TcpStream::connect(server.addr().unwrap())?;
+        write!(stream, "GET /buffer/ HTTP/1.1\r\n\r\n")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.contains("<li><a href=\"/buffer/1/\">One</a></li>"));
+
+        server.send(String::from("# Main"))?;
+        server.send_buffer("2", String::from("# Two"))?;
+        // Both changes may be sent in one message, if they're coalesced.
+        let mut tabs = read_json(&mut websocket, "tabs")?;
+        if tabs["tabs"].as_array().unwrap().len() < 3 {
+            tabs = read_json(&mut websocket, "tabs")?;
+        }
+        assert_eq!(
+            tabs["tabs"],
+            json!([
+                { "path": "/", "title": "Main" },
+                { "path": "/buffer/1/", "title": "One" },
+                { "path": "/buffer/2/", "title": "Two" },
+            ])
+        );
+
+        server.close_buffer("1");
+        assert_eq!(
+            read_json(&mut websocket, "tabs")?["tabs"]
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn auto_scroll() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        let mut websocket = connect(&server)?;
+        assert_eq!(
+            read_json(&mut websocket, "auto_scroll")?,
+            json!({ "type": "auto_scroll", "enabled": true })
+        );
+
+        server.scroll_to_line(42);
+        assert_eq!(
+            read_json(&mut websocket, "scroll")?,
+            json!({ "type": "scroll", "line": 42 })
+        );
+
+        server.set_auto_scroll(false);
+        assert_eq!(
+            read_json(&mut websocket, "auto_scroll")?,
+            json!({ "type": "auto_scroll", "enabled": false })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn focus() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        let mut websocket = connect(&server)?;
+        assert_eq!(read_json(&mut websocket, "focus")?["enabled"], false);
+
+        server.set_focus(true);
+        assert!(server.focus());
+        assert_eq!(read_json(&mut websocket, "focus")?["enabled"], true);
+        assert!(server.status().settings.focus);
+
+        Ok(())
+    }
+
+    #[test]
+    fn zoom() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        let mut websocket = connect(&server)?;
+        assert_eq!(read_json(&mut websocket, "zoom")?["percent"], 100);
+
+        server.set_zoom(150);
+        assert_eq!(read_json(&mut websocket, "zoom")?["percent"], 150);
+        server.set_zoom(1000);
+        assert_eq!(server.zoom(), 500);
+
+        Ok(())
+    }
+
+    #[test]
+    fn shutdown_notifies_clients() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        server.set_close_on_shutdown(true);
+        let mut websocket = connect(&server)?;
+
+        server.shutdown();
+
+        assert_eq!(
+            read_json(&mut websocket, "shutdown")?,
+            json!({ "type": "shutdown", "close": true })
+        );
+        assert!(matches!(websocket.read()?, Message::Close(_)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn open_specific_browser() -> Result<(), Box<dyn Error>> {
+        let server = Server::bind(&["localhost:0"])?;
+        let output = env::temp_dir().join(format!("composer-open-{}", process::id()));
+
+        // Returns the URL that a script that writes `$0` to the output file was opened with.
+        let open = |args: &[&str], buffer, anchor| -> Result<Option<String>, Box<dyn Error>> {
+            let mut command = Command::new("sh");
+            command
+                .arg("-c")
+                .arg(format!(
+                    "printf %s \"$0\" > {}.tmp && mv {0}.tmp {0}",
+                    output.display()
+                ))
+                .args(args);
+            server.open_specific_browser(command, buffer, anchor)?;
+
+            for _ in 0..100 {
+                if let Ok(url) = fs::read_to_string(&output) {
+                    fs::remove_file(&output)?;
+                    return Ok(Some(url));
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+            Ok(None)
+        };
+
+        assert_eq!(
+            open(&[], Some("notes"), Some("#caf\u{e9} au lait"))?,
+            Some(format!(
+                "{}#caf%C3%A9%20au%20lait",
+                server.buffer_url("notes")
+            ))
+        );
+
+        // The URL replaces `{url}` rather than being appended, so it's `$0` either way.
+        assert_eq!(
+            open(&["{url}", "--new-window"], None, None)?,
+            Some(server.url())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn raise_preview() -> Result<(), Box<dyn Error>> {
+        let server = Server::bind(&["localhost:0"])?;
+        assert!(!server.raise_preview(None, None));
+
+        let mut websocket = connect(&server)?;
+        read_json(&mut websocket, "title")?;
+        assert!(server.raise_preview(None, Some("#usage")));
+        assert_eq!(
+            read_json(&mut websocket, "raise")?,
+            json!({ "type": "raise", "anchor": "usage" })
+        );
+        assert!(!server.raise_preview(Some("1"), None));
+
+        Ok(())
+    }
+
+    #[test]
+    fn print_preview() -> Result<(), Box<dyn Error>> {
+        let server = Server::bind(&["localhost:0"])?;
+        assert!(!server.print_preview(None));
+
+        let mut websocket = connect(&server)?;
+        read_json(&mut websocket, "title")?;
+        assert!(server.print_preview(None));
+        read_json(&mut websocket, "print")?;
+        assert!(!server.print_preview(Some("1")));
+
+        let mut stream = TcpStream::connect(server.addr().unwrap())?;
+        write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.contains("href=\"/__/css/print.css\""));
+
+        Ok(())
+    }
+
+    #[test]
+    fn close_browser_closes_clients() -> Result<(), Box<dyn Error>> {
+        let mut server = Server::bind(&["localhost:0"])?;
+        let mut websocket = connect(&server)?;
+
+        server.close_browser();
+
+        assert_eq!(
+            read_json(&mut websocket, "close_browser")?,
+            json!({ "type": "close_browser" })
+        );
+        assert!(matches!(websocket.read()?, Message::Close(_)));
+
+        // The server should still accept new previews.
+        let mut websocket = connect(&server)?;
+        server.send(String::from("Still here"))?;
+        read_json(&mut websocket, "html")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn multiple_addresses() -> Result<(), Box<dyn Error>> {
+        // Skip the IPv6 address on machines that don't support it.
+        let addrs = if TcpListener::bind("[::1]:0").is_ok() {
+            vec!["127.0.0.1:0", "[::1]:0"]
+        } else {
+            vec!["127.0.0.1:0"]
+        };
+
+        let server = Server::bind(&addrs)?;
+        assert_eq!(server.addrs().len(), addrs.len());
+        assert_eq!(server.addr().unwrap(), server.addrs()[0]);
+        assert!(server
+            .addrs()
+            .iter()
+            .all(|addr| addr.port() == server.addr().unwrap().port()));
+        assert_eq!(server.url(), server.urls()[0]);
+
+        for &addr in server.addrs() {
+            let mut stream = TcpStream::connect(addr)?;
+            write!(stream, "GET / HTTP/1.1\r\n\r\n")?;
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            assert!(response.starts_with("HTTP/1.1 200"));
+        }
+
+        assert!(Server::bind::<&str>(&[]).is_err());
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket() -> Result<(), Box<dyn Error>> {
+        use std::os::unix::fs::PermissionsExt;
+        use std::os::unix::net::UnixStream;
+
+        let path = env::temp_dir().join(format!("composer-{}.sock", process::id()));
+
+        let server = Server::bind_unix(&path)?;
+        assert_eq!(server.addr(), None);
+        assert!(server.url().starts_with("http+unix://%2F"));
+        assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+
+        let mut stream = UnixStream::connect(&path)?;
+        write!(stream, "GET / HTTP/1.1\r\n\r\n")?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.starts_with("HTTP/1.1 200"));
+
+        drop(server);
+        assert!(!path.exists());
+
+        Ok(())
+    }
+}
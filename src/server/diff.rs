@@ -0,0 +1,272 @@
+//! Diffs between renders of a document, so that pages only replace the parts of the preview that
+//! changed.
+//!
+//! Rendered HTML is split into top-level blocks. Pages keep track of the nodes created from each
+//! block, and a patch replaces a run of blocks with new ones.
+
+use std::ops::Range;
+
+use crate::render::LINE_ATTRIBUTES;
+
+/// Elements that have no closing tag.
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Elements whose contents aren't parsed as HTML.
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style", "textarea"];
+
+/// The changes that turn the blocks of one render into the blocks of another.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Patch {
+    /// The index of the first block that's replaced.
+    pub start: usize,
+
+    /// The number of old blocks that are replaced.
+    pub delete: usize,
+
+    /// The new blocks that replace them.
+    pub insert: Range<usize>,
+
+    /// How far the lines of the blocks after the replaced ones moved.
+    pub line_offset: i64,
+}
+
+impl Patch {
+    /// Whether the patch leaves the blocks unchanged.
+    pub fn is_empty(&self) -> bool {
+        self.delete == 0 && self.insert.is_empty() && self.line_offset == 0
+    }
+}
+
+/// Splits HTML into its top-level elements. Whitespace and text between elements belongs to the
+/// surrounding blocks, so joining the blocks gives back the HTML.
+///
+/// HTML that isn't well-formed may end up in fewer, larger blocks.
+pub fn split_blocks(html: &str) -> Vec<&str> {
+    let mut blocks = vec![];
+    let mut start = 0;
+    let mut depth = 0usize;
+    let mut i = 0;
+
+    while let Some(offset) = html[i..].find('<') {
+        let tag = i + offset;
+        let rest = &html[tag..];
+
+        i = if rest.starts_with("<!--") {
+            rest.find("-->").map_or(html.len(), |end| tag + end + 3)
+        } else if rest.starts_with("</") {
+            depth = depth.saturating_sub(1);
+            tag_end(html, tag)
+        } else if rest[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            let name = &rest[1..];
+            let name = &name[..name
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+                .unwrap_or(name.len())];
+            let end = tag_end(html, tag);
+
+            if RAW_TEXT_ELEMENTS
+                .iter()
+                .any(|e| e.eq_ignore_ascii_case(name))
+            {
+                let close = format!("</{}", name.to_ascii_lowercase());
+                match html[end..].to_ascii_lowercase().find(&close) {
+                    Some(close) => tag_end(html, end + close),
+                    None => html.len(),
+                }
+            } else {
+                if !VOID_ELEMENTS.iter().any(|e| e.eq_ignore_ascii_case(name))
+                    && !html[..end].ends_with("/>")
+                {
+                    depth += 1;
+                }
+                end
+            }
+        } else {
+            // Doctypes and processing instructions don't affect nesting, and neither does a
+            // stray `<`.
+            if rest.starts_with("<!") || rest.starts_with("<?") {
+                tag_end(html, tag)
+            } else {
+                tag + 1
+            }
+        };
+
+        if depth == 0 {
+            i = html.len() - html[i..].trim_start().len();
+            blocks.push(&html[start..i]);
+            start = i;
+        }
+    }
+
+    if start < html.len() {
+        blocks.push(&html[start..]);
+    }
+
+    blocks
+}
+
+/// Returns the index just past the `>` that ends the tag starting at an index.
+fn tag_end(html: &str, start: usize) -> usize {
+    let mut quote = None;
+
+    for (i, c) in html[start..].char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '>') => return start + i + 1,
+            _ => (),
+        }
+    }
+
+    html.len()
+}
+
+/// Finds the run of blocks that changed between two renders.
+///
+/// Blocks after the change are kept if the only difference is that their lines moved by the same
+/// amount, which happens whenever lines are added or removed.
+pub fn diff(old: &[impl AsRef<str>], new: &[&str]) -> Patch {
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(old, new)| old.as_ref() == **new)
+        .count();
+
+    let mut suffix = 0;
+    let mut line_offset = None;
+    for (old, new) in old[prefix..].iter().rev().zip(new[prefix..].iter().rev()) {
+        match moved_lines(old.as_ref(), new) {
+            Some(offsets) if offsets.iter().all(|&o| o == *line_offset.get_or_insert(o)) => {
+                suffix += 1
+            }
+            _ => break,
+        }
+    }
+
+    Patch {
+        start: prefix,
+        delete: old.len() - prefix - suffix,
+        insert: prefix..new.len() - suffix,
+        line_offset: line_offset.unwrap_or(0),
+    }
+}
+
+/// If two blocks only differ in the lines of their line attributes, returns how far each line
+/// moved.
+fn moved_lines(old: &str, new: &str) -> Option<Vec<i64>> {
+    let (old_text, old_lines) = split_lines(old);
+    let (new_text, new_lines) = split_lines(new);
+
+    if old_text != new_text {
+        return None;
+    }
+
+    Some(
+        old_lines
+            .iter()
+            .zip(new_lines)
+            .map(|(old, new)| new - old)
+            .collect(),
+    )
+}
+
+/// Splits a block around the values of its line attributes.
+fn split_lines(mut block: &str) -> (Vec<&str>, Vec<i64>) {
+    let mut text = vec![];
+    let mut lines = vec![];
+
+    while let Some(start) = LINE_ATTRIBUTES
+        .iter()
+        .filter_map(|attribute| block.find(attribute).map(|i| i + attribute.len()))
+        .min()
+    {
+        let end = block[start..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(block.len(), |end| start + end);
+
+        text.push(&block[..start]);
+        lines.push(block[start..end].parse().unwrap_or(-1));
+        block = &block[end..];
+    }
+
+    text.push(block);
+    (text, lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, split_blocks, Patch};
+
+    #[test]
+    fn split() {
+        assert_eq!(
+            split_blocks("<h1>Title</h1>\n<p>One<br>two</p>\n<hr />\n<!-- note -->\n"),
+            vec![
+                "<h1>Title</h1>\n",
+                "<p>One<br>two</p>\n",
+                "<hr />\n",
+                "<!-- note -->\n"
+            ]
+        );
+        assert_eq!(
+            split_blocks("<details>\n<p>a > b</p>\n</details>\n<script>if (a<b) {}</script>"),
+            vec![
+                "<details>\n<p>a > b</p>\n</details>\n",
+                "<script>if (a<b) {}</script>"
+            ]
+        );
+        assert_eq!(
+            split_blocks("<div title=\"a>b\">\n"),
+            vec!["<div title=\"a>b\">\n"]
+        );
+        assert_eq!(split_blocks("text"), vec!["text"]);
+    }
+
+    #[test]
+    fn diff_blocks() {
+        let old = [
+            "<h1 data-source-line=\"1\">A</h1>\n",
+            "<p data-source-line=\"3\">B</p>\n",
+            "<p data-source-line=\"5\">C</p>\n",
+        ];
+
+        assert_eq!(
+            diff(
+                &old,
+                &[
+                    "<h1 data-source-line=\"1\">A</h1>\n",
+                    "<p data-source-line=\"3\">B!</p>\n",
+                    "<p data-source-line=\"5\">C</p>\n",
+                ]
+            ),
+            Patch {
+                start: 1,
+                delete: 1,
+                insert: 1..2,
+                line_offset: 0,
+            }
+        );
+
+        assert_eq!(
+            diff(
+                &old,
+                &[
+                    "<h1 data-source-line=\"1\">A</h1>\n",
+                    "<p data-source-line=\"3\">B</p>\n",
+                    "<p data-source-line=\"5\">New</p>\n",
+                    "<p data-source-line=\"7\">C</p>\n",
+                ]
+            ),
+            Patch {
+                start: 2,
+                delete: 0,
+                insert: 2..3,
+                line_offset: 2,
+            }
+        );
+
+        assert!(diff(&old, &old).is_empty());
+    }
+}
@@ -0,0 +1,103 @@
+//! The addresses that other devices on the network, such as a phone, can reach the server at.
+//!
+//! A server that is bound to an unspecified address, such as `0.0.0.0`, listens on every network
+//! interface, so its addresses are those of the interfaces.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+
+/// Returns the addresses of the network that the server is reachable at on the addresses it's
+/// bound to. Loopback addresses are only reachable from this computer, so they're left out.
+pub fn reachable_addrs(bound: &[SocketAddr]) -> Vec<SocketAddr> {
+    let mut addrs = vec![];
+    for addr in bound {
+        let ips = if addr.ip().is_unspecified() {
+            interface_ips()
+                .into_iter()
+                .filter(|ip| ip.is_ipv4() == addr.is_ipv4())
+                .collect()
+        } else {
+            vec![addr.ip()]
+        };
+
+        for ip in ips {
+            // IPv6 link-local addresses need the interface in URLs, which browsers don't support.
+            let link_local = match ip {
+                IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 == 0xfe80,
+                IpAddr::V4(_) => false,
+            };
+            let addr = SocketAddr::new(ip, addr.port());
+            if !ip.is_loopback() && !link_local && !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+    }
+    addrs
+}
+
+/// Returns the addresses of the network interfaces that are up.
+#[cfg(unix)]
+fn interface_ips() -> Vec<IpAddr> {
+    let mut interfaces = std::ptr::null_mut();
+    // SAFETY: The list of interfaces is only read until it's freed, and each address is read as
+    // the type of its family.
+    unsafe {
+        if libc::getifaddrs(&mut interfaces) != 0 {
+            return route_ips();
+        }
+
+        let mut ips = vec![];
+        let mut interface = interfaces;
+        while let Some(current) = interface.as_ref() {
+            let up = current.ifa_flags & libc::IFF_UP as libc::c_uint != 0;
+            if let (true, Some(addr)) = (up, current.ifa_addr.as_ref()) {
+                match i32::from(addr.sa_family) {
+                    libc::AF_INET => {
+                        let addr = &*(addr as *const libc::sockaddr).cast::<libc::sockaddr_in>();
+                        ips.push(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                            addr.sin_addr.s_addr,
+                        ))));
+                    }
+                    libc::AF_INET6 => {
+                        let addr = &*(addr as *const libc::sockaddr).cast::<libc::sockaddr_in6>();
+                        ips.push(IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr)));
+                    }
+                    _ => (),
+                }
+            }
+            interface = current.ifa_next;
+        }
+
+        libc::freeifaddrs(interfaces);
+        ips
+    }
+}
+
+#[cfg(not(unix))]
+fn interface_ips() -> Vec<IpAddr> {
+    route_ips()
+}
+
+/// Returns the addresses that connections to the internet would be made from, which are those of
+/// the interfaces of the default routes.
+///
+/// Connecting a UDP socket only chooses the route: no packets are sent, so the addresses of the
+/// documentation ranges are used.
+fn route_ips() -> Vec<IpAddr> {
+    let destinations = [
+        SocketAddr::new(Ipv4Addr::new(192, 0, 2, 1).into(), 9),
+        SocketAddr::new(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).into(), 9),
+    ];
+
+    destinations
+        .iter()
+        .filter_map(|destination| {
+            let local = match destination {
+                SocketAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+                SocketAddr::V6(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+            };
+            let socket = UdpSocket::bind(local).ok()?;
+            socket.connect(destination).ok()?;
+            Some(socket.local_addr().ok()?.ip())
+        })
+        .collect()
+}
@@ -0,0 +1,225 @@
+//! The queues of signals waiting to be sent to each client.
+//!
+//! A page in a background tab or on a slow link may not read updates as fast as the editor sends
+//! them. Most signals only tell the connection to send the latest state of the document, so a
+//! signal that is already queued isn't queued again. A client that falls behind is sent the
+//! current document once it catches up, instead of every version in between.
+
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex};
+
+use super::Signal;
+
+/// How many signals may wait for a client before it's considered unable to keep up. Coalescing
+/// keeps queues much shorter than this, unless the page is sent many events, such as printing.
+const CAPACITY: usize = 64;
+
+/// Creates a queue of signals for a client.
+pub fn channel() -> (SignalSender, SignalReceiver) {
+    let queue = Arc::new(Queue {
+        state: Mutex::new(State {
+            signals: VecDeque::new(),
+            closed: false,
+            overflowed: false,
+        }),
+        ready: Condvar::new(),
+    });
+
+    (SignalSender(Arc::clone(&queue)), SignalReceiver(queue))
+}
+
+#[derive(Debug)]
+struct Queue {
+    state: Mutex<State>,
+    /// Notified when a signal is queued or the sender is dropped.
+    ready: Condvar,
+}
+
+#[derive(Debug)]
+struct State {
+    signals: VecDeque<Signal>,
+    /// Whether the sender was dropped.
+    closed: bool,
+    /// Whether the queue overflowed, so the client was dropped because it couldn't keep up.
+    overflowed: bool,
+}
+
+/// The half of a queue that the server sends signals to. The receiver stops once it's dropped.
+#[derive(Debug)]
+pub struct SignalSender(Arc<Queue>);
+
+impl SignalSender {
+    /// Queues a signal, unless it's already queued.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the queue is full, in which case the client should be dropped.
+    pub fn send(&self, signal: Signal) -> Result<(), Overflow> {
+        let mut state = self.0.state.lock().unwrap();
+        if !coalesce(&mut state.signals, signal) {
+            return Ok(());
+        }
+
+        if state.signals.len() > CAPACITY {
+            state.signals.clear();
+            state.overflowed = true;
+            return Err(Overflow);
+        }
+
+        self.0.ready.notify_one();
+        Ok(())
+    }
+}
+
+impl Drop for SignalSender {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().closed = true;
+        self.0.ready.notify_one();
+    }
+}
+
+/// The half of a queue that a connection receives signals from.
+#[derive(Debug)]
+pub struct SignalReceiver(Arc<Queue>);
+
+impl SignalReceiver {
+    /// Waits for the next signal. Returns `None` once the sender is dropped and every signal was
+    /// received, or as soon as the queue overflows.
+    pub fn recv(&self) -> Option<Signal> {
+        let mut state = self.0.state.lock().unwrap();
+        loop {
+            if state.overflowed {
+                return None;
+            }
+            if let Some(signal) = state.signals.pop_front() {
+                return Some(signal);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.0.ready.wait(state).unwrap();
+        }
+    }
+
+    /// Returns whether the client was dropped because it couldn't keep up with its signals.
+    pub fn fell_behind(&self) -> bool {
+        self.0.state.lock().unwrap().overflowed
+    }
+}
+
+/// The error returned when a client's queue is full.
+#[derive(Debug)]
+pub struct Overflow;
+
+/// Adds a signal to the queue, or merges it into a queued signal of the same kind. Returns whether
+/// the queue grew.
+///
+/// A queued signal reads the state of the document when it's received, so replacing it with a
+/// newer one of the same kind, in place, sends the same state as receiving both would. Printing
+/// and raising the page are events rather than state, so each of them is queued.
+fn coalesce(signals: &mut VecDeque<Signal>, signal: Signal) -> bool {
+    match signal {
+        Signal::Print | Signal::Raise(_) => (),
+        Signal::Images(sources) => {
+            let pending = signals.iter_mut().find_map(|signal| match signal {
+                Signal::Images(pending) => Some(pending),
+                _ => None,
+            });
+
+            match pending {
+                Some(pending) => {
+                    for source in sources {
+                        if !pending.contains(&source) {
+                            pending.push(source);
+                        }
+                    }
+                    return false;
+                }
+                None => {
+                    signals.push_back(Signal::Images(sources));
+                    return true;
+                }
+            }
+        }
+        _ => {
+            let kind = mem::discriminant(&signal);
+            if let Some(pending) = signals.iter_mut().find(|s| mem::discriminant(*s) == kind) {
+                *pending = signal;
+                return false;
+            }
+        }
+    }
+
+    signals.push_back(signal);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::{channel, Signal, CAPACITY};
+
+    #[test]
+    fn coalesce() {
+        let (tx, rx) = channel();
+
+        tx.send(Signal::NewMarkdown).unwrap();
+        tx.send(Signal::Scroll(1)).unwrap();
+        tx.send(Signal::Images(vec![String::from("a.png")]))
+            .unwrap();
+        tx.send(Signal::NewMarkdown).unwrap();
+        tx.send(Signal::Scroll(2)).unwrap();
+        tx.send(Signal::Print).unwrap();
+        tx.send(Signal::Images(vec![
+            String::from("a.png"),
+            String::from("b.png"),
+        ]))
+        .unwrap();
+        tx.send(Signal::Print).unwrap();
+        drop(tx);
+
+        assert!(matches!(rx.recv(), Some(Signal::NewMarkdown)));
+        assert!(matches!(rx.recv(), Some(Signal::Scroll(2))));
+        match rx.recv() {
+            Some(Signal::Images(sources)) => assert_eq!(sources, ["a.png", "b.png"]),
+            signal => panic!("unexpected signal: {:?}", signal),
+        }
+        assert!(matches!(rx.recv(), Some(Signal::Print)));
+        assert!(matches!(rx.recv(), Some(Signal::Print)));
+        assert!(rx.recv().is_none());
+        assert!(!rx.fell_behind());
+    }
+
+    #[test]
+    fn overflow() {
+        let (tx, rx) = channel();
+
+        for _ in 0..CAPACITY {
+            tx.send(Signal::Print).unwrap();
+        }
+        tx.send(Signal::Print).unwrap_err();
+
+        assert!(rx.recv().is_none());
+        assert!(rx.fell_behind());
+    }
+
+    #[test]
+    fn wait() {
+        let (tx, rx) = channel();
+
+        let receiver = thread::spawn(move || {
+            let mut received = 0;
+            while rx.recv().is_some() {
+                received += 1;
+            }
+            received
+        });
+
+        tx.send(Signal::NewMarkdown).unwrap();
+        drop(tx);
+
+        assert_eq!(receiver.join().unwrap(), 1);
+    }
+}
@@ -0,0 +1,164 @@
+//! Connections to the server, which may be encrypted with TLS or made over a unix domain socket.
+
+use std::fs::File;
+use std::io::{self, prelude::*, BufReader};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rustls::crypto::ring;
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+
+/// How long a TLS websocket may wait for a message before letting the other half of the
+/// connection write.
+const TLS_READ_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Loads a certificate chain and private key from PEM files.
+pub fn load_tls_config(cert: &Path, key: &Path) -> io::Result<ServerConfig> {
+    let invalid = |path: &Path, e: &dyn std::fmt::Display| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid PEM file {}: {}", path.display(), e),
+        )
+    };
+
+    let certs = CertificateDer::pem_reader_iter(BufReader::new(File::open(cert)?))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| invalid(cert, &e))?;
+    let key = PrivateKeyDer::from_pem_reader(BufReader::new(File::open(key)?))
+        .map_err(|e| invalid(key, &e))?;
+
+    ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// A socket that the server accepts connections on.
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Waits for the next connection.
+    pub fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(conn, _)| Stream::Tcp(conn)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.accept().map(|(conn, _)| Stream::Unix(conn)),
+        }
+    }
+}
+
+/// A connection accepted by the server.
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+
+    /// A TLS connection. The connection is shared between the reading and writing halves of a
+    /// websocket.
+    Tls(Arc<Mutex<TlsStream>>),
+}
+
+impl Stream {
+    /// Wraps a connection in TLS. The handshake is completed on the first read or write.
+    pub fn tls(conn: TcpStream, config: Arc<ServerConfig>) -> io::Result<Self> {
+        let tls = ServerConnection::new(config).map_err(io::Error::other)?;
+        Ok(Stream::Tls(Arc::new(Mutex::new(TlsStream(
+            StreamOwned::new(tls, conn),
+        )))))
+    }
+
+    /// Returns another handle to the same connection, so that it may be read and written from
+    /// different threads.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Stream::Tcp(conn) => Ok(Stream::Tcp(conn.try_clone()?)),
+            #[cfg(unix)]
+            Stream::Unix(conn) => Ok(Stream::Unix(conn.try_clone()?)),
+            Stream::Tls(conn) => {
+                // Reading holds the lock on the connection, so reads must give up periodically.
+                conn.lock()
+                    .unwrap()
+                    .0
+                    .sock
+                    .set_read_timeout(Some(TLS_READ_TIMEOUT))?;
+                Ok(Stream::Tls(Arc::clone(conn)))
+            }
+        }
+    }
+
+    /// Sets how long a write may block before failing, so that a peer that stopped reading
+    /// doesn't block the writer forever.
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(conn) => conn.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(conn) => conn.set_write_timeout(timeout),
+            Stream::Tls(conn) => conn.lock().unwrap().0.sock.set_write_timeout(timeout),
+        }
+    }
+
+    /// Returns whether the connection is over a unix domain socket, which browsers can't connect
+    /// to.
+    pub fn is_unix(&self) -> bool {
+        match self {
+            #[cfg(unix)]
+            Stream::Unix(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(conn) => conn.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(conn) => conn.read(buf),
+            Stream::Tls(conn) => conn.lock().unwrap().0.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(conn) => conn.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(conn) => conn.write(buf),
+            Stream::Tls(conn) => conn.lock().unwrap().0.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(conn) => conn.flush(),
+            #[cfg(unix)]
+            Stream::Unix(conn) => conn.flush(),
+            Stream::Tls(conn) => conn.lock().unwrap().0.flush(),
+        }
+    }
+}
+
+/// A TLS connection that notifies the peer when it is closed, so that responses delimited by the
+/// end of the connection aren't mistaken for truncated ones.
+#[derive(Debug)]
+pub struct TlsStream(StreamOwned<ServerConnection, TcpStream>);
+
+impl Drop for TlsStream {
+    fn drop(&mut self) {
+        self.0.conn.send_close_notify();
+        let _ = self.0.flush();
+    }
+}
@@ -0,0 +1,126 @@
+//! Downscaled copies of large local images, so that documents full of screenshots don't slow down
+//! the preview.
+//!
+//! Thumbnails are created by an external program, such as ImageMagick, and kept until the image
+//! is modified.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use log::*;
+
+#[derive(Debug)]
+pub struct Thumbnails {
+    /// The program that creates thumbnails, followed by its arguments. The program reads the image
+    /// from stdin and prints the thumbnail to stdout.
+    command: Vec<String>,
+
+    /// The size of image files above which they're replaced by thumbnails.
+    min_size: u64,
+
+    /// The thumbnails that were already created, by the path and modification time of the image.
+    cache: Mutex<HashMap<PathBuf, (SystemTime, Vec<u8>)>>,
+}
+
+impl Thumbnails {
+    pub fn new(command: Vec<String>, min_size: u64) -> Self {
+        Thumbnails {
+            command,
+            min_size,
+            cache: Mutex::default(),
+        }
+    }
+
+    /// Returns a thumbnail of a local image, or `None` if the file should be served as-is because
+    /// it's small, it isn't a raster image, or the thumbnail couldn't be created.
+    pub fn get(&self, path: &Path) -> Option<Vec<u8>> {
+        let is_raster_image = mime_guess::from_path(path)
+            .first()
+            .is_some_and(|mime| mime.type_() == "image" && mime.subtype() != "svg");
+        if !is_raster_image {
+            return None;
+        }
+
+        let metadata = fs::metadata(path).ok()?;
+        if metadata.len() <= self.min_size {
+            return None;
+        }
+        let modified = metadata.modified().ok()?;
+
+        let mut cache = self.cache.lock().unwrap();
+        if let Some((created, thumbnail)) = cache.get(path) {
+            if *created == modified {
+                return Some(thumbnail.clone());
+            }
+        }
+
+        match self.create(path) {
+            Ok(thumbnail) => {
+                cache.insert(path.to_owned(), (modified, thumbnail.clone()));
+                Some(thumbnail)
+            }
+            Err(e) => {
+                warn!("could not create thumbnail of {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let (program, args) = self
+            .command
+            .split_first()
+            .ok_or_else(|| io::Error::other("thumbnail command was empty"))?;
+
+        debug!("creating thumbnail of {}", path.display());
+        let output = Command::new(program)
+            .args(args)
+            .stdin(File::open(path)?)
+            .stderr(Stdio::null())
+            .output()?;
+
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "{} exited with {}",
+                program, output.status
+            )));
+        }
+        if output.stdout.is_empty() {
+            return Err(io::Error::other(format!("{} printed nothing", program)));
+        }
+
+        Ok(output.stdout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::Thumbnails;
+
+    #[test]
+    fn thumbnails() {
+        let dir = env::temp_dir().join(format!("composer-thumbnails-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("small.png"), "small").unwrap();
+        fs::write(dir.join("large.png"), "a large image").unwrap();
+        fs::write(dir.join("large.svg"), "a large image").unwrap();
+
+        let command = ["head", "-c", "7"];
+        let thumbnails = Thumbnails::new(command.iter().map(|&s| s.to_owned()).collect(), 8);
+
+        assert_eq!(thumbnails.get(&dir.join("small.png")), None);
+        assert_eq!(thumbnails.get(&dir.join("large.svg")), None);
+        assert_eq!(thumbnails.get(&dir.join("large.png")).unwrap(), b"a large");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
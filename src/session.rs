@@ -0,0 +1,219 @@
+//! Sessions that remember the last rendered document and the settings of the preview, so that a
+//! restarted server shows the previous content right away instead of a blank page.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use log::*;
+use pulldown_cmark::Options;
+use serde::{Deserialize, Serialize};
+
+use crate::render;
+use crate::server::{ColorScheme, Server, Typography};
+
+/// The state of the server that is restored on startup.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    pub markdown: Option<String>,
+    pub file_name: Option<String>,
+    pub static_root: Option<PathBuf>,
+    pub title: Option<String>,
+    pub auto_scroll: Option<bool>,
+    pub toc: Option<bool>,
+    pub presentation: Option<bool>,
+    pub zoom: Option<u32>,
+    pub color_scheme: Option<ColorScheme>,
+    pub typography: Option<Typography>,
+
+    /// The names of the markdown extensions that are enabled.
+    pub extensions: Option<Vec<String>>,
+}
+
+impl Session {
+    /// Captures the current document and settings of the server.
+    pub fn capture(server: &Server) -> Session {
+        let extensions = server.extensions();
+
+        Session {
+            markdown: server.markdown().map(String::from),
+            file_name: server.file_name().map(String::from),
+            static_root: server.static_root(),
+            title: server.title().map(String::from),
+            auto_scroll: Some(server.auto_scroll()),
+            toc: Some(server.toc()),
+            presentation: Some(server.presentation()),
+            zoom: Some(server.zoom()),
+            color_scheme: Some(server.color_scheme()),
+            typography: Some(server.typography()),
+            extensions: Some(
+                render::EXTENSIONS
+                    .iter()
+                    .filter(|&&(_, extension)| extensions.contains(extension))
+                    .map(|&(name, _)| String::from(name))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Applies the settings of the session to the server, then renders its document.
+    pub fn restore(&self, server: &mut Server) -> io::Result<()> {
+        if let Some(root) = &self.static_root {
+            // The directory may have been removed since the session was saved.
+            if let Err(e) = server.set_static_root(root) {
+                warn!("could not restore the static root: {}", e);
+            }
+        }
+        if self.title.is_some() {
+            server.set_title(self.title.clone());
+        }
+        if let Some(auto_scroll) = self.auto_scroll {
+            server.set_auto_scroll(auto_scroll);
+        }
+        if let Some(toc) = self.toc {
+            server.set_toc(toc);
+        }
+        if let Some(presentation) = self.presentation {
+            server.set_presentation(presentation)?;
+        }
+        if let Some(zoom) = self.zoom {
+            server.set_zoom(zoom);
+        }
+        if let Some(color_scheme) = self.color_scheme {
+            server.set_color_scheme(color_scheme);
+        }
+        if let Some(typography) = &self.typography {
+            server.set_typography(typography.clone());
+        }
+        if let Some(names) = &self.extensions {
+            let extensions = names
+                .iter()
+                .filter_map(|name| render::extension(name))
+                .fold(Options::empty(), |extensions, extension| {
+                    extensions | extension
+                });
+            server.set_extensions(extensions)?;
+        }
+
+        match (&self.markdown, &self.file_name) {
+            (Some(markdown), Some(file_name)) => server.send_file(file_name, markdown.clone()),
+            (Some(markdown), None) => server.send(markdown.clone()),
+            (None, _) => Ok(()),
+        }
+    }
+
+    /// Reads a session from a file. A session that doesn't exist yet is empty.
+    pub fn load(path: &Path) -> Result<Session> {
+        match fs::read(path) {
+            Ok(json) => serde_json::from_slice(&json)
+                .map_err(|e| anyhow!("invalid session {}: {}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Session::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes the session to a file, replacing the previous session at once so that it isn't
+    /// lost if the server is killed while writing.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let temp = path.with_extension("json.tmp");
+        fs::write(&temp, serde_json::to_vec(self)?)?;
+        fs::rename(temp, path)
+    }
+}
+
+/// Returns the file of a named session, in the `markdown-composer` directory of the user's state
+/// directory, such as `~/.local/state/markdown-composer/sessions/notes.json`.
+///
+/// Names may only contain ASCII letters, digits, `-`, `_` and `.`.
+pub fn path(name: &str) -> Result<PathBuf> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(anyhow!("invalid session name: {:?}", name));
+    }
+
+    let state_dir = match (env::var_os("XDG_STATE_HOME"), env::var_os("HOME")) {
+        (Some(dir), _) if !dir.is_empty() => PathBuf::from(dir),
+        (_, Some(home)) => Path::new(&home).join(".local/state"),
+        _ => match env::var_os("LOCALAPPDATA") {
+            Some(dir) => PathBuf::from(dir),
+            None => return Err(anyhow!("could not find a directory to store sessions in")),
+        },
+    };
+
+    Ok(state_dir
+        .join("markdown-composer")
+        .join("sessions")
+        .join(format!("{}.json", name)))
+}
+
+/// Saves the session of the server whenever it changes.
+#[derive(Debug)]
+pub struct SessionFile {
+    path: PathBuf,
+    last: Session,
+}
+
+impl SessionFile {
+    pub fn new(path: PathBuf, session: Session) -> SessionFile {
+        SessionFile {
+            path,
+            last: session,
+        }
+    }
+
+    /// Writes the session of the server if it changed since it was last written.
+    pub fn update(&mut self, server: &Server) {
+        let session = Session::capture(server);
+        if session == self.last {
+            return;
+        }
+
+        debug!("saving session: {}", self.path.display());
+        if let Err(e) = session.save(&self.path) {
+            warn!("could not save session {}: {}", self.path.display(), e);
+        }
+        self.last = session;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use crate::server::ColorScheme;
+
+    use super::Session;
+
+    #[test]
+    fn save_and_load() {
+        let dir = env::temp_dir().join(format!("composer-session-{}", process::id()));
+        let path = dir.join("sessions/test.json");
+
+        assert_eq!(Session::load(&path).unwrap(), Session::default());
+
+        let session = Session {
+            markdown: Some(String::from("# Notes")),
+            file_name: Some(String::from("notes.md")),
+            color_scheme: Some(ColorScheme::Dark),
+            extensions: Some(vec![String::from("tables")]),
+            ..Session::default()
+        };
+        session.save(&path).unwrap();
+        assert_eq!(Session::load(&path).unwrap(), session);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,404 @@
+//! Spell checking of rendered documents.
+//!
+//! Words are checked by a spell checker that speaks the ispell pipe protocol, such as `hunspell
+//! -a` or `aspell -a`, which is kept running between renders. Misspelled words in the text of the
+//! rendered HTML are wrapped in a `<span class="misspelled">` whose title lists suggestions.
+//! Code, math and diagrams aren't checked, nor are URLs and email addresses.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{self, prelude::*, BufReader};
+use std::path::Path;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use handlebars::html_escape;
+use log::*;
+
+/// Elements whose text isn't checked.
+const SKIPPED_ELEMENTS: &[&str] = &[
+    "code", "kbd", "math", "pre", "samp", "script", "style", "svg", "textarea",
+];
+
+/// The number of suggestions that are displayed for a misspelled word.
+const MAX_SUGGESTIONS: usize = 5;
+
+/// Marks misspelled words in HTML.
+#[derive(Debug)]
+pub struct SpellChecker {
+    command: Command,
+    process: Option<Process>,
+
+    /// Whether the spell checker couldn't be run, so that it isn't run again for every render.
+    failed: bool,
+
+    /// The words that were checked, and the suggestions for them if they're misspelled.
+    words: HashMap<String, Option<Vec<String>>>,
+}
+
+#[derive(Debug)]
+struct Process {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A piece of HTML, which is either a word that should be checked or anything else.
+#[derive(Debug, PartialEq, Eq)]
+enum Piece<'a> {
+    Word(&'a str),
+    Other(&'a str),
+}
+
+impl SpellChecker {
+    /// Creates a spell checker that runs a command speaking the ispell pipe protocol, such as
+    /// `aspell -a`.
+    pub fn new(mut command: Command) -> Self {
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+        SpellChecker {
+            command,
+            process: None,
+            failed: false,
+            words: HashMap::new(),
+        }
+    }
+
+    /// Creates a spell checker that runs `hunspell`, with the dictionary of a language such as
+    /// `en_US`, or hunspell's default dictionary. Words in the personal dictionary, if any, are
+    /// accepted too.
+    pub fn hunspell(language: Option<&str>, personal_dictionary: Option<&Path>) -> Self {
+        let mut command = Command::new("hunspell");
+        command.arg("-a");
+        if let Some(language) = language {
+            command.arg("-d").arg(language);
+        }
+        if let Some(dictionary) = personal_dictionary {
+            command.arg("-p").arg(dictionary);
+        }
+        SpellChecker::new(command)
+    }
+
+    /// Wraps the misspelled words in the text of HTML in `<span class="misspelled">`s.
+    ///
+    /// If the spell checker can't be run, the HTML is returned unchanged.
+    pub fn mark(&mut self, html: &str) -> String {
+        if self.failed {
+            return html.to_owned();
+        }
+
+        let pieces = split(html);
+
+        for piece in &pieces {
+            if let Piece::Word(word) = piece {
+                if let Err(e) = self.check(word) {
+                    warn!("could not run spell checker: {}", e);
+                    self.process = None;
+                    self.failed = true;
+                    return html.to_owned();
+                }
+            }
+        }
+
+        let mut marked = String::with_capacity(html.len());
+        for piece in pieces {
+            match piece {
+                Piece::Word(word) => match &self.words[word] {
+                    Some(suggestions) => {
+                        let title = if suggestions.is_empty() {
+                            String::from("Unknown word")
+                        } else {
+                            format!("Did you mean: {}?", suggestions.join(", "))
+                        };
+                        marked.push_str(&format!(
+                            "<span class=\"misspelled\" title=\"{}\">{}</span>",
+                            html_escape(&title),
+                            word
+                        ));
+                    }
+                    None => marked.push_str(word),
+                },
+                Piece::Other(html) => marked.push_str(html),
+            }
+        }
+        marked
+    }
+
+    /// Checks a word, unless it was already checked.
+    fn check(&mut self, word: &str) -> io::Result<()> {
+        if self.words.contains_key(word) {
+            return Ok(());
+        }
+        let process = match &mut self.process {
+            Some(process) => process,
+            None => {
+                let mut child = self.command.spawn()?;
+                let stdin = child.stdin.take().unwrap();
+                let stdout = child.stdout.take().unwrap();
+                let mut process = Process {
+                    child,
+                    stdin,
+                    stdout: BufReader::new(stdout),
+                };
+
+                // The first line identifies the spell checker.
+                let mut banner = String::new();
+                if process.stdout.read_line(&mut banner)? == 0 {
+                    return Err(io::Error::other("spell checker exited"));
+                }
+                self.process.insert(process)
+            }
+        };
+
+        // A leading `^` stops the line from being read as a command.
+        writeln!(process.stdin, "^{}", word)?;
+        process.stdin.flush()?;
+
+        // Each word of the line is answered on its own line, and the answers end with a blank line.
+        let mut suggestions = None;
+        loop {
+            let mut line = String::new();
+            if process.stdout.read_line(&mut line)? == 0 {
+                return Err(io::Error::other("spell checker exited"));
+            }
+
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            suggestions = match line.chars().next() {
+                Some('&') => Some(
+                    line.split_once(": ")
+                        .map(|(_, suggestions)| {
+                            suggestions
+                                .split(", ")
+                                .take(MAX_SUGGESTIONS)
+                                .map(String::from)
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                ),
+                Some('#') => Some(vec![]),
+                _ => None,
+            };
+        }
+
+        self.words.insert(word.to_owned(), suggestions);
+        Ok(())
+    }
+}
+
+/// Removes the marks of misspelled words from HTML, such as for exports.
+pub fn unmark(html: &str) -> Cow<'_, str> {
+    const MARK: &str = "<span class=\"misspelled\"";
+    if !html.contains(MARK) {
+        return Cow::Borrowed(html);
+    }
+
+    let mut unmarked = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find(MARK) {
+        unmarked.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        // Marks only contain the word, and their titles are escaped.
+        let word_start = rest.find('>').map_or(rest.len(), |end| end + 1);
+        let word_end = rest.find("</span>").unwrap_or(rest.len());
+        unmarked.push_str(&rest[word_start..word_end]);
+        rest = rest.get(word_end + "</span>".len()..).unwrap_or("");
+    }
+    unmarked.push_str(rest);
+    Cow::Owned(unmarked)
+}
+
+/// Splits HTML into the words of its text and everything else.
+fn split(html: &str) -> Vec<Piece<'_>> {
+    let mut pieces = vec![];
+
+    // The element whose text isn't checked that is being skipped, if any, and the number of
+    // elements of the same name that are open.
+    let mut skipped: Option<(String, usize)> = None;
+
+    let mut rest = html;
+    while !rest.is_empty() {
+        if rest.starts_with('<') {
+            let end = if rest.starts_with("<!--") {
+                rest.find("-->").map_or(rest.len(), |end| end + 3)
+            } else {
+                rest.find('>').map_or(rest.len(), |end| end + 1)
+            };
+            let tag = &rest[..end];
+            pieces.push(Piece::Other(tag));
+            rest = &rest[end..];
+
+            let (closing, name) = match tag.strip_prefix("</") {
+                Some(name) => (true, name),
+                None => (false, &tag[1..]),
+            };
+            let name = name
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            let self_closing = tag.ends_with("/>");
+
+            match &mut skipped {
+                Some((skipped_name, depth)) if *skipped_name == name => {
+                    if closing {
+                        *depth -= 1;
+                    } else if !self_closing {
+                        *depth += 1;
+                    }
+                    if *depth == 0 {
+                        skipped = None;
+                    }
+                }
+                Some(_) => (),
+                None if !closing && !self_closing => {
+                    // Math that is left for the preview to typeset is in `<span class="math">`s.
+                    let is_math = name == "span" && tag.contains("class=\"math");
+                    if SKIPPED_ELEMENTS.contains(&&*name) || is_math {
+                        skipped = Some((name, 1));
+                    }
+                }
+                None => (),
+            }
+            continue;
+        }
+
+        let end = rest.find('<').unwrap_or(rest.len());
+        let (text, after) = rest.split_at(end);
+        if skipped.is_some() {
+            pieces.push(Piece::Other(text));
+        } else {
+            split_words(text, &mut pieces);
+        }
+        rest = after;
+    }
+
+    pieces
+}
+
+/// Splits text into words and everything else.
+fn split_words<'a>(text: &'a str, pieces: &mut Vec<Piece<'a>>) {
+    let mut start = 0;
+    for (chunk_start, chunk) in
+        text.split_inclusive(char::is_whitespace)
+            .scan(0, |offset, chunk| {
+                let start = *offset;
+                *offset += chunk.len();
+                Some((start, chunk))
+            })
+    {
+        // URLs and email addresses aren't words.
+        if chunk.contains("://") || chunk.contains('@') {
+            continue;
+        }
+
+        let mut chars = chunk.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            // Character references, such as `&amp;`, aren't words.
+            if c == '&' {
+                for (_, c) in chars.by_ref() {
+                    if c == ';' {
+                        break;
+                    }
+                }
+                continue;
+            }
+            if !c.is_alphanumeric() {
+                continue;
+            }
+
+            let mut end = i + c.len_utf8();
+            let mut is_word = c.is_alphabetic();
+            while let Some(&(j, c)) = chars.peek() {
+                // Apostrophes are part of words, as in `don't`, but not at their ends.
+                let apostrophe = matches!(c, '\'' | '\u{2019}')
+                    && chunk[j + c.len_utf8()..].starts_with(char::is_alphabetic);
+                if !c.is_alphanumeric() && c != '_' && !apostrophe {
+                    break;
+                }
+                is_word &= !c.is_numeric() && c != '_';
+                end = j + c.len_utf8();
+                chars.next();
+            }
+
+            if is_word {
+                let word_start = chunk_start + i;
+                if start < word_start {
+                    pieces.push(Piece::Other(&text[start..word_start]));
+                }
+                pieces.push(Piece::Word(&text[word_start..chunk_start + end]));
+                start = chunk_start + end;
+            }
+        }
+    }
+
+    if start < text.len() {
+        pieces.push(Piece::Other(&text[start..]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::{split, unmark, Piece, SpellChecker};
+
+    #[test]
+    fn words() {
+        let words = split(
+            "<p>Don't <em>check</em> x2 &amp; https://e.com a@b.c</p>\
+             <pre><code>let <code>x</code> y</code></pre><span class=\"math\">\\alpha</span>end",
+        )
+        .into_iter()
+        .filter_map(|piece| match piece {
+            Piece::Word(word) => Some(word),
+            Piece::Other(_) => None,
+        })
+        .collect::<Vec<_>>();
+        assert_eq!(words, ["Don't", "check", "end"]);
+    }
+
+    #[test]
+    fn mark() {
+        // A stand-in for hunspell that only knows "hello".
+        let script = r##"
+            echo '@(#) International Ispell Version 3.2.06 (but really Hunspell 1.7.0)'
+            while read -r line; do
+                case "$line" in
+                    ^hello) echo '*' ;;
+                    ^wrold) echo '& wrold 2 0: world, wold' ;;
+                    *) echo "# ${line#^} 0" ;;
+                esac
+                echo
+            done
+        "##;
+        let mut command = Command::new("sh");
+        command.args(["-c", script]);
+        let mut checker = SpellChecker::new(command);
+
+        assert_eq!(
+            checker.mark("<h1>hello wrold</h1>\n<p>zzz <code>zzz</code></p>"),
+            "<h1>hello <span class=\"misspelled\" title=\"Did you mean: world, wold?\">wrold</span></h1>\n\
+             <p><span class=\"misspelled\" title=\"Unknown word\">zzz</span> <code>zzz</code></p>"
+        );
+
+        assert_eq!(
+            unmark(&checker.mark("<p>hello wrold</p>")),
+            "<p>hello wrold</p>"
+        );
+
+        let mut checker = SpellChecker::new(Command::new("/nonexistent"));
+        assert_eq!(checker.mark("<p>hello</p>"), "<p>hello</p>");
+    }
+}
@@ -0,0 +1,191 @@
+//! Statistics of rendered documents, such as their length in words.
+//!
+//! The statistics are counted from the text of the HTML, so that they're the same whichever
+//! format or renderer the document was rendered with, and markup such as link destinations isn't
+//! counted. Code blocks, math and diagrams aren't prose, so they aren't counted, and neither are
+//! the buttons that the renderer adds, such as to copy code.
+
+use serde::Serialize;
+
+/// Elements whose text isn't counted.
+const SKIPPED_ELEMENTS: &[&str] = &[
+    "button", "math", "pre", "script", "style", "svg", "template",
+];
+
+/// Elements that may be in the middle of a word, such as `<em>un</em>likely`. Other elements
+/// separate words, such as the cells of a table.
+const INLINE_ELEMENTS: &[&str] = &[
+    "a", "abbr", "b", "bdi", "bdo", "cite", "code", "del", "dfn", "em", "i", "ins", "kbd", "mark",
+    "q", "s", "samp", "small", "span", "strong", "sub", "sup", "u", "var",
+];
+
+/// The number of words that are read per minute, to estimate the reading time.
+const WORDS_PER_MINUTE: usize = 200;
+
+/// The length of a document.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Stats {
+    pub words: usize,
+
+    /// The number of characters of the text, counting each run of whitespace as one space.
+    pub characters: usize,
+
+    /// The number of minutes that the document takes to read, rounded up.
+    pub reading_time_minutes: usize,
+}
+
+impl Stats {
+    /// Counts the words and characters of the text of HTML.
+    pub fn from_html(html: &str) -> Self {
+        let mut words = 0;
+        let mut characters = 0;
+
+        // Whether the text so far ends in the middle of a word, or with whitespace.
+        let mut in_word = false;
+        let mut after_space = true;
+
+        // The element whose text isn't counted that is being skipped, if any, and the number of
+        // elements of the same name that are open.
+        let mut skipped: Option<(String, usize)> = None;
+
+        let mut rest = html;
+        while !rest.is_empty() {
+            if rest.starts_with('<') {
+                let end = if rest.starts_with("<!--") {
+                    rest.find("-->").map_or(rest.len(), |end| end + 3)
+                } else {
+                    rest.find('>').map_or(rest.len(), |end| end + 1)
+                };
+                let tag = &rest[..end];
+                rest = &rest[end..];
+
+                let (closing, name) = match tag.strip_prefix("</") {
+                    Some(name) => (true, name),
+                    None => (false, &tag[1..]),
+                };
+                let name = name
+                    .split(|c: char| !c.is_ascii_alphanumeric())
+                    .next()
+                    .unwrap_or_default()
+                    .to_ascii_lowercase();
+                let self_closing = tag.ends_with("/>");
+
+                match &mut skipped {
+                    Some((skipped_name, depth)) if *skipped_name == name => {
+                        if closing {
+                            *depth -= 1;
+                        } else if !self_closing {
+                            *depth += 1;
+                        }
+                        if *depth == 0 {
+                            skipped = None;
+                        }
+                    }
+                    Some(_) => (),
+                    None if !closing && !self_closing => {
+                        // Math that is left for the preview to typeset is in `<span
+                        // class="math">`s.
+                        let is_math = name == "span" && tag.contains("class=\"math");
+                        if SKIPPED_ELEMENTS.contains(&&*name) || is_math {
+                            skipped = Some((name.clone(), 1));
+                        }
+                    }
+                    None => (),
+                }
+
+                if !INLINE_ELEMENTS.contains(&&*name) {
+                    in_word = false;
+                    if !after_space {
+                        characters += 1;
+                        after_space = true;
+                    }
+                }
+                continue;
+            }
+
+            let end = rest.find('<').unwrap_or(rest.len());
+            let (text, after) = rest.split_at(end);
+            rest = after;
+            if skipped.is_some() {
+                continue;
+            }
+
+            let mut chars = text.chars();
+            while let Some(c) = chars.next() {
+                // Character references, such as `&amp;`, are one character.
+                let c = if c == '&' {
+                    let reference = chars.as_str();
+                    match reference.find(';') {
+                        Some(end) if end <= 32 => {
+                            chars = reference[end + 1..].chars();
+                            if reference.starts_with("nbsp") || reference.starts_with("#160") {
+                                ' '
+                            } else {
+                                '&'
+                            }
+                        }
+                        _ => c,
+                    }
+                } else {
+                    c
+                };
+
+                if c.is_whitespace() {
+                    in_word = false;
+                    if !after_space {
+                        characters += 1;
+                        after_space = true;
+                    }
+                    continue;
+                }
+
+                characters += 1;
+                after_space = false;
+                if c.is_alphanumeric() && !in_word {
+                    in_word = true;
+                    words += 1;
+                }
+            }
+        }
+
+        // Whitespace at the end isn't counted.
+        if after_space && characters > 0 {
+            characters -= 1;
+        }
+
+        Stats {
+            words,
+            characters,
+            reading_time_minutes: words.div_ceil(WORDS_PER_MINUTE),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Stats;
+
+    #[test]
+    fn count() {
+        let stats = Stats::from_html(
+            "<h1 data-source-line=\"1\">Hello, <em>wor</em>ld!</h1>\n\
+             <pre><code>not counted</code></pre>\n\
+             <table><tr><td>a</td><td>b&nbsp;&amp;&nbsp;c</td></tr></table>\
+             <p>x <span class=\"math inline\">\\alpha</span> -- <a href=\"https://e.com\">y</a></p>\n",
+        );
+        assert_eq!(
+            stats,
+            Stats {
+                words: 7,
+                characters: 28,
+                reading_time_minutes: 1,
+            }
+        );
+
+        assert_eq!(Stats::from_html(""), Stats::default());
+        assert_eq!(
+            Stats::from_html(&"<p>word</p>".repeat(201)).reading_time_minutes,
+            2
+        );
+    }
+}
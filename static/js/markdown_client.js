@@ -0,0 +1,1491 @@
+document.addEventListener('DOMContentLoaded', function() {
+    // Finds the elements matching a selector among some nodes and their descendants.
+    function select(nodes, selector) {
+        var elements = [];
+        for (var i = 0; i < nodes.length; i++) {
+            var node = nodes[i];
+            if (node.nodeType !== Node.ELEMENT_NODE) {
+                continue;
+            }
+
+            if (node.matches(selector)) {
+                elements.push(node);
+            }
+            elements.push.apply(elements, node.querySelectorAll(selector));
+        }
+        return elements;
+    }
+
+    function syntaxHighlight(nodes) {
+        if (hljs !== undefined) {
+            var codeBlocks = select(nodes, 'pre code');
+            for (var i = 0; i < codeBlocks.length; i++) {
+                var codeBlock = codeBlocks[i];
+                hljs.highlightElement(codeBlock);
+
+                // Since the github css doesn't play nice with highlight.js, we
+                // need to set the background of all `pre` elements to be the
+                // color of the inner `code` block.
+                codeBlock.parentNode.style.background = (
+                    getComputedStyle(codeBlock)
+                        .getPropertyValue('background'));
+            }
+        }
+    }
+
+    // Typesets elements with the `math` class using whichever math library was loaded by the
+    // server. Delimiters that external renderers may leave around the math are removed.
+    function renderMath(nodes) {
+        var elements = select(nodes, '.math');
+        if (elements.length === 0) {
+            return;
+        }
+
+        for (var i = 0; i < elements.length; i++) {
+            var element = elements[i];
+            var display = element.classList.contains('math-display') ||
+                element.classList.contains('display');
+            var tex = element.textContent.trim()
+                .replace(/^\\[\[(]/, '')
+                .replace(/\\[\])]$/, '');
+
+            if (typeof katex !== 'undefined') {
+                katex.render(tex, element, { displayMode: display, throwOnError: false });
+            } else if (window.MathJax) {
+                element.textContent = display ? '\\[' + tex + '\\]' : '\\(' + tex + '\\)';
+            }
+        }
+
+        if (typeof katex === 'undefined' && window.MathJax && MathJax.startup) {
+            MathJax.startup.promise.then(function() {
+                MathJax.typesetClear([previewWindow]);
+                return MathJax.typesetPromise([previewWindow]);
+            });
+        }
+    }
+
+    // The columns that data tables are sorted by, keyed by the source line of the table, so that
+    // tables stay sorted when they're rendered again.
+    var tableSorts = {};
+
+    // Sorts the rows of a data table by a column. Columns of numbers are sorted numerically, and
+    // empty cells are sorted last.
+    function sortTable(table, column, descending) {
+        var headers = table.tHead.rows[0].cells;
+        for (var i = 0; i < headers.length; i++) {
+            headers[i].removeAttribute('aria-sort');
+        }
+        headers[column].setAttribute('aria-sort', descending ? 'descending' : 'ascending');
+
+        var numeric = headers[column].dataset.type === 'number';
+        var body = table.tBodies[0];
+        var rows = Array.prototype.slice.call(body.rows);
+        rows.sort(function(a, b) {
+            var x = a.cells[column].textContent.trim();
+            var y = b.cells[column].textContent.trim();
+            if (x === '' || y === '') {
+                return (x === '') - (y === '');
+            }
+
+            var order = numeric ? parseFloat(x) - parseFloat(y)
+                : x.localeCompare(y, undefined, { numeric: true });
+            return descending ? -order : order;
+        });
+        rows.forEach(function(row) {
+            body.appendChild(row);
+        });
+    }
+
+    function sortTables(nodes) {
+        select(nodes, 'table.data-table').forEach(function(table) {
+            var sort = tableSorts[table.dataset.sourceLine];
+            if (sort !== undefined && sort.column < table.tHead.rows[0].cells.length) {
+                sortTable(table, sort.column, sort.descending);
+            }
+        });
+    }
+
+    // The ranges of lines of the markdown that changed since the file was committed to git.
+    var changes = [];
+
+    // Marks the elements that were rendered from the lines that changed.
+    function markChanges() {
+        select([previewWindow], '.git-changed').forEach(function(element) {
+            element.classList.remove('git-changed');
+        });
+        if (changes.length === 0) {
+            return;
+        }
+
+        var blocks = previewWindow.querySelectorAll('[data-source-line]');
+        changes.forEach(function(range) {
+            var first = elementAtLine(range[0]);
+            if (first !== null) {
+                first.classList.add('git-changed');
+            }
+            for (var i = 0; i < blocks.length; i++) {
+                var line = parseInt(blocks[i].dataset.sourceLine, 10);
+                if (line > range[0] && line <= range[1]) {
+                    blocks[i].classList.add('git-changed');
+                }
+            }
+        });
+    }
+
+    // The problems that the linter found in the markdown.
+    var diagnostics = [];
+
+    // Marks the elements that the linter's diagnostics are on, listing their messages in the
+    // elements' titles.
+    function markDiagnostics() {
+        select([previewWindow], '.lint-marked').forEach(function(element) {
+            element.classList.remove('lint-marked');
+            element.removeAttribute('title');
+        });
+
+        diagnostics.forEach(function(diagnostic) {
+            var element = elementAtLine(diagnostic.line);
+            if (element === null) {
+                return;
+            }
+
+            var location = diagnostic.column === null ? diagnostic.line
+                : diagnostic.line + ':' + diagnostic.column;
+            var message = 'Line ' + location + ': ' + diagnostic.message;
+            if (element.classList.contains('lint-marked')) {
+                element.title += '\n' + message;
+            } else {
+                element.classList.add('lint-marked');
+                element.title = message;
+            }
+        });
+    }
+
+    // The text searched for with the search box, whose matches are highlighted in the preview.
+    var searchQuery = '';
+
+    // Highlights the matches of the search query in the text of the preview, replacing the old
+    // highlights. Like the server's search, matching ignores case and the kind of whitespace.
+    function highlightMatches() {
+        select([previewWindow], 'mark.composer-search-match').forEach(function(mark) {
+            var parent = mark.parentNode;
+            parent.replaceChild(document.createTextNode(mark.textContent), mark);
+            parent.normalize();
+        });
+
+        var words = searchQuery.split(/\s+/).filter(function(word) {
+            return word !== '';
+        });
+        if (words.length === 0) {
+            return;
+        }
+        var pattern = new RegExp(words.map(function(word) {
+            return word.replace(/[.*+?^${}()|[\]\\]/g, '\\$&');
+        }).join('\\s+'), 'gi');
+
+        var walker = document.createTreeWalker(previewWindow, NodeFilter.SHOW_TEXT, {
+            acceptNode: function(node) {
+                return node.parentNode.closest('button, script, style, svg') === null ?
+                    NodeFilter.FILTER_ACCEPT : NodeFilter.FILTER_REJECT;
+            },
+        });
+        var texts = [];
+        while (walker.nextNode()) {
+            texts.push(walker.currentNode);
+        }
+
+        texts.forEach(function(text) {
+            var value = text.nodeValue;
+            var fragment = document.createDocumentFragment();
+            var end = 0;
+            var match;
+            pattern.lastIndex = 0;
+            while ((match = pattern.exec(value)) !== null) {
+                fragment.appendChild(document.createTextNode(value.slice(end, match.index)));
+                var mark = document.createElement('mark');
+                mark.className = 'composer-search-match';
+                mark.textContent = match[0];
+                fragment.appendChild(mark);
+                end = pattern.lastIndex;
+            }
+            if (end > 0) {
+                fragment.appendChild(document.createTextNode(value.slice(end)));
+                text.parentNode.replaceChild(fragment, text);
+            }
+        });
+    }
+
+    // Scrolls to the highlighted match after the current one, if any, wrapping around.
+    function nextMatch() {
+        var marks = previewWindow.querySelectorAll('mark.composer-search-match');
+        if (marks.length === 0) {
+            return;
+        }
+
+        var current = previewWindow.querySelector('mark.composer-search-match.current');
+        var index = Array.prototype.indexOf.call(marks, current);
+        if (current !== null) {
+            current.classList.remove('current');
+        }
+        var next = marks[(index + 1) % marks.length];
+        next.classList.add('current');
+        next.scrollIntoView({ block: 'center' });
+    }
+
+    // The blocks of HTML sent by the server and the nodes created from them, so that patches can
+    // replace the nodes of some blocks.
+    var blocks = [];
+
+    // Large documents are windowed: only the blocks near the visible part of the preview are added
+    // to the page, and the others are replaced by placeholders. Headings are always added, so that
+    // they're listed in the table of contents.
+    var blockObserver = null;
+
+    function parseBlock(html) {
+        var template = document.createElement('template');
+        template.innerHTML = html;
+        return Array.prototype.slice.call(template.content.childNodes);
+    }
+
+    function createBlock(html, placeholder) {
+        var block = {
+            html: html,
+            nodes: [],
+            placeholder: false,
+            pinned: /^\s*<h[1-6][\s>]/i.test(html),
+            observed: null,
+            height: null,
+        };
+
+        if (placeholder && !block.pinned) {
+            block.nodes = [createPlaceholder(block)];
+            block.placeholder = true;
+        } else {
+            block.nodes = parseBlock(html);
+        }
+        return block;
+    }
+
+    // Creates an element that takes up the space of a block, and that the preview can scroll to.
+    function createPlaceholder(block) {
+        var placeholder = document.createElement('div');
+        placeholder.className = 'composer-placeholder';
+        placeholder.style.height = (block.height || Math.ceil(block.html.length / 4)) + 'px';
+
+        var line = /data-source-line="(\d+)"/.exec(block.html);
+        if (line !== null) {
+            placeholder.dataset.sourceLine = line[1];
+        }
+        return placeholder;
+    }
+
+    // Watches whether a block is near the visible part of the preview.
+    function observe(block) {
+        if (blockObserver === null || block.pinned) {
+            return;
+        }
+
+        block.observed = block.nodes.find(function(node) {
+            return node.nodeType === Node.ELEMENT_NODE;
+        }) || null;
+        if (block.observed !== null) {
+            block.observed.composerBlock = block;
+            blockObserver.observe(block.observed);
+        }
+    }
+
+    function unobserve(block) {
+        if (blockObserver !== null && block.observed !== null) {
+            blockObserver.unobserve(block.observed);
+        }
+        block.observed = null;
+    }
+
+    // Replaces the nodes of a block with new ones.
+    function replaceNodes(block, nodes) {
+        unobserve(block);
+        for (var i = 0; i < nodes.length; i++) {
+            previewWindow.insertBefore(nodes[i], block.nodes[0]);
+        }
+        block.nodes.forEach(function(node) {
+            if (node.parentNode !== null) {
+                node.parentNode.removeChild(node);
+            }
+        });
+        block.nodes = nodes;
+        observe(block);
+    }
+
+    function onBlocksVisible(entries) {
+        entries.forEach(function(entry) {
+            var block = entry.target.composerBlock;
+            if (block.observed !== entry.target) {
+                return;
+            }
+
+            if (entry.isIntersecting && block.placeholder) {
+                var nodes = parseBlock(block.html);
+                block.placeholder = false;
+                replaceNodes(block, nodes);
+                syntaxHighlight(nodes);
+                renderMath(nodes);
+                sortTables(nodes);
+                markChanges();
+                markDiagnostics();
+                highlightMatches();
+            } else if (!entry.isIntersecting && !block.placeholder) {
+                var range = document.createRange();
+                range.setStartBefore(block.nodes[0]);
+                range.setEndAfter(block.nodes[block.nodes.length - 1]);
+                block.height = Math.ceil(range.getBoundingClientRect().height);
+                block.placeholder = true;
+                replaceNodes(block, [createPlaceholder(block)]);
+            }
+        });
+    }
+
+    // Adds every block of a windowed preview to the page, so that the whole document is printed.
+    // Blocks that aren't visible are windowed again afterwards.
+    function showAllBlocks() {
+        var nodes = [];
+        blocks.forEach(function(block) {
+            if (block.placeholder) {
+                var blockNodes = parseBlock(block.html);
+                block.placeholder = false;
+                replaceNodes(block, blockNodes);
+                nodes.push.apply(nodes, blockNodes);
+            }
+        });
+
+        syntaxHighlight(nodes);
+        renderMath(nodes);
+        sortTables(nodes);
+        markChanges();
+        markDiagnostics();
+        highlightMatches();
+    }
+
+    window.addEventListener('beforeprint', showAllBlocks);
+
+    // Replaces blocks of the preview with new ones, and returns the nodes that were added. The
+    // lines of the blocks after the replaced ones are moved by the line offset.
+    function patchPreview(start, deleteCount, html, lineOffset) {
+        var next = null;
+        for (var n = start + deleteCount; n < blocks.length && next === null; n++) {
+            next = blocks[n].nodes[0] || null;
+        }
+
+        blocks.slice(start, start + deleteCount).forEach(function(block) {
+            unobserve(block);
+            block.nodes.forEach(function(node) {
+                if (node.parentNode !== null) {
+                    node.parentNode.removeChild(node);
+                }
+            });
+        });
+
+        var inserted = html.map(function(html) {
+            return createBlock(html, false);
+        });
+        var nodes = [].concat.apply([], inserted.map(function(block) {
+            return block.nodes;
+        }));
+        for (var i = 0; i < nodes.length; i++) {
+            previewWindow.insertBefore(nodes[i], next);
+        }
+        inserted.forEach(observe);
+
+        if (lineOffset !== 0) {
+            var moved = blocks.slice(start + deleteCount);
+            moved.forEach(function(block) {
+                block.html = block.html.replace(
+                    /(data-(?:source|task)-line=")(\d+)/g,
+                    function(match, attribute, line) {
+                        return attribute + (parseInt(line, 10) + lineOffset);
+                    });
+            });
+
+            var movedNodes = [].concat.apply([], moved.map(function(block) {
+                return block.nodes;
+            }));
+            select(movedNodes, '[data-source-line], [data-task-line]').forEach(function(element) {
+                ['sourceLine', 'taskLine'].forEach(function(attribute) {
+                    if (element.dataset[attribute] !== undefined) {
+                        element.dataset[attribute] =
+                            parseInt(element.dataset[attribute], 10) + lineOffset;
+                    }
+                });
+            });
+        }
+
+        blocks.splice.apply(blocks, [start, deleteCount].concat(inserted));
+        return nodes;
+    }
+
+    // Replaces the whole preview with new blocks. If the preview is windowed, blocks are added
+    // once they're scrolled near.
+    function replacePreview(html, windowed) {
+        if (blockObserver !== null) {
+            blockObserver.disconnect();
+        }
+        blockObserver = windowed ?
+            new IntersectionObserver(onBlocksVisible, { rootMargin: '100% 0px' }) : null;
+
+        previewWindow.innerHTML = '';
+        blocks = html.map(function(html) {
+            return createBlock(html, windowed);
+        });
+
+        var nodes = [];
+        blocks.forEach(function(block) {
+            block.nodes.forEach(function(node) {
+                previewWindow.appendChild(node);
+                if (!block.placeholder) {
+                    nodes.push(node);
+                }
+            });
+            observe(block);
+        });
+        return nodes;
+    }
+
+    // Records where the reader is in the preview: the first block that is visible, and the
+    // heading of the section that it's in, along with their distance from the top of the window.
+    function scrollAnchor() {
+        var children = previewWindow.children;
+        var anchor = null;
+        var heading = null;
+        for (var i = 0; i < children.length; i++) {
+            if (/^H[1-6]$/.test(children[i].tagName)) {
+                heading = children[i];
+            }
+            if (children[i].getBoundingClientRect().bottom > 0) {
+                anchor = children[i];
+                break;
+            }
+        }
+
+        if (anchor === null) {
+            return null;
+        }
+
+        var describe = function(element) {
+            return {
+                element: element,
+                tagName: element.tagName,
+                id: element.id,
+                text: element.textContent,
+                top: element.getBoundingClientRect().top,
+            };
+        };
+        return {
+            index: i,
+            block: describe(anchor),
+            heading: heading === null ? null : describe(heading),
+        };
+    }
+
+    // Finds an element of the preview that is the same as a recorded one, even if it was
+    // replaced: an element with the same id, or else the nearest one with the same tag and text.
+    function findAnchor(anchor, index) {
+        if (anchor.element.isConnected) {
+            return anchor.element;
+        }
+        if (anchor.id) {
+            var element = document.getElementById(anchor.id);
+            if (element !== null && previewWindow.contains(element)) {
+                return element;
+            }
+        }
+
+        var children = previewWindow.children;
+        var found = null;
+        for (var i = 0; i < children.length; i++) {
+            if (children[i].tagName === anchor.tagName &&
+                    children[i].textContent === anchor.text &&
+                    (found === null || Math.abs(i - index) < Math.abs(found.index - index))) {
+                found = { element: children[i], index: i };
+            }
+        }
+        return found === null ? null : found.element;
+    }
+
+    // Scrolls the preview so that the block the reader was at stays where it was, or else the
+    // heading of its section, rather than restoring a raw offset that may now be elsewhere.
+    function restoreScrollAnchor(anchor) {
+        var targets = anchor.heading === null ? [anchor.block] : [anchor.block, anchor.heading];
+        for (var i = 0; i < targets.length; i++) {
+            var element = findAnchor(targets[i], anchor.index);
+            if (element !== null) {
+                window.scrollBy(0, element.getBoundingClientRect().top - targets[i].top);
+                return;
+            }
+        }
+    }
+
+    function showBanner(text) {
+        var banner = document.getElementById('composer-banner');
+        if (banner === null) {
+            return;
+        }
+
+        banner.textContent = text;
+        banner.hidden = false;
+        banner.dataset.error = '';
+    }
+
+    // Shows an error of the server in the banner, or hides the banner if it shows an error and
+    // the error is null.
+    function showError(text) {
+        var banner = document.getElementById('composer-banner');
+        if (banner === null) {
+            return;
+        }
+
+        if (text !== null) {
+            showBanner(text);
+            banner.dataset.error = 'true';
+        } else if (banner.dataset.error === 'true') {
+            banner.hidden = true;
+            banner.dataset.error = '';
+        }
+    }
+
+    // Scrolls the preview to the last block rendered from at or before the given line of the
+    // markdown.
+    // Returns the last element of the preview that was rendered from the line or a line before
+    // it, or null.
+    function elementAtLine(line) {
+        var blocks = previewWindow.querySelectorAll('[data-source-line]');
+        var target = null;
+        for (var i = 0; i < blocks.length; i++) {
+            if (parseInt(blocks[i].dataset.sourceLine, 10) > line) {
+                break;
+            }
+            target = blocks[i];
+        }
+        return target;
+    }
+
+    function scrollToLine(line) {
+        var target = elementAtLine(line);
+        if (target === null) {
+            return;
+        }
+
+        if (presentation) {
+            var slide = target.closest('#markdown-preview > section');
+            Reveal.slide(Array.prototype.indexOf.call(previewWindow.children, slide));
+        } else {
+            target.scrollIntoView({ block: 'center' });
+        }
+    }
+
+    // The preview is rendered after the page loads, so the browser can't scroll to the anchor of
+    // the URL, such as `#installation`, by itself. The preview is scrolled to it once it's rendered.
+    var pendingFragment = null;
+    try {
+        pendingFragment = decodeURIComponent(window.location.hash.slice(1)) || null;
+    } catch (e) {
+        console.warn('invalid anchor: ' + window.location.hash);
+    }
+
+    function scrollToFragment() {
+        if (pendingFragment === null) {
+            return;
+        }
+
+        var target = document.getElementById(pendingFragment);
+        if (target === null || !previewWindow.contains(target)) {
+            // Windowed blocks are only added to the page near the visible part of the preview, so
+            // the preview is scrolled to the placeholder of the block instead.
+            var id = 'id="' + pendingFragment + '"';
+            var block = blocks.find(function(block) {
+                return block.placeholder && block.html.indexOf(id) !== -1;
+            });
+            target = block === undefined ? null : block.nodes[0];
+        }
+        if (target === null) {
+            return;
+        }
+
+        pendingFragment = null;
+        if (presentation) {
+            var slide = target.closest('#markdown-preview > section');
+            Reveal.slide(Array.prototype.indexOf.call(previewWindow.children, slide));
+        } else {
+            target.scrollIntoView();
+        }
+    }
+
+    // In focus mode, every block of the preview except the one at the editor's cursor is dimmed,
+    // and that block is kept in the middle of the window.
+    var focusMode = false;
+
+    // Marks the top-level block of the preview that contains the element at the cursor's line.
+    function focusCursorBlock() {
+        select([previewWindow], '.composer-focused').forEach(function(element) {
+            element.classList.remove('composer-focused');
+        });
+
+        var target = focusMode && cursorLine !== null ? elementAtLine(cursorLine) : null;
+        while (target !== null && target.parentNode !== previewWindow) {
+            target = target.parentNode;
+        }
+        if (target !== null) {
+            target.classList.add('composer-focused');
+        }
+        previewWindow.classList.toggle('composer-focusing', target !== null);
+    }
+
+    // Converts the text of a heading to an id, like GitHub does.
+    function slugify(text) {
+        return text.trim().toLowerCase()
+            .replace(/[^\w\- ]/g, '')
+            .replace(/ /g, '-');
+    }
+
+    // Regenerates the table of contents from the headings of the preview. Headings without an id
+    // are given one, so that the entries can link to them.
+    function updateToc() {
+        var toc = document.getElementById('composer-toc-list');
+        if (toc === null) {
+            return;
+        }
+
+        toc.innerHTML = '';
+
+        var headings = previewWindow.querySelectorAll('h1, h2, h3, h4, h5, h6');
+        var parents = [{ level: 0, item: null, list: toc }];
+
+        for (var i = 0; i < headings.length; i++) {
+            var heading = headings[i];
+            var level = parseInt(heading.tagName.substring(1), 10);
+
+            if (!heading.id) {
+                var slug = slugify(heading.textContent);
+                var id = slug;
+                for (var n = 1; document.getElementById(id) !== null; n++) {
+                    id = slug + '-' + n;
+                }
+                heading.id = id;
+            }
+
+            while (parents[parents.length - 1].level >= level) {
+                parents.pop();
+            }
+
+            var parent = parents[parents.length - 1];
+            if (parent.list === null) {
+                parent.list = document.createElement('ul');
+                parent.item.appendChild(parent.list);
+            }
+
+            var link = document.createElement('a');
+            link.href = '#' + encodeURIComponent(heading.id);
+            link.textContent = heading.textContent;
+
+            var item = document.createElement('li');
+            item.appendChild(link);
+            parent.list.appendChild(item);
+
+            parents.push({ level: level, item: item, list: null });
+        }
+    }
+
+    // Links the tab bar to every document served by the composer. The tab bar is hidden unless
+    // there's another document to switch to.
+    function updateTabs(tabs) {
+        var tabBar = document.getElementById('composer-tabs');
+        if (tabBar === null) {
+            return;
+        }
+
+        tabBar.innerHTML = '';
+
+        var current = false;
+        for (var i = 0; i < tabs.length; i++) {
+            var link = document.createElement('a');
+            link.href = tabs[i].path;
+            link.textContent = tabs[i].title;
+            if (tabs[i].path === window.location.pathname) {
+                link.classList.add('active');
+                link.setAttribute('aria-current', 'page');
+                current = true;
+            }
+            tabBar.appendChild(link);
+        }
+
+        tabBar.hidden = tabs.length === 0 || (current && tabs.length === 1);
+    }
+
+    function fetchJson(url) {
+        return fetch(url).then(function(response) {
+            return response.json();
+        });
+    }
+
+    function updateSettings(settings) {
+        fetch('/__/settings', {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify(settings),
+        });
+    }
+
+    function changeFontSize(delta) {
+        var size = parseFloat(getComputedStyle(previewWindow).fontSize);
+        updateSettings({ font_size: String(Math.max(size + delta, 8)) });
+    }
+
+    var autoScroll = true;
+    var cursorLine = null;
+
+    var previewWindow = document.getElementById('markdown-preview');
+
+    // In presentation mode, the page loads reveal.js and each slide of the preview is a section.
+    var presentation = typeof Reveal !== 'undefined';
+    if (presentation) {
+        Reveal.initialize({ hash: false });
+    }
+
+    // Scales the preview, like the browser's zoom does. Slides are scaled by reveal.js instead,
+    // which fits smaller slides to the window.
+    var slideSize = presentation ?
+        { width: Reveal.getConfig().width, height: Reveal.getConfig().height } : null;
+
+    function setZoom(percent) {
+        if (presentation) {
+            Reveal.configure({
+                width: slideSize.width * 100 / percent,
+                height: slideSize.height * 100 / percent,
+            });
+        } else {
+            document.documentElement.style.zoom = percent === 100 ? '' : percent / 100;
+        }
+    }
+
+    syntaxHighlight([previewWindow]);
+    renderMath([previewWindow]);
+    updateToc();
+
+    // Custom templates may leave out the table of contents.
+    var tocSidebar = document.getElementById('composer-toc');
+    if (tocSidebar !== null) {
+        document.getElementById('composer-toc-toggle').addEventListener('click', function() {
+            tocSidebar.classList.toggle('collapsed');
+        });
+    }
+
+    // Choices made from the toolbar are stored by the server, which updates every preview.
+    var toolbar = document.getElementById('composer-toolbar');
+    if (toolbar !== null) {
+        var colorScheme = document.getElementById('composer-color-scheme');
+        var highlightTheme = document.getElementById('composer-highlight-theme');
+        var tocButton = document.getElementById('composer-toc-button');
+
+        Promise.all([fetchJson('/__/highlight-themes'), fetchJson('/__/settings')])
+            .then(function(results) {
+                var themes = results[0];
+                var settings = results[1];
+
+                for (var i = 0; i < themes.length; i++) {
+                    var option = document.createElement('option');
+                    option.value = option.textContent = themes[i];
+                    highlightTheme.appendChild(option);
+                }
+
+                colorScheme.value = settings.color_scheme;
+                highlightTheme.value = settings.highlight_theme || '';
+            });
+
+        colorScheme.addEventListener('change', function() {
+            updateSettings({ color_scheme: colorScheme.value });
+        });
+        highlightTheme.addEventListener('change', function() {
+            updateSettings({ highlight_theme: highlightTheme.value });
+        });
+
+        document.getElementById('composer-font-smaller').addEventListener('click', function() {
+            changeFontSize(-2);
+        });
+        document.getElementById('composer-font-larger').addEventListener('click', function() {
+            changeFontSize(2);
+        });
+
+        // The QR code of the page's address on the network is only fetched when it's shown.
+        var qrButton = document.getElementById('composer-qr-button');
+        if (qrButton !== null) {
+            var qrPopover = document.getElementById('composer-qr');
+            var qrCode = document.getElementById('composer-qr-code');
+
+            qrButton.addEventListener('click', function() {
+                if (!qrCode.hasAttribute('src')) {
+                    qrCode.src = '/api/qr?path=' + encodeURIComponent(window.location.pathname);
+                }
+                qrPopover.hidden = !qrPopover.hidden;
+                qrButton.setAttribute('aria-expanded', String(!qrPopover.hidden));
+            });
+            document.addEventListener('click', function(event) {
+                if (!toolbar.contains(event.target)) {
+                    qrPopover.hidden = true;
+                    qrButton.setAttribute('aria-expanded', 'false');
+                }
+            });
+        }
+
+        // The table of contents isn't shown beside the markdown.
+        if (tocSidebar === null || document.body.classList.contains('composer-side-by-side')) {
+            tocButton.hidden = true;
+        } else {
+            tocButton.addEventListener('click', function() {
+                updateSettings({ toc: tocSidebar.hidden });
+            });
+        }
+    }
+
+    // The search box searches every document, listing the matches so that the preview may jump to
+    // them, even if they're in blocks of a large document that aren't on the page.
+    var searchBox = document.getElementById('composer-search');
+    if (searchBox !== null) {
+        var searchResults = document.getElementById('composer-search-results');
+        var searchTimeout = null;
+
+        var showSearchResults = function(results) {
+            searchResults.innerHTML = '';
+            results.forEach(function(result) {
+                result.matches.forEach(function(match) {
+                    var link = document.createElement('a');
+                    var current = result.path === window.location.pathname;
+                    link.href = result.path + '?search=' + encodeURIComponent(searchQuery) +
+                        (match.line === null ? '' : '&line=' + match.line);
+                    link.addEventListener('click', function(event) {
+                        if (current) {
+                            event.preventDefault();
+                            if (match.line !== null) {
+                                scrollToLine(match.line);
+                            }
+                        }
+                    });
+
+                    var location = document.createElement('span');
+                    location.className = 'composer-search-location';
+                    location.textContent = (current ? '' : result.title + ' \u00b7 ') +
+                        (match.line === null ? '' : 'Line ' + match.line);
+                    link.appendChild(location);
+                    link.appendChild(document.createTextNode(match.snippet));
+
+                    var item = document.createElement('li');
+                    item.appendChild(link);
+                    searchResults.appendChild(item);
+                });
+            });
+
+            if (results.length === 0) {
+                var empty = document.createElement('li');
+                empty.className = 'composer-search-empty';
+                empty.textContent = 'No matches';
+                searchResults.appendChild(empty);
+            }
+            searchResults.hidden = false;
+        };
+
+        var search = function() {
+            searchQuery = searchBox.value.trim();
+            highlightMatches();
+            if (searchQuery === '') {
+                searchResults.hidden = true;
+                return;
+            }
+
+            var query = searchQuery;
+            fetchJson('/api/search?q=' + encodeURIComponent(query)).then(function(results) {
+                if (query === searchQuery) {
+                    showSearchResults(results);
+                }
+            });
+        };
+
+        searchBox.addEventListener('input', function() {
+            clearTimeout(searchTimeout);
+            searchTimeout = setTimeout(search, 200);
+        });
+        searchBox.addEventListener('keydown', function(event) {
+            if (event.key === 'Enter') {
+                event.preventDefault();
+                nextMatch();
+            } else if (event.key === 'Escape') {
+                searchBox.value = '';
+                search();
+            }
+        });
+        searchBox.addEventListener('focus', function() {
+            searchResults.hidden = searchQuery === '';
+        });
+        document.addEventListener('click', function(event) {
+            if (!toolbar.contains(event.target)) {
+                searchResults.hidden = true;
+            }
+        });
+
+        // Results in other documents link to them with the search, and the line to jump to.
+        var params = new URLSearchParams(window.location.search);
+        if (params.has('search')) {
+            searchBox.value = params.get('search');
+            search();
+            if (params.has('line')) {
+                scrollToLine(parseInt(params.get('line'), 10));
+            }
+        }
+    }
+
+    // Pages that the composer opened are marked, so that they may close themselves when the
+    // preview ends. The mark is removed from the URL, but kept for reloads of the page.
+    var openedByComposer = false;
+    try {
+        var pageParams = new URLSearchParams(window.location.search);
+        if (pageParams.has('opened')) {
+            pageParams.delete('opened');
+            var query = pageParams.toString();
+            history.replaceState(null, '', window.location.pathname + (query ? '?' + query : '') +
+                window.location.hash);
+            sessionStorage.setItem('composer-opened', 'true');
+        }
+        openedByComposer = sessionStorage.getItem('composer-opened') !== null;
+    } catch (e) {
+        console.warn('could not read whether the composer opened the page: ' + e);
+    }
+
+    // Buffers are previewed under their own path, so updates are requested relative to the page.
+    var webSocketScheme = window.location.protocol === 'https:' ? 'wss://' : 'ws://';
+    var webSocketUrl = webSocketScheme + window.location.host + window.location.pathname;
+
+    // Large updates are gzipped if the browser can decompress them.
+    if (typeof DecompressionStream !== 'undefined') {
+        webSocketUrl += '?compress=gzip';
+    }
+
+    var socket = new ReconnectingWebSocket(webSocketUrl);
+    socket.maxReconnectInterval = 5000;
+
+    // Some proxies block websockets. If the websocket never connects, updates are received as
+    // server-sent events instead, and messages to the server are posted.
+    var socketOpened = false;
+    var events = null;
+
+    function send(message) {
+        if (events === null) {
+            socket.send(JSON.stringify(message));
+        } else {
+            fetch('__/events', {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/json' },
+                body: JSON.stringify(message),
+            });
+        }
+    }
+
+    function disconnect() {
+        socket.close();
+        if (events !== null) {
+            events.close();
+        }
+    }
+
+    previewWindow.addEventListener('click', function(event) {
+        var button = event.target.closest('button.copy-code');
+        if (button === null) {
+            return;
+        }
+
+        var code = button.parentNode.querySelector('code');
+        navigator.clipboard.writeText(code.textContent).then(function() {
+            button.textContent = 'Copied!';
+            setTimeout(function() {
+                button.textContent = 'Copy';
+            }, 2000);
+        });
+    });
+
+    // Clicking the header of a data table's column sorts the table by it, and clicking it again
+    // reverses the order.
+    previewWindow.addEventListener('click', function(event) {
+        var header = event.target.closest('table.data-table > thead th');
+        if (header === null) {
+            return;
+        }
+
+        var table = header.closest('table');
+        var descending = header.getAttribute('aria-sort') === 'ascending';
+        tableSorts[table.dataset.sourceLine] = {
+            column: header.cellIndex,
+            descending: descending,
+        };
+        sortTable(table, header.cellIndex, descending);
+    });
+
+    // The editor owns the markdown, so clicking a task only asks it to toggle the task. The
+    // checkbox is updated when the new markdown is rendered.
+    previewWindow.addEventListener('change', function(event) {
+        var checkbox = event.target;
+        if (!checkbox.classList.contains('task-list-item-checkbox')) {
+            return;
+        }
+
+        send({
+            type: 'toggle_task',
+            line: parseInt(checkbox.dataset.taskLine, 10),
+        });
+    });
+
+    // Links to other markdown files, such as wiki links, are rendered by the server, since the
+    // browser would display the raw markdown. Paths are relative to the static root, even on the
+    // page of a buffer.
+    previewWindow.addEventListener('click', function(event) {
+        var link = event.target.closest('a[href]');
+        if (link === null || link.origin !== window.location.origin ||
+                link.pathname === window.location.pathname ||
+                event.button !== 0 || event.ctrlKey || event.metaKey || event.shiftKey) {
+            return;
+        }
+
+        var path = link.pathname.replace(/^\/buffer\/[^\/]+\//, '/');
+        if (!link.classList.contains('wikilink') &&
+                !/\.(md|markdown|mdown|mkdn?|mkd)$/i.test(path)) {
+            return;
+        }
+
+        event.preventDefault();
+        send({
+            type: 'open',
+            path: decodeURIComponent(path),
+        });
+    });
+
+    // In the side-by-side layout, the markdown is displayed beside the preview, and the two are
+    // scrolled together by the source lines of the preview's blocks.
+    var sourcePane = document.getElementById('composer-source');
+
+    function showSource(markdown) {
+        var fragment = document.createDocumentFragment();
+        markdown.split('\n').forEach(function(text, i) {
+            var line = document.createElement('span');
+            line.className = 'composer-source-line';
+            line.dataset.line = i + 1;
+            line.textContent = text + '\n';
+            fragment.appendChild(line);
+        });
+
+        var scrollTop = sourcePane.scrollTop;
+        sourcePane.textContent = '';
+        sourcePane.appendChild(fragment);
+        sourcePane.scrollTop = scrollTop;
+    }
+
+    // Returns the line of the markdown at the top of the preview, with the fraction of the way to
+    // the next block's line that has been scrolled past.
+    function previewTopLine() {
+        var blocks = previewWindow.querySelectorAll('[data-source-line]');
+        for (var i = 0; i < blocks.length; i++) {
+            var rect = blocks[i].getBoundingClientRect();
+            if (rect.bottom <= 0) {
+                continue;
+            }
+
+            var line = parseInt(blocks[i].dataset.sourceLine, 10);
+            var next = line + 1;
+            for (var j = i + 1; j < blocks.length; j++) {
+                var nextLine = parseInt(blocks[j].dataset.sourceLine, 10);
+                if (nextLine > line) {
+                    next = nextLine;
+                    break;
+                }
+            }
+            var fraction = rect.height > 0 ? Math.max(0, -rect.top) / rect.height : 0;
+            return line + fraction * (next - line);
+        }
+        return null;
+    }
+
+    function sourceLineTop(line) {
+        var lines = sourcePane.children;
+        var index = Math.min(Math.max(Math.floor(line) - 1, 0), lines.length - 1);
+        if (index < 0) {
+            return 0;
+        }
+
+        var top = lines[index].offsetTop;
+        return top + (line - Math.floor(line)) * lines[index].offsetHeight;
+    }
+
+    // Scrolls the preview so that the block rendered from a line of the markdown is at the top,
+    // part of the way to the next block as far as the line is between them.
+    function scrollPreviewToSourceLine(line) {
+        var target = elementAtLine(Math.floor(line));
+        if (target === null) {
+            window.scrollTo(0, 0);
+            return;
+        }
+
+        var targetLine = parseInt(target.dataset.sourceLine, 10);
+        var targetTop = target.getBoundingClientRect().top + window.scrollY;
+        var blocks = previewWindow.querySelectorAll('[data-source-line]');
+        var next = null;
+        for (var i = 0; i < blocks.length; i++) {
+            if (parseInt(blocks[i].dataset.sourceLine, 10) > targetLine) {
+                next = blocks[i];
+                break;
+            }
+        }
+
+        var top = targetTop;
+        if (next !== null) {
+            var nextLine = parseInt(next.dataset.sourceLine, 10);
+            var nextTop = next.getBoundingClientRect().top + window.scrollY;
+            top += (line - targetLine) / (nextLine - targetLine) * (nextTop - targetTop);
+        }
+        window.scrollTo(0, top);
+    }
+
+    if (sourcePane !== null) {
+        // Only the pane under the pointer leads, so that the panes don't scroll each other back
+        // and forth.
+        window.addEventListener('scroll', function() {
+            if (sourcePane.matches(':hover')) {
+                return;
+            }
+
+            var line = previewTopLine();
+            if (line !== null) {
+                sourcePane.scrollTop = sourceLineTop(line);
+            }
+        });
+
+        sourcePane.addEventListener('scroll', function() {
+            if (!sourcePane.matches(':hover')) {
+                return;
+            }
+
+            var lines = sourcePane.children;
+            var low = 0;
+            var high = lines.length - 1;
+            while (low < high) {
+                var middle = Math.floor((low + high) / 2);
+                if (lines[middle].offsetTop + lines[middle].offsetHeight <= sourcePane.scrollTop) {
+                    low = middle + 1;
+                } else {
+                    high = middle;
+                }
+            }
+            if (lines.length === 0) {
+                return;
+            }
+
+            var height = lines[low].offsetHeight;
+            var fraction = height > 0 ? (sourcePane.scrollTop - lines[low].offsetTop) / height : 0;
+            scrollPreviewToSourceLine(low + 1 + Math.max(0, fraction));
+        });
+    }
+
+    var statsStrip = document.getElementById('composer-stats');
+
+    function plural(count, noun) {
+        return count.toLocaleString() + ' ' + noun + (count === 1 ? '' : 's');
+    }
+
+    function showStats(stats) {
+        if (statsStrip === null) {
+            return;
+        }
+
+        statsStrip.hidden = stats.characters === 0;
+        statsStrip.textContent = [
+            plural(stats.words, 'word'),
+            plural(stats.characters, 'character'),
+            stats.reading_time_minutes + ' min read',
+        ].join(' \u00b7 ');
+    }
+
+    var timingsOverlay = document.getElementById('composer-timings');
+
+    // How long the page took to display the last update, including highlighting its code blocks,
+    // until it's reported to the server.
+    var displayMs = null;
+
+    function formatMs(ms) {
+        return ms.toFixed(1) + ' ms';
+    }
+
+    // Shows how long each stage of a render took, or hides the render timings if the render is
+    // null. Renders that were just sent are answered with how long they took to display.
+    function showTimings(render) {
+        if (timingsOverlay === null) {
+            return;
+        }
+
+        timingsOverlay.hidden = render === null;
+        if (render === null) {
+            return;
+        }
+
+        var stages = [
+            ['parse', render.parse_ms],
+            ['external', render.external_renderer_ms],
+            ['postprocess', render.postprocess_ms],
+            ['send', render.broadcast_ms],
+            ['display', render.broadcast_ms !== null ? displayMs : null],
+        ];
+        var lines = ['render #' + render.id + (render.cached ? ' (cached)' : '') + ': ' +
+            formatMs(render.duration_ms)];
+        stages.forEach(function(stage) {
+            if (stage[1] !== null) {
+                lines.push('  ' + (stage[0] + ':').padEnd(13) + formatMs(stage[1]));
+            }
+        });
+        timingsOverlay.textContent = lines.join('\n');
+
+        if (render.broadcast_ms !== null && displayMs !== null) {
+            send({ type: 'displayed', render: render.id, ms: displayMs });
+            displayMs = null;
+        }
+    }
+
+    var backButton = document.getElementById('composer-back');
+    if (backButton !== null) {
+        backButton.addEventListener('click', function() {
+            send({ type: 'back' });
+        });
+    }
+
+    // Reloads the images that were modified. A query is added to their URLs so that the browser
+    // doesn't display its cached copy.
+    function reloadImages(sources) {
+        var version = Date.now();
+        select([previewWindow], 'img').forEach(function(image) {
+            var src = image.dataset.composerSrc || image.getAttribute('src');
+            if (sources.indexOf(src) !== -1) {
+                image.dataset.composerSrc = src;
+                image.src = src + (src.indexOf('?') === -1 ? '?' : '&') + 'v=' + version;
+            }
+        });
+    }
+
+    // Replaces the contents of the custom stylesheets, which were modified. The page is reloaded
+    // if stylesheets were added or removed.
+    function updateStyles(styles) {
+        var elements = document.querySelectorAll('style[data-custom-style]');
+        if (elements.length !== styles.length) {
+            window.location.reload();
+            return;
+        }
+
+        for (var i = 0; i < styles.length; i++) {
+            elements[i].textContent = styles[i];
+        }
+    }
+
+    // Quotes text as a CSS string, like the server does for the page layout.
+    function cssString(text) {
+        return '"' + text.replace(/["\\]/g, '\\$&').replace(/\n/g, '\\A ')
+            .replace(/</g, '\\3C ') + '"';
+    }
+
+    // Replaces the title in the headers and footers of printed pages, which the server filled in
+    // with the title of the page when it was loaded.
+    function updatePageLayoutTitle(title) {
+        var pageLayout = document.getElementById('composer-page-layout');
+        if (pageLayout !== null && title !== document.title) {
+            pageLayout.textContent = pageLayout.textContent
+                .split(cssString(document.title)).join(cssString(title));
+        }
+    }
+
+    function onMessage(event) {
+        var message = JSON.parse(event.data);
+
+        switch (message.type) {
+            case 'html':
+            case 'patch':
+                // Only the blocks that changed are replaced, so that the rest of the preview
+                // keeps its state.
+                var displayStarted = performance.now();
+                var following = (autoScroll || focusMode) && cursorLine !== null;
+                var anchor = presentation || following ? null : scrollAnchor();
+                var nodes;
+                if (message.type === 'html') {
+                    nodes = replacePreview(message.blocks, message.windowed);
+                } else {
+                    nodes = patchPreview(
+                        message.start, message.delete, message.blocks, message.line_offset);
+                }
+
+                if (presentation) {
+                    Reveal.sync();
+                }
+                syntaxHighlight(nodes);
+                renderMath(nodes);
+                sortTables(nodes);
+                markChanges();
+                markDiagnostics();
+                highlightMatches();
+                focusCursorBlock();
+                updateToc();
+                if (following) {
+                    scrollToLine(cursorLine);
+                } else if (anchor !== null) {
+                    restoreScrollAnchor(anchor);
+                }
+                scrollToFragment();
+                displayMs = performance.now() - displayStarted;
+                break;
+            case 'auto_scroll':
+                autoScroll = message.enabled;
+                break;
+            case 'toc':
+                if (tocSidebar !== null) {
+                    tocSidebar.hidden = presentation || sourcePane !== null || !message.enabled;
+                }
+                break;
+            case 'zoom':
+                setZoom(message.percent);
+                break;
+            case 'focus':
+                // Slides are already displayed one at a time.
+                focusMode = message.enabled && !presentation;
+                document.body.classList.toggle('composer-focus', focusMode);
+                focusCursorBlock();
+                if (focusMode && cursorLine !== null) {
+                    scrollToLine(cursorLine);
+                }
+                break;
+            case 'scroll':
+                cursorLine = message.line;
+                focusCursorBlock();
+                if (autoScroll || focusMode) {
+                    scrollToLine(cursorLine);
+                }
+                break;
+            case 'title':
+                updatePageLayoutTitle(message.title);
+                document.title = message.title;
+                break;
+            case 'reload':
+                window.location.reload();
+                break;
+            case 'print':
+                window.print();
+                break;
+            case 'raise':
+                // Browsers only let pages focus themselves in some cases, such as when they
+                // opened the window.
+                window.focus();
+                if (message.anchor !== null) {
+                    pendingFragment = message.anchor;
+                    scrollToFragment();
+                }
+                break;
+            case 'tabs':
+                updateTabs(message.tabs);
+                break;
+            case 'images':
+                reloadImages(message.sources);
+                break;
+            case 'styles':
+                updateStyles(message.styles);
+                break;
+            case 'history':
+                if (backButton !== null) {
+                    backButton.hidden = !message.can_go_back;
+                }
+                break;
+            case 'error':
+                showError(message.message);
+                break;
+            case 'source':
+                if (sourcePane !== null) {
+                    showSource(message.markdown);
+                }
+                break;
+            case 'stats':
+                showStats(message.stats);
+                break;
+            case 'timings':
+                showTimings(message.render);
+                break;
+            case 'changes':
+                changes = message.lines;
+                markChanges();
+                break;
+            case 'diagnostics':
+                diagnostics = message.diagnostics;
+                markDiagnostics();
+                break;
+            case 'shutdown':
+                // The server is going away for good, so don't try to reconnect.
+                disconnect();
+                document.body.classList.add('composer-ended');
+                if (message.close && openedByComposer) {
+                    window.close();
+                }
+                showBanner('The preview has ended.');
+                break;
+            case 'close_browser':
+                disconnect();
+                document.body.classList.add('composer-ended');
+
+                // Browsers only allow scripts to close windows that were opened by a script,
+                // so let the user know if the tab is still around.
+                window.open('', '_self', '');
+                window.close();
+                showBanner('The preview was closed. You may close this tab.');
+                break;
+        }
+    }
+
+    // Gzipped updates are decompressed asynchronously, so every message waits for the ones before
+    // it to be handled.
+    var received = Promise.resolve();
+
+    socket.onmessage = function(event) {
+        received = received.then(function() {
+            if (typeof event.data === 'string') {
+                return event.data;
+            }
+            var stream = event.data.stream().pipeThrough(new DecompressionStream('gzip'));
+            return new Response(stream).text();
+        }).then(function(data) {
+            onMessage({ data: data });
+        }).catch(function(e) {
+            console.error('could not handle update: ' + e);
+        });
+    };
+
+    socket.onopen = function() {
+        socketOpened = true;
+    };
+
+    // The server disconnects pages that can't keep up with its updates with the "try again later"
+    // code. They reconnect and receive the latest document, so the window stays open.
+    var fellBehind = false;
+
+    socket.onconnecting = function(event) {
+        fellBehind = event.code === 1013;
+    };
+
+    socket.onclose = function(event) {
+        if (!socketOpened && events === null) {
+            socket.close();
+            events = new EventSource('__/events');
+            events.onmessage = onMessage;
+            return;
+        }
+
+        if (fellBehind) {
+            return;
+        }
+
+        // Close the browser window.
+        window.open('', '_self', '');
+        window.close();
+    }
+});
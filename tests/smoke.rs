@@ -19,6 +19,31 @@ fn send_data_rpc(cmd: &mut Command) {
     cmd.write_stdin(serde_json::to_vec(&rpc).unwrap());
 }
 
+#[cfg(feature = "msgpack")]
+fn get_url_rpc(cmd: &mut Command) -> String {
+    let rpc = (0, 1, "get_url", Vec::<String>::new());
+
+    let output = cmd.write_stdin(rmp_serde::to_vec(&rpc).unwrap()).unwrap();
+
+    let (msg_type, id, error, url): (u64, u64, (), String) =
+        rmp_serde::from_slice(&output.stdout).unwrap();
+    assert_eq!((msg_type, id, error), (1, 1, ()));
+    url
+}
+
+#[cfg(feature = "json-rpc")]
+fn get_url_rpc(cmd: &mut Command) -> String {
+    use serde_json::json;
+
+    let rpc = vec![json!(1), json!({ "method": "get_url", "params": [] })];
+
+    let output = cmd.write_stdin(serde_json::to_vec(&rpc).unwrap()).unwrap();
+
+    let (id, url): (u64, String) = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(id, 1);
+    url
+}
+
 #[test]
 fn rpc() {
     let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
@@ -28,3 +53,392 @@ fn rpc() {
 
     cmd.assert().success();
 }
+
+#[test]
+fn get_url() {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    cmd.args(["--no-auto-open", "--port", "0"]);
+
+    let url = get_url_rpc(&mut cmd);
+
+    assert!(url.starts_with("http://"), "unexpected URL: {}", url);
+}
+
+#[test]
+fn print_url() {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    let output = cmd.args(["--no-auto-open", "--print-url"]).unwrap();
+
+    let (method, (url, urls, _)) = listening_notification(&output.stdout);
+    assert_eq!(method, "listening");
+    assert!(url.starts_with("http://"), "unexpected URL: {}", url);
+    assert_eq!(urls[0], url);
+}
+
+type Listening = (String, (String, Vec<String>, Vec<String>));
+
+#[cfg(feature = "msgpack")]
+fn listening_notification(stdout: &[u8]) -> Listening {
+    let (msg_type, function, (_, notification)): (u64, String, (String, Listening)) =
+        rmp_serde::from_read(stdout).unwrap();
+    assert_eq!((msg_type, &*function), (2, "nvim_call_function"));
+    notification
+}
+
+#[cfg(feature = "json-rpc")]
+fn listening_notification(stdout: &[u8]) -> Listening {
+    let mut messages = serde_json::Deserializer::from_slice(stdout).into_iter();
+    let (command, _, notification): (String, String, Listening) =
+        serde_json::from_value(messages.next().unwrap().unwrap()).unwrap();
+    assert_eq!(command, "call");
+    notification
+}
+
+#[test]
+fn empty_browser() {
+    for flag in ["--browser", "--pdf-browser"] {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        let output = cmd.args(["--no-auto-open", flag, " "]).output().unwrap();
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("not a command"));
+    }
+}
+
+#[test]
+fn port_file() {
+    let port_file = std::env::temp_dir().join(format!("composer-port-{}", std::process::id()));
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    cmd.args(["--no-auto-open", "--port-file"]).arg(&port_file);
+
+    let url = get_url_rpc(&mut cmd);
+
+    assert!(url.starts_with("http://"), "unexpected URL: {}", url);
+    assert!(!port_file.exists());
+}
+
+#[test]
+fn export_html() {
+    let dir = std::env::temp_dir().join(format!("composer-export-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("doc.md"), "# Exported").unwrap();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    cmd.arg("--export-html")
+        .arg(dir.join("doc.html"))
+        .arg(dir.join("doc.md"))
+        .assert()
+        .success();
+
+    let html = std::fs::read_to_string(dir.join("doc.html")).unwrap();
+    assert!(html.contains(">Exported</h1>"));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn render_only() {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    let output = cmd
+        .arg("--render-only")
+        .write_stdin("# Rendered")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let html = String::from_utf8(output.stdout).unwrap();
+    assert!(html.contains(">Rendered</h1>"));
+}
+
+#[test]
+fn bench() {
+    let dir = std::env::temp_dir().join(format!("composer-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("doc.md"), "# Measured").unwrap();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    let output = cmd
+        .arg("--bench")
+        .arg(dir.join("doc.md"))
+        .args(["--iterations", "3"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let report = String::from_utf8(output.stdout).unwrap();
+    assert!(report.starts_with("3 renders of 10 bytes"), "{}", report);
+    assert!(report.contains("p99"));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn completions() {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    let output = cmd.args(["completions", "bash"]).output().unwrap();
+
+    assert!(output.status.success());
+    let script = String::from_utf8(output.stdout).unwrap();
+    assert!(script.contains("_markdown-composer()"), "{}", script);
+    assert!(script.contains("--render-only"));
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    cmd.args(["completions", "tcsh"]).assert().failure();
+}
+
+#[test]
+fn man_page() {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    let output = cmd.arg("--generate-man").output().unwrap();
+
+    assert!(output.status.success());
+    let page = String::from_utf8(output.stdout).unwrap();
+    assert!(page.starts_with(".TH MARKDOWN-COMPOSER 1"), "{}", page);
+    assert!(page.contains("\\fBsend_data(data: String)\\fR"));
+    assert!(page.contains("\\fB\\-\\-render\\-only\\fR"));
+    assert!(page.contains(".SH SUBCOMMANDS"));
+}
+
+#[test]
+fn check_links() {
+    let dir = std::env::temp_dir().join(format!("composer-check-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("doc.md"),
+        "[ok](doc.md)\n\n![missing](missing.png)",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    let output = cmd
+        .current_dir(&dir)
+        .arg("--check")
+        .arg("doc.md")
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    let problems: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(problems[0]["line"], 3);
+    assert_eq!(problems[0]["url"], "missing.png");
+    assert_eq!(problems.as_array().unwrap().len(), 1);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn session() {
+    let dir = std::env::temp_dir().join(format!("composer-session-{}", std::process::id()));
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    cmd.env("XDG_STATE_HOME", &dir)
+        .args(["--no-auto-open", "--session", "notes"]);
+    send_data_rpc(&mut cmd);
+    cmd.assert().success();
+
+    let session = std::fs::read(dir.join("markdown-composer/sessions/notes.json")).unwrap();
+    let session: serde_json::Value = serde_json::from_slice(&session).unwrap();
+    assert!(session["markdown"].as_str().unwrap().starts_with("Hello, "));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn json_log_format() {
+    let dir = std::env::temp_dir().join(format!("composer-json-log-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("doc.md"), "# Logged").unwrap();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    cmd.args(["--log-level", "info", "--log-format", "json", "--log-file"])
+        .arg(dir.join("composer.log"))
+        .arg("--export-html")
+        .arg(dir.join("doc.html"))
+        .arg(dir.join("doc.md"))
+        .assert()
+        .success();
+
+    let log = std::fs::read_to_string(dir.join("composer.log")).unwrap();
+    let records = log
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert!(records.iter().any(|record| record["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("exporting HTML")));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(feature = "msgpack")]
+fn get_url_over(mut conn: impl std::io::Read + std::io::Write) -> String {
+    let rpc = (0, 1, "get_url", Vec::<String>::new());
+    conn.write_all(&rmp_serde::to_vec(&rpc).unwrap()).unwrap();
+
+    let (_, _, _, url): (u64, u64, (), String) = rmp_serde::from_read(conn).unwrap();
+    url
+}
+
+#[cfg(feature = "json-rpc")]
+fn get_url_over(mut conn: impl std::io::Read + std::io::Write) -> String {
+    use serde_json::json;
+
+    let rpc = vec![json!(1), json!({ "method": "get_url", "params": [] })];
+    conn.write_all(&serde_json::to_vec(&rpc).unwrap()).unwrap();
+
+    let mut responses = serde_json::Deserializer::from_reader(conn).into_iter();
+    let (_, url): (u64, String) =
+        serde_json::from_value(responses.next().unwrap().unwrap()).unwrap();
+    url
+}
+
+#[cfg(feature = "msgpack")]
+fn shutdown_rpc() -> Vec<u8> {
+    rmp_serde::to_vec(&(2, "shutdown", Vec::<String>::new())).unwrap()
+}
+
+#[cfg(feature = "json-rpc")]
+fn shutdown_rpc() -> Vec<u8> {
+    br#"[0, {"method": "shutdown", "params": []}]"#.to_vec()
+}
+
+/// Connects to an editor socket of a composer that is starting up.
+fn connect_editor<S>(connect: impl Fn() -> std::io::Result<S>) -> S {
+    for _ in 0..100 {
+        if let Ok(conn) = connect() {
+            return conn;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    panic!("could not connect to the composer");
+}
+
+#[cfg(unix)]
+#[test]
+fn rpc_socket() {
+    use std::io::prelude::*;
+    use std::os::unix::net::UnixStream;
+
+    use assert_cmd::cargo::CommandCargoExt;
+
+    let socket = std::env::temp_dir().join(format!("composer-rpc-{}.sock", std::process::id()));
+
+    let mut child = std::process::Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["--no-auto-open", "--rpc-socket"])
+        .arg(&socket)
+        .spawn()
+        .unwrap();
+
+    let connect = || connect_editor(|| UnixStream::connect(&socket));
+
+    // Editors may disconnect and reattach without stopping the server.
+    let url = get_url_over(connect());
+    assert!(url.starts_with("http://"), "unexpected URL: {}", url);
+    assert_eq!(get_url_over(connect()), url);
+
+    connect().write_all(&shutdown_rpc()).unwrap();
+
+    assert!(child.wait().unwrap().success());
+    assert!(!socket.exists());
+}
+
+#[test]
+fn rpc_listen() {
+    use std::io::prelude::*;
+    use std::net::{TcpListener, TcpStream};
+
+    use assert_cmd::cargo::CommandCargoExt;
+
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let mut child = std::process::Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["--no-auto-open", "--rpc-listen"])
+        .arg(addr.to_string())
+        .spawn()
+        .unwrap();
+
+    let connect = || connect_editor(|| TcpStream::connect(addr));
+
+    let url = get_url_over(connect());
+    assert!(url.starts_with("http://"), "unexpected URL: {}", url);
+
+    connect().write_all(&shutdown_rpc()).unwrap();
+
+    assert!(child.wait().unwrap().success());
+
+    // Other machines could read and write files, so only loopback addresses are accepted.
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    let output = cmd
+        .args(["--no-auto-open", "--rpc-listen", "0.0.0.0:0"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("refusing to accept RPCs"));
+}
+
+#[cfg(unix)]
+#[test]
+fn exit_signal() {
+    use std::os::unix::net::UnixStream;
+
+    use assert_cmd::cargo::CommandCargoExt;
+
+    let socket = std::env::temp_dir().join(format!("composer-signal-{}.sock", std::process::id()));
+
+    let mut child = std::process::Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["--no-auto-open", "--rpc-socket"])
+        .arg(&socket)
+        .spawn()
+        .unwrap();
+
+    get_url_over(connect_editor(|| UnixStream::connect(&socket)));
+
+    // The composer shuts down gracefully, removing its socket.
+    let status = std::process::Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    assert!(child.wait().unwrap().success());
+    assert!(!socket.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn parent_exit() {
+    use std::os::unix::net::UnixStream;
+
+    use assert_cmd::cargo::CommandCargoExt;
+
+    let socket = std::env::temp_dir().join(format!("composer-parent-{}.sock", std::process::id()));
+
+    let mut parent = std::process::Command::new("sleep")
+        .arg("60")
+        .spawn()
+        .unwrap();
+    let mut child = std::process::Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["--no-auto-open", "--parent-pid", &parent.id().to_string()])
+        .arg("--rpc-socket")
+        .arg(&socket)
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    get_url_over(connect_editor(|| UnixStream::connect(&socket)));
+
+    // The composer shuts down even though its stdin is still open.
+    parent.kill().unwrap();
+    parent.wait().unwrap();
+
+    assert!(child.wait().unwrap().success());
+    assert!(!socket.exists());
+}